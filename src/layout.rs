@@ -1,23 +1,135 @@
 use std::{
-    cell::{RefCell, RefMut},
     io::Cursor,
-    rc::Rc,
+    sync::{Arc, Mutex, MutexGuard},
 };
 
 use thiserror::Error;
 
+use crate::open_type::TableDirectoryError;
+
 #[derive(Error, Debug)]
 pub enum LayoutError {
     #[error(transparent)]
     IoError(#[from] std::io::Error),
+    #[error(transparent)]
+    InvalidTableDirectory(#[from] TableDirectoryError),
+    #[error("failed to write table '{}': {source}", String::from_utf8_lossy(.tag))]
+    TableIoError {
+        tag: [u8; 4],
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("table '{}' reserved {reserved} bytes but attempted to write {attempted}", String::from_utf8_lossy(.tag))]
+    ReservationOverflow {
+        tag: [u8; 4],
+        reserved: usize,
+        attempted: usize,
+    },
+    #[error("invalid value for '{field}' in table '{}': {reason}", String::from_utf8_lossy(.tag))]
+    InvalidValue {
+        tag: [u8; 4],
+        field: &'static str,
+        reason: String,
+    },
+    #[error("table '{}' reserved {reserved} bytes but only {written} were written", String::from_utf8_lossy(.tag))]
+    UnderfilledReservation {
+        tag: [u8; 4],
+        reserved: usize,
+        written: usize,
+    },
+    #[error("glyph {glyph_id} in table '{}' is invalid: {source}", String::from_utf8_lossy(.tag))]
+    GlyphError {
+        tag: [u8; 4],
+        glyph_id: usize,
+        #[source]
+        source: Box<LayoutError>,
+    },
+}
+
+impl LayoutError {
+    pub fn invalid_value(tag: [u8; 4], field: &'static str, reason: impl Into<String>) -> Self {
+        Self::InvalidValue {
+            tag,
+            field,
+            reason: reason.into(),
+        }
+    }
+
+    /**
+     * Attaches the tag of the table that was written when the error occurred to errors that do not carry one yet.
+     */
+    pub fn with_table(self, tag: [u8; 4]) -> Self {
+        match self {
+            Self::IoError(source) => {
+                match source
+                    .get_ref()
+                    .and_then(|e| e.downcast_ref::<ReservationOverflowInfo>())
+                {
+                    Some(overflow) => Self::ReservationOverflow {
+                        tag,
+                        reserved: overflow.reserved,
+                        attempted: overflow.attempted,
+                    },
+                    None => Self::TableIoError { tag, source },
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+/** The payload of the [std::io::Error] returned when a write exceeds the length of a [Reservation]. */
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("attempted to write {attempted} bytes into a reservation of {reserved} bytes")]
+pub struct ReservationOverflowInfo {
+    pub reserved: usize,
+    pub attempted: usize,
 }
 
-pub trait Layouted {
+/**
+ * Layouted objects only touch their own reservation during a pass, so independent ones can run their passes on
+ * different threads against the same snapshot of the file.
+ */
+pub trait Layouted: Send {
     fn reservation(&self) -> &Reservation;
     fn requires_another_pass(&self) -> bool;
     fn pass(&mut self, current_file: &[u8]) -> Result<(), LayoutError>;
 }
 
+impl<T: Layouted + ?Sized> Layouted for Box<T> {
+    fn reservation(&self) -> &Reservation {
+        (**self).reservation()
+    }
+
+    fn requires_another_pass(&self) -> bool {
+        (**self).requires_another_pass()
+    }
+
+    fn pass(&mut self, current_file: &[u8]) -> Result<(), LayoutError> {
+        (**self).pass(current_file)
+    }
+}
+
+/**
+ * Lays out `root` and runs passes until nothing requires another one, then returns the bytes. With `parallel` the
+ * passes may run on several threads; the bytes are the same either way.
+ */
+pub fn build<L: Layouted>(
+    root: &impl Layoutable<L>,
+    parallel: bool,
+) -> Result<Vec<u8>, LayoutError> {
+    let mut layouter = Layouter::new(4);
+    layouter.set_parallel(parallel);
+
+    let mut layouted = root.layout(&mut layouter);
+
+    while layouted.requires_another_pass() {
+        layouted.pass(&layouter.get_result())?;
+    }
+
+    Ok(layouter.get_result())
+}
+
 pub trait Layoutable<L> {
     fn layout(&self, layouter: &mut Layouter) -> L;
 }
@@ -28,22 +140,85 @@ impl<L, T: Fn(&mut Layouter) -> L> Layoutable<L> for T {
     }
 }
 
+#[derive(Debug)]
+struct Buffer {
+    cursor: Cursor<Box<[u8]>>,
+    /** The furthest position any write has reached. */
+    written: usize,
+}
+
+type SharedBuffer = Arc<Mutex<Buffer>>;
+
+/** How reservations that received fewer bytes than they reserved are reported. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillCheck {
+    /** Under-filled reservations are errors. */
+    #[default]
+    Strict,
+    /** Under-filled reservations are left zero padded and collected as warnings, see [Layouter::take_warnings]. */
+    Lenient,
+}
+
+/** The warnings of a [FillCheck::Lenient] layout, shared between the layouter and what it layouted. */
+pub type Warnings = Arc<Mutex<Vec<LayoutError>>>;
+
+/**
+ * Hands out reservations at deterministic offsets. Reserving happens sequentially, only the passes writing into the
+ * reservations may run in parallel.
+ */
 #[derive(Debug)]
 pub struct Layouter {
     alignment: usize,
     current_length: usize,
-    buffer: Vec<Rc<RefCell<Cursor<Box<[u8]>>>>>,
+    fill_check: FillCheck,
+    warnings: Warnings,
+    parallel: bool,
+    buffer: Vec<SharedBuffer>,
 }
 
 impl Layouter {
     pub fn new(alignment: usize) -> Self {
+        Self::with_fill_check(alignment, FillCheck::default())
+    }
+
+    pub fn with_fill_check(alignment: usize, fill_check: FillCheck) -> Self {
         Self {
             alignment,
             current_length: 0,
+            fill_check,
+            warnings: Warnings::default(),
+            parallel: false,
             buffer: Vec::new(),
         }
     }
 
+    pub fn fill_check(&self) -> FillCheck {
+        self.fill_check
+    }
+
+    pub fn warnings(&self) -> Warnings {
+        self.warnings.clone()
+    }
+
+    /** Removes and returns the warnings reported so far. */
+    pub fn take_warnings(&self) -> Vec<LayoutError> {
+        std::mem::take(
+            &mut self
+                .warnings
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+    }
+
+    /** Whether layouted objects may serialize their parts in parallel. The output is the same either way. */
+    pub fn parallel(&self) -> bool {
+        self.parallel
+    }
+
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
     pub fn reserve(&mut self, len: usize) -> Reservation {
         let padding = (self.alignment - (len % self.alignment)) % self.alignment;
 
@@ -54,7 +229,10 @@ impl Layouter {
         let offset = self.current_length;
         self.current_length += actual_len;
 
-        let buffer = Rc::new(RefCell::new(Cursor::new(vec![0; actual_len].into())));
+        let buffer = Arc::new(Mutex::new(Buffer {
+            cursor: Cursor::new(vec![0; actual_len].into()),
+            written: 0,
+        }));
 
         self.buffer.push(buffer.clone());
 
@@ -69,12 +247,12 @@ impl Layouter {
         let mut result = Vec::with_capacity(self.current_length);
 
         for buffer in self.buffer.iter() {
-            result.extend(buffer.borrow().get_ref().iter());
+            result.extend(lock(buffer).cursor.get_ref().iter());
         }
 
         assert_eq!(result.len(), self.current_length);
 
-        return result;
+        result
     }
 }
 
@@ -82,17 +260,13 @@ impl Layouter {
 pub struct Reservation {
     offset: usize,
     len: usize,
-    buffer: Rc<RefCell<Cursor<Box<[u8]>>>>,
+    buffer: SharedBuffer,
 }
 
 pub trait SeekWrite: std::io::Write + std::io::Seek {}
 
 impl<T: std::io::Write + std::io::Seek> SeekWrite for T {}
 
-pub trait SeekRead: std::io::Read + std::io::Seek {}
-
-impl<T: std::io::Read + std::io::Seek> SeekRead for T {}
-
 impl Reservation {
     pub fn offset(&self) -> usize {
         self.offset
@@ -102,16 +276,116 @@ impl Reservation {
         self.len
     }
 
-    pub fn writer(&mut self) -> RefMut<dyn SeekWrite> {
-        let mut writer = self.buffer.borrow_mut();
-        writer.set_position(0);
-        writer
+    /** The number of bytes up to the furthest position that has been written. */
+    pub fn written(&self) -> usize {
+        lock(&self.buffer).written
+    }
+
+    /**
+     * Compares what was written against the reserved length. Only under-filled reservations can show up here,
+     * writing past the end already fails in the writer. A lenient check returns the error as a warning instead.
+     */
+    pub fn check_fill(
+        &self,
+        tag: [u8; 4],
+        fill_check: FillCheck,
+    ) -> Result<Option<LayoutError>, LayoutError> {
+        let written = self.written();
+
+        if written == self.len {
+            return Ok(None);
+        }
+
+        let error = LayoutError::UnderfilledReservation {
+            tag,
+            reserved: self.len,
+            written,
+        };
+
+        match fill_check {
+            FillCheck::Strict => Err(error),
+            FillCheck::Lenient => Ok(Some(error)),
+        }
+    }
+
+    pub fn writer(&mut self) -> ReservationWriter<'_> {
+        let mut buffer = lock(&self.buffer);
+        buffer.cursor.set_position(0);
+
+        ReservationWriter {
+            reserved: self.len,
+            buffer,
+        }
+    }
+
+    pub fn reader(&self) -> ReservationReader<'_> {
+        let mut buffer = lock(&self.buffer);
+        buffer.cursor.set_position(0);
+
+        ReservationReader { buffer }
+    }
+}
+
+/**
+ * A poisoned buffer only means another pass panicked while writing it; the bytes are rewritten on the next pass
+ * anyway, so the lock is taken regardless.
+ */
+fn lock(buffer: &Mutex<Buffer>) -> MutexGuard<'_, Buffer> {
+    buffer
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub struct ReservationReader<'a> {
+    buffer: MutexGuard<'a, Buffer>,
+}
+
+impl std::io::Read for ReservationReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.buffer.cursor.read(buf)
     }
+}
 
-    pub fn reader(&self) -> RefMut<dyn SeekRead> {
-        let mut reader = self.buffer.borrow_mut();
-        reader.set_position(0);
-        reader
+impl std::io::Seek for ReservationReader<'_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.buffer.cursor.seek(pos)
+    }
+}
+
+/** Writes into a [Reservation], refusing writes past its length instead of spilling into the padding. */
+pub struct ReservationWriter<'a> {
+    reserved: usize,
+    buffer: MutexGuard<'a, Buffer>,
+}
+
+impl std::io::Write for ReservationWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let attempted = self.buffer.cursor.position() as usize + buf.len();
+
+        if attempted > self.reserved {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                ReservationOverflowInfo {
+                    reserved: self.reserved,
+                    attempted,
+                },
+            ));
+        }
+
+        let written = self.buffer.cursor.write(buf)?;
+        self.buffer.written = self.buffer.written.max(attempted);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.buffer.cursor.flush()
+    }
+}
+
+impl std::io::Seek for ReservationWriter<'_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.buffer.cursor.seek(pos)
     }
 }
 
@@ -173,4 +447,86 @@ mod test {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn can_not_write_into_the_padding() {
+        let mut layouter = Layouter::new(4);
+
+        let result = layouter.reserve(3).writer().write_all(b"abcd");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn written_bytes_are_tracked() -> std::io::Result<()> {
+        let mut layouter = Layouter::new(4);
+        let mut reservation = layouter.reserve(6);
+
+        reservation.writer().write_all(b"abcd")?;
+        assert_eq!(reservation.written(), 4);
+
+        reservation.writer().write_all(b"ab")?;
+        assert_eq!(reservation.written(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn underfilled_reservations_are_errors_unless_lenient() -> std::io::Result<()> {
+        let mut layouter = Layouter::new(4);
+        let mut reservation = layouter.reserve(6);
+
+        reservation.writer().write_all(b"abcd")?;
+
+        assert!(matches!(
+            reservation.check_fill(*b"test", FillCheck::Strict),
+            Err(LayoutError::UnderfilledReservation {
+                reserved: 6,
+                written: 4,
+                ..
+            })
+        ));
+        assert!(matches!(
+            reservation.check_fill(*b"test", FillCheck::Lenient),
+            Ok(Some(LayoutError::UnderfilledReservation { .. }))
+        ));
+
+        reservation.writer().write_all(b"abcdef")?;
+
+        assert!(matches!(
+            reservation.check_fill(*b"test", FillCheck::Strict),
+            Ok(None)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn layouters_and_reservations_can_cross_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Layouter>();
+        assert_send_sync::<Reservation>();
+    }
+
+    #[test]
+    fn overflows_are_reported_with_the_table() {
+        use byteorder::{WriteBytesExt, BE};
+
+        let mut layouter = Layouter::new(1);
+        let mut reservation = layouter.reserve(6);
+
+        let mut writer = reservation.writer();
+        writer.write_u32::<BE>(0).unwrap();
+        let error = LayoutError::from(writer.write_u32::<BE>(0).unwrap_err()).with_table(*b"maxp");
+
+        assert!(matches!(
+            error,
+            LayoutError::ReservationOverflow {
+                tag,
+                reserved: 6,
+                attempted: 8
+            } if &tag == b"maxp"
+        ));
+    }
 }
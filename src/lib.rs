@@ -1,7 +1,4 @@
-#![feature(associated_type_defaults)]
-#![feature(once_cell)]
-
-pub use layout::{LayoutError, Layoutable, Layouted, Layouter};
+pub use layout::{build, FillCheck, LayoutError, Layoutable, Layouted, Layouter, Warnings};
 pub use manifest::Manifest;
 
 mod layout;
@@ -9,4 +6,193 @@ pub mod manifest;
 pub mod open_type;
 
 #[cfg(test)]
-mod test {}
+pub(crate) mod test {
+    use chrono::{TimeZone, Utc};
+
+    use crate::{
+        build,
+        manifest::{TimestampSource, Timestamps},
+        open_type::{
+            reader::{Font, TRUE_TYPE_VERSION},
+            tables::*,
+            true_type::{Contour, Instrution, Point},
+            Fixed,
+        },
+        LayoutError, Manifest,
+    };
+
+    /** A complete TrueType font with `glyph_count` square glyphs mapped from 'A' onwards, plus `.notdef`. */
+    pub(crate) fn sample_font(glyph_count: u16) -> Font {
+        let glyphs: Vec<_> = std::iter::once(Glyph::Empty)
+            .chain((0..glyph_count).map(|i| {
+                let size = 10 + i as i16 % 30;
+
+                Glyph::Simple {
+                    contours: vec![Contour {
+                        points: vec![
+                            Point::on_curve(0, 0),
+                            Point::on_curve(0, size),
+                            Point::on_curve(size, size),
+                            Point::on_curve(size, 0),
+                        ],
+                    }],
+                    instructions: vec![Instrution::PushBytes(Box::new([5, 2]))],
+                }
+            }))
+            .collect();
+
+        let glyf = Glyf { glyphs };
+        let true_type_limits = TrueTypeLimits::of(&glyf);
+
+        let date = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+
+        Font {
+            sfnt_version: TRUE_TYPE_VERSION,
+            head: Some(Head {
+                created: date,
+                modified: date,
+                revision: Fixed { major: 0, minor: 1 },
+                flags: Flags::default(),
+                min_x: 0,
+                min_y: 0,
+                max_x: 40,
+                max_y: 40,
+                units_per_em: 64,
+                smalest_recocnizeable_size: 6,
+            }),
+            name: Some(Name {
+                names: vec![NameRecord {
+                    name_id: 1,
+                    content: String::from("Sample"),
+                }],
+            }),
+            post: Some(Post::default()),
+            loca: Some(glyf.loca()),
+            glyf: Some(glyf),
+            cmap: Some(CMap::new_with_ranges(vec![CharacterRange {
+                start: 'A',
+                end: char::from_u32('A' as u32 + glyph_count as u32 - 1).unwrap(),
+                start_index: 1,
+            }])),
+            hhea: Some(HHead {
+                ascender: 40,
+                descender: 0,
+                line_gap: 0,
+                advance_width_max: 40,
+                min_left_side_bearing: 0,
+                min_right_side_bearing: 0,
+                x_max_extent: 40,
+                caret_slope_rise: 1,
+                caret_slope_run: 0,
+                caret_offset: 0,
+                metric_data_format: 0,
+                number_of_hmetrics: 1,
+            }),
+            os2: Some(OS2 {
+                avg_glyph_width: 40,
+                weight_class: 400,
+                width_class: 5,
+                subscript: Script {
+                    x_size: 30,
+                    y_size: 30,
+                    x_offset: 0,
+                    y_offset: 10,
+                },
+                superscript: Script {
+                    x_size: 30,
+                    y_size: 30,
+                    x_offset: 0,
+                    y_offset: -10,
+                },
+                strikeout_size: 4,
+                strikeout_position: 20,
+                panose: Panose::default(),
+                typo_ascender: 40,
+                typo_descender: 0,
+                typo_line_gap: 0,
+                win_ascent: 40,
+                win_descent: 0,
+                x_height: 30,
+                cap_height: 40,
+                default_cahr: 0,
+                break_char: ' ' as u16,
+                max_context: 1,
+            }),
+            maxp: Some(MaxP {
+                number_of_glyphs: glyph_count + 1,
+                true_type_limits: Some(true_type_limits),
+            }),
+            hmtx: Some(Hmtx {
+                horizontal_metrics: vec![HorizontalMetric {
+                    advance_width: 40,
+                    left_side_bearing: 0,
+                }],
+                left_side_bearings: vec![0; glyph_count as usize],
+            }),
+            raw_tables: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parallel_layout_produces_the_same_bytes() -> Result<(), LayoutError> {
+        let file = sample_font(500).into_file();
+
+        assert_eq!(build(&file, false)?, build(&file, true)?);
+
+        Ok(())
+    }
+
+    fn build_with(timestamps: Timestamps) -> Result<Vec<u8>, LayoutError> {
+        let mut font = sample_font(10);
+        let head = font.head.as_mut().expect("the sample font has a head");
+        head.created = timestamps.created;
+        head.modified = timestamps.modified;
+
+        build(&font.into_file(), false)
+    }
+
+    #[test]
+    fn fixed_dates_produce_the_same_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+                "name": "Test",
+                "glyphs": [],
+                "created": "2023-01-01T00:00:00Z",
+                "modified": "2024-06-01T12:00:00Z"
+            }"#,
+        )?;
+
+        for (source_date_epoch, manifest, expected) in [
+            (Some("1672531200"), None, TimestampSource::SourceDateEpoch),
+            (
+                Some("1672531200"),
+                Some(&manifest),
+                TimestampSource::SourceDateEpoch,
+            ),
+            (None, Some(&manifest), TimestampSource::Manifest),
+        ] {
+            let (first, source) = Timestamps::resolve_with(source_date_epoch, manifest)?;
+            let (second, _) = Timestamps::resolve_with(source_date_epoch, manifest)?;
+
+            assert_eq!(source, expected);
+            assert_eq!(build_with(first)?, build_with(second)?);
+        }
+
+        let (_, source) = Timestamps::resolve_with(None, None)?;
+        assert_eq!(source, TimestampSource::Clock);
+
+        Ok(())
+    }
+
+    #[test]
+    fn files_can_be_built_on_other_threads() -> Result<(), LayoutError> {
+        let file = sample_font(20).into_file();
+        let expected = build(&file, false)?;
+
+        let built = std::thread::scope(|scope| scope.spawn(|| build(&file, true)).join());
+
+        assert_eq!(built.expect("the thread does not panic")?, expected);
+
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
-use std::{error::Error, io::Write};
+use std::{error::Error, io::Write, path::Path};
 
-use chrono::Utc;
 use font_generator::{
+    manifest::{TimestampSource, Timestamps},
     open_type::{
         tables::*,
         true_type::{Contour, Instrution, Point},
@@ -11,10 +11,240 @@ use font_generator::{
 };
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let doc = File::new_with_tables(vec![
+    let args: Vec<String> = std::env::args().collect();
+    let arg = |index: usize| args.get(index).map(String::as_str);
+
+    match arg(1) {
+        // `inspect <font>` prints the tables of an existing font
+        Some("inspect") => {
+            let input = arg(2).ok_or("inspect requires a font")?;
+
+            open_type::inspect::inspect(&std::fs::read(input)?, &mut std::io::stdout().lock())?;
+
+            return Ok(());
+        }
+        // `--round-trip <input> [output]` reads an existing font and writes it again unchanged
+        Some("--round-trip") => {
+            let input = arg(2).ok_or("--round-trip requires a font")?;
+            let output = arg(3).unwrap_or("./out.otf");
+
+            let written = open_type::round_trip::round_trip(&std::fs::read(input)?)?;
+
+            std::fs::write(output, written)?;
+
+            return Ok(());
+        }
+        // `dump <font> [output]` describes the font as JSON, on stdout without an output
+        Some("dump") => {
+            let input = arg(2).ok_or("dump requires a font")?;
+
+            let text = open_type::dump::dump(&std::fs::read(input)?)?;
+
+            match arg(3) {
+                Some(output) => std::fs::write(output, text)?,
+                None => println!("{}", text),
+            }
+
+            return Ok(());
+        }
+        // `compile <json> [output]` builds a font from a description written by `dump`
+        Some("compile") => {
+            let input = arg(2).ok_or("compile requires a font description")?;
+            let output = arg(3).unwrap_or("./out.otf");
+
+            let written = open_type::dump::compile(&std::fs::read_to_string(input)?)?;
+
+            std::fs::write(output, written)?;
+
+            return Ok(());
+        }
+        // `subset <font> <text> [output]` keeps only the glyphs needed for the characters of the text
+        Some("subset") => {
+            let input = arg(2).ok_or("subset requires a font")?;
+            let text = arg(3).ok_or("subset requires the characters to keep")?;
+            let output = arg(4).unwrap_or("./out.otf");
+
+            let font = open_type::reader::Font::read(&std::fs::read(input)?)?;
+            let subset = open_type::subset::subset(&font, &text.chars().collect())?;
+
+            std::fs::write(output, build(&subset.into_file(), false)?)?;
+
+            return Ok(());
+        }
+        // `cff [--subroutinize] <font> [output]` replaces the TrueType outlines of a font with CFF ones
+        Some("cff") => {
+            let subroutinize = arg(2) == Some("--subroutinize");
+            let first = if subroutinize { 3 } else { 2 };
+
+            let input = arg(first).ok_or("cff requires a font")?;
+            let output = arg(first + 1).unwrap_or("./out.otf");
+
+            let mut font = open_type::reader::Font::read(&std::fs::read(input)?)?;
+
+            let glyf = font
+                .glyf
+                .take()
+                .ok_or("cff requires a font with TrueType outlines")?;
+            let units_per_em = font.head.as_ref().map_or(1000, |head| head.units_per_em);
+            let font_name = font
+                .name
+                .iter()
+                .flat_map(|name| name.names.iter())
+                .find(|record| record.name_id == 6)
+                .map_or("Untitled", |record| record.content.as_str())
+                .to_string();
+            let hmtx = font.hmtx.as_ref().ok_or("cff requires a font with hmtx")?;
+
+            let mut cff = Cff::from_glyf(&font_name, units_per_em, &glyf, hmtx);
+
+            if subroutinize {
+                cff.subroutinize = true;
+                println!("Subroutines saved {} bytes.", cff.charstrings().saved());
+            }
+
+            font.loca = None;
+            if let Some(maxp) = font.maxp.as_mut() {
+                maxp.true_type_limits = None;
+            }
+
+            let mut file = font.into_file();
+            file.add_table(Box::new(cff));
+
+            std::fs::write(output, build(&file, false)?)?;
+
+            return Ok(());
+        }
+        // `woff <font> [output]` wraps a font for the web
+        Some("woff") => {
+            let input = arg(2).ok_or("woff requires a font")?;
+            let output = arg(3).unwrap_or("./out.woff");
+
+            let data = open_type::woff::woff(&std::fs::read(input)?, &Default::default())?;
+
+            std::fs::write(output, data)?;
+
+            return Ok(());
+        }
+        // `woff2 <font> [output]` wraps a font for the web with the stronger compression of WOFF 2.0
+        Some("woff2") => {
+            let input = arg(2).ok_or("woff2 requires a font")?;
+            let output = arg(3).unwrap_or("./out.woff2");
+
+            let data = open_type::woff2::woff2(&std::fs::read(input)?, &Default::default())?;
+
+            std::fs::write(output, data)?;
+
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // `--reproducible [manifest] [output]` refuses to stamp the font with the current time
+    let reproducible = arg(1) == Some("--reproducible");
+    let first = if reproducible { 2 } else { 1 };
+
+    let manifest: Option<Manifest> = match arg(first) {
+        Some(path) => Some(serde_json::from_reader(std::fs::File::open(path)?)?),
+        None => None,
+    };
+
+    let timestamps = if reproducible {
+        Timestamps::reproducible(manifest.as_ref())?
+    } else {
+        let (timestamps, source) = Timestamps::resolve(manifest.as_ref())?;
+
+        if source == TimestampSource::Clock {
+            eprintln!("Warning: neither SOURCE_DATE_EPOCH nor a `created` date is given, the font is stamped with the current time");
+        }
+
+        timestamps
+    };
+
+    let glyf = Glyf {
+        glyphs: vec![
+            Glyph::Empty,
+            Glyph::Simple {
+                contours: vec![Contour {
+                    points: vec![
+                        Point::on_curve(0, 0),
+                        Point::on_curve(0, 40),
+                        Point::on_curve(14, 40),
+                        Point::on_curve(14, 0),
+                    ],
+                }],
+                instructions: vec![Instrution::PushBytes(Box::new([5, 2]))],
+            },
+        ],
+    };
+
+    let true_type_limits = TrueTypeLimits::of(&glyf);
+
+    let cmap = CMap::new_with_ranges(vec![CharacterRange {
+        start: 'o',
+        end: 'o',
+        start_index: 1,
+    }]);
+
+    let glyph_id = |char| {
+        cmap.glyph_id(char)
+            .and_then(|glyph| u16::try_from(glyph).ok())
+    };
+
+    let manifest_dir = arg(first)
+        .and_then(|p| Path::new(p).parent())
+        .unwrap_or(Path::new(""));
+
+    let mut layout_tables: Vec<Box<dyn LayoutableTable>> = Vec::new();
+    let mut gpos = Gpos::default();
+    let mut gdef = Gdef::default();
+
+    if let Some(manifest) = manifest.as_ref() {
+        let kerning = &manifest.kerning;
+        if !kerning.is_empty() {
+            gpos = kerning.to_gpos(glyph_id)?;
+        }
+        if !kerning.is_empty() && kerning.legacy_table {
+            layout_tables.push(Box::new(kerning.to_kern(glyph_id)?));
+        }
+
+        let anchors = manifest.resolve_anchors(manifest_dir, glyph_id)?;
+        anchors::add_mark_features(&mut gpos, &anchors);
+
+        gdef = manifest.glyph_definitions.to_gdef(&cmap, &anchors)?;
+    }
+
+    if let Some(features) = manifest.as_ref().and_then(|m| m.features.as_ref()) {
+        let source = std::fs::read_to_string(manifest_dir.join(features))?;
+        let compiled = fea::compile(&source, &fea::glyph_names(glyf.glyphs.len() as u16, &cmap))?;
+
+        if let Some(compiled) = compiled.gpos {
+            if !gpos.lookups.is_empty() {
+                return Err("the kerning and anchors of the manifest and the GPOS of the feature file can not be combined".into());
+            }
+            gpos = compiled;
+        }
+        if let Some(gsub) = compiled.gsub {
+            layout_tables.push(Box::new(gsub));
+        }
+        if let Some(compiled) = compiled.gdef {
+            if !gdef.is_empty() {
+                return Err("the glyph definitions and anchors of the manifest and the GDEF of the feature file can not be combined".into());
+            }
+            gdef = compiled;
+        }
+    }
+
+    if !gpos.lookups.is_empty() {
+        layout_tables.push(Box::new(gpos));
+    }
+    if !gdef.is_empty() {
+        layout_tables.push(Box::new(gdef));
+    }
+
+    let mut doc = File::new_with_tables(vec![
         Box::new(Head {
-            created: Utc::now(),
-            modified: Utc::now(),
+            created: timestamps.created,
+            modified: timestamps.modified,
             revision: Fixed { major: 0, minor: 1 },
             flags: Flags::default(),
             min_x: 0,
@@ -45,27 +275,9 @@ fn main() -> Result<(), Box<dyn Error>> {
             ],
         }),
         Box::new(Post::default()),
-        Box::new(Glyf {
-            glyphs: vec![Glyph::Simple {
-                contours: vec![Contour {
-                    points: vec![
-                        Point::on_curve(0, 0),
-                        Point::on_curve(0, 40),
-                        Point::on_curve(14, 40),
-                        Point::on_curve(14, 0),
-                    ],
-                }],
-                instructions: vec![Instrution::PushBytes(Box::new([5, 2]))],
-            }],
-        }),
-        Box::new(Loca {
-            offsets: vec![0, 0, 0x25],
-        }),
-        Box::new(CMap::new_with_ranges(vec![CharacterRange {
-            start: 'o',
-            end: 'o',
-            start_index: 1,
-        }])),
+        Box::new(glyf.loca()),
+        Box::new(glyf),
+        Box::new(cmap),
         Box::new(HHead {
             ascender: 1,
             descender: -1,
@@ -112,29 +324,26 @@ fn main() -> Result<(), Box<dyn Error>> {
         }),
         Box::new(MaxP {
             number_of_glyphs: 2,
+            true_type_limits: Some(true_type_limits),
         }),
         Box::new(Hmtx {
             horizontal_metrics: vec![HorizontalMetric {
                 advance_width: 14,
                 left_side_bearing: 0,
             }],
-            left_side_bearings: vec![],
+            left_side_bearings: vec![0],
         }),
     ]);
 
-    let mut file = Layouter::new(4);
-
-    let mut doc = doc.layout(&mut file);
+    for table in layout_tables {
+        doc.add_table(table);
+    }
 
-    let mut result = file.get_result();
+    doc.validate()?;
 
-    while doc.requires_another_pass() {
-        println!("Execute another pass.");
-        doc.pass(result.as_slice())?;
-        result = file.get_result();
-    }
+    let result = build(&doc, false)?;
 
-    let path = std::env::args().nth(2).unwrap_or("./out.otf".to_string());
+    let path = arg(first + 1).unwrap_or("./out.otf");
     let mut file = std::fs::File::create(path)?;
 
     file.write_all(&result)?;
@@ -1,9 +1,36 @@
+use std::{collections::BTreeMap, path::Path};
+
+use chrono::{DateTime, TimeZone, Utc};
 use serde::Deserialize;
+use thiserror::Error;
+
+use crate::open_type::{
+    anchors::{self, svg_anchors, AnchorMap, SvgAnchorError},
+    tables::{Anchor, CMap, CaretValue, ClassPair, Gdef, GlyphClass, GlyphPair, Gpos, Kern},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Manifest {
     pub name: String,
     pub glyphs: Vec<GlyphRange>,
+    /** Creation date written to the `head` table. Without it every build is stamped with the current time. */
+    #[serde(default)]
+    pub created: Option<DateTime<Utc>>,
+    /** Modification date written to the `head` table, defaults to `created`. */
+    #[serde(default)]
+    pub modified: Option<DateTime<Utc>>,
+    /** Kerning written to a `GPOS` table, left out if there is none. */
+    #[serde(default)]
+    pub kerning: Kerning,
+    /** Glyph definitions written to a `GDEF` table, left out if there are none. */
+    #[serde(default)]
+    pub glyph_definitions: GlyphDefinitions,
+    /** Anchors for the `mark` and `mkmk` features by character, see [mark_lookups](crate::open_type::anchors::mark_lookups). */
+    #[serde(default)]
+    pub anchors: BTreeMap<char, AnchorSource>,
+    /** Path of a feature file in the Adobe syntax, relative to the manifest. */
+    #[serde(default)]
+    pub features: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -12,3 +39,478 @@ pub struct GlyphRange {
     pub end: char,
     pub file: String,
 }
+
+/**
+ * Kerning between characters and classes of characters. A side of a pair is either a single character or the name
+ * of a class prefixed by `@`.
+ */
+#[derive(Debug, Default, Deserialize)]
+pub struct Kerning {
+    /** Classes by name, each a string of the characters in the class. */
+    #[serde(default)]
+    pub classes: BTreeMap<String, String>,
+    #[serde(default)]
+    pub pairs: Vec<KerningPair>,
+    /** Also write the kerning to a `kern` table, for applications that do not read `GPOS`. */
+    #[serde(default)]
+    pub legacy_table: bool,
+}
+
+/**
+ * Glyph definitions by character. Each class and set is a string of its characters, explicit glyph classes take
+ * precedence over those the anchors imply, which take precedence over inferred ones.
+ */
+#[derive(Debug, Default, Deserialize)]
+pub struct GlyphDefinitions {
+    /** Classify every character by its Unicode general category, see [GlyphClass::infer]. */
+    #[serde(default)]
+    pub infer_classes: bool,
+    #[serde(default)]
+    pub bases: String,
+    #[serde(default)]
+    pub ligatures: String,
+    #[serde(default)]
+    pub marks: String,
+    #[serde(default)]
+    pub components: String,
+    /** Contour point indices that marks attach to. */
+    #[serde(default)]
+    pub attach_points: BTreeMap<char, Vec<u16>>,
+    /** Caret positions inside ligatures, in font units. */
+    #[serde(default)]
+    pub ligature_carets: BTreeMap<char, Vec<i16>>,
+    /** Mark attachment classes, numbered from 1 in order. */
+    #[serde(default)]
+    pub mark_attach_classes: Vec<String>,
+    /** Mark glyph sets, numbered from 0 in order. */
+    #[serde(default)]
+    pub mark_glyph_sets: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum AnchorSource {
+    /** Anchors by name, each as `[x, y]` in font units. */
+    Points(BTreeMap<String, (i16, i16)>),
+    /** The path of an SVG file relative to the manifest, whose named points are the anchors, see [svg_anchors]. */
+    Svg(String),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KerningPair {
+    pub left: String,
+    pub right: String,
+    /** Added to the advance of the left glyph, in font units. */
+    pub value: i16,
+}
+
+#[derive(Error, Debug)]
+pub enum KerningError {
+    #[error("the kerning class `{0}` is not defined")]
+    UnknownClass(String),
+    #[error("`{0}` is neither a single character nor a class")]
+    InvalidSide(String),
+    #[error("the kerned character {0:?} has no glyph")]
+    MissingGlyph(char),
+}
+
+#[derive(Error, Debug)]
+pub enum GlyphDefinitionError {
+    #[error("the character {0:?} has no glyph")]
+    MissingGlyph(char),
+    #[error("there are more than 255 mark attachment classes")]
+    TooManyMarkAttachClasses,
+}
+
+#[derive(Error, Debug)]
+pub enum AnchorError {
+    #[error("the character {0:?} has anchors but no glyph")]
+    MissingGlyph(char),
+    #[error("could not read the anchors of {char:?} from {path}: {source}")]
+    Io {
+        char: char,
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("the anchors of {char:?}: {source}")]
+    Svg { char: char, source: SvgAnchorError },
+}
+
+#[derive(Error, Debug)]
+pub enum TimestampError {
+    #[error("SOURCE_DATE_EPOCH {0:?} is not a number of seconds since 1970-01-01")]
+    InvalidSourceDateEpoch(String),
+    #[error("a reproducible build needs SOURCE_DATE_EPOCH or a `created` date in the manifest")]
+    MissingSourceDate,
+}
+
+/** Where the dates of [Timestamps] come from. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    SourceDateEpoch,
+    Manifest,
+    /** Neither gave a date, so the build is stamped with the current time and not reproducible. */
+    Clock,
+}
+
+/** The dates stamped into the `head` table. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamps {
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+}
+
+impl Timestamps {
+    /**
+     * Takes the dates from `SOURCE_DATE_EPOCH` (see <https://reproducible-builds.org/specs/source-date-epoch/>) or
+     * else from the manifest. Fails if neither provides one, so the build does not silently depend on the clock.
+     */
+    pub fn reproducible(manifest: Option<&Manifest>) -> Result<Self, TimestampError> {
+        let source_date_epoch = std::env::var("SOURCE_DATE_EPOCH").ok();
+
+        Self::sourced(source_date_epoch.as_deref(), manifest).map(|(timestamps, _)| timestamps)
+    }
+
+    /** Like [Timestamps::reproducible], but falls back to the current time. */
+    pub fn resolve(manifest: Option<&Manifest>) -> Result<(Self, TimestampSource), TimestampError> {
+        let source_date_epoch = std::env::var("SOURCE_DATE_EPOCH").ok();
+
+        Self::resolve_with(source_date_epoch.as_deref(), manifest)
+    }
+
+    /** [Timestamps::resolve] with the value of `SOURCE_DATE_EPOCH` given instead of read from the environment. */
+    pub fn resolve_with(
+        source_date_epoch: Option<&str>,
+        manifest: Option<&Manifest>,
+    ) -> Result<(Self, TimestampSource), TimestampError> {
+        match Self::sourced(source_date_epoch, manifest) {
+            Err(TimestampError::MissingSourceDate) => {
+                let now = Utc::now();
+
+                let timestamps = Self {
+                    created: now,
+                    modified: now,
+                };
+
+                Ok((timestamps, TimestampSource::Clock))
+            }
+            result => result,
+        }
+    }
+
+    fn sourced(
+        source_date_epoch: Option<&str>,
+        manifest: Option<&Manifest>,
+    ) -> Result<(Self, TimestampSource), TimestampError> {
+        if let Some(value) = source_date_epoch {
+            return Ok((
+                Self::from_source_date_epoch(value)?,
+                TimestampSource::SourceDateEpoch,
+            ));
+        }
+
+        manifest
+            .and_then(Self::from_manifest)
+            .map(|timestamps| (timestamps, TimestampSource::Manifest))
+            .ok_or(TimestampError::MissingSourceDate)
+    }
+
+    pub fn from_source_date_epoch(value: &str) -> Result<Self, TimestampError> {
+        let date = value
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+            .ok_or_else(|| TimestampError::InvalidSourceDateEpoch(value.to_string()))?;
+
+        Ok(Self {
+            created: date,
+            modified: date,
+        })
+    }
+
+    fn from_manifest(manifest: &Manifest) -> Option<Self> {
+        let created = manifest.created?;
+
+        Some(Self {
+            created,
+            modified: manifest.modified.unwrap_or(created),
+        })
+    }
+}
+
+impl Kerning {
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /** Builds the `kern` feature of `GPOS`. */
+    pub fn to_gpos(&self, glyph_id: impl Fn(char) -> Option<u16>) -> Result<Gpos, KerningError> {
+        let (pairs, class_pairs) = self.resolve(glyph_id)?;
+
+        Ok(Gpos::kerning(pairs, class_pairs))
+    }
+
+    /** Builds a `kern` table from the same pairs as [Kerning::to_gpos]. */
+    pub fn to_kern(&self, glyph_id: impl Fn(char) -> Option<u16>) -> Result<Kern, KerningError> {
+        let (pairs, class_pairs) = self.resolve(glyph_id)?;
+
+        Ok(Kern::from_pairs(&pairs, &class_pairs))
+    }
+
+    /** Pairs of characters become glyph pairs and all others class pairs. A character kerned against a class is a class of its own. */
+    fn resolve(
+        &self,
+        glyph_id: impl Fn(char) -> Option<u16>,
+    ) -> Result<(Vec<GlyphPair>, Vec<ClassPair>), KerningError> {
+        let glyph = |char: char| glyph_id(char).ok_or(KerningError::MissingGlyph(char));
+
+        let side = |side: &str| -> Result<Vec<u16>, KerningError> {
+            if let Some(name) = side.strip_prefix('@') {
+                let class = self
+                    .classes
+                    .get(name)
+                    .ok_or_else(|| KerningError::UnknownClass(name.to_string()))?;
+
+                return class.chars().map(glyph).collect();
+            }
+
+            let mut chars = side.chars();
+            match (chars.next(), chars.next()) {
+                (Some(char), None) => Ok(vec![glyph(char)?]),
+                _ => Err(KerningError::InvalidSide(side.to_string())),
+            }
+        };
+
+        let mut pairs = Vec::new();
+        let mut class_pairs = Vec::new();
+
+        for pair in self.pairs.iter() {
+            let (left, right) = (side(&pair.left)?, side(&pair.right)?);
+
+            if pair.left.starts_with('@') || pair.right.starts_with('@') {
+                class_pairs.push(ClassPair::kerning(left, right, pair.value));
+            } else {
+                pairs.push(GlyphPair::kerning(left[0], right[0], pair.value));
+            }
+        }
+
+        Ok((pairs, class_pairs))
+    }
+}
+
+impl Manifest {
+    /** The anchors by glyph, SVG files are read relative to the directory of the manifest. */
+    pub fn resolve_anchors(
+        &self,
+        directory: &Path,
+        glyph_id: impl Fn(char) -> Option<u16>,
+    ) -> Result<AnchorMap, AnchorError> {
+        let mut anchors = AnchorMap::new();
+
+        for (char, source) in self.anchors.iter() {
+            let glyph = glyph_id(*char).ok_or(AnchorError::MissingGlyph(*char))?;
+
+            let named = match source {
+                AnchorSource::Points(points) => points
+                    .iter()
+                    .map(|(name, (x, y))| (name.clone(), Anchor { x: *x, y: *y }))
+                    .collect(),
+                AnchorSource::Svg(path) => {
+                    let svg = std::fs::read_to_string(directory.join(path)).map_err(|source| {
+                        AnchorError::Io {
+                            char: *char,
+                            path: path.clone(),
+                            source,
+                        }
+                    })?;
+
+                    svg_anchors(&svg).map_err(|source| AnchorError::Svg {
+                        char: *char,
+                        source,
+                    })?
+                }
+            };
+
+            anchors.entry(glyph).or_default().extend(named);
+        }
+
+        Ok(anchors)
+    }
+}
+
+impl GlyphDefinitions {
+    pub fn is_empty(&self) -> bool {
+        !self.infer_classes
+            && [&self.bases, &self.ligatures, &self.marks, &self.components]
+                .iter()
+                .all(|class| class.is_empty())
+            && self.attach_points.is_empty()
+            && self.ligature_carets.is_empty()
+            && self.mark_attach_classes.is_empty()
+            && self.mark_glyph_sets.is_empty()
+    }
+
+    /** The `GDEF` table, with the glyph classes [anchors::glyph_classes] derives from the anchors. */
+    pub fn to_gdef(&self, cmap: &CMap, anchors: &AnchorMap) -> Result<Gdef, GlyphDefinitionError> {
+        let glyph = |char: char| {
+            cmap.glyph_id(char)
+                .and_then(|glyph| u16::try_from(glyph).ok())
+                .ok_or(GlyphDefinitionError::MissingGlyph(char))
+        };
+        let glyphs = |chars: &str| chars.chars().map(glyph).collect::<Result<Vec<_>, _>>();
+
+        let mut glyph_classes = Vec::new();
+        for (chars, class) in [
+            (&self.bases, GlyphClass::Base),
+            (&self.ligatures, GlyphClass::Ligature),
+            (&self.marks, GlyphClass::Mark),
+            (&self.components, GlyphClass::Component),
+        ] {
+            glyph_classes.extend(glyphs(chars)?.into_iter().map(|glyph| (glyph, class)));
+        }
+
+        glyph_classes.extend(anchors::glyph_classes(anchors));
+
+        if self.infer_classes {
+            glyph_classes.extend(GlyphClass::infer(cmap));
+        }
+
+        if self.mark_attach_classes.len() > 255 {
+            return Err(GlyphDefinitionError::TooManyMarkAttachClasses);
+        }
+
+        let mut mark_attach_classes = Vec::new();
+        for (n, chars) in self.mark_attach_classes.iter().enumerate() {
+            mark_attach_classes.extend(
+                glyphs(chars)?
+                    .into_iter()
+                    .map(|glyph| (glyph, n as u16 + 1)),
+            );
+        }
+
+        Ok(Gdef {
+            glyph_classes,
+            attach_points: self
+                .attach_points
+                .iter()
+                .map(|(char, points)| Ok((glyph(*char)?, points.clone())))
+                .collect::<Result<_, GlyphDefinitionError>>()?,
+            ligature_carets: self
+                .ligature_carets
+                .iter()
+                .map(|(char, carets)| {
+                    let carets = carets.iter().map(|x| CaretValue::Coordinate(*x)).collect();
+                    Ok((glyph(*char)?, carets))
+                })
+                .collect::<Result<_, GlyphDefinitionError>>()?,
+            mark_attach_classes,
+            mark_glyph_sets: self
+                .mark_glyph_sets
+                .iter()
+                .map(|set| glyphs(set))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::open_type::tables::CharacterRange;
+
+    #[test]
+    fn source_date_epoch_is_parsed_as_seconds() {
+        let timestamps = Timestamps::from_source_date_epoch("1672531200").unwrap();
+
+        assert_eq!(
+            timestamps.created,
+            Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(timestamps.created, timestamps.modified);
+
+        assert!(Timestamps::from_source_date_epoch("yesterday").is_err());
+    }
+
+    #[test]
+    fn manifest_dates_are_used() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{ "name": "Test", "glyphs": [], "created": "2023-01-01T00:00:00Z" }"#,
+        )
+        .unwrap();
+
+        let timestamps = Timestamps::from_manifest(&manifest).unwrap();
+
+        assert_eq!(timestamps.created, timestamps.modified);
+        assert_eq!(
+            timestamps.created,
+            Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn kerning_resolves_characters_and_classes() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+                "name": "Test",
+                "glyphs": [],
+                "kerning": {
+                    "classes": { "round": "oc" },
+                    "pairs": [
+                        { "left": "T", "right": "o", "value": -40 },
+                        { "left": "T", "right": "@round", "value": -30 }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let glyph_id = |char: char| "Toc".find(char).map(|index| index as u16 + 1);
+        let gpos = manifest.kerning.to_gpos(glyph_id).unwrap();
+
+        assert_eq!(
+            gpos.lookups[0].positioning,
+            crate::open_type::tables::Positioning::Pair {
+                pairs: vec![GlyphPair::kerning(1, 2, -40)],
+                class_pairs: vec![ClassPair::kerning(vec![1], vec![2, 3], -30)],
+            }
+        );
+
+        let kern = manifest.kerning.to_kern(glyph_id).unwrap();
+        assert_eq!(
+            kern.pairs.iter().map(|p| p.value).collect::<Vec<_>>(),
+            vec![-40, -30]
+        );
+
+        let missing = |_| None;
+        assert!(matches!(
+            manifest.kerning.to_gpos(missing),
+            Err(KerningError::MissingGlyph('T'))
+        ));
+    }
+
+    #[test]
+    fn anchor_classes_override_inferred_ones() {
+        let definitions: GlyphDefinitions =
+            serde_json::from_str(r#"{ "infer_classes": true, "bases": "b" }"#).unwrap();
+        let cmap = CMap::new_with_ranges(vec![CharacterRange {
+            start: 'a',
+            end: 'b',
+            start_index: 1,
+        }]);
+        let mark = BTreeMap::from([(String::from("_top"), Anchor { x: 0, y: 500 })]);
+        let anchors = AnchorMap::from([(1, mark.clone()), (2, mark)]);
+
+        let gdef = definitions.to_gdef(&cmap, &anchors).unwrap();
+        let class = |glyph| {
+            gdef.glyph_classes
+                .iter()
+                .find(|(g, _)| *g == glyph)
+                .map(|(_, class)| *class)
+        };
+
+        // 'a' is a letter, but its anchor makes it a mark; 'b' is a base since the manifest says so
+        assert_eq!(class(1), Some(GlyphClass::Mark));
+        assert_eq!(class(2), Some(GlyphClass::Base));
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use thiserror::Error;
+
+use crate::open_type::tables::{Anchor, GlyphClass, Gpos, MarkAnchor, PositionLookup, Positioning};
+
+/** The named anchors of each glyph, by glyph id. */
+pub type AnchorMap = BTreeMap<u16, BTreeMap<String, Anchor>>;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SvgAnchorError {
+    #[error("the anchor `{anchor}` has no valid `{attribute}`")]
+    InvalidCoordinate {
+        anchor: String,
+        attribute: &'static str,
+    },
+}
+
+fn is_mark(anchors: &BTreeMap<String, Anchor>) -> bool {
+    anchors.keys().any(|name| name.starts_with('_'))
+}
+
+/** The anchor name and component number of a ligature anchor such as `top_2`, numbered from 1. */
+fn component(name: &str) -> Option<(&str, usize)> {
+    let (name, index) = name.rsplit_once('_')?;
+    let index = index.parse().ok().filter(|index| *index >= 1)?;
+
+    (!name.is_empty()).then_some((name, index))
+}
+
+fn lookup(positioning: Positioning) -> PositionLookup {
+    PositionLookup {
+        name: None,
+        flags: 0,
+        mark_filtering_set: None,
+        positioning,
+    }
+}
+
+/**
+ * The lookups of the `mark` and `mkmk` features, by the usual anchor names of font sources: a mark with an anchor
+ * `_top` attaches to the anchor `top` of a base glyph, to `top_1`, `top_2`, ... of the components of a ligature and,
+ * in `mkmk`, to `top` of a preceding mark. Glyphs with an anchor starting with `_` are marks. Every anchor name gets
+ * lookups of its own, so a mark can have several.
+ */
+pub fn mark_lookups(anchors: &AnchorMap) -> (Vec<PositionLookup>, Vec<PositionLookup>) {
+    let names: BTreeSet<&str> = anchors
+        .values()
+        .flat_map(|anchors| anchors.keys())
+        .filter_map(|name| name.strip_prefix('_'))
+        .collect();
+
+    let (mark_glyphs, others): (Vec<_>, Vec<_>) =
+        anchors.iter().partition(|(_, anchors)| is_mark(anchors));
+
+    let mut mark = Vec::new();
+    let mut mkmk = Vec::new();
+
+    for name in names {
+        let mark_name = format!("_{}", name);
+        let marks: Vec<MarkAnchor> = anchors
+            .iter()
+            .filter_map(|(glyph, anchors)| {
+                anchors.get(&mark_name).map(|anchor| MarkAnchor {
+                    glyph: *glyph,
+                    class: 0,
+                    anchor: *anchor,
+                })
+            })
+            .collect();
+
+        let bases: Vec<(u16, Vec<Option<Anchor>>)> = others
+            .iter()
+            .filter_map(|(glyph, anchors)| Some((**glyph, vec![Some(*anchors.get(name)?)])))
+            .collect();
+
+        let ligatures: Vec<(u16, Vec<Vec<Option<Anchor>>>)> = others
+            .iter()
+            .filter_map(|(glyph, anchors)| {
+                let components: Vec<(usize, Anchor)> = anchors
+                    .iter()
+                    .filter_map(|(n, anchor)| match component(n) {
+                        Some((n, index)) if n == name => Some((index, *anchor)),
+                        _ => None,
+                    })
+                    .collect();
+
+                let count = components.iter().map(|(index, _)| *index).max()?;
+                let mut rows = vec![vec![None]; count];
+                for (index, anchor) in components {
+                    rows[index - 1] = vec![Some(anchor)];
+                }
+
+                Some((**glyph, rows))
+            })
+            .collect();
+
+        let base_marks: Vec<(u16, Vec<Option<Anchor>>)> = mark_glyphs
+            .iter()
+            .filter_map(|(glyph, anchors)| Some((**glyph, vec![Some(*anchors.get(name)?)])))
+            .collect();
+
+        if !bases.is_empty() {
+            mark.push(lookup(Positioning::MarkToBase {
+                marks: marks.clone(),
+                bases,
+            }));
+        }
+        if !ligatures.is_empty() {
+            mark.push(lookup(Positioning::MarkToLigature {
+                marks: marks.clone(),
+                ligatures,
+            }));
+        }
+        if !base_marks.is_empty() {
+            mkmk.push(lookup(Positioning::MarkToMark { marks, base_marks }));
+        }
+    }
+
+    (mark, mkmk)
+}
+
+/** Adds the `mark` and `mkmk` features of [mark_lookups], each only if it has lookups. */
+pub fn add_mark_features(gpos: &mut Gpos, anchors: &AnchorMap) {
+    let (mark, mkmk) = mark_lookups(anchors);
+
+    if !mark.is_empty() {
+        gpos.add_feature(*b"mark", mark);
+    }
+    if !mkmk.is_empty() {
+        gpos.add_feature(*b"mkmk", mkmk);
+    }
+}
+
+/** Glyph classes by the same conventions: marks, ligatures with component anchors and bases with other anchors. */
+pub fn glyph_classes(anchors: &AnchorMap) -> Vec<(u16, GlyphClass)> {
+    anchors
+        .iter()
+        .map(|(glyph, anchors)| {
+            let class = if is_mark(anchors) {
+                GlyphClass::Mark
+            } else if anchors.keys().any(|name| component(name).is_some()) {
+                GlyphClass::Ligature
+            } else {
+                GlyphClass::Base
+            };
+
+            (*glyph, class)
+        })
+        .collect()
+}
+
+/** The attributes of a tag, without its element name. */
+fn attributes(tag: &str) -> HashMap<&str, &str> {
+    let mut attributes = HashMap::new();
+    let mut rest = tag.trim_start_matches(|c: char| !c.is_whitespace());
+
+    while let Some((name, value)) = rest.split_once('=') {
+        let value = value.trim_start();
+
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some((value, after)) = value[1..].split_once(quote) else {
+            break;
+        };
+
+        // Attributes without a value end up before the name
+        if let Some(name) = name.split_whitespace().last() {
+            attributes.insert(name, value);
+        }
+        rest = after;
+    }
+
+    attributes
+}
+
+/**
+ * Anchors from the named points of an SVG glyph: elements with an `id` of `anchor-` followed by the anchor name,
+ * placed by `cx` and `cy` like circles or else by `x` and `y`. As in the `SVG ` table the baseline is at y = 0 and y
+ * grows downwards, transforms are not applied.
+ */
+pub fn svg_anchors(source: &str) -> Result<BTreeMap<String, Anchor>, SvgAnchorError> {
+    let mut anchors = BTreeMap::new();
+
+    for tag in source.split('<').skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        if tag.starts_with(['/', '!', '?']) {
+            continue;
+        }
+
+        let attributes = attributes(tag);
+        let Some(name) = attributes
+            .get("id")
+            .and_then(|id| id.strip_prefix("anchor-"))
+        else {
+            continue;
+        };
+
+        let coordinate = |attribute: &'static str, sign: f64| {
+            attributes
+                .get(attribute)
+                .and_then(|value| value.trim().parse::<f64>().ok())
+                .map(|value| (sign * value).round())
+                .filter(|value| (i16::MIN as f64..=i16::MAX as f64).contains(value))
+                .map(|value| value as i16)
+                .ok_or_else(|| SvgAnchorError::InvalidCoordinate {
+                    anchor: name.to_string(),
+                    attribute,
+                })
+        };
+
+        let (x, y) = match attributes.contains_key("cx") {
+            true => ("cx", "cy"),
+            false => ("x", "y"),
+        };
+        let anchor = Anchor {
+            x: coordinate(x, 1.0)?,
+            y: coordinate(y, -1.0)?,
+        };
+
+        anchors.entry(name.to_string()).or_insert(anchor);
+    }
+
+    Ok(anchors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /** Anchors of a glyph by name and position. */
+    type Named<'a> = &'a [(&'a str, i16, i16)];
+
+    fn anchors(entries: &[(u16, Named)]) -> AnchorMap {
+        entries
+            .iter()
+            .map(|(glyph, anchors)| {
+                let anchors = anchors
+                    .iter()
+                    .map(|(name, x, y)| (name.to_string(), Anchor { x: *x, y: *y }))
+                    .collect();
+                (*glyph, anchors)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn anchor_names_decide_the_lookups() {
+        let anchors = anchors(&[
+            (1, &[("top", 250, 500)]),
+            (2, &[("top_1", 200, 500), ("top_2", 600, 520)]),
+            (3, &[("_top", 100, 450), ("top", 100, 700)]),
+        ]);
+
+        let (mark, mkmk) = mark_lookups(&anchors);
+        let top = MarkAnchor {
+            glyph: 3,
+            class: 0,
+            anchor: Anchor { x: 100, y: 450 },
+        };
+
+        assert_eq!(
+            mark.into_iter().map(|l| l.positioning).collect::<Vec<_>>(),
+            vec![
+                Positioning::MarkToBase {
+                    marks: vec![top],
+                    bases: vec![(1, vec![Some(Anchor { x: 250, y: 500 })])],
+                },
+                Positioning::MarkToLigature {
+                    marks: vec![top],
+                    ligatures: vec![(
+                        2,
+                        vec![
+                            vec![Some(Anchor { x: 200, y: 500 })],
+                            vec![Some(Anchor { x: 600, y: 520 })]
+                        ]
+                    )],
+                },
+            ]
+        );
+        assert_eq!(
+            mkmk[0].positioning,
+            Positioning::MarkToMark {
+                marks: vec![top],
+                base_marks: vec![(3, vec![Some(Anchor { x: 100, y: 700 })])],
+            }
+        );
+
+        assert_eq!(
+            glyph_classes(&anchors),
+            vec![
+                (1, GlyphClass::Base),
+                (2, GlyphClass::Ligature),
+                (3, GlyphClass::Mark)
+            ]
+        );
+    }
+
+    #[test]
+    fn svg_points_become_anchors() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <path d="M0 0 L10 -10"/>
+            <!-- anchors -->
+            <circle id="anchor-top" cx="250" cy="-500.4" r="5"/>
+            <use hidden id='anchor-_bottom' x="100" y="20"/>
+        </svg>"#;
+
+        assert_eq!(
+            svg_anchors(svg).unwrap(),
+            BTreeMap::from([
+                (String::from("_bottom"), Anchor { x: 100, y: -20 }),
+                (String::from("top"), Anchor { x: 250, y: 500 }),
+            ])
+        );
+
+        assert_eq!(
+            svg_anchors(r#"<circle id="anchor-top" cx="a" cy="0"/>"#),
+            Err(SvgAnchorError::InvalidCoordinate {
+                anchor: String::from("top"),
+                attribute: "cx"
+            })
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use std::{any::Any, sync::Arc};
+
+use super::{
+    file::{
+        check_fills, directory_issues, into_result, pass_tables, tag_issues, write_directory,
+        LayoutableTable, LayoutedTable, TableDirectoryError,
+    },
+    reader::{Font, COLLECTION_TAG},
+};
+use crate::layout::{
+    FillCheck, LayoutError, Layoutable, Layouted, Layouter, Reservation, Warnings,
+};
+
+/** A table that can be part of several fonts of a [Collection]. */
+pub type SharedTable = Arc<dyn LayoutableTable>;
+
+/**
+ * A TrueType collection. Tables that several fonts share by holding the same [SharedTable] are written once and
+ * referenced from every font's table directory.
+ */
+#[derive(Default)]
+pub struct Collection {
+    fonts: Vec<Vec<SharedTable>>,
+}
+
+impl Collection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_font(&mut self, tables: Vec<SharedTable>) {
+        self.fonts.push(tables);
+    }
+
+    /** Builds a collection from the fonts in which tables that are equal in several fonts are shared. */
+    pub fn from_fonts(fonts: Vec<Font>) -> Self {
+        let mut pool = TablePool::default();
+
+        Self {
+            fonts: fonts
+                .into_iter()
+                .map(|font| pool.share_font(font))
+                .collect(),
+        }
+    }
+
+    /** Checks the table set of every font like [File::validate](super::File::validate). */
+    pub fn validate(&self) -> Result<(), TableDirectoryError> {
+        let issues: Vec<_> = self
+            .fonts
+            .iter()
+            .flat_map(|tables| {
+                let tags: Vec<_> = tables.iter().map(|t| t.tag()).collect();
+                tag_issues(&tags)
+            })
+            .collect();
+
+        into_result(issues)
+    }
+}
+
+impl Layoutable<Box<dyn Layouted>> for Collection {
+    fn layout(&self, layouter: &mut Layouter) -> Box<dyn Layouted> {
+        // Version 1.0 header: tag, version, number of fonts and one offset per font
+        let reservation = layouter.reserve(12 + 4 * self.fonts.len());
+
+        let directories: Vec<_> = self
+            .fonts
+            .iter()
+            .map(|tables| layouter.reserve(12 + 16 * tables.len()))
+            .collect();
+
+        let mut unique: Vec<&SharedTable> = Vec::new();
+        let mut tables = Vec::new();
+
+        let fonts = self
+            .fonts
+            .iter()
+            .map(|font| {
+                font.iter()
+                    .map(
+                        |table| match unique.iter().position(|known| Arc::ptr_eq(known, table)) {
+                            Some(index) => index,
+                            None => {
+                                unique.push(table);
+                                tables.push(table.layout(layouter));
+
+                                tables.len() - 1
+                            }
+                        },
+                    )
+                    .collect()
+            })
+            .collect();
+
+        Box::new(LayoutedCollection {
+            requires_pass: true,
+            fill_check: layouter.fill_check(),
+            warnings: layouter.warnings(),
+            parallel: layouter.parallel(),
+            reservation,
+            directories,
+            tables,
+            fonts,
+        })
+    }
+}
+
+struct LayoutedCollection {
+    requires_pass: bool,
+    fill_check: FillCheck,
+    warnings: Warnings,
+    parallel: bool,
+    reservation: Reservation,
+    directories: Vec<Reservation>,
+    /** Every table once, no matter how many fonts contain it. */
+    tables: Vec<Box<dyn LayoutedTable>>,
+    /** The indices into `tables` of each font's tables. */
+    fonts: Vec<Vec<usize>>,
+}
+
+fn font_tables<'a>(
+    tables: &'a [Box<dyn LayoutedTable>],
+    font: &[usize],
+) -> Vec<&'a dyn LayoutedTable> {
+    font.iter().map(|index| tables[*index].as_ref()).collect()
+}
+
+impl Layouted for LayoutedCollection {
+    fn reservation(&self) -> &Reservation {
+        &self.reservation
+    }
+
+    fn requires_another_pass(&self) -> bool {
+        self.requires_pass || self.tables.iter().any(|t| t.requires_another_pass())
+    }
+
+    fn pass(&mut self, current_file: &[u8]) -> Result<(), LayoutError> {
+        use byteorder::{WriteBytesExt, BE};
+
+        let issues: Vec<_> = self
+            .fonts
+            .iter()
+            .flat_map(|font| directory_issues(&font_tables(&self.tables, font)))
+            .collect();
+        into_result(issues)?;
+
+        pass_tables(&mut self.tables, current_file, self.parallel)?;
+
+        let mut writer = self.reservation.writer();
+
+        writer.write_u32::<BE>(COLLECTION_TAG)?;
+        writer.write_u16::<BE>(1)?; // Major version
+        writer.write_u16::<BE>(0)?; // Minor version
+        writer.write_u32::<BE>(self.fonts.len() as u32)?;
+
+        for directory in self.directories.iter() {
+            writer.write_u32::<BE>(directory.offset() as u32)?;
+        }
+
+        drop(writer);
+
+        for (directory, font) in self.directories.iter_mut().zip(self.fonts.iter()) {
+            write_directory(&mut directory.writer(), &font_tables(&self.tables, font))?;
+        }
+
+        self.requires_pass = false;
+
+        if !self.requires_another_pass() {
+            check_fills(&self.tables, self.fill_check, &self.warnings)?;
+        }
+
+        Ok(())
+    }
+}
+
+/** Hands out one [SharedTable] for all tables that are equal. */
+#[derive(Default)]
+struct TablePool {
+    tables: Vec<(Box<dyn Any>, SharedTable)>,
+}
+
+impl TablePool {
+    fn share<T: LayoutableTable + PartialEq + Clone + 'static>(&mut self, table: T) -> SharedTable {
+        let known = self
+            .tables
+            .iter()
+            .find(|(known, _)| known.downcast_ref::<T>() == Some(&table));
+
+        if let Some((_, shared)) = known {
+            return shared.clone();
+        }
+
+        let shared: SharedTable = Arc::new(table.clone());
+        self.tables.push((Box::new(table), shared.clone()));
+
+        shared
+    }
+
+    /** The tables of the font in the order [Font::into_file] uses. */
+    fn share_font(&mut self, font: Font) -> Vec<SharedTable> {
+        let mut tables = Vec::new();
+
+        fn push<T: LayoutableTable + PartialEq + Clone + 'static>(
+            pool: &mut TablePool,
+            tables: &mut Vec<SharedTable>,
+            table: Option<T>,
+        ) {
+            if let Some(table) = table {
+                tables.push(pool.share(table));
+            }
+        }
+
+        push(self, &mut tables, font.head);
+        push(self, &mut tables, font.hhea);
+        push(self, &mut tables, font.maxp);
+        push(self, &mut tables, font.os2);
+        push(self, &mut tables, font.hmtx);
+        push(self, &mut tables, font.cmap);
+        push(self, &mut tables, font.loca);
+        push(self, &mut tables, font.glyf);
+        push(self, &mut tables, font.name);
+        push(self, &mut tables, font.post);
+
+        for table in font.raw_tables {
+            push(self, &mut tables, Some(table));
+        }
+
+        tables
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        build,
+        open_type::{reader::read_directory, tables::NameRecord},
+        test::sample_font,
+    };
+
+    fn styles() -> Vec<Font> {
+        ["Regular", "Bold"]
+            .into_iter()
+            .map(|style| {
+                let mut font = sample_font(50);
+                font.name.as_mut().unwrap().names.push(NameRecord {
+                    name_id: 2,
+                    content: String::from(style),
+                });
+                font
+            })
+            .collect()
+    }
+
+    #[test]
+    fn equal_tables_are_written_once() {
+        let data = build(&Collection::from_fonts(styles()), false).unwrap();
+
+        let separate: usize = styles()
+            .into_iter()
+            .map(|font| build(&font.into_file(), false).unwrap().len())
+            .sum();
+        assert!(data.len() < separate);
+
+        let fonts = Font::read_collection(&data).unwrap();
+        assert_eq!(fonts, styles());
+
+        // Both directories point at the same glyf table
+        let glyf_offsets: Vec<_> = [12 + 4 * 2, 12 + 4 * 2 + 12 + 16 * 10]
+            .into_iter()
+            .map(|offset| {
+                let (_, records) = read_directory(&data[offset..]).unwrap();
+                records.iter().find(|r| &r.tag == b"glyf").unwrap().offset
+            })
+            .collect();
+        assert_eq!(glyf_offsets[0], glyf_offsets[1]);
+    }
+
+    #[test]
+    fn every_font_is_validated() {
+        let mut incomplete = sample_font(1);
+        incomplete.post = None;
+
+        let collection = Collection::from_fonts(vec![sample_font(1), incomplete]);
+
+        assert!(collection.validate().is_err());
+        assert!(matches!(
+            build(&collection, false),
+            Err(LayoutError::InvalidTableDirectory(_))
+        ));
+    }
+}
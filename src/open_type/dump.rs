@@ -0,0 +1,81 @@
+use thiserror::Error;
+
+use super::reader::{Font, ReadError};
+use crate::{build, LayoutError};
+
+#[derive(Error, Debug)]
+pub enum DumpError {
+    #[error(transparent)]
+    ReadError(#[from] ReadError),
+    #[error(transparent)]
+    LayoutError(#[from] LayoutError),
+    #[error("the text is not a valid font description: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+/**
+ * Describes the font as JSON with one entry per table, named by its tag. Fields carry the names of the table structs.
+ * `loca` is left out when `glyf` is present as it follows from the glyphs.
+ */
+pub fn dump(data: &[u8]) -> Result<String, DumpError> {
+    let mut font = Font::read(data)?;
+
+    if font.glyf.is_some() {
+        font.loca = None;
+    }
+
+    Ok(serde_json::to_string_pretty(&font)?)
+}
+
+/** Builds the binary font from a description written by [dump]. A missing `loca` is computed from `glyf`. */
+pub fn compile(text: &str) -> Result<Vec<u8>, DumpError> {
+    let mut font: Font = serde_json::from_str(text)?;
+
+    if font.loca.is_none() {
+        font.loca = font.glyf.as_ref().map(|glyf| glyf.loca());
+    }
+
+    Ok(build(&font.into_file(), false)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{build, open_type::tables::RawTable, test::sample_font};
+
+    #[test]
+    fn dumped_fonts_compile_to_the_same_bytes() -> Result<(), DumpError> {
+        let mut font = sample_font(4);
+        font.raw_tables.push(RawTable {
+            tag: *b"DSIG",
+            data: vec![0, 0, 0, 1, 0, 0, 0, 0],
+        });
+
+        let data = build(&font.into_file(), false)?;
+
+        assert_eq!(compile(&dump(&data)?)?, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn edited_values_are_compiled() -> Result<(), DumpError> {
+        let data = build(&sample_font(1).into_file(), false)?;
+
+        let text = dump(&data)?.replace("\"weight_class\": 400", "\"weight_class\": 700");
+
+        let font = Font::read(&compile(&text)?)?;
+
+        assert_eq!(font.os2.map(|os2| os2.weight_class), Some(700));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_text_is_rejected() {
+        assert!(matches!(
+            compile("{ \"head\": 5 }"),
+            Err(DumpError::JsonError(_))
+        ));
+    }
+}
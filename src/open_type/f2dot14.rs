@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 use std::{cmp::Ordering, ops::Add};
 
-#[derive(Debug, PartialEq, Eq, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct F2Dot14 {
     int: i8,
     fract: u16,
@@ -10,7 +12,7 @@ const MAX_FRACT: u16 = 0x3fff;
 
 impl F2Dot14 {
     pub fn try_create(int: i8, fract: u16) -> Option<Self> {
-        if int >= -2 && int < 2 && fract <= MAX_FRACT {
+        if (-2..2).contains(&int) && fract <= MAX_FRACT {
             Some(Self { int, fract })
         } else {
             None
@@ -23,23 +25,36 @@ impl F2Dot14 {
             fract: fract.clamp(0, MAX_FRACT),
         }
     }
+
+    /** Interprets the 16 bits as stored in a font file. */
+    pub fn from_bits(bits: i16) -> Self {
+        Self {
+            int: (bits >> 14) as i8,
+            fract: bits as u16 & MAX_FRACT,
+        }
+    }
+
+    /** The 16 bits as stored in a font file. */
+    pub fn to_bits(&self) -> i16 {
+        ((self.int as i16) << 14) | self.fract as i16
+    }
+
+    pub fn to_f32(&self) -> f32 {
+        self.int as f32 + self.fract as f32 / (MAX_FRACT + 1) as f32
+    }
+}
+
+impl Ord for F2Dot14 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.int
+            .cmp(&other.int)
+            .then_with(|| self.fract.cmp(&other.fract))
+    }
 }
 
 impl PartialOrd for F2Dot14 {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.int < other.int {
-            Some(Ordering::Less)
-        } else if self.int > other.int {
-            Some(Ordering::Greater)
-        } else {
-            if self.fract < other.fract {
-                Some(Ordering::Less)
-            } else if self.fract > other.fract {
-                Some(Ordering::Greater)
-            } else {
-                Some(Ordering::Equal)
-            }
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -67,3 +82,23 @@ impl Neg for F2Dot14 {
 
 impl Sub for F2Dot14 {}
 */
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bits_round_trip() {
+        for bits in [0x7fff, 0x7000, 0x0001, 0x0000, -1, -0x4000, -0x8000] {
+            assert_eq!(F2Dot14::from_bits(bits).to_bits(), bits);
+        }
+    }
+
+    #[test]
+    fn bits_are_converted_to_floats() {
+        assert_eq!(F2Dot14::from_bits(0x4000).to_f32(), 1.0);
+        assert_eq!(F2Dot14::from_bits(0x7000).to_f32(), 1.75);
+        assert_eq!(F2Dot14::from_bits(-0x4000).to_f32(), -1.0);
+        assert_eq!(F2Dot14::from_bits(-0x2000).to_f32(), -0.5);
+    }
+}
@@ -0,0 +1,1654 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::open_type::tables::{
+    lookup_flags, CaretValue, ClassPair, ContextRule, Feature, Gdef, GlyphClass, GlyphPair, Gpos,
+    Gsub, LanguageSystem, Ligature, PositionLookup, Positioning, ReverseChainRule, SequenceLookup,
+    Substitution, SubstitutionLookup, ValueRecord, DEFAULT_LANGUAGE, DEFAULT_SCRIPT,
+};
+
+use super::{
+    lexer::{Spanned, Token},
+    CompiledFeatures, FeaError,
+};
+
+type Tag = [u8; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Table {
+    Gsub,
+    Gpos,
+}
+
+enum Rule {
+    Substitution(Substitution),
+    Positioning(Positioning),
+}
+
+impl Rule {
+    fn table(&self) -> Table {
+        match self {
+            Rule::Substitution(_) => Table::Gsub,
+            Rule::Positioning(_) => Table::Gpos,
+        }
+    }
+}
+
+/** A position of a rule: a glyph or a class, and what applies to it in a contextual rule. */
+struct Item {
+    glyphs: Vec<u16>,
+    class: bool,
+    marked: bool,
+    lookups: Vec<String>,
+    value: Option<ValueRecord>,
+}
+
+/** Backtrack, input and lookahead of a contextual rule. */
+type Context = (Vec<Item>, Vec<Item>, Vec<Item>);
+
+/** The lookup flags and the mark filtering set they refer to. */
+#[derive(Debug, Clone, Copy, Default)]
+struct Flags {
+    flags: u16,
+    mark_filtering_set: Option<u16>,
+}
+
+/** A feature or lookup block, which the rules in it are added to. */
+struct Block {
+    /** Set for named lookups, whose rules all go into the same lookup. */
+    name: Option<String>,
+    feature: Option<Tag>,
+    flags: Flags,
+    /** The lookup the next rule is added to if it is of the same kind. */
+    current: Option<(Table, usize)>,
+    /** The language systems new lookups of a feature are registered for. */
+    systems: Vec<(Tag, Tag)>,
+    script: Tag,
+}
+
+pub(super) struct Compiler<'a> {
+    tokens: Vec<Spanned>,
+    position: usize,
+    glyph_ids: HashMap<&'a str, u16>,
+    glyph_count: usize,
+    classes: HashMap<String, Vec<u16>>,
+    language_systems: Vec<(Tag, Tag)>,
+    gsub: Vec<SubstitutionLookup>,
+    gpos: Vec<PositionLookup>,
+    lookup_index: HashMap<String, (Table, usize)>,
+    /** The lookups of each feature, by table and language system. */
+    registrations: BTreeMap<(Table, Tag, Tag, Tag), Vec<u16>>,
+    gdef: Gdef,
+    anonymous_lookups: usize,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(tokens: Vec<Spanned>, glyph_order: &'a [String]) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            glyph_ids: glyph_order
+                .iter()
+                .enumerate()
+                .map(|(glyph_id, name)| (name.as_str(), glyph_id as u16))
+                .collect(),
+            glyph_count: glyph_order.len(),
+            classes: HashMap::new(),
+            language_systems: Vec::new(),
+            gsub: Vec::new(),
+            gpos: Vec::new(),
+            lookup_index: HashMap::new(),
+            registrations: BTreeMap::new(),
+            gdef: Gdef::default(),
+            anonymous_lookups: 0,
+        }
+    }
+
+    pub fn compile(mut self) -> Result<CompiledFeatures, FeaError> {
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Name(keyword) => match keyword.as_str() {
+                    "languagesystem" => self.language_system()?,
+                    "feature" => self.feature()?,
+                    "lookup" => {
+                        self.position += 1;
+                        let name = self.name()?;
+                        self.lookup_block(name, None)?;
+                    }
+                    "table" => self.table()?,
+                    _ => return Err(self.unsupported(format!("`{}`", keyword))),
+                },
+                Token::Class(_) => self.class_definition()?,
+                Token::Symbol(';') => self.position += 1,
+                _ => return Err(self.syntax("expected a statement")),
+            }
+        }
+
+        Ok(self.finish())
+    }
+
+    fn finish(self) -> CompiledFeatures {
+        let (features, language_systems) = self.features(Table::Gsub);
+        let gsub = Gsub {
+            language_systems,
+            features,
+            lookups: self.gsub.clone(),
+        };
+
+        let (features, language_systems) = self.features(Table::Gpos);
+        let gpos = Gpos {
+            language_systems,
+            features,
+            lookups: self.gpos.clone(),
+        };
+
+        CompiledFeatures {
+            gsub: (!gsub.lookups.is_empty()).then_some(gsub),
+            gpos: (!gpos.lookups.is_empty()).then_some(gpos),
+            gdef: (!self.gdef.is_empty()).then_some(self.gdef),
+        }
+    }
+
+    /** Features with the same tag and lookups are shared by language systems. */
+    fn features(&self, table: Table) -> (Vec<Feature>, Vec<LanguageSystem>) {
+        let mut features: Vec<Feature> = Vec::new();
+        let mut systems: BTreeMap<(Tag, Tag), Vec<u16>> = BTreeMap::new();
+
+        for ((_, script, language, tag), lookups) in
+            self.registrations.iter().filter(|((t, ..), _)| *t == table)
+        {
+            let mut lookups = lookups.clone();
+            lookups.sort_unstable();
+            lookups.dedup();
+
+            let index = match features
+                .iter()
+                .position(|f| f.tag == *tag && f.lookups == lookups)
+            {
+                Some(index) => index,
+                None => {
+                    features.push(Feature { tag: *tag, lookups });
+                    features.len() - 1
+                }
+            };
+
+            systems
+                .entry((*script, *language))
+                .or_default()
+                .push(index as u16);
+        }
+
+        let systems = systems
+            .into_iter()
+            .map(|((script, language), features)| LanguageSystem {
+                script,
+                language,
+                features,
+            })
+            .collect();
+
+        (features, systems)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|t| &t.token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(1, |t| t.line)
+    }
+
+    fn next(&mut self) -> Result<Token, FeaError> {
+        let token = self.peek().cloned().ok_or(FeaError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn syntax(&self, message: impl Into<String>) -> FeaError {
+        FeaError::Syntax {
+            line: self.line(),
+            message: message.into(),
+        }
+    }
+
+    fn unsupported(&self, what: impl Into<String>) -> FeaError {
+        FeaError::Unsupported {
+            line: self.line(),
+            what: what.into(),
+        }
+    }
+
+    fn is_keyword(&self, keywords: &[&str]) -> bool {
+        matches!(self.peek(), Some(Token::Name(name)) if keywords.contains(&name.as_str()))
+    }
+
+    /** Consumes one of the keywords if it comes next. */
+    fn keyword(&mut self, keywords: &[&str]) -> bool {
+        let found = self.is_keyword(keywords);
+        self.position += usize::from(found);
+        found
+    }
+
+    /** Consumes the symbol if it comes next. */
+    fn symbol(&mut self, symbol: char) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        self.position += usize::from(found);
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), FeaError> {
+        match self.symbol(symbol) {
+            true => Ok(()),
+            false if self.peek().is_none() => Err(FeaError::UnexpectedEnd),
+            false => Err(self.syntax(format!("expected `{}`", symbol))),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, FeaError> {
+        match self.next()? {
+            Token::Name(name) | Token::Escaped(name) => Ok(name),
+            _ => {
+                self.position -= 1;
+                Err(self.syntax("expected a name"))
+            }
+        }
+    }
+
+    fn tag(&mut self) -> Result<Tag, FeaError> {
+        let name = self.name()?;
+
+        match name.len() {
+            1..=4 => {
+                let mut tag = *b"    ";
+                tag[..name.len()].copy_from_slice(name.as_bytes());
+                Ok(tag)
+            }
+            _ => Err(self.syntax(format!("`{}` is not a tag", name))),
+        }
+    }
+
+    fn number(&mut self) -> Result<i32, FeaError> {
+        match self.next()? {
+            Token::Number(number) => Ok(number),
+            _ => {
+                self.position -= 1;
+                Err(self.syntax("expected a number"))
+            }
+        }
+    }
+
+    fn i16(&mut self) -> Result<i16, FeaError> {
+        let number = self.number()?;
+        i16::try_from(number).map_err(|_| self.syntax(format!("{} is out of range", number)))
+    }
+
+    /** Expects the label that closes a block, followed by `;`. */
+    fn block_end(&mut self, label: &str) -> Result<(), FeaError> {
+        if self.name()? != label {
+            self.position -= 1;
+            return Err(self.syntax(format!("expected `{}` to close the block", label)));
+        }
+
+        self.expect_symbol(';')
+    }
+
+    fn language_system(&mut self) -> Result<(), FeaError> {
+        self.position += 1;
+
+        let system = (self.tag()?, self.tag()?);
+        if !self.language_systems.contains(&system) {
+            self.language_systems.push(system);
+        }
+
+        self.expect_symbol(';')
+    }
+
+    fn all_systems(&self) -> Vec<(Tag, Tag)> {
+        match self.language_systems.is_empty() {
+            true => vec![(DEFAULT_SCRIPT, DEFAULT_LANGUAGE)],
+            false => self.language_systems.clone(),
+        }
+    }
+
+    fn class_definition(&mut self) -> Result<(), FeaError> {
+        let Token::Class(name) = self.next()? else {
+            unreachable!("called on a class token")
+        };
+
+        self.expect_symbol('=')?;
+        let (glyphs, _) = self
+            .glyph_set()?
+            .ok_or_else(|| self.syntax("expected glyphs"))?;
+        self.expect_symbol(';')?;
+
+        self.classes.insert(name, glyphs);
+
+        Ok(())
+    }
+
+    fn feature(&mut self) -> Result<(), FeaError> {
+        self.position += 1;
+
+        let label = self.name()?;
+        self.position -= 1;
+        let tag = self.tag()?;
+
+        self.keyword(&["useExtension"]);
+        self.expect_symbol('{')?;
+
+        let mut block = Block {
+            name: None,
+            feature: Some(tag),
+            flags: Flags::default(),
+            current: None,
+            systems: self.all_systems(),
+            script: DEFAULT_SCRIPT,
+        };
+
+        while !self.symbol('}') {
+            self.statement(&mut block)?;
+        }
+
+        self.block_end(&label)
+    }
+
+    fn lookup_block(&mut self, name: String, parent: Option<&mut Block>) -> Result<(), FeaError> {
+        if self.lookup_index.contains_key(&name) {
+            return Err(self.syntax(format!("the lookup `{}` is defined twice", name)));
+        }
+
+        self.keyword(&["useExtension"]);
+        self.expect_symbol('{')?;
+
+        let mut block = Block {
+            name: Some(name.clone()),
+            feature: parent.as_ref().and_then(|p| p.feature),
+            flags: parent.as_ref().map_or(Flags::default(), |p| p.flags),
+            current: None,
+            systems: parent.as_ref().map_or(Vec::new(), |p| p.systems.clone()),
+            script: parent.as_ref().map_or(DEFAULT_SCRIPT, |p| p.script),
+        };
+
+        while !self.symbol('}') {
+            self.statement(&mut block)?;
+        }
+
+        if let Some(parent) = parent {
+            parent.current = None;
+        }
+
+        self.block_end(&name)
+    }
+
+    fn statement(&mut self, block: &mut Block) -> Result<(), FeaError> {
+        let keyword = match self.peek() {
+            Some(Token::Class(_)) => return self.class_definition(),
+            Some(Token::Symbol(';')) => {
+                self.position += 1;
+                return Ok(());
+            }
+            Some(Token::Name(keyword)) => keyword.clone(),
+            Some(_) => return Err(self.syntax("expected a statement")),
+            None => return Err(FeaError::UnexpectedEnd),
+        };
+
+        let in_feature = block.name.is_none();
+        self.position += 1;
+
+        match keyword.as_str() {
+            "script" if in_feature => {
+                block.script = self.tag()?;
+                block.systems = vec![(block.script, DEFAULT_LANGUAGE)];
+                block.current = None;
+                self.expect_symbol(';')
+            }
+            "language" if in_feature => self.language(block),
+            "lookup" if in_feature => {
+                let line = self.line();
+                let name = self.name()?;
+
+                if !self.symbol(';') {
+                    return self.lookup_block(name, Some(block));
+                }
+
+                let (table, index) = *self
+                    .lookup_index
+                    .get(&name)
+                    .ok_or(FeaError::UnknownLookup { line, name })?;
+                self.register(block, table, index);
+                block.current = None;
+
+                Ok(())
+            }
+            "lookupflag" => {
+                if block.name.is_some() && block.current.is_some() {
+                    return Err(self.syntax("the lookup flags have to precede the rules"));
+                }
+
+                block.flags = self.lookup_flags()?;
+                block.current = None;
+                Ok(())
+            }
+            "sub" | "substitute" => self.substitution(block, false),
+            "rsub" | "reversesub" => self.substitution(block, true),
+            "pos" | "position" => self.positioning(block, false),
+            "enum" | "enumerate" => {
+                if !self.keyword(&["pos", "position"]) {
+                    return Err(self.syntax("expected `pos` after `enum`"));
+                }
+                self.positioning(block, true)
+            }
+            "ignore" => self.ignore(block),
+            // Subtables are split where needed anyway
+            "subtable" => self.expect_symbol(';'),
+            _ => {
+                self.position -= 1;
+                Err(self.unsupported(format!("`{}` here", keyword)))
+            }
+        }
+    }
+
+    /** A language of the current script, which gets the lookups of the default language unless excluded. */
+    fn language(&mut self, block: &mut Block) -> Result<(), FeaError> {
+        let language = self.tag()?;
+        let include_default = !self.keyword(&["exclude_dflt", "excludeDFLT"]);
+        self.keyword(&["include_dflt", "includeDFLT"]);
+
+        if self.is_keyword(&["required"]) {
+            return Err(self.unsupported("a required feature"));
+        }
+        self.expect_symbol(';')?;
+
+        let system = (block.script, language);
+
+        if let (Some(tag), true) = (
+            block.feature,
+            include_default && language != DEFAULT_LANGUAGE,
+        ) {
+            for table in [Table::Gsub, Table::Gpos] {
+                let default = (table, block.script, DEFAULT_LANGUAGE, tag);
+
+                if let Some(lookups) = self.registrations.get(&default).cloned() {
+                    self.registrations
+                        .entry((table, system.0, system.1, tag))
+                        .or_default()
+                        .extend(lookups);
+                }
+            }
+        }
+
+        block.systems = vec![system];
+        block.current = None;
+
+        Ok(())
+    }
+
+    fn lookup_flags(&mut self) -> Result<Flags, FeaError> {
+        if let Some(Token::Number(_)) = self.peek() {
+            let number = self.number()?;
+            self.expect_symbol(';')?;
+
+            return u16::try_from(number)
+                .ok()
+                .filter(|flags| flags & lookup_flags::USE_MARK_FILTERING_SET == 0)
+                .map(|flags| Flags {
+                    flags,
+                    mark_filtering_set: None,
+                })
+                .ok_or_else(|| self.unsupported(format!("lookup flags {}", number)));
+        }
+
+        let mut flags = Flags::default();
+
+        while !self.symbol(';') {
+            flags.flags |= match self.name()?.as_str() {
+                "RightToLeft" => lookup_flags::RIGHT_TO_LEFT,
+                "IgnoreBaseGlyphs" => lookup_flags::IGNORE_BASE_GLYPHS,
+                "IgnoreLigatures" => lookup_flags::IGNORE_LIGATURES,
+                "IgnoreMarks" => lookup_flags::IGNORE_MARKS,
+                "MarkAttachmentType" => self.mark_attachment_class()? << 8,
+                "UseMarkFilteringSet" => {
+                    flags.mark_filtering_set = Some(self.mark_filtering_set()?);
+                    lookup_flags::USE_MARK_FILTERING_SET
+                }
+                flag => {
+                    self.position -= 1;
+                    return Err(self.syntax(format!("`{}` is not a lookup flag", flag)));
+                }
+            };
+        }
+
+        Ok(flags)
+    }
+
+    fn mark_set(&mut self) -> Result<Vec<u16>, FeaError> {
+        let (mut glyphs, _) = self
+            .glyph_set()?
+            .ok_or_else(|| self.syntax("expected a class of marks"))?;
+
+        glyphs.sort_unstable();
+        glyphs.dedup();
+
+        Ok(glyphs)
+    }
+
+    /** The `GDEF` mark attachment class of the marks, which gets the next number if they have none yet. */
+    fn mark_attachment_class(&mut self) -> Result<u16, FeaError> {
+        let glyphs = self.mark_set()?;
+        let classes = &self.gdef.mark_attach_classes;
+
+        let existing: Vec<u16> = classes
+            .iter()
+            .filter(|(glyph, _)| glyphs.contains(glyph))
+            .map(|(_, class)| *class)
+            .collect();
+
+        if existing.is_empty() {
+            let class = classes.iter().map(|(_, class)| *class).max().unwrap_or(0) + 1;
+            if class > 255 {
+                return Err(self.unsupported("more than 255 mark attachment classes"));
+            }
+
+            self.gdef
+                .mark_attach_classes
+                .extend(glyphs.into_iter().map(|glyph| (glyph, class)));
+
+            return Ok(class);
+        }
+
+        // The same marks as an earlier class, which then has no other glyphs
+        let class = existing[0];
+        let size = classes.iter().filter(|(_, c)| *c == class).count();
+
+        match existing.len() == glyphs.len()
+            && existing.iter().all(|c| *c == class)
+            && size == glyphs.len()
+        {
+            true => Ok(class),
+            false => Err(self.syntax("mark attachment classes can not share glyphs")),
+        }
+    }
+
+    /** The index of the `GDEF` mark glyph set, which is added if it is new. */
+    fn mark_filtering_set(&mut self) -> Result<u16, FeaError> {
+        let glyphs = self.mark_set()?;
+        let sets = &mut self.gdef.mark_glyph_sets;
+
+        let index = match sets.iter().position(|set| *set == glyphs) {
+            Some(index) => index,
+            None => {
+                sets.push(glyphs);
+                sets.len() - 1
+            }
+        };
+
+        Ok(index as u16)
+    }
+
+    fn register(&mut self, block: &Block, table: Table, lookup: usize) {
+        let Some(tag) = block.feature else {
+            return;
+        };
+
+        for (script, language) in block.systems.iter() {
+            self.registrations
+                .entry((table, *script, *language, tag))
+                .or_default()
+                .push(lookup as u16);
+        }
+    }
+
+    fn push_lookup(&mut self, name: Option<String>, flags: Flags, rule: Rule) -> (Table, usize) {
+        let table = rule.table();
+
+        let index = match rule {
+            Rule::Substitution(substitution) => {
+                self.gsub.push(SubstitutionLookup {
+                    name: name.clone(),
+                    flags: flags.flags,
+                    mark_filtering_set: flags.mark_filtering_set,
+                    substitution,
+                });
+                self.gsub.len() - 1
+            }
+            Rule::Positioning(positioning) => {
+                self.gpos.push(PositionLookup {
+                    name: name.clone(),
+                    flags: flags.flags,
+                    mark_filtering_set: flags.mark_filtering_set,
+                    positioning,
+                });
+                self.gpos.len() - 1
+            }
+        };
+
+        if let Some(name) = name {
+            self.lookup_index.insert(name, (table, index));
+        }
+
+        (table, index)
+    }
+
+    /** A lookup that is only applied by a contextual rule, named so the rule can refer to it. */
+    fn anonymous_lookup(&mut self, flags: Flags, rule: Rule) -> String {
+        self.anonymous_lookups += 1;
+
+        // Names in feature files have no spaces, so this one can not collide
+        let name = format!("anonymous {}", self.anonymous_lookups);
+        self.push_lookup(Some(name.clone()), flags, rule);
+
+        name
+    }
+
+    /** Adds the rule to the current lookup of the block, or starts a new lookup if it is of another kind. */
+    fn add_rule(&mut self, block: &mut Block, rule: Rule) -> Result<(), FeaError> {
+        let rule = match block.current {
+            Some((table, index)) if table == rule.table() => match self.merge(index, rule) {
+                Ok(()) => return Ok(()),
+                Err(rule) => rule,
+            },
+            _ => rule,
+        };
+
+        if block.current.is_some() && block.name.is_some() {
+            return Err(self.syntax("the rules of a lookup have to be of the same kind"));
+        }
+
+        let (table, index) = self.push_lookup(block.name.clone(), block.flags, rule);
+        block.current = Some((table, index));
+        self.register(block, table, index);
+
+        Ok(())
+    }
+
+    /** Adds the rule to the lookup if it is of the same kind, otherwise gives it back. */
+    fn merge(&mut self, index: usize, rule: Rule) -> Result<(), Rule> {
+        use Positioning as P;
+        use Substitution as S;
+
+        match rule {
+            Rule::Substitution(rule) => match (&mut self.gsub[index].substitution, rule) {
+                (
+                    S::Single { substitutions },
+                    S::Single {
+                        substitutions: more,
+                    },
+                ) => substitutions.extend(more),
+                (
+                    S::Multiple { substitutions },
+                    S::Multiple {
+                        substitutions: more,
+                    },
+                ) => substitutions.extend(more),
+                (S::Alternate { alternates }, S::Alternate { alternates: more }) => {
+                    alternates.extend(more)
+                }
+                (S::Ligature { ligatures }, S::Ligature { ligatures: more }) => {
+                    ligatures.extend(more)
+                }
+                (S::Context { rules }, S::Context { rules: more }) => rules.extend(more),
+                (S::ReverseChainSingle { rules }, S::ReverseChainSingle { rules: more }) => {
+                    rules.extend(more)
+                }
+                (_, rule) => return Err(Rule::Substitution(rule)),
+            },
+            Rule::Positioning(rule) => match (&mut self.gpos[index].positioning, rule) {
+                (P::Single { adjustments }, P::Single { adjustments: more }) => {
+                    adjustments.extend(more)
+                }
+                (
+                    P::Pair { pairs, class_pairs },
+                    P::Pair {
+                        pairs: more_pairs,
+                        class_pairs: more_class_pairs,
+                    },
+                ) => {
+                    pairs.extend(more_pairs);
+                    class_pairs.extend(more_class_pairs);
+                }
+                (P::Context { rules }, P::Context { rules: more }) => rules.extend(more),
+                (_, rule) => return Err(Rule::Positioning(rule)),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn glyph(&self, token: &Token, line: usize) -> Result<u16, FeaError> {
+        match token {
+            Token::Name(name) | Token::Escaped(name) => self
+                .glyph_ids
+                .get(name.as_str())
+                .copied()
+                .ok_or_else(|| FeaError::UnknownGlyph {
+                    line,
+                    name: name.clone(),
+                }),
+            Token::Cid(glyph_id) if (*glyph_id as usize) < self.glyph_count => Ok(*glyph_id),
+            Token::Cid(glyph_id) => Err(FeaError::UnknownGlyph {
+                line,
+                name: format!("\\{}", glyph_id),
+            }),
+            _ => Err(self.syntax("expected a glyph")),
+        }
+    }
+
+    fn class(&self, name: &str, line: usize) -> Result<Vec<u16>, FeaError> {
+        self.classes
+            .get(name)
+            .cloned()
+            .ok_or_else(|| FeaError::UnknownClass {
+                line,
+                name: name.to_string(),
+            })
+    }
+
+    /**
+     * A glyph, a named class or a class in brackets, and whether it is a class. `None` if the rule continues with
+     * something else.
+     */
+    fn glyph_set(&mut self) -> Result<Option<(Vec<u16>, bool)>, FeaError> {
+        let line = self.line();
+
+        let set = match self.peek().cloned() {
+            Some(Token::Name(name)) if ["by", "from", "lookup"].contains(&name.as_str()) => {
+                return Ok(None)
+            }
+            Some(token @ (Token::Name(_) | Token::Escaped(_) | Token::Cid(_))) => {
+                self.position += 1;
+                (vec![self.glyph(&token, line)?], false)
+            }
+            Some(Token::Class(name)) => {
+                self.position += 1;
+                (self.class(&name, line)?, true)
+            }
+            Some(Token::Symbol('[')) => {
+                self.position += 1;
+                (self.class_literal()?, true)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(set))
+    }
+
+    fn class_literal(&mut self) -> Result<Vec<u16>, FeaError> {
+        let mut glyphs = Vec::new();
+
+        while !self.symbol(']') {
+            let line = self.line();
+
+            match self.next()? {
+                Token::Class(name) => glyphs.extend(self.class(&name, line)?),
+                token @ (Token::Name(_) | Token::Escaped(_) | Token::Cid(_)) => {
+                    if self.symbol('-') {
+                        let end = self.next()?;
+                        glyphs.extend(self.range(&token, &end, line)?);
+                    } else {
+                        glyphs.push(self.glyph(&token, line)?);
+                    }
+                }
+                _ => {
+                    self.position -= 1;
+                    return Err(self.syntax("expected a glyph or `]`"));
+                }
+            }
+        }
+
+        Ok(glyphs)
+    }
+
+    /**
+     * The glyphs of a range. Glyph names have to differ in a single letter, as in `a.sc-z.sc`, or in a number of the
+     * same number of digits, as in `uni0030-uni0039`.
+     */
+    fn range(&self, start: &Token, end: &Token, line: usize) -> Result<Vec<u16>, FeaError> {
+        let invalid = || FeaError::Syntax {
+            line,
+            message: String::from("the range does not name a sequence of glyphs"),
+        };
+
+        let (first, last) = match (start, end) {
+            (Token::Cid(first), Token::Cid(last)) if first <= last => {
+                return (*first..=*last)
+                    .map(|glyph_id| self.glyph(&Token::Cid(glyph_id), line))
+                    .collect();
+            }
+            (
+                Token::Name(first) | Token::Escaped(first),
+                Token::Name(last) | Token::Escaped(last),
+            ) => (first, last),
+            _ => return Err(invalid()),
+        };
+
+        // Names are ASCII, so byte and character positions agree
+        let prefix = first
+            .bytes()
+            .zip(last.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = first[prefix..]
+            .bytes()
+            .rev()
+            .zip(last[prefix..].bytes().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let (from, to) = (
+            &first[prefix..first.len() - suffix],
+            &last[prefix..last.len() - suffix],
+        );
+        let name = |middle: &str| {
+            format!(
+                "{}{}{}",
+                &first[..prefix],
+                middle,
+                &first[first.len() - suffix..]
+            )
+        };
+
+        let names: Vec<String> = match (from.as_bytes(), to.as_bytes()) {
+            ([a], [b])
+                if a.is_ascii_alphabetic()
+                    && a.is_ascii_lowercase() == b.is_ascii_lowercase()
+                    && a <= b =>
+            {
+                (*a..=*b).map(|c| name(&(c as char).to_string())).collect()
+            }
+            (a, b)
+                if a.len() == b.len()
+                    && !a.is_empty()
+                    && a.iter().chain(b.iter()).all(u8::is_ascii_digit) =>
+            {
+                let (Ok(a), Ok(b)) = (from.parse::<u64>(), to.parse::<u64>()) else {
+                    return Err(invalid());
+                };
+                if a > b {
+                    return Err(invalid());
+                }
+                if b - a >= self.glyph_count as u64 {
+                    return Err(FeaError::Syntax {
+                        line,
+                        message: String::from("the range is longer than the font has glyphs"),
+                    });
+                }
+
+                (a..=b)
+                    .map(|n| name(&format!("{:0width$}", n, width = from.len())))
+                    .collect()
+            }
+            _ => return Err(invalid()),
+        };
+
+        names
+            .into_iter()
+            .map(|name| self.glyph(&Token::Name(name), line))
+            .collect()
+    }
+
+    /** A value record as a single advance, `<NULL>` or four numbers in angle brackets, if one comes next. */
+    fn value_record(&mut self) -> Result<Option<ValueRecord>, FeaError> {
+        if let Some(Token::Number(_)) = self.peek() {
+            return Ok(Some(ValueRecord::kerning(self.i16()?)));
+        }
+
+        if !self.symbol('<') {
+            return Ok(None);
+        }
+
+        let value = if self.keyword(&["NULL"]) {
+            ValueRecord::default()
+        } else {
+            let x_placement = self.i16()?;
+
+            if self.is_symbol('>') {
+                ValueRecord::kerning(x_placement)
+            } else {
+                ValueRecord {
+                    x_placement,
+                    y_placement: self.i16()?,
+                    x_advance: self.i16()?,
+                    y_advance: self.i16()?,
+                }
+            }
+        };
+
+        if !self.symbol('>') {
+            return Err(match self.peek() {
+                Some(Token::Number(_)) => self.unsupported("device tables"),
+                _ => self.syntax("expected `>`"),
+            });
+        }
+
+        Ok(Some(value))
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    /** The positions of a rule up to `by`, `from` or its end. */
+    fn pattern(&mut self, table: Table) -> Result<Vec<Item>, FeaError> {
+        let mut items = Vec::new();
+
+        while let Some((glyphs, class)) = self.glyph_set()? {
+            let mut item = Item {
+                glyphs,
+                class,
+                marked: self.symbol('\''),
+                lookups: Vec::new(),
+                value: None,
+            };
+
+            while self.keyword(&["lookup"]) {
+                item.lookups.push(self.name()?);
+            }
+
+            if table == Table::Gpos {
+                item.value = self.value_record()?;
+            }
+
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+
+    /** Splits a contextual pattern into backtrack, the marked input and lookahead. */
+    fn context(&self, mut items: Vec<Item>) -> Result<Context, FeaError> {
+        let first = items.iter().position(|item| item.marked);
+        let last = items.iter().rposition(|item| item.marked);
+
+        let (Some(first), Some(last)) = (first, last) else {
+            return Err(self.syntax("a contextual rule needs marked glyphs"));
+        };
+
+        if items[first..=last].iter().any(|item| !item.marked) {
+            return Err(self.syntax("the marked glyphs have to follow each other"));
+        }
+
+        if items.iter().enumerate().any(|(n, item)| {
+            !(first..=last).contains(&n) && (item.value.is_some() || !item.lookups.is_empty())
+        }) {
+            return Err(self.syntax("only marked glyphs can have lookups or values"));
+        }
+
+        let lookahead = items.split_off(last + 1);
+        let input = items.split_off(first);
+
+        Ok((items, input, lookahead))
+    }
+
+    /** The lookups a contextual rule applies, checked to be lookups of the table. */
+    fn sequence_lookups(
+        &self,
+        input: &[Item],
+        table: Table,
+        line: usize,
+    ) -> Result<Vec<SequenceLookup>, FeaError> {
+        let mut lookups = Vec::new();
+
+        for (index, item) in input.iter().enumerate() {
+            for name in item.lookups.iter() {
+                match self.lookup_index.get(name) {
+                    Some((t, _)) if *t == table => {}
+                    Some(_) => {
+                        return Err(FeaError::Syntax {
+                            line,
+                            message: format!("the lookup `{}` belongs to the other table", name),
+                        })
+                    }
+                    None => {
+                        return Err(FeaError::UnknownLookup {
+                            line,
+                            name: name.clone(),
+                        })
+                    }
+                }
+
+                lookups.push(SequenceLookup {
+                    index: index as u16,
+                    lookup: name.clone(),
+                });
+            }
+        }
+
+        Ok(lookups)
+    }
+
+    fn sets(items: &[Item]) -> Vec<Vec<u16>> {
+        items.iter().map(|item| item.glyphs.clone()).collect()
+    }
+
+    /** `ignore sub` and `ignore pos`: contexts in which the following rules of the lookup do not apply. */
+    fn ignore(&mut self, block: &mut Block) -> Result<(), FeaError> {
+        let table = match self.name()?.as_str() {
+            "sub" | "substitute" => Table::Gsub,
+            "pos" | "position" => Table::Gpos,
+            _ => {
+                self.position -= 1;
+                return Err(self.syntax("expected `sub` or `pos` after `ignore`"));
+            }
+        };
+
+        let mut rules = Vec::new();
+
+        loop {
+            let pattern = self.pattern(table)?;
+            let (backtrack, input, lookahead) = self.context(pattern)?;
+
+            rules.push(ContextRule {
+                backtrack: Self::sets(&backtrack),
+                input: Self::sets(&input),
+                lookahead: Self::sets(&lookahead),
+                lookups: Vec::new(),
+            });
+
+            if !self.symbol(',') {
+                break;
+            }
+        }
+
+        self.expect_symbol(';')?;
+
+        let rule = match table {
+            Table::Gsub => Rule::Substitution(Substitution::Context { rules }),
+            Table::Gpos => Rule::Positioning(Positioning::Context { rules }),
+        };
+
+        self.add_rule(block, rule)
+    }
+
+    fn substitution(&mut self, block: &mut Block, reverse: bool) -> Result<(), FeaError> {
+        let line = self.line();
+        let target = self.pattern(Table::Gsub)?;
+        let marked = target.iter().any(|item| item.marked);
+
+        if target.is_empty() {
+            return Err(self.syntax("expected glyphs to substitute"));
+        }
+
+        let rule = if reverse {
+            let (backtrack, input, lookahead) = match marked {
+                true => self.context(target)?,
+                false => (Vec::new(), target, Vec::new()),
+            };
+
+            if !self.keyword(&["by"]) {
+                return Err(self.syntax("expected `by`"));
+            }
+            let replacement = self.pattern(Table::Gsub)?;
+
+            let [input] = &input[..] else {
+                return Err(self.syntax("a reverse substitution replaces a single glyph"));
+            };
+            let [replacement] = &replacement[..] else {
+                return Err(self.syntax("a reverse substitution replaces a single glyph"));
+            };
+
+            Substitution::ReverseChainSingle {
+                rules: vec![ReverseChainRule {
+                    backtrack: Self::sets(&backtrack),
+                    lookahead: Self::sets(&lookahead),
+                    substitutions: self.single_substitutions(input, replacement)?,
+                }],
+            }
+        } else if self.keyword(&["by"]) {
+            if self.is_keyword(&["NULL"]) {
+                return Err(self.unsupported("deleting glyphs"));
+            }
+            let replacement = self.pattern(Table::Gsub)?;
+
+            if marked {
+                let (backtrack, input, lookahead) = self.context(target)?;
+                let inline = self.simple_substitution(&input, &replacement)?;
+                let lookup = self.anonymous_lookup(block.flags, Rule::Substitution(inline));
+
+                Substitution::Context {
+                    rules: vec![ContextRule {
+                        backtrack: Self::sets(&backtrack),
+                        input: Self::sets(&input),
+                        lookahead: Self::sets(&lookahead),
+                        lookups: vec![SequenceLookup { index: 0, lookup }],
+                    }],
+                }
+            } else {
+                self.simple_substitution(&target, &replacement)?
+            }
+        } else if self.keyword(&["from"]) {
+            let alternates = self.glyph_set()?;
+
+            match (&target[..], alternates) {
+                ([item], Some((alternates, _))) if !marked && item.glyphs.len() == 1 => {
+                    Substitution::Alternate {
+                        alternates: vec![(item.glyphs[0], alternates)],
+                    }
+                }
+                _ => return Err(self.syntax("expected a single glyph and a class of alternates")),
+            }
+        } else {
+            let (backtrack, input, lookahead) = self.context(target)?;
+            let lookups = self.sequence_lookups(&input, Table::Gsub, line)?;
+
+            if lookups.is_empty() {
+                return Err(self.syntax("expected `by` or lookups for the marked glyphs"));
+            }
+
+            Substitution::Context {
+                rules: vec![ContextRule {
+                    backtrack: Self::sets(&backtrack),
+                    input: Self::sets(&input),
+                    lookahead: Self::sets(&lookahead),
+                    lookups,
+                }],
+            }
+        };
+
+        self.expect_symbol(';')?;
+
+        self.add_rule(block, Rule::Substitution(rule))
+    }
+
+    /** Glyph by glyph, a class by a glyph or a class by a class of the same length. */
+    fn single_substitutions(
+        &self,
+        target: &Item,
+        replacement: &Item,
+    ) -> Result<Vec<(u16, u16)>, FeaError> {
+        match replacement.glyphs.len() {
+            1 => Ok(target
+                .glyphs
+                .iter()
+                .map(|glyph| (*glyph, replacement.glyphs[0]))
+                .collect()),
+            n if n == target.glyphs.len() => Ok(target
+                .glyphs
+                .iter()
+                .copied()
+                .zip(replacement.glyphs.iter().copied())
+                .collect()),
+            _ => {
+                Err(self.syntax("the replacement has to be a glyph or a class of the same length"))
+            }
+        }
+    }
+
+    /** A single, multiple or ligature substitution, by the number of glyphs on each side. */
+    fn simple_substitution(
+        &self,
+        target: &[Item],
+        replacement: &[Item],
+    ) -> Result<Substitution, FeaError> {
+        let is_glyph = |item: &Item| item.glyphs.len() == 1 && !item.class;
+
+        match (target, replacement) {
+            ([target], [replacement]) => Ok(Substitution::Single {
+                substitutions: self.single_substitutions(target, replacement)?,
+            }),
+            ([target], replacement) if is_glyph(target) && replacement.iter().all(is_glyph) => {
+                Ok(Substitution::Multiple {
+                    substitutions: vec![(
+                        target.glyphs[0],
+                        replacement.iter().map(|item| item.glyphs[0]).collect(),
+                    )],
+                })
+            }
+            (components, [ligature]) if !components.is_empty() && is_glyph(ligature) => {
+                // Every combination of the glyphs of the components
+                let mut sequences: Vec<Vec<u16>> = vec![Vec::new()];
+                for component in components {
+                    sequences = sequences
+                        .into_iter()
+                        .flat_map(|sequence| {
+                            component.glyphs.iter().map(move |glyph| {
+                                let mut sequence = sequence.clone();
+                                sequence.push(*glyph);
+                                sequence
+                            })
+                        })
+                        .collect();
+                }
+
+                Ok(Substitution::Ligature {
+                    ligatures: sequences
+                        .into_iter()
+                        .map(|components| Ligature {
+                            components,
+                            glyph: ligature.glyphs[0],
+                        })
+                        .collect(),
+                })
+            }
+            _ => Err(self.syntax("the substitution is neither single, multiple nor a ligature")),
+        }
+    }
+
+    fn positioning(&mut self, block: &mut Block, enumerate: bool) -> Result<(), FeaError> {
+        let line = self.line();
+
+        if self.is_keyword(&["cursive", "base", "ligature", "mark"]) {
+            return Err(self.unsupported("attachment positioning"));
+        }
+
+        let items = self.pattern(Table::Gpos)?;
+
+        let rule = if items.iter().any(|item| item.marked) {
+            let (backtrack, input, lookahead) = self.context(items)?;
+            let mut lookups = self.sequence_lookups(&input, Table::Gpos, line)?;
+
+            for (index, item) in input.iter().enumerate() {
+                if let Some(value) = item.value {
+                    let adjustments = item.glyphs.iter().map(|glyph| (*glyph, value)).collect();
+                    let lookup = self.anonymous_lookup(
+                        block.flags,
+                        Rule::Positioning(Positioning::Single { adjustments }),
+                    );
+
+                    lookups.push(SequenceLookup {
+                        index: index as u16,
+                        lookup,
+                    });
+                }
+            }
+
+            if lookups.is_empty() {
+                return Err(self.syntax("expected values or lookups for the marked glyphs"));
+            }
+
+            Positioning::Context {
+                rules: vec![ContextRule {
+                    backtrack: Self::sets(&backtrack),
+                    input: Self::sets(&input),
+                    lookahead: Self::sets(&lookahead),
+                    lookups,
+                }],
+            }
+        } else {
+            match &items[..] {
+                [item] => {
+                    let value = item.value.ok_or_else(|| self.syntax("expected a value"))?;
+
+                    Positioning::Single {
+                        adjustments: item.glyphs.iter().map(|glyph| (*glyph, value)).collect(),
+                    }
+                }
+                [first, second] => {
+                    let (first_value, second_value) = match (first.value, second.value) {
+                        (None, None) => return Err(self.syntax("expected a value")),
+                        // A single value after the pair adjusts the first glyph
+                        (None, Some(value)) => (value, ValueRecord::default()),
+                        (first, second) => (first.unwrap_or_default(), second.unwrap_or_default()),
+                    };
+
+                    if enumerate || (!first.class && !second.class) {
+                        let pairs = first
+                            .glyphs
+                            .iter()
+                            .flat_map(|a| second.glyphs.iter().map(move |b| (*a, *b)))
+                            .map(|(first, second)| GlyphPair {
+                                first,
+                                second,
+                                first_value,
+                                second_value,
+                            })
+                            .collect();
+
+                        Positioning::Pair {
+                            pairs,
+                            class_pairs: Vec::new(),
+                        }
+                    } else {
+                        Positioning::Pair {
+                            pairs: Vec::new(),
+                            class_pairs: vec![ClassPair {
+                                first: first.glyphs.clone(),
+                                second: second.glyphs.clone(),
+                                first_value,
+                                second_value,
+                            }],
+                        }
+                    }
+                }
+                _ => return Err(self.syntax("expected one or two glyphs to position")),
+            }
+        };
+
+        self.expect_symbol(';')?;
+
+        self.add_rule(block, Rule::Positioning(rule))
+    }
+
+    /** `table GDEF { ... } GDEF;` with glyph classes, attachment points and ligature carets. */
+    fn table(&mut self) -> Result<(), FeaError> {
+        self.position += 1;
+
+        let tag = self.name()?;
+        if tag != "GDEF" {
+            self.position -= 1;
+            return Err(self.unsupported(format!("table `{}`", tag)));
+        }
+
+        self.expect_symbol('{')?;
+
+        while !self.symbol('}') {
+            let statement = self.name()?;
+
+            if statement == "GlyphClassDef" {
+                self.glyph_class_def()?;
+                continue;
+            }
+
+            let (glyphs, _) = self
+                .glyph_set()?
+                .ok_or_else(|| self.syntax("expected glyphs"))?;
+
+            let mut numbers = Vec::new();
+            while !self.symbol(';') {
+                numbers.push(self.number()?);
+            }
+
+            let to_u16 = |number: &i32| u16::try_from(*number).ok();
+            let to_i16 = |number: &i32| i16::try_from(*number).ok();
+
+            match statement.as_str() {
+                "Attach" => {
+                    let points = numbers.iter().map(to_u16).collect::<Option<Vec<_>>>();
+                    let points = points.ok_or_else(|| self.syntax("invalid point index"))?;
+
+                    self.gdef
+                        .attach_points
+                        .extend(glyphs.into_iter().map(|glyph| (glyph, points.clone())));
+                }
+                "LigatureCaretByPos" | "LigatureCaretByIndex" => {
+                    let carets = match statement.as_str() {
+                        "LigatureCaretByPos" => numbers
+                            .iter()
+                            .map(|n| to_i16(n).map(CaretValue::Coordinate))
+                            .collect::<Option<Vec<_>>>(),
+                        _ => numbers
+                            .iter()
+                            .map(|n| to_u16(n).map(CaretValue::ContourPoint))
+                            .collect(),
+                    };
+                    let carets = carets.ok_or_else(|| self.syntax("invalid caret"))?;
+
+                    self.gdef
+                        .ligature_carets
+                        .extend(glyphs.into_iter().map(|glyph| (glyph, carets.clone())));
+                }
+                _ => return Err(self.unsupported(format!("`{}` in GDEF", statement))),
+            }
+        }
+
+        self.block_end("GDEF")
+    }
+
+    /** `GlyphClassDef bases, ligatures, marks, components;` where any of the classes may be left empty. */
+    fn glyph_class_def(&mut self) -> Result<(), FeaError> {
+        let classes = [
+            GlyphClass::Base,
+            GlyphClass::Ligature,
+            GlyphClass::Mark,
+            GlyphClass::Component,
+        ];
+
+        for (n, class) in classes.into_iter().enumerate() {
+            if n > 0 {
+                self.expect_symbol(',')?;
+            }
+
+            if let Some((glyphs, _)) = self.glyph_set()? {
+                self.gdef
+                    .glyph_classes
+                    .extend(glyphs.into_iter().map(|glyph| (glyph, class)));
+            }
+        }
+
+        self.expect_symbol(';')
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::open_type::fea::{compile, FeaError};
+
+    use super::*;
+
+    fn glyph_order() -> Vec<String> {
+        [
+            ".notdef", "a", "b", "c", "f", "i", "f_i", "a.sc", "b.sc", "c.sc",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+    }
+
+    #[test]
+    fn substitutions_are_registered_per_language_system() {
+        let source = "
+            languagesystem DFLT dflt;
+            languagesystem latn dflt;
+            @lower = [a - c];
+
+            feature liga {
+                sub f i by f_i;
+                script latn;
+                language TRK exclude_dflt;
+                sub a by b;
+            } liga;
+
+            feature smcp {
+                sub @lower by [a.sc - c.sc];
+            } smcp;
+        ";
+
+        let gsub = compile(source, &glyph_order()).unwrap().gsub.unwrap();
+
+        assert_eq!(
+            gsub.lookups
+                .iter()
+                .map(|l| l.substitution.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                Substitution::Ligature {
+                    ligatures: vec![Ligature {
+                        components: vec![4, 5],
+                        glyph: 6
+                    }]
+                },
+                Substitution::Single {
+                    substitutions: vec![(1, 2)]
+                },
+                Substitution::Single {
+                    substitutions: vec![(1, 7), (2, 8), (3, 9)]
+                },
+            ]
+        );
+
+        let systems: Vec<_> = gsub
+            .language_systems
+            .iter()
+            .map(|s| {
+                let features = s.features.iter().map(|f| &gsub.features[*f as usize]);
+                (
+                    s.script,
+                    s.language,
+                    features
+                        .map(|f| (f.tag, f.lookups.clone()))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            systems,
+            vec![
+                (
+                    *b"DFLT",
+                    DEFAULT_LANGUAGE,
+                    vec![(*b"liga", vec![0]), (*b"smcp", vec![2])]
+                ),
+                (*b"latn", *b"TRK ", vec![(*b"liga", vec![1])]),
+                (
+                    *b"latn",
+                    DEFAULT_LANGUAGE,
+                    vec![(*b"liga", vec![0]), (*b"smcp", vec![2])]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn positioning_and_contextual_rules() {
+        let source = "
+            lookup raise { pos a <0 20 0 0>; } raise;
+
+            feature kern {
+                pos a b -40;
+                pos [a b] c 10;
+                enum pos [a b] a -5;
+                pos c a' lookup raise b;
+                sub c a' by b;
+            } kern;
+        ";
+
+        let compiled = compile(source, &glyph_order()).unwrap();
+        let gpos = compiled.gpos.unwrap();
+
+        let Positioning::Pair { pairs, class_pairs } = &gpos.lookups[1].positioning else {
+            panic!("expected pair positioning");
+        };
+        assert_eq!(
+            pairs,
+            &vec![
+                GlyphPair::kerning(1, 2, -40),
+                GlyphPair::kerning(1, 1, -5),
+                GlyphPair::kerning(2, 1, -5),
+            ]
+        );
+        assert_eq!(
+            class_pairs,
+            &vec![ClassPair::kerning(vec![1, 2], vec![3], 10)]
+        );
+
+        assert_eq!(
+            gpos.lookups[2].positioning,
+            Positioning::Context {
+                rules: vec![ContextRule {
+                    backtrack: vec![vec![3]],
+                    input: vec![vec![1]],
+                    lookahead: vec![vec![2]],
+                    lookups: vec![SequenceLookup {
+                        index: 0,
+                        lookup: String::from("raise")
+                    }],
+                }]
+            }
+        );
+        assert_eq!(gpos.features[0].lookups, vec![1, 2]);
+
+        // The inline substitution becomes a lookup of its own, applied by the contextual one
+        let gsub = compiled.gsub.unwrap();
+        assert_eq!(gsub.lookups.len(), 2);
+        assert_eq!(gsub.features[0].lookups, vec![1]);
+    }
+
+    #[test]
+    fn mark_flags_and_glyph_definitions_end_up_in_gdef() {
+        let source = "
+            @top = [a b];
+
+            table GDEF {
+                GlyphClassDef [f i], f_i, @top, ;
+                LigatureCaretByPos f_i 300;
+                Attach f 2 5;
+            } GDEF;
+
+            feature test {
+                lookupflag MarkAttachmentType @top;
+                sub f by i;
+                lookupflag UseMarkFilteringSet [c];
+                sub i by f;
+                lookupflag MarkAttachmentType [b a] IgnoreLigatures;
+                sub c by a;
+            } test;
+        ";
+
+        let compiled = compile(source, &glyph_order()).unwrap();
+
+        let flags: Vec<_> = compiled
+            .gsub
+            .unwrap()
+            .lookups
+            .iter()
+            .map(|l| (l.flags, l.mark_filtering_set))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                (0x0100, None),
+                (lookup_flags::USE_MARK_FILTERING_SET, Some(0)),
+                (0x0100 | lookup_flags::IGNORE_LIGATURES, None),
+            ]
+        );
+
+        assert_eq!(
+            compiled.gdef,
+            Some(Gdef {
+                glyph_classes: vec![
+                    (4, GlyphClass::Base),
+                    (5, GlyphClass::Base),
+                    (6, GlyphClass::Ligature),
+                    (1, GlyphClass::Mark),
+                    (2, GlyphClass::Mark),
+                ],
+                attach_points: vec![(4, vec![2, 5])],
+                ligature_carets: vec![(6, vec![CaretValue::Coordinate(300)])],
+                mark_attach_classes: vec![(1, 1), (2, 1)],
+                mark_glyph_sets: vec![vec![3]],
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_reported_with_their_line() {
+        assert_eq!(
+            compile("feature liga {\n  sub f x by f_i;\n} liga;", &glyph_order()).err(),
+            Some(FeaError::UnknownGlyph {
+                line: 2,
+                name: String::from("x")
+            })
+        );
+        assert_eq!(
+            compile(
+                "feature liga {\n\n  lookup missing;\n} liga;",
+                &glyph_order()
+            )
+            .err(),
+            Some(FeaError::UnknownLookup {
+                line: 3,
+                name: String::from("missing")
+            })
+        );
+    }
+
+    #[test]
+    fn ranges_too_long_for_the_font_are_rejected() {
+        for source in [
+            "@all = [a0000000000 - a9999999999];",
+            "@all = [a000000000000000000000 - a999999999999999999999];",
+            "@all = [a000 - a999];",
+        ] {
+            assert!(
+                matches!(
+                    compile(source, &glyph_order()),
+                    Err(FeaError::Syntax { line: 1, .. })
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::FeaError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /** A keyword, glyph name, tag or lookup label. */
+    Name(String),
+    /** A name escaped by a backslash, never a keyword. */
+    Escaped(String),
+    /** A glyph id given as `\123`. */
+    Cid(u16),
+    /** The name of a glyph class, without the `@`. */
+    Class(String),
+    Number(i32),
+    String(String),
+    Symbol(char),
+}
+
+#[derive(Debug, Clone)]
+pub struct Spanned {
+    pub token: Token,
+    pub line: usize,
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/** Splits a feature file into tokens, dropping whitespace and comments. */
+pub fn tokenize(source: &str) -> Result<Vec<Spanned>, FeaError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    let take_while = |chars: &mut std::iter::Peekable<std::str::Chars>, f: fn(char) -> bool| {
+        let mut taken = String::new();
+        while let Some(c) = chars.next_if(|c| f(*c)) {
+            taken.push(c);
+        }
+        taken
+    };
+
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+                continue;
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '#' => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+                continue;
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            string.push(c);
+                        }
+                        None => {
+                            return Err(FeaError::Syntax {
+                                line,
+                                message: String::from("the string is not closed"),
+                            })
+                        }
+                    }
+                }
+                Token::String(string)
+            }
+            '@' => {
+                chars.next();
+                Token::Class(take_while(&mut chars, is_name_char))
+            }
+            '\\' => {
+                chars.next();
+                match chars.peek() {
+                    Some(c) if c.is_ascii_digit() => {
+                        let digits = take_while(&mut chars, |c| c.is_ascii_digit());
+                        Token::Cid(digits.parse().map_err(|_| FeaError::Syntax {
+                            line,
+                            message: format!("glyph id {} is too large", digits),
+                        })?)
+                    }
+                    _ => Token::Escaped(take_while(&mut chars, is_name_char)),
+                }
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                chars.next();
+                let digits = take_while(&mut chars, |c| c.is_ascii_digit());
+
+                match (c, digits.is_empty()) {
+                    ('-', true) => Token::Symbol('-'),
+                    _ => {
+                        let number = format!("{}{}", c, digits);
+                        Token::Number(number.parse().map_err(|_| FeaError::Syntax {
+                            line,
+                            message: format!("{} is too large", number),
+                        })?)
+                    }
+                }
+            }
+            c if is_name_start(c) => Token::Name(take_while(&mut chars, is_name_char)),
+            ';' | '{' | '}' | '[' | ']' | '<' | '>' | '\'' | ',' | '=' => {
+                chars.next();
+                Token::Symbol(c)
+            }
+            c => {
+                return Err(FeaError::Syntax {
+                    line,
+                    message: format!("unexpected character {:?}", c),
+                })
+            }
+        };
+
+        tokens.push(Spanned { token, line });
+    }
+
+    Ok(tokens)
+}
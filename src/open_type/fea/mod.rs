@@ -0,0 +1,174 @@
+mod compiler;
+mod lexer;
+
+use thiserror::Error;
+
+use crate::open_type::tables::{CMap, Gdef, Gpos, Gsub};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum FeaError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("line {line}: there is no glyph named `{name}`")]
+    UnknownGlyph { line: usize, name: String },
+    #[error("line {line}: the glyph class `@{name}` is not defined")]
+    UnknownClass { line: usize, name: String },
+    #[error("line {line}: there is no lookup named `{name}`")]
+    UnknownLookup { line: usize, name: String },
+    #[error("line {line}: {what} is not supported")]
+    Unsupported { line: usize, what: String },
+    #[error("the feature file ends unexpectedly")]
+    UnexpectedEnd,
+}
+
+/** The tables a feature file describes, those it leaves empty are `None`. */
+#[derive(Debug, Default)]
+pub struct CompiledFeatures {
+    pub gsub: Option<Gsub>,
+    pub gpos: Option<Gpos>,
+    pub gdef: Option<Gdef>,
+}
+
+/**
+ * Compiles a feature file in the Adobe syntax, see
+ * <https://adobe-type-tools.github.io/afdko/OpenTypeFeatureFileSpecification.html>. The glyph order gives the name
+ * of every glyph by its id, glyphs can also be given by id as `\123`.
+ *
+ * Supported are language systems, glyph classes, features and named lookups with their lookup flags, all GSUB
+ * substitutions including contextual and reverse chaining ones, single, pair and contextual positioning as well as
+ * the `GDEF` table. Mark attachment types and mark filtering sets of lookup flags are added to `GDEF`.
+ */
+pub fn compile(source: &str, glyph_order: &[String]) -> Result<CompiledFeatures, FeaError> {
+    let tokens = lexer::tokenize(source)?;
+
+    compiler::Compiler::new(tokens, glyph_order).compile()
+}
+
+const ASCII_NAMES: [&str; 95] = [
+    "space",
+    "exclam",
+    "quotedbl",
+    "numbersign",
+    "dollar",
+    "percent",
+    "ampersand",
+    "quotesingle",
+    "parenleft",
+    "parenright",
+    "asterisk",
+    "plus",
+    "comma",
+    "hyphen",
+    "period",
+    "slash",
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "colon",
+    "semicolon",
+    "less",
+    "equal",
+    "greater",
+    "question",
+    "at",
+    "A",
+    "B",
+    "C",
+    "D",
+    "E",
+    "F",
+    "G",
+    "H",
+    "I",
+    "J",
+    "K",
+    "L",
+    "M",
+    "N",
+    "O",
+    "P",
+    "Q",
+    "R",
+    "S",
+    "T",
+    "U",
+    "V",
+    "W",
+    "X",
+    "Y",
+    "Z",
+    "bracketleft",
+    "backslash",
+    "bracketright",
+    "asciicircum",
+    "underscore",
+    "grave",
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "braceleft",
+    "bar",
+    "braceright",
+    "asciitilde",
+];
+
+/**
+ * Names glyphs after the first character mapped to them: printable ASCII by the Adobe Glyph List, everything else as
+ * `uniXXXX` or `uXXXXX`. Glyph 0 is `.notdef`, glyphs without a character are `glyph` followed by their id.
+ */
+pub fn glyph_names(glyph_count: u16, cmap: &CMap) -> Vec<String> {
+    let mut names: Vec<Option<String>> = vec![None; glyph_count as usize];
+
+    for (char, glyph_id) in cmap.mappings() {
+        let Some(name) = names.get_mut(glyph_id as usize) else {
+            continue;
+        };
+
+        if name.is_none() {
+            *name = Some(match char as u32 {
+                code @ 0x20..=0x7E => ASCII_NAMES[code as usize - 0x20].to_string(),
+                code @ ..=0xFFFF => format!("uni{:04X}", code),
+                code => format!("u{:05X}", code),
+            });
+        }
+    }
+
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(glyph_id, name)| match glyph_id {
+            0 => String::from(".notdef"),
+            _ => name.unwrap_or_else(|| format!("glyph{}", glyph_id)),
+        })
+        .collect()
+}
@@ -1,12 +1,124 @@
-use crate::layout::{LayoutError, Layoutable, Layouted, Layouter, Reservation};
+use rayon::prelude::*;
+use thiserror::Error;
 
-use super::search::SearchData;
+use crate::layout::{
+    FillCheck, LayoutError, Layoutable, Layouted, Layouter, Reservation, SeekWrite, Warnings,
+};
+
+use super::{
+    reader::{CFF_VERSION, TRUE_TYPE_VERSION},
+    search::SearchData,
+};
+
+/** The directory stores `numTables * 16` in a u16 `rangeShift`, so this is the most tables a file can hold. */
+pub const MAX_TABLES: usize = (u16::MAX / 16) as usize;
+
+const COMMON_TABLES: [[u8; 4]; 8] = [
+    *b"cmap", *b"head", *b"hhea", *b"hmtx", *b"maxp", *b"name", *b"OS/2", *b"post",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutlineFormat {
+    TrueType,
+    Cff,
+    Cff2,
+}
+
+impl OutlineFormat {
+    fn detect(tags: &[[u8; 4]]) -> Self {
+        if tags.contains(b"CFF ") {
+            OutlineFormat::Cff
+        } else if tags.contains(b"CFF2") {
+            OutlineFormat::Cff2
+        } else {
+            OutlineFormat::TrueType
+        }
+    }
+
+    /** The version at the start of the table directory, `OTTO` for fonts with CFF outlines. */
+    pub fn sfnt_version(&self) -> u32 {
+        match self {
+            OutlineFormat::TrueType => TRUE_TYPE_VERSION,
+            OutlineFormat::Cff | OutlineFormat::Cff2 => CFF_VERSION,
+        }
+    }
+
+    pub fn required_tables(&self) -> Vec<[u8; 4]> {
+        let outline_tables: &[[u8; 4]] = match self {
+            OutlineFormat::TrueType => &[*b"glyf", *b"loca"],
+            OutlineFormat::Cff => &[*b"CFF "],
+            OutlineFormat::Cff2 => &[*b"CFF2"],
+        };
+
+        COMMON_TABLES
+            .iter()
+            .chain(outline_tables.iter())
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TableDirectoryIssue {
+    #[error("table '{}' occurs {count} times", String::from_utf8_lossy(.tag))]
+    Duplicate { tag: [u8; 4], count: usize },
+    #[error("required table '{}' is missing", String::from_utf8_lossy(.tag))]
+    Missing { tag: [u8; 4] },
+    #[error("{count} tables exceed the maximum of {MAX_TABLES}")]
+    TooManyTables { count: usize },
+    #[error("offset {offset} of table '{}' does not fit into 32 bits", String::from_utf8_lossy(.tag))]
+    OffsetOverflow { tag: [u8; 4], offset: usize },
+    #[error("length {length} of table '{}' does not fit into 32 bits", String::from_utf8_lossy(.tag))]
+    LengthOverflow { tag: [u8; 4], length: usize },
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid table directory: {}", .issues.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+pub struct TableDirectoryError {
+    pub issues: Vec<TableDirectoryIssue>,
+}
+
+pub(crate) fn tag_issues(tags: &[[u8; 4]]) -> Vec<TableDirectoryIssue> {
+    let mut issues = Vec::new();
+
+    if tags.len() > MAX_TABLES {
+        issues.push(TableDirectoryIssue::TooManyTables { count: tags.len() });
+    }
+
+    let mut sorted = tags.to_vec();
+    sorted.sort();
+
+    for group in sorted.chunk_by(|a, b| a == b) {
+        if group.len() > 1 {
+            issues.push(TableDirectoryIssue::Duplicate {
+                tag: group[0],
+                count: group.len(),
+            });
+        }
+    }
+
+    for tag in OutlineFormat::detect(tags).required_tables() {
+        if !tags.contains(&tag) {
+            issues.push(TableDirectoryIssue::Missing { tag });
+        }
+    }
+
+    issues
+}
+
+pub(crate) fn into_result(issues: Vec<TableDirectoryIssue>) -> Result<(), TableDirectoryError> {
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(TableDirectoryError { issues })
+    }
+}
 
 pub trait LayoutedTable: Layouted {
     fn tag(&self) -> [u8; 4];
 }
 
-pub trait LayoutableTable: Layoutable<Box<dyn LayoutedTable>> {
+pub trait LayoutableTable: Layoutable<Box<dyn LayoutedTable>> + Send + Sync {
     fn tag(&self) -> [u8; 4];
 }
 
@@ -18,6 +130,26 @@ impl File {
     pub fn new_with_tables(tables: Vec<Box<dyn LayoutableTable>>) -> Self {
         Self { tables }
     }
+
+    pub fn add_table(&mut self, table: Box<dyn LayoutableTable>) {
+        self.tables.push(table);
+    }
+
+    pub fn outline_format(&self) -> OutlineFormat {
+        OutlineFormat::detect(&self.tags())
+    }
+
+    /**
+     * Checks the table set for duplicate tags, missing required tables and a table count the directory can not hold.
+     * Offsets and lengths are only known after layout and are checked by the layouted file before it writes the directory.
+     */
+    pub fn validate(&self) -> Result<(), TableDirectoryError> {
+        into_result(tag_issues(&self.tags()))
+    }
+
+    fn tags(&self) -> Vec<[u8; 4]> {
+        self.tables.iter().map(|t| t.tag()).collect()
+    }
 }
 
 impl Layoutable<Box<dyn Layouted>> for File {
@@ -38,6 +170,9 @@ impl Layoutable<Box<dyn Layouted>> for File {
 
         Box::new(LayoutedFile {
             requires_pass: true,
+            fill_check: layouter.fill_check(),
+            warnings: layouter.warnings(),
+            parallel: layouter.parallel(),
             reservation,
             tables,
         })
@@ -46,6 +181,9 @@ impl Layoutable<Box<dyn Layouted>> for File {
 
 struct LayoutedFile {
     requires_pass: bool,
+    fill_check: FillCheck,
+    warnings: Warnings,
+    parallel: bool,
     reservation: Reservation,
     tables: Vec<Box<dyn LayoutedTable>>,
 }
@@ -60,40 +198,148 @@ impl Layouted for LayoutedFile {
     }
 
     fn pass(&mut self, current_file: &[u8]) -> Result<(), LayoutError> {
-        use byteorder::{WriteBytesExt, BE};
+        let tables: Vec<&dyn LayoutedTable> = self.tables.iter().map(AsRef::as_ref).collect();
+        into_result(directory_issues(&tables))?;
 
-        for table in self.tables.iter_mut() {
-            table.pass(current_file)?;
+        pass_tables(&mut self.tables, current_file, self.parallel)?;
+
+        let tables: Vec<&dyn LayoutedTable> = self.tables.iter().map(AsRef::as_ref).collect();
+        write_directory(&mut self.reservation.writer(), &tables)?;
+
+        self.requires_pass = false;
+
+        if !self.requires_another_pass() {
+            check_fills(&self.tables, self.fill_check, &self.warnings)?;
         }
 
-        let search_data = SearchData::for_length(self.tables.len() as u16);
-        let mut writer = self.reservation.writer();
+        Ok(())
+    }
+}
 
-        writer.write_u32::<BE>(0x00010000)?; // Magic Number
-        writer.write_u16::<BE>(self.tables.len() as u16)?;
+/** The issues of [tag_issues] plus offsets and lengths that do not fit into the directory. */
+pub(crate) fn directory_issues(tables: &[&dyn LayoutedTable]) -> Vec<TableDirectoryIssue> {
+    let tags: Vec<_> = tables.iter().map(|t| t.tag()).collect();
+    let mut issues = tag_issues(&tags);
 
-        writer.write_u16::<BE>(search_data.search_range)?;
-        writer.write_u16::<BE>(search_data.entry_selector)?;
-        writer.write_u16::<BE>(search_data.range_shift)?;
+    for table in tables.iter() {
+        let reservation = table.reservation();
 
-        let mut tables: Vec<_> = self.tables.iter().collect();
-        tables.sort_by_key(|t| String::from(std::str::from_utf8(&t.tag()).unwrap_or("_MISSING_")));
+        if u32::try_from(reservation.offset()).is_err() {
+            issues.push(TableDirectoryIssue::OffsetOverflow {
+                tag: table.tag(),
+                offset: reservation.offset(),
+            });
+        }
 
-        for table in tables.iter() {
-            writer.write_all(&table.tag())?;
-            writer.write_u32::<BE>(checksum(table.reservation())?)?;
-            writer.write_u32::<BE>(table.reservation().offset() as u32)?;
-            writer.write_u32::<BE>(table.reservation().len() as u32)?;
+        if u32::try_from(reservation.len()).is_err() {
+            issues.push(TableDirectoryIssue::LengthOverflow {
+                tag: table.tag(),
+                length: reservation.len(),
+            });
         }
+    }
 
-        self.requires_pass = false;
+    issues
+}
 
-        Ok(())
+/** Runs one pass of every table, tagging errors with the table they occured in. */
+pub(crate) fn pass_tables(
+    tables: &mut [Box<dyn LayoutedTable>],
+    current_file: &[u8],
+    parallel: bool,
+) -> Result<(), LayoutError> {
+    let pass = |table: &mut Box<dyn LayoutedTable>| {
+        let tag = table.tag();
+        table
+            .pass(current_file)
+            .map_err(|error| error.with_table(tag))
+    };
+
+    // Every table only writes its own reservation, so the passes are independent. Results are collected in table
+    // order to report the same error the sequential path would.
+    let results: Vec<_> = if parallel {
+        tables.par_iter_mut().map(pass).collect()
+    } else {
+        tables.iter_mut().map(pass).collect()
+    };
+
+    results.into_iter().collect()
+}
+
+/** Fails on the first under-filled table, or adds them all to `warnings` if the check is lenient. */
+pub(crate) fn check_fills(
+    tables: &[Box<dyn LayoutedTable>],
+    fill_check: FillCheck,
+    warnings: &Warnings,
+) -> Result<(), LayoutError> {
+    let mut underfilled = Vec::new();
+
+    for table in tables.iter() {
+        underfilled.extend(table.reservation().check_fill(table.tag(), fill_check)?);
+    }
+
+    warnings
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .extend(underfilled);
+
+    Ok(())
+}
+
+/** Writes the offset table and the table records, the size of which is `12 + 16 * tables`. */
+pub(crate) fn write_directory(
+    writer: &mut dyn SeekWrite,
+    tables: &[&dyn LayoutedTable],
+) -> Result<(), LayoutError> {
+    use byteorder::{WriteBytesExt, BE};
+
+    let search_data = SearchData::for_length(tables.len() as u16);
+    let tags: Vec<_> = tables.iter().map(|t| t.tag()).collect();
+
+    writer.write_u32::<BE>(OutlineFormat::detect(&tags).sfnt_version())?;
+    writer.write_u16::<BE>(tables.len() as u16)?;
+
+    writer.write_u16::<BE>(search_data.search_range)?;
+    writer.write_u16::<BE>(search_data.entry_selector)?;
+    writer.write_u16::<BE>(search_data.range_shift)?;
+
+    // The directory has to be sorted by the raw tag bytes for binary search; tags are unique at this point.
+    let mut tables = tables.to_vec();
+    tables.sort_by_key(|t| t.tag());
+
+    for table in tables.iter() {
+        writer.write_all(&table.tag())?;
+        writer.write_u32::<BE>(table_checksum(*table)?)?;
+        writer.write_u32::<BE>(table.reservation().offset() as u32)?;
+        writer.write_u32::<BE>(table.reservation().len() as u32)?;
     }
+
+    Ok(())
+}
+
+/**
+ * The checksum of `head` is taken with its checksum adjustment set to 0. The adjustment is only written once the
+ * file checksum is stable, so including it would change the directory and with it the file checksum again.
+ */
+fn table_checksum(table: &dyn LayoutedTable) -> std::io::Result<u32> {
+    use byteorder::{ReadBytesExt, BE};
+    use std::io::{Seek, SeekFrom};
+
+    let sum = checksum(table.reservation())?;
+
+    if table.tag() != *b"head" {
+        return Ok(sum);
+    }
+
+    let mut reader = table.reservation().reader();
+    reader.seek(SeekFrom::Start(8))?;
+
+    Ok(sum.wrapping_sub(reader.read_u32::<BE>()?))
 }
 
 fn checksum(reservation: &Reservation) -> std::io::Result<u32> {
     use byteorder::{ReadBytesExt, BE};
+    use std::io::{Read, Seek};
 
     let mut reader = reservation.reader();
 
@@ -114,8 +360,164 @@ fn checksum(reservation: &Reservation) -> std::io::Result<u32> {
 
         buffer.resize(4, 0);
 
-        sum += buffer.as_slice().read_u32::<BE>()?;
+        sum = sum.wrapping_add(buffer.as_slice().read_u32::<BE>()?);
     }
 
     Ok(sum)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::open_type::tables::RawTable;
+
+    struct Stub([u8; 4]);
+
+    impl LayoutableTable for Stub {
+        fn tag(&self) -> [u8; 4] {
+            self.0
+        }
+    }
+
+    impl Layoutable<Box<dyn LayoutedTable>> for Stub {
+        fn layout(&self, layouter: &mut Layouter) -> Box<dyn LayoutedTable> {
+            RawTable {
+                tag: self.0,
+                data: vec![],
+            }
+            .layout(layouter)
+        }
+    }
+
+    fn file_with(tags: &[[u8; 4]]) -> File {
+        File::new_with_tables(
+            tags.iter()
+                .map(|tag| -> Box<dyn LayoutableTable> { Box::new(Stub(*tag)) })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn a_complete_true_type_file_is_valid() {
+        let file = file_with(&OutlineFormat::TrueType.required_tables());
+
+        assert_eq!(file.validate(), Ok(()));
+    }
+
+    #[test]
+    fn duplicates_are_reported() {
+        let mut tags = OutlineFormat::TrueType.required_tables();
+        tags.push(*b"cmap");
+
+        let error = file_with(&tags).validate().unwrap_err();
+
+        assert_eq!(
+            error.issues,
+            vec![TableDirectoryIssue::Duplicate {
+                tag: *b"cmap",
+                count: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_tables_depend_on_the_outline_format() {
+        let mut tags = COMMON_TABLES.to_vec();
+        tags.push(*b"CFF ");
+
+        assert_eq!(file_with(&tags).outline_format(), OutlineFormat::Cff);
+        assert_eq!(file_with(&tags).validate(), Ok(()));
+
+        tags.retain(|t| t != b"head");
+
+        assert_eq!(
+            file_with(&tags).validate().unwrap_err().issues,
+            vec![TableDirectoryIssue::Missing { tag: *b"head" }]
+        );
+    }
+
+    #[test]
+    fn too_many_tables_are_reported() {
+        let mut tags = OutlineFormat::TrueType.required_tables();
+        tags.extend((0..MAX_TABLES as u32).map(|i| i.to_be_bytes()));
+
+        let error = file_with(&tags).validate().unwrap_err();
+
+        assert!(error.issues.contains(&TableDirectoryIssue::TooManyTables {
+            count: MAX_TABLES + 10
+        }));
+    }
+
+    #[test]
+    fn checksums_are_balanced() {
+        use crate::open_type::reader::{checksum, read_directory};
+
+        let data = crate::build(&crate::test::sample_font(3).into_file(), false).unwrap();
+
+        let (_, records) = read_directory(&data).unwrap();
+
+        for record in records.iter() {
+            assert!(record.has_valid_checksum(&data).unwrap(), "{:?}", record);
+        }
+
+        assert_eq!(checksum(&data), 0xB1B0AFBA);
+    }
+
+    /** A table that reserves four bytes and never writes them. */
+    struct Unwritten;
+
+    impl LayoutableTable for Unwritten {
+        fn tag(&self) -> [u8; 4] {
+            *b"test"
+        }
+    }
+
+    impl Layoutable<Box<dyn LayoutedTable>> for Unwritten {
+        fn layout(&self, layouter: &mut Layouter) -> Box<dyn LayoutedTable> {
+            Box::new(LayoutedUnwritten(layouter.reserve(4)))
+        }
+    }
+
+    struct LayoutedUnwritten(Reservation);
+
+    impl Layouted for LayoutedUnwritten {
+        fn reservation(&self) -> &Reservation {
+            &self.0
+        }
+
+        fn requires_another_pass(&self) -> bool {
+            false
+        }
+
+        fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
+            Ok(())
+        }
+    }
+
+    impl LayoutedTable for LayoutedUnwritten {
+        fn tag(&self) -> [u8; 4] {
+            *b"test"
+        }
+    }
+
+    #[test]
+    fn lenient_layouts_collect_underfilled_tables_as_warnings() -> Result<(), LayoutError> {
+        let mut file = crate::test::sample_font(1).into_file();
+        file.add_table(Box::new(Unwritten));
+
+        let mut layouter = Layouter::with_fill_check(4, FillCheck::Lenient);
+        let mut layouted = file.layout(&mut layouter);
+
+        while layouted.requires_another_pass() {
+            layouted.pass(&layouter.get_result())?;
+        }
+
+        assert!(matches!(
+            layouter.take_warnings().as_slice(),
+            [LayoutError::UnderfilledReservation { tag, reserved: 4, written: 0 }] if tag == b"test"
+        ));
+        assert!(layouter.take_warnings().is_empty());
+
+        Ok(())
+    }
+}
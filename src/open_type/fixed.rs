@@ -1,11 +1,19 @@
-use byteorder::{ByteOrder, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Fixed {
     pub major: i16,
     pub minor: u16,
 }
 
+impl Fixed {
+    pub fn to_f32(&self) -> f32 {
+        self.major as f32 + self.minor as f32 / 65536.0
+    }
+}
+
 pub trait FixedWriteExt: std::io::Write {
     fn write_fixed<T: ByteOrder>(&mut self, fixed: &Fixed) -> std::io::Result<()> {
         self.write_i16::<T>(fixed.major)?;
@@ -14,3 +22,14 @@ pub trait FixedWriteExt: std::io::Write {
 }
 
 impl<W: std::io::Write + ?Sized> FixedWriteExt for W {}
+
+pub trait FixedReadExt: std::io::Read {
+    fn read_fixed<T: ByteOrder>(&mut self) -> std::io::Result<Fixed> {
+        Ok(Fixed {
+            major: self.read_i16::<T>()?,
+            minor: self.read_u16::<T>()?,
+        })
+    }
+}
+
+impl<R: std::io::Read + ?Sized> FixedReadExt for R {}
@@ -0,0 +1,298 @@
+use std::io::Write;
+
+use super::{
+    reader::{read_directory, Font, ReadError},
+    tables::Glyph,
+    true_type::{ComponentPlacement, ComponentTransform, Contour},
+};
+
+/**
+ * Writes a human readable description of the font: the table directory with checksum validation, followed by the
+ * decoded contents of every table this crate understands.
+ */
+pub fn inspect(data: &[u8], out: &mut dyn Write) -> Result<(), ReadError> {
+    let (sfnt_version, records) = read_directory(data)?;
+
+    writeln!(
+        out,
+        "sfnt version {:#010x}, {} tables",
+        sfnt_version,
+        records.len()
+    )?;
+    writeln!(out)?;
+    writeln!(out, "tag      offset     length  checksum    valid")?;
+
+    for record in records.iter() {
+        let valid = match record.has_valid_checksum(data) {
+            Ok(true) => "yes",
+            Ok(false) => "NO",
+            Err(_) => "out of bounds",
+        };
+
+        writeln!(
+            out,
+            "{:<6} {:>8} {:>10}  {:#010x}  {}",
+            tag(&record.tag),
+            record.offset,
+            record.length,
+            record.checksum,
+            valid
+        )?;
+    }
+
+    let font = Font::read(data)?;
+
+    if let Some(head) = &font.head {
+        section(out, b"head")?;
+        writeln!(out, "revision            {}", head.revision.to_f32())?;
+        writeln!(out, "flags               {:?}", head.flags)?;
+        writeln!(out, "units per em        {}", head.units_per_em)?;
+        writeln!(out, "created             {}", head.created)?;
+        writeln!(out, "modified            {}", head.modified)?;
+        writeln!(
+            out,
+            "bounds              ({}, {}) - ({}, {})",
+            head.min_x, head.min_y, head.max_x, head.max_y
+        )?;
+        writeln!(
+            out,
+            "smallest size       {} ppem",
+            head.smalest_recocnizeable_size
+        )?;
+    }
+
+    if let Some(hhea) = &font.hhea {
+        section(out, b"hhea")?;
+        writeln!(out, "ascender            {}", hhea.ascender)?;
+        writeln!(out, "descender           {}", hhea.descender)?;
+        writeln!(out, "line gap            {}", hhea.line_gap)?;
+        writeln!(out, "max advance width   {}", hhea.advance_width_max)?;
+        writeln!(out, "horizontal metrics  {}", hhea.number_of_hmetrics)?;
+    }
+
+    if let Some(maxp) = &font.maxp {
+        section(out, b"maxp")?;
+        writeln!(out, "glyphs              {}", maxp.number_of_glyphs)?;
+    }
+
+    if let Some(os2) = &font.os2 {
+        section(out, b"OS/2")?;
+        writeln!(out, "weight class        {}", os2.weight_class)?;
+        writeln!(out, "width class         {}", os2.width_class)?;
+        writeln!(out, "average width       {}", os2.avg_glyph_width)?;
+        writeln!(
+            out,
+            "typo metrics        ascender {}, descender {}, line gap {}",
+            os2.typo_ascender, os2.typo_descender, os2.typo_line_gap
+        )?;
+        writeln!(
+            out,
+            "win metrics         ascent {}, descent {}",
+            os2.win_ascent, os2.win_descent
+        )?;
+        writeln!(
+            out,
+            "heights             x {}, cap {}",
+            os2.x_height, os2.cap_height
+        )?;
+    }
+
+    if let Some(post) = &font.post {
+        section(out, b"post")?;
+        writeln!(out, "italic angle        {}", post.italic_angle.to_f32())?;
+        writeln!(
+            out,
+            "underline           position {}, thickness {}",
+            post.underline_position, post.underline_thickness
+        )?;
+        writeln!(out, "fixed pitch         {}", post.is_fixed_pitch)?;
+    }
+
+    if let Some(name) = &font.name {
+        section(out, b"name")?;
+
+        for record in name.names.iter() {
+            writeln!(out, "{:>5}  {:?}", record.name_id, record.content)?;
+        }
+    }
+
+    if let Some(cmap) = &font.cmap {
+        section(out, b"cmap")?;
+
+        for range in cmap.ranges() {
+            write!(
+                out,
+                "U+{:04X}..U+{:04X}  ->  ",
+                range.start as u32, range.end as u32
+            )?;
+
+            let last = (range.end as u32)
+                .checked_sub(range.start as u32)
+                .and_then(|length| range.start_index.checked_add(length));
+
+            match last {
+                Some(last) => writeln!(out, "glyph {}..{}", range.start_index, last)?,
+                None => writeln!(out, "glyph {}..  invalid range", range.start_index)?,
+            }
+        }
+    }
+
+    if let Some(glyf) = &font.glyf {
+        section(out, b"glyf")?;
+
+        for (glyph_id, glyph) in glyf.glyphs.iter().enumerate() {
+            write!(out, "glyph {}", glyph_id)?;
+
+            if let Some(hmtx) = &font.hmtx {
+                if let Some(metric) = hmtx.metric(glyph_id) {
+                    write!(
+                        out,
+                        "  advance {}, left side bearing {}",
+                        metric.advance_width, metric.left_side_bearing
+                    )?;
+                }
+            }
+
+            writeln!(out)?;
+            write_glyph(out, glyph)?;
+        }
+    }
+
+    for table in font.raw_tables.iter() {
+        section(out, &table.tag)?;
+        writeln!(out, "{} bytes, not decoded", table.data.len())?;
+    }
+
+    Ok(())
+}
+
+fn tag(tag: &[u8; 4]) -> String {
+    format!("'{}'", String::from_utf8_lossy(tag))
+}
+
+fn section(out: &mut dyn Write, table: &[u8; 4]) -> std::io::Result<()> {
+    writeln!(out)?;
+    writeln!(out, "== {} ==", tag(table))
+}
+
+fn write_glyph(out: &mut dyn Write, glyph: &Glyph) -> std::io::Result<()> {
+    match glyph {
+        Glyph::Empty => writeln!(out, "    empty"),
+        Glyph::Simple {
+            contours,
+            instructions,
+        } => {
+            for (index, contour) in contours.iter().enumerate() {
+                writeln!(out, "    contour {}: {}", index, points(contour))?;
+            }
+
+            let length: usize = instructions.iter().map(|i| i.size()).sum();
+            writeln!(out, "    {} bytes of instructions", length)
+        }
+        Glyph::Composite {
+            components,
+            instructions,
+        } => {
+            for component in components.iter() {
+                let placement = match component.placement {
+                    ComponentPlacement::Offset { x, y } => format!("offset ({}, {})", x, y),
+                    ComponentPlacement::MatchPoints { parent, child } => {
+                        format!("point {} on point {}", child, parent)
+                    }
+                };
+
+                let transform = match component.transform {
+                    ComponentTransform::Identity => String::new(),
+                    ComponentTransform::Scale(scale) => format!(", scale {}", scale.to_f32()),
+                    ComponentTransform::ScaleXY { x, y } => {
+                        format!(", scale ({}, {})", x.to_f32(), y.to_f32())
+                    }
+                    ComponentTransform::Matrix { xx, xy, yx, yy } => format!(
+                        ", matrix [{} {}; {} {}]",
+                        xx.to_f32(),
+                        xy.to_f32(),
+                        yx.to_f32(),
+                        yy.to_f32()
+                    ),
+                };
+
+                writeln!(
+                    out,
+                    "    component glyph {}, {}{}",
+                    component.glyph_index, placement, transform
+                )?;
+            }
+
+            let length: usize = instructions.iter().map(|i| i.size()).sum();
+            writeln!(out, "    {} bytes of instructions", length)
+        }
+    }
+}
+
+/** On-curve points are written as `(x, y)`, off-curve points as `[x, y]`. */
+fn points(contour: &Contour) -> String {
+    contour
+        .points
+        .iter()
+        .map(|p| {
+            if p.is_on_curve {
+                format!("({}, {})", p.x, p.y)
+            } else {
+                format!("[{}, {}]", p.x, p.y)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{build, test::sample_font};
+
+    fn inspected(data: &[u8]) -> String {
+        let mut out = Vec::new();
+        inspect(data, &mut out).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn tables_are_described() {
+        let text = inspected(&build(&sample_font(2).into_file(), false).unwrap());
+
+        assert!(text.contains("'glyf'"));
+        assert!(!text.contains("NO"));
+        assert!(text.contains("created             2023-01-01 00:00:00 UTC"));
+        assert!(text.contains("    1  \"Sample\""));
+        assert!(text.contains("U+0041..U+0042  ->  glyph 1..2"));
+        assert!(text.contains("glyph 2  advance 40, left side bearing 0"));
+        assert!(text.contains("contour 0: (0, 0) (0, 11) (11, 11) (11, 0)"));
+    }
+
+    #[test]
+    fn ranges_past_the_last_glyph_id_are_marked() {
+        let mut data = build(&sample_font(2).into_file(), false).unwrap();
+
+        // The first glyph of the only group, after the cmap header, one encoding record and the subtable header
+        let (_, records) = read_directory(&data).unwrap();
+        let cmap = records.iter().find(|r| &r.tag == b"cmap").unwrap();
+        let start_index = cmap.offset as usize + 12 + 16 + 8;
+        data[start_index..start_index + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        assert!(inspected(&data).contains("U+0041..U+0042  ->  glyph 4294967295..  invalid range"));
+    }
+
+    #[test]
+    fn damaged_tables_are_marked() {
+        let mut data = build(&sample_font(2).into_file(), false).unwrap();
+
+        let (_, records) = read_directory(&data).unwrap();
+        let post = records.iter().find(|r| &r.tag == b"post").unwrap();
+        data[post.offset as usize + 31] ^= 0xFF;
+
+        assert!(inspected(&data)
+            .lines()
+            .any(|line| line.starts_with("'post'") && line.ends_with("NO")));
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, HashMap};
+
+use thiserror::Error;
+
+use super::{
+    reader::Font,
+    tables::{
+        Bounds, CMap, CharacterRange, Glyf, Glyph, Hmtx, HorizontalMetric, MaxP,
+        MAX_COMPONENT_DEPTH,
+    },
+    true_type::{ComponentPlacement, Contour, Point},
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    #[error("at least one font is required")]
+    NoFonts,
+    #[error("font {index} has no '{}' table, which merging requires", String::from_utf8_lossy(.tag))]
+    MissingTable { index: usize, tag: [u8; 4] },
+    #[error("font {index} has {units_per_em} units per em instead of {expected}")]
+    DifferentUnitsPerEm {
+        index: usize,
+        units_per_em: u16,
+        expected: u16,
+    },
+    #[error("glyph {glyph_id} of font {index} can not be merged, one of its components does not exist or they nest too deep")]
+    InvalidComponent { index: usize, glyph_id: u16 },
+    #[error("glyph {glyph_id} of font {index} has no horizontal metric")]
+    MissingMetric { index: usize, glyph_id: u16 },
+    #[error("the merged font would have {0} glyphs, at most 65535 are possible")]
+    TooManyGlyphs(usize),
+}
+
+/** Which font maps a character that several fonts map. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /** The font given first keeps its mapping. */
+    #[default]
+    First,
+    /** The font given last keeps its mapping. */
+    Last,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeOptions {
+    pub priority: Priority,
+    /** Scale the glyphs of fonts with other units per em instead of rejecting them. */
+    pub rescale: bool,
+}
+
+/**
+ * Combines the glyphs of all fonts into the first one. Glyphs that are identical after merging, including their
+ * metrics, are stored once. `head`, `hhea`, `OS/2`, `name` and `post` come from the first font, the glyph dependent
+ * values in them are recomputed. Raw tables are dropped, they may refer to glyph ids that no longer exist.
+ */
+pub fn merge(fonts: &[Font], options: MergeOptions) -> Result<Font, MergeError> {
+    let base = fonts.first().ok_or(MergeError::NoFonts)?;
+
+    let units_per_em = base
+        .head
+        .as_ref()
+        .ok_or(MergeError::MissingTable {
+            index: 0,
+            tag: *b"head",
+        })?
+        .units_per_em;
+
+    let mut merger = Merger::default();
+    let mut new_ids: Vec<BTreeMap<u16, u16>> = Vec::with_capacity(fonts.len());
+
+    for (index, font) in fonts.iter().enumerate() {
+        let source = Source::new(index, font, units_per_em, options.rescale)?;
+
+        let mut ids = BTreeMap::new();
+
+        // Only the first font contributes its .notdef
+        let first_glyph = if index == 0 { 0 } else { 1 };
+
+        for glyph_id in first_glyph..source.glyf.glyphs.len() as u16 {
+            merger.add(&source, glyph_id, &mut ids, 0)?;
+        }
+
+        ids.insert(0, 0);
+        new_ids.push(ids);
+    }
+
+    if merger.glyphs.len() > u16::MAX as usize {
+        return Err(MergeError::TooManyGlyphs(merger.glyphs.len()));
+    }
+
+    let mut mapping: BTreeMap<char, u32> = BTreeMap::new();
+
+    let mut by_priority: Vec<_> = fonts.iter().zip(new_ids.iter()).collect();
+    if options.priority == Priority::Last {
+        by_priority.reverse();
+    }
+
+    for (font, ids) in by_priority {
+        let Some(cmap) = font.cmap.as_ref() else {
+            continue;
+        };
+
+        for (char, glyph_id) in cmap.mappings() {
+            if let Some(new_id) = u16::try_from(glyph_id).ok().and_then(|id| ids.get(&id)) {
+                mapping.entry(char).or_insert(*new_id as u32);
+            }
+        }
+    }
+
+    let (glyphs, metrics): (Vec<_>, Vec<_>) = merger.glyphs.into_iter().unzip();
+
+    let glyf = Glyf { glyphs };
+    let hmtx = Hmtx::new_with_metrics(metrics);
+
+    let mut merged = base.clone();
+
+    merged.raw_tables.clear();
+
+    merged.cmap = Some(CMap::new_with_ranges(
+        mapping
+            .into_iter()
+            .map(|(char, glyph_id)| CharacterRange {
+                start: char,
+                end: char,
+                start_index: glyph_id,
+            })
+            .collect(),
+    ));
+
+    merged
+        .maxp
+        .get_or_insert_with(MaxP::default)
+        .update_limits(&glyf);
+
+    if let Some(hhea) = merged.hhea.as_mut() {
+        hhea.update_metrics(&glyf, &hmtx);
+    }
+
+    if let Some(head) = merged.head.as_mut() {
+        let outlines: Vec<Contour> = (0..glyf.glyphs.len() as u16)
+            .filter_map(|glyph_id| glyf.outline(glyph_id))
+            .flatten()
+            .collect();
+
+        let bounds = Bounds::of(&outlines);
+
+        head.min_x = bounds.x_min;
+        head.min_y = bounds.y_min;
+        head.max_x = bounds.x_max;
+        head.max_y = bounds.y_max;
+    }
+
+    if let Some(os2) = merged.os2.as_mut() {
+        let widths: Vec<u32> = (0..glyf.glyphs.len())
+            .filter_map(|glyph_id| hmtx.metric(glyph_id))
+            .map(|m| m.advance_width as u32)
+            .filter(|width| *width > 0)
+            .collect();
+
+        if !widths.is_empty() {
+            os2.avg_glyph_width = (widths.iter().sum::<u32>() / widths.len() as u32) as i16;
+        }
+    }
+
+    merged.loca = Some(glyf.loca());
+    merged.glyf = Some(glyf);
+    merged.hmtx = Some(hmtx);
+
+    Ok(merged)
+}
+
+/** The tables of one font taking part in the merge. */
+struct Source<'a> {
+    index: usize,
+    glyf: &'a Glyf,
+    hmtx: &'a Hmtx,
+    scale: Option<f32>,
+}
+
+impl<'a> Source<'a> {
+    fn new(
+        index: usize,
+        font: &'a Font,
+        units_per_em: u16,
+        rescale: bool,
+    ) -> Result<Self, MergeError> {
+        let missing = |tag: &[u8; 4]| MergeError::MissingTable { index, tag: *tag };
+
+        let head = font.head.as_ref().ok_or_else(|| missing(b"head"))?;
+
+        let scale = match head.units_per_em {
+            same if same == units_per_em => None,
+            other if rescale => Some(units_per_em as f32 / other as f32),
+            other => {
+                return Err(MergeError::DifferentUnitsPerEm {
+                    index,
+                    units_per_em: other,
+                    expected: units_per_em,
+                })
+            }
+        };
+
+        Ok(Self {
+            index,
+            glyf: font.glyf.as_ref().ok_or_else(|| missing(b"glyf"))?,
+            hmtx: font.hmtx.as_ref().ok_or_else(|| missing(b"hmtx"))?,
+            scale,
+        })
+    }
+
+    fn scaled(&self, value: i16) -> i16 {
+        match self.scale {
+            Some(scale) => (value as f32 * scale).round() as i16,
+            None => value,
+        }
+    }
+
+    fn metric(&self, glyph_id: u16) -> Result<HorizontalMetric, MergeError> {
+        let metric = self
+            .hmtx
+            .metric(glyph_id as usize)
+            .ok_or(MergeError::MissingMetric {
+                index: self.index,
+                glyph_id,
+            })?;
+
+        Ok(HorizontalMetric {
+            advance_width: match self.scale {
+                Some(scale) => (metric.advance_width as f32 * scale).round() as u16,
+                None => metric.advance_width,
+            },
+            left_side_bearing: self.scaled(metric.left_side_bearing),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Merger {
+    glyphs: Vec<(Glyph, HorizontalMetric)>,
+    known: HashMap<(Glyph, HorizontalMetric), u16>,
+}
+
+impl Merger {
+    /** Adds the glyph and its components, returning its id in the merged font. */
+    fn add(
+        &mut self,
+        source: &Source,
+        glyph_id: u16,
+        ids: &mut BTreeMap<u16, u16>,
+        depth: usize,
+    ) -> Result<u16, MergeError> {
+        if let Some(new_id) = ids.get(&glyph_id) {
+            return Ok(*new_id);
+        }
+
+        let invalid = MergeError::InvalidComponent {
+            index: source.index,
+            glyph_id,
+        };
+
+        if depth > MAX_COMPONENT_DEPTH {
+            return Err(invalid);
+        }
+
+        let glyph = match source.glyf.glyphs.get(glyph_id as usize).ok_or(invalid)? {
+            Glyph::Empty => Glyph::Empty,
+            Glyph::Simple {
+                contours,
+                instructions,
+            } => Glyph::Simple {
+                contours: contours
+                    .iter()
+                    .map(|contour| Contour {
+                        points: contour
+                            .points
+                            .iter()
+                            .map(|p| Point {
+                                is_on_curve: p.is_on_curve,
+                                x: source.scaled(p.x),
+                                y: source.scaled(p.y),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+                instructions: instructions.clone(),
+            },
+            Glyph::Composite {
+                components,
+                instructions,
+            } => {
+                let mut merged_components = Vec::with_capacity(components.len());
+
+                for component in components.iter() {
+                    let mut component = component.clone();
+
+                    component.glyph_index =
+                        self.add(source, component.glyph_index, ids, depth + 1)?;
+
+                    if let ComponentPlacement::Offset { x, y } = component.placement {
+                        component.placement = ComponentPlacement::Offset {
+                            x: source.scaled(x),
+                            y: source.scaled(y),
+                        };
+                    }
+
+                    merged_components.push(component);
+                }
+
+                Glyph::Composite {
+                    components: merged_components,
+                    instructions: instructions.clone(),
+                }
+            }
+        };
+
+        let key = (glyph, source.metric(glyph_id)?);
+
+        let new_id = match self.known.get(&key) {
+            Some(new_id) => *new_id,
+            None => {
+                // Ids beyond u16 are reported once all glyphs are known
+                let new_id = self.glyphs.len() as u16;
+
+                self.known.insert(key.clone(), new_id);
+                self.glyphs.push(key);
+
+                new_id
+            }
+        };
+
+        ids.insert(glyph_id, new_id);
+
+        Ok(new_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{build, open_type::reader::Font, test::sample_font};
+
+    fn shifted_cmap(font: &mut Font, start: char) {
+        let count = font.glyf.as_ref().unwrap().glyphs.len() as u32 - 1;
+
+        font.cmap = Some(CMap::new_with_ranges(vec![CharacterRange {
+            start,
+            end: char::from_u32(start as u32 + count - 1).unwrap(),
+            start_index: 1,
+        }]));
+    }
+
+    #[test]
+    fn glyphs_of_all_fonts_are_combined() {
+        let latin = sample_font(2);
+
+        let mut greek = sample_font(4);
+        shifted_cmap(&mut greek, 'α');
+
+        let merged = merge(&[latin, greek], MergeOptions::default()).unwrap();
+
+        // The first two glyphs of both fonts are identical and stored once
+        assert_eq!(merged.glyf.as_ref().unwrap().glyphs.len(), 5);
+        assert_eq!(merged.maxp.as_ref().unwrap().number_of_glyphs, 5);
+
+        let cmap = merged.cmap.as_ref().unwrap();
+        assert_eq!(cmap.glyph_id('A'), Some(1));
+        assert_eq!(cmap.glyph_id('α'), Some(1));
+        assert_eq!(cmap.glyph_id('δ'), Some(4));
+
+        let written = build(&merged.clone().into_file(), false).unwrap();
+        assert_eq!(
+            Font::read(&written).unwrap().differences(&merged),
+            Vec::<[u8; 4]>::new()
+        );
+    }
+
+    #[test]
+    fn conflicts_are_resolved_by_priority() {
+        let first = sample_font(1);
+        let mut second = sample_font(1);
+
+        second.glyf.as_mut().unwrap().glyphs[1] = Glyph::Empty;
+        second.loca = Some(second.glyf.as_ref().unwrap().loca());
+
+        let glyph_of_a = |priority| {
+            let options = MergeOptions {
+                priority,
+                ..MergeOptions::default()
+            };
+
+            let merged = merge(&[first.clone(), second.clone()], options).unwrap();
+            let glyph_id = merged.cmap.as_ref().unwrap().glyph_id('A').unwrap();
+
+            merged.glyf.unwrap().glyphs[glyph_id as usize].clone()
+        };
+
+        assert_ne!(glyph_of_a(Priority::First), Glyph::Empty);
+        assert_eq!(glyph_of_a(Priority::Last), Glyph::Empty);
+    }
+
+    #[test]
+    fn other_units_per_em_are_rescaled_on_request() {
+        let base = sample_font(1);
+
+        let mut large = sample_font(1);
+        large.head.as_mut().unwrap().units_per_em = 128;
+        shifted_cmap(&mut large, 'a');
+
+        assert_eq!(
+            merge(&[base.clone(), large.clone()], MergeOptions::default()),
+            Err(MergeError::DifferentUnitsPerEm {
+                index: 1,
+                units_per_em: 128,
+                expected: 64,
+            })
+        );
+
+        let options = MergeOptions {
+            rescale: true,
+            ..MergeOptions::default()
+        };
+        let merged = merge(&[base, large], options).unwrap();
+
+        let glyph_id = merged.cmap.as_ref().unwrap().glyph_id('a').unwrap();
+        let hmtx = merged.hmtx.as_ref().unwrap();
+
+        assert_eq!(hmtx.metric(glyph_id as usize).unwrap().advance_width, 20);
+        assert_eq!(
+            merged.glyf.unwrap().outline(glyph_id as u16).unwrap()[0].points[2],
+            Point::on_curve(5, 5)
+        );
+    }
+}
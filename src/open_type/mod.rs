@@ -1,9 +1,22 @@
+pub mod anchors;
+pub mod collection;
+pub mod dump;
 mod f2dot14;
+pub mod fea;
 mod file;
 mod fixed;
+pub mod inspect;
+pub mod merge;
+pub mod postscript;
+pub mod reader;
+pub mod round_trip;
 mod search;
+pub mod subroutinize;
+pub mod subset;
 pub mod tables;
 pub mod true_type;
+pub mod woff;
+pub mod woff2;
 
 pub use f2dot14::*;
 pub use file::*;
@@ -0,0 +1,762 @@
+use serde::{Deserialize, Serialize};
+
+use super::true_type::Contour;
+
+/** A piece of a [CubicContour], ending at `x`, `y`. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Segment {
+    Line {
+        x: i16,
+        y: i16,
+    },
+    /** A cubic Bézier curve with the control points `x1`, `y1` and `x2`, `y2`. */
+    Curve {
+        x1: i16,
+        y1: i16,
+        x2: i16,
+        y2: i16,
+        x: i16,
+        y: i16,
+    },
+}
+
+impl Segment {
+    pub fn end(&self) -> (i16, i16) {
+        match *self {
+            Segment::Line { x, y } | Segment::Curve { x, y, .. } => (x, y),
+        }
+    }
+
+    /** The control points, if any, and the end. */
+    pub fn points(&self) -> Vec<(i16, i16)> {
+        match *self {
+            Segment::Line { x, y } => vec![(x, y)],
+            Segment::Curve {
+                x1,
+                y1,
+                x2,
+                y2,
+                x,
+                y,
+            } => vec![(x1, y1), (x2, y2), (x, y)],
+        }
+    }
+}
+
+/** A closed outline made of lines and cubic curves, as CFF stores it. The last segment returns to the start. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CubicContour {
+    pub start_x: i16,
+    pub start_y: i16,
+    pub segments: Vec<Segment>,
+}
+
+impl CubicContour {
+    /**
+     * Converts a TrueType contour. Each quadratic curve becomes the cubic curve of the same shape, up to rounding of
+     * the control points. Off-curve points in a row get the on-curve point between them that TrueType implies.
+     * `None` for contours with less than two points.
+     */
+    pub fn from_quadratic(contour: &Contour) -> Option<Self> {
+        let points: Vec<_> = contour
+            .points
+            .iter()
+            .map(|p| (p.is_on_curve, (p.x, p.y)))
+            .collect();
+
+        if points.len() < 2 {
+            return None;
+        }
+
+        // Start on an on-curve point, or between the first two points if there is none
+        let first_on_curve = points.iter().position(|(on_curve, _)| *on_curve);
+        let start = match first_on_curve {
+            Some(index) => points[index].1,
+            None => midpoint(points[0].1, points[1].1),
+        };
+        let offset = first_on_curve.map_or(1, |index| index + 1);
+
+        let mut segments = Vec::with_capacity(points.len());
+        let mut current = start;
+        let mut control = None;
+
+        for step in 0..points.len() {
+            let (on_curve, position) = points[(offset + step) % points.len()];
+
+            match (on_curve, control) {
+                (true, None) => segments.push(Segment::Line {
+                    x: position.0,
+                    y: position.1,
+                }),
+                (true, Some(c)) => {
+                    segments.push(cubic(current, c, position));
+                    control = None;
+                }
+                (false, None) => {
+                    control = Some(position);
+                    continue;
+                }
+                (false, Some(c)) => {
+                    let implied = midpoint(c, position);
+                    segments.push(cubic(current, c, implied));
+                    control = Some(position);
+                    current = implied;
+                    continue;
+                }
+            }
+
+            current = position;
+        }
+
+        if let Some(c) = control {
+            segments.push(cubic(current, c, start));
+        }
+
+        Some(Self {
+            start_x: start.0,
+            start_y: start.1,
+            segments,
+        })
+    }
+
+    /** The start and the ends and control points of all segments. */
+    pub fn points(&self) -> impl Iterator<Item = (i16, i16)> + '_ {
+        std::iter::once((self.start_x, self.start_y))
+            .chain(self.segments.iter().flat_map(Segment::points))
+    }
+
+    /** Whether both contours consist of the same kinds of segments in the same order. */
+    pub fn has_structure_of(&self, other: &CubicContour) -> bool {
+        self.segments.len() == other.segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(other.segments.iter())
+                .all(|(a, b)| std::mem::discriminant(a) == std::mem::discriminant(b))
+    }
+}
+
+fn midpoint(a: (i16, i16), b: (i16, i16)) -> (i16, i16) {
+    (
+        ((a.0 as i32 + b.0 as i32) / 2) as i16,
+        ((a.1 as i32 + b.1 as i32) / 2) as i16,
+    )
+}
+
+/** The cubic curve of a quadratic one: its control points lie two thirds of the way to the quadratic one. */
+fn cubic(from: (i16, i16), control: (i16, i16), to: (i16, i16)) -> Segment {
+    let third = |end: i16, control: i16| {
+        (end as f32 + (control as f32 - end as f32) * 2.0 / 3.0).round() as i16
+    };
+
+    Segment::Curve {
+        x1: third(from.0, control.0),
+        y1: third(from.1, control.1),
+        x2: third(to.0, control.0),
+        y2: third(to.1, control.1),
+        x: to.0,
+        y: to.1,
+    }
+}
+
+/** Type 2 charstring operators. */
+pub(crate) mod operators {
+    pub const RLINETO: u8 = 5;
+    pub const RRCURVETO: u8 = 8;
+    pub const CALLSUBR: u8 = 10;
+    pub const RETURN: u8 = 11;
+    pub const ENDCHAR: u8 = 14;
+    pub const VSINDEX: u8 = 15;
+    pub const BLEND: u8 = 16;
+    pub const RMOVETO: u8 = 21;
+    pub const CALLGSUBR: u8 = 29;
+    /** Follows [ESCAPE](super::ESCAPE). */
+    pub const ADD: u8 = 10;
+}
+
+/** The CFF charstring interpreter keeps at most this many operands. */
+const MAX_OPERANDS: usize = 48;
+
+/** CFF2 raised the limit to make room for blended operands. */
+pub(crate) const MAX_CFF2_OPERANDS: usize = 513;
+
+/**
+ * Encodes the contours as a Type 2 charstring. `width` is the advance width minus `nominalWidthX` of the Private
+ * DICT, `None` if the advance width is `defaultWidthX`. Lines back to the start are left out as contours close
+ * themselves. Charstrings that are going to be subroutinized keep one operand free, as a call may push its
+ * subroutine number in the middle of any run of operands.
+ */
+pub fn charstring(contours: &[CubicContour], width: Option<i32>, subroutinize: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    let max_operands = match subroutinize {
+        true => MAX_OPERANDS - 1,
+        false => MAX_OPERANDS,
+    };
+
+    let width = write_operations(&mut out, &operations(contours, &[]), width, max_operands);
+
+    if let Some(width) = width {
+        write_charstring_number(&mut out, width);
+    }
+    out.push(operators::ENDCHAR);
+
+    out
+}
+
+/**
+ * Encodes the contours as a CFF2 charstring, which has neither a width nor `endchar`. `deltas` hold, for every
+ * region of the item variation data `vsindex` selects, contours of the same structure with the difference to the
+ * default outline. Operands that vary are written with `blend`.
+ */
+pub fn cff2_charstring(
+    contours: &[CubicContour],
+    vsindex: u16,
+    deltas: &[Vec<CubicContour>],
+) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    if vsindex != 0 {
+        write_charstring_number(&mut out, vsindex as i32);
+        out.push(operators::VSINDEX);
+    }
+
+    write_operations(
+        &mut out,
+        &operations(contours, deltas),
+        None,
+        MAX_CFF2_OPERANDS,
+    );
+
+    out
+}
+
+/** An operator with the operands of the default outline, followed by those of every region. */
+struct Operation {
+    operator: u8,
+    operands: Vec<Vec<i16>>,
+}
+
+fn operations(contours: &[CubicContour], deltas: &[Vec<CubicContour>]) -> Vec<Operation> {
+    use operators::*;
+
+    let masters: Vec<&[CubicContour]> = std::iter::once(contours)
+        .chain(deltas.iter().map(Vec::as_slice))
+        .collect();
+
+    // Coordinates are relative to the previous point and wrap like they do in `glyf`
+    let mut current = vec![(0i16, 0i16); masters.len()];
+    let mut operations = Vec::new();
+
+    for (index, contour) in contours.iter().enumerate() {
+        // The line back to the start can only be left out if it returns there in every region
+        let closes = masters.iter().all(|master| {
+            let contour = &master[index];
+
+            matches!(
+                contour.segments.last(),
+                Some(Segment::Line { x, y }) if (*x, *y) == (contour.start_x, contour.start_y)
+            )
+        });
+
+        for step in 0..=contour.segments.len() - closes as usize {
+            let operator = match step {
+                0 => RMOVETO,
+                _ => match contour.segments[step - 1] {
+                    Segment::Line { .. } => RLINETO,
+                    Segment::Curve { .. } => RRCURVETO,
+                },
+            };
+
+            let operands = masters
+                .iter()
+                .zip(current.iter_mut())
+                .map(|(master, current)| {
+                    let contour = &master[index];
+
+                    let points = match step {
+                        0 => vec![(contour.start_x, contour.start_y)],
+                        _ => contour.segments[step - 1].points(),
+                    };
+
+                    points
+                        .into_iter()
+                        .flat_map(|(x, y)| {
+                            let delta = [x.wrapping_sub(current.0), y.wrapping_sub(current.1)];
+                            *current = (x, y);
+                            delta
+                        })
+                        .collect()
+                })
+                .collect();
+
+            operations.push(Operation { operator, operands });
+        }
+    }
+
+    operations
+}
+
+/**
+ * Writes the operations, with lines and curves in a row joined as far as `max_operands` allows. Returns the width
+ * if there was no operation to put it in front of.
+ */
+fn write_operations(
+    out: &mut Vec<u8>,
+    operations: &[Operation],
+    mut width: Option<i32>,
+    max_operands: usize,
+) -> Option<i32> {
+    use operators::*;
+
+    let regions = operations.first().map_or(0, |o| o.operands.len() - 1);
+
+    // A blended value takes its default and one delta per region, the blend itself takes the count of values
+    let limit = match regions {
+        0 => max_operands,
+        _ => (max_operands - 1) / (regions + 1),
+    };
+
+    for run in operations.chunk_by(|a, b| a.operator == b.operator && a.operator != RMOVETO) {
+        let per_operation = run[0].operands[0].len();
+
+        for chunk in run.chunks((limit / per_operation).max(1)) {
+            // The width goes in front of the first operator that clears the stack
+            if let Some(width) = width.take() {
+                write_charstring_number(out, width);
+            }
+
+            for operand in chunk.iter().flat_map(|o| o.operands[0].iter()) {
+                write_charstring_number(out, *operand as i32);
+            }
+
+            let blended = chunk
+                .iter()
+                .flat_map(|o| o.operands[1..].iter().flatten())
+                .any(|delta| *delta != 0);
+
+            if blended {
+                for operation in chunk.iter() {
+                    for value in 0..per_operation {
+                        for deltas in operation.operands[1..].iter() {
+                            write_charstring_number(out, deltas[value] as i32);
+                        }
+                    }
+                }
+
+                write_charstring_number(out, (chunk.len() * per_operation) as i32);
+                out.push(BLEND);
+            }
+
+            out.push(run[0].operator);
+        }
+    }
+
+    width
+}
+
+/**
+ * The shortest encoding of an integer in a charstring. Integers take at most 16 bits, larger values are written as a
+ * sum with `add`, which only Type 2 charstrings have.
+ */
+pub(crate) fn write_charstring_number(out: &mut Vec<u8>, value: i32) {
+    match value {
+        -107..=107 => out.push((value + 139) as u8),
+        108..=1131 => {
+            let value = value - 108;
+            out.extend([(value >> 8) as u8 + 247, value as u8]);
+        }
+        -1131..=-108 => {
+            let value = -value - 108;
+            out.extend([(value >> 8) as u8 + 251, value as u8]);
+        }
+        -32768..=32767 => {
+            out.push(28);
+            out.extend((value as i16).to_be_bytes());
+        }
+        _ => {
+            let first = value.clamp(-32768, 32767);
+            write_charstring_number(out, first);
+
+            let mut rest = value - first;
+            while rest != 0 {
+                let part = rest.clamp(-32768, 32767);
+                write_charstring_number(out, part);
+                out.extend([ESCAPE, operators::ADD]);
+                rest -= part;
+            }
+        }
+    }
+}
+
+/** The shortest encoding of an integer operand in a DICT. */
+pub(crate) fn write_dict_integer(out: &mut Vec<u8>, value: i32) {
+    match value {
+        -107..=107 => out.push((value + 139) as u8),
+        108..=1131 => {
+            let value = value - 108;
+            out.extend([(value >> 8) as u8 + 247, value as u8]);
+        }
+        -1131..=-108 => {
+            let value = -value - 108;
+            out.extend([(value >> 8) as u8 + 251, value as u8]);
+        }
+        -32768..=32767 => {
+            out.push(28);
+            out.extend((value as i16).to_be_bytes());
+        }
+        _ => write_dict_offset(out, value),
+    }
+}
+
+/** Always takes five bytes, so offsets can be written before the data they point to is placed. */
+pub(crate) fn write_dict_offset(out: &mut Vec<u8>, value: i32) {
+    out.push(29);
+    out.extend(value.to_be_bytes());
+}
+
+/** Real numbers are written as their decimal digits, two to a byte. */
+pub(crate) fn write_dict_real(out: &mut Vec<u8>, value: f64) {
+    let mut nibbles: Vec<u8> = format!("{}", value)
+        .bytes()
+        .map(|c| match c {
+            b'0'..=b'9' => c - b'0',
+            b'.' => 0xA,
+            b'-' => 0xE,
+            _ => unreachable!("floats are formatted without exponent"),
+        })
+        .collect();
+
+    nibbles.push(0xF);
+    if nibbles.len() % 2 == 1 {
+        nibbles.push(0xF);
+    }
+
+    out.push(30);
+    out.extend(nibbles.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+}
+
+/** The FontMatrix of a Top DICT, left out for 1000 units per em as that is the default. */
+pub(crate) fn write_font_matrix(dict: &mut Vec<u8>, units_per_em: u16) {
+    if units_per_em == 1000 {
+        return;
+    }
+
+    let scale = 1.0 / units_per_em as f64;
+
+    for value in [scale, 0.0, 0.0, scale, 0.0, 0.0] {
+        write_dict_real(dict, value);
+    }
+    dict.extend([ESCAPE, 7]); // FontMatrix
+}
+
+/** Operators of two bytes start with this escape. */
+pub(crate) const ESCAPE: u8 = 12;
+
+/** An INDEX: the number of items, the size of the offsets and the 1 based offsets, followed by the items. */
+pub(crate) fn index(items: &[Vec<u8>]) -> Vec<u8> {
+    write_index((items.len() as u16).to_be_bytes().to_vec(), items)
+}
+
+/** CFF2 counts the items of an INDEX with 32 bits. */
+pub(crate) fn cff2_index(items: &[Vec<u8>]) -> Vec<u8> {
+    write_index((items.len() as u32).to_be_bytes().to_vec(), items)
+}
+
+fn write_index(count: Vec<u8>, items: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = count;
+
+    if items.is_empty() {
+        return out;
+    }
+
+    let end = 1 + items.iter().map(Vec::len).sum::<usize>();
+    let offset_size = match end {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        0x10000..=0xFFFFFF => 3,
+        _ => 4,
+    };
+
+    out.push(offset_size as u8);
+
+    let mut offset = 1;
+    for item in std::iter::once(&Vec::new()).chain(items.iter()) {
+        offset += item.len();
+        out.extend(&(offset as u32).to_be_bytes()[4 - offset_size..]);
+    }
+
+    for item in items.iter() {
+        out.extend(item);
+    }
+
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{operators::*, *};
+    use crate::open_type::true_type::Point;
+
+    /**
+     * The items of the INDEX at the start of `data` and the data after it. The count takes `count_size` bytes, 2 in
+     * CFF and 4 in CFF2.
+     */
+    pub(crate) fn read_index(data: &[u8], count_size: usize) -> (Vec<&[u8]>, &[u8]) {
+        let count = data[..count_size]
+            .iter()
+            .fold(0, |value, byte| value << 8 | *byte as usize);
+        if count == 0 {
+            return (Vec::new(), &data[count_size..]);
+        }
+
+        let offset_size = data[count_size] as usize;
+        let offset = |n: usize| {
+            let start = count_size + 1 + n * offset_size;
+            data[start..start + offset_size]
+                .iter()
+                .fold(0, |value, byte| value << 8 | *byte as usize)
+        };
+
+        // Offsets are 1 based
+        let base = count_size + (count + 1) * offset_size;
+        let items = (0..count)
+            .map(|n| &data[base + offset(n)..base + offset(n + 1)])
+            .collect();
+
+        (items, &data[base + offset(count)..])
+    }
+
+    /** The operands of the operator in a DICT, only integers are supported. */
+    pub(crate) fn dict_operands(mut dict: &[u8], operator: u8) -> Vec<i32> {
+        let mut operands = Vec::new();
+
+        while let Some((&byte, rest)) = dict.split_first() {
+            dict = rest;
+
+            match byte {
+                32..=246 => operands.push(byte as i32 - 139),
+                247..=254 => {
+                    let value = (byte as i32 - 247) % 4 * 256 + dict[0] as i32 + 108;
+                    operands.push(if byte < 251 { value } else { -value });
+                    dict = &dict[1..];
+                }
+                28 => {
+                    operands.push(i16::from_be_bytes([dict[0], dict[1]]) as i32);
+                    dict = &dict[2..];
+                }
+                29 => {
+                    operands.push(i32::from_be_bytes(dict[..4].try_into().unwrap()));
+                    dict = &dict[4..];
+                }
+                30 => {
+                    let end = dict.iter().position(|b| b & 0xF == 0xF).unwrap();
+                    dict = &dict[end + 1..];
+                    operands.push(0);
+                }
+                _ if byte == operator => return operands,
+                ESCAPE => {
+                    dict = &dict[1..];
+                    operands.clear();
+                }
+                _ => operands.clear(),
+            }
+        }
+
+        panic!("operator {} not found", operator);
+    }
+
+    /**
+     * The width operand, if present, and the contours of a charstring without hints or subroutines. Blended
+     * operands are evaluated with the `scalars` of the regions.
+     */
+    pub(crate) fn decode(
+        mut charstring: &[u8],
+        scalars: &[f32],
+    ) -> (Option<i32>, Vec<CubicContour>) {
+        let mut stack: Vec<i32> = Vec::new();
+        let mut width = None;
+        let mut first_operator = true;
+        let mut contours: Vec<CubicContour> = Vec::new();
+        let (mut x, mut y) = (0i16, 0i16);
+
+        while let Some((&byte, rest)) = charstring.split_first() {
+            charstring = rest;
+
+            let operator = match byte {
+                32..=246 => {
+                    stack.push(byte as i32 - 139);
+                    continue;
+                }
+                247..=250 => {
+                    stack.push((byte as i32 - 247) * 256 + charstring[0] as i32 + 108);
+                    charstring = &charstring[1..];
+                    continue;
+                }
+                251..=254 => {
+                    stack.push(-(byte as i32 - 251) * 256 - charstring[0] as i32 - 108);
+                    charstring = &charstring[1..];
+                    continue;
+                }
+                28 => {
+                    stack.push(i16::from_be_bytes([charstring[0], charstring[1]]) as i32);
+                    charstring = &charstring[2..];
+                    continue;
+                }
+                operator => operator,
+            };
+
+            // Neither clears the stack, so they can not carry the width
+            match operator {
+                BLEND => {
+                    let count = stack.pop().unwrap() as usize;
+                    let deltas = stack.split_off(stack.len() - count * scalars.len());
+
+                    let first = stack.len() - count;
+                    for (value, deltas) in
+                        stack[first..].iter_mut().zip(deltas.chunks(scalars.len()))
+                    {
+                        let delta: f32 =
+                            deltas.iter().zip(scalars).map(|(d, s)| *d as f32 * s).sum();
+                        *value += delta.round() as i32;
+                    }
+
+                    continue;
+                }
+                VSINDEX => {
+                    stack.clear();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let expected = match operator {
+                RMOVETO => 2,
+                ENDCHAR => 0,
+                _ => stack.len(),
+            };
+            if first_operator && stack.len() > expected {
+                width = Some(stack.remove(0));
+            }
+            first_operator = false;
+
+            let mut moves = stack.chunks(2).map(|d| {
+                x = x.wrapping_add(d[0] as i16);
+                y = y.wrapping_add(d[1] as i16);
+                (x, y)
+            });
+
+            match operator {
+                RMOVETO => {
+                    let (start_x, start_y) = moves.next().unwrap();
+                    contours.push(CubicContour {
+                        start_x,
+                        start_y,
+                        segments: Vec::new(),
+                    });
+                }
+                RLINETO => {
+                    let lines = moves
+                        .map(|(x, y)| Segment::Line { x, y })
+                        .collect::<Vec<_>>();
+                    contours.last_mut().unwrap().segments.extend(lines);
+                }
+                RRCURVETO => {
+                    let points = moves.collect::<Vec<_>>();
+                    let curves = points.chunks(3).map(|p| Segment::Curve {
+                        x1: p[0].0,
+                        y1: p[0].1,
+                        x2: p[1].0,
+                        y2: p[1].1,
+                        x: p[2].0,
+                        y: p[2].1,
+                    });
+                    contours.last_mut().unwrap().segments.extend(curves);
+                }
+                ENDCHAR => break,
+                operator => panic!("unexpected operator {}", operator),
+            }
+
+            stack.clear();
+        }
+
+        (width, contours)
+    }
+
+    #[test]
+    fn quadratic_curves_become_cubic_ones() {
+        let contour = Contour {
+            points: vec![
+                Point::off_curve(0, 30),
+                Point::on_curve(0, 0),
+                Point::on_curve(30, 0),
+                Point::off_curve(30, 30),
+            ],
+        };
+
+        let cubic = CubicContour::from_quadratic(&contour).unwrap();
+
+        assert_eq!((cubic.start_x, cubic.start_y), (0, 0));
+        assert_eq!(
+            cubic.segments,
+            vec![
+                Segment::Line { x: 30, y: 0 },
+                // The implied point between both off-curve points
+                Segment::Curve {
+                    x1: 30,
+                    y1: 20,
+                    x2: 25,
+                    y2: 30,
+                    x: 15,
+                    y: 30,
+                },
+                Segment::Curve {
+                    x1: 5,
+                    y1: 30,
+                    x2: 0,
+                    y2: 20,
+                    x: 0,
+                    y: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_take_the_shortest_form() {
+        let encoded = |value| {
+            let mut out = Vec::new();
+            write_charstring_number(&mut out, value);
+            out
+        };
+
+        assert_eq!(encoded(0), vec![139]);
+        assert_eq!(encoded(-107), vec![32]);
+        assert_eq!(encoded(108), vec![247, 0]);
+        assert_eq!(encoded(1131), vec![250, 255]);
+        assert_eq!(encoded(-1131), vec![254, 255]);
+        assert_eq!(encoded(1132), vec![28, 0x04, 0x6C]);
+        assert_eq!(
+            encoded(40000),
+            vec![28, 0x7F, 0xFF, 28, 0x1C, 0x41, ESCAPE, ADD]
+        );
+        assert_eq!(
+            encoded(-65536),
+            vec![28, 0x80, 0x00, 28, 0x80, 0x00, ESCAPE, ADD]
+        );
+
+        let mut real = Vec::new();
+        write_dict_real(&mut real, -0.015625);
+        assert_eq!(real, vec![30, 0xE0, 0xA0, 0x15, 0x62, 0x5F]);
+    }
+
+    #[test]
+    fn indexes_use_the_smallest_offsets() {
+        assert_eq!(index(&[]), vec![0, 0]);
+        assert_eq!(
+            index(&[vec![1, 2], vec![3]]),
+            vec![0, 2, 1, 1, 3, 4, 1, 2, 3]
+        );
+        assert_eq!(index(&[vec![0; 300]])[2..7], [2, 0, 1, 1, 45]);
+    }
+}
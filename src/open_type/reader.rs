@@ -0,0 +1,435 @@
+use serde::{Deserialize, Serialize};
+
+use thiserror::Error;
+
+use super::{
+    file::File,
+    tables::{CMap, Glyf, HHead, Head, Hmtx, Loca, MaxP, Name, Post, RawTable, OS2},
+    LayoutableTable,
+};
+
+#[derive(Error, Debug)]
+pub enum ReadError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("the data ends before table '{}' is complete", String::from_utf8_lossy(.tag))]
+    Truncated { tag: [u8; 4] },
+    #[error("the data is not an OpenType font, the sfnt version is {0:#010x}")]
+    UnknownSfntVersion(u32),
+    #[error("table '{}' lies outside of the file", String::from_utf8_lossy(.tag))]
+    OutOfBounds { tag: [u8; 4] },
+    #[error("table '{}' is required to read '{}'", String::from_utf8_lossy(.tag), String::from_utf8_lossy(.required_by))]
+    MissingTable { tag: [u8; 4], required_by: [u8; 4] },
+    #[error("table '{}' has the unsupported version {version:#x}", String::from_utf8_lossy(.tag))]
+    UnsupportedVersion { tag: [u8; 4], version: u32 },
+    #[error("invalid value for '{field}' in table '{}': {reason}", String::from_utf8_lossy(.tag))]
+    InvalidValue {
+        tag: [u8; 4],
+        field: &'static str,
+        reason: String,
+    },
+    #[error("glyph {glyph_id} can not be read: {source}")]
+    GlyphError {
+        glyph_id: usize,
+        #[source]
+        source: Box<ReadError>,
+    },
+}
+
+impl ReadError {
+    pub fn invalid_value(tag: [u8; 4], field: &'static str, reason: impl Into<String>) -> Self {
+        Self::InvalidValue {
+            tag,
+            field,
+            reason: reason.into(),
+        }
+    }
+
+    /** Turns running out of data while reading the table with the given tag into [ReadError::Truncated]. */
+    pub fn with_table(self, tag: [u8; 4]) -> Self {
+        match self {
+            Self::IoError(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Self::Truncated { tag }
+            }
+            other => other,
+        }
+    }
+}
+
+/** An entry of the table directory. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableRecord {
+    pub tag: [u8; 4],
+    pub checksum: u32,
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl TableRecord {
+    /** The bytes of the table inside the whole file. */
+    pub fn data<'a>(&self, file: &'a [u8]) -> Result<&'a [u8], ReadError> {
+        let start = self.offset as usize;
+        let end = start + self.length as usize;
+
+        file.get(start..end)
+            .ok_or(ReadError::OutOfBounds { tag: self.tag })
+    }
+
+    /**
+     * Whether the stored checksum matches the table data. For `head` the checksum adjustment is left out, as it
+     * is written after the checksum is taken.
+     */
+    pub fn has_valid_checksum(&self, file: &[u8]) -> Result<bool, ReadError> {
+        let data = self.data(file)?;
+
+        let mut sum = checksum(data);
+
+        if &self.tag == b"head" {
+            if let Some(adjustment) = data.get(8..12) {
+                sum = sum.wrapping_sub(checksum(adjustment));
+            }
+        }
+
+        Ok(sum == self.checksum)
+    }
+}
+
+/** The sum of all big endian `u32`s in the data, the last one padded with zeros. */
+pub fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+pub const TRUE_TYPE_VERSION: u32 = 0x00010000;
+pub const CFF_VERSION: u32 = u32::from_be_bytes(*b"OTTO");
+/** The tag a TrueType collection starts with instead of an sfnt version. */
+pub const COLLECTION_TAG: u32 = u32::from_be_bytes(*b"ttcf");
+/** Used by old Apple TrueType fonts. */
+pub const APPLE_TRUE_TYPE_VERSION: u32 = u32::from_be_bytes(*b"true");
+
+/** Reads the sfnt version and table directory at the start of a font file. */
+pub fn read_directory(data: &[u8]) -> Result<(u32, Vec<TableRecord>), ReadError> {
+    use byteorder::{ReadBytesExt, BE};
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    let mut reader = Cursor::new(data);
+
+    let sfnt_version = reader.read_u32::<BE>()?;
+
+    if ![TRUE_TYPE_VERSION, CFF_VERSION, APPLE_TRUE_TYPE_VERSION].contains(&sfnt_version) {
+        return Err(ReadError::UnknownSfntVersion(sfnt_version));
+    }
+
+    let number_of_tables = reader.read_u16::<BE>()?;
+
+    // searchRange, entrySelector and rangeShift follow from the number of tables
+    reader.seek(SeekFrom::Current(6))?;
+
+    let records = (0..number_of_tables)
+        .map(|_| {
+            let mut tag = [0; 4];
+            std::io::Read::read_exact(&mut reader, &mut tag)?;
+
+            Ok(TableRecord {
+                tag,
+                checksum: reader.read_u32::<BE>()?,
+                offset: reader.read_u32::<BE>()?,
+                length: reader.read_u32::<BE>()?,
+            })
+        })
+        .collect::<std::io::Result<_>>()?;
+
+    Ok((sfnt_version, records))
+}
+
+/**
+ * A font read from a file. Tables this crate understands are parsed into their structs, all others are kept as
+ * they are in [Font::raw_tables].
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Font {
+    pub sfnt_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head: Option<Head>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hhea: Option<HHead>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maxp: Option<MaxP>,
+    #[serde(rename = "OS/2", skip_serializing_if = "Option::is_none")]
+    pub os2: Option<OS2>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hmtx: Option<Hmtx>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmap: Option<CMap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loca: Option<Loca>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glyf: Option<Glyf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<Name>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post: Option<Post>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub raw_tables: Vec<RawTable>,
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Self {
+            sfnt_version: TRUE_TYPE_VERSION,
+            head: None,
+            hhea: None,
+            maxp: None,
+            os2: None,
+            hmtx: None,
+            cmap: None,
+            loca: None,
+            glyf: None,
+            name: None,
+            post: None,
+            raw_tables: Vec::new(),
+        }
+    }
+}
+
+impl Font {
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        Self::read_at(data, 0)
+    }
+
+    /** Reads every font of a TrueType collection. */
+    pub fn read_collection(data: &[u8]) -> Result<Vec<Self>, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data;
+
+        let tag = reader.read_u32::<BE>()?;
+        if tag != COLLECTION_TAG {
+            return Err(ReadError::UnknownSfntVersion(tag));
+        }
+
+        let major_version = reader.read_u16::<BE>()?;
+        if major_version > 2 {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"ttcf",
+                version: major_version as u32,
+            });
+        }
+        reader.read_u16::<BE>()?; // minor version
+
+        let number_of_fonts = reader.read_u32::<BE>()?;
+
+        (0..number_of_fonts)
+            .map(|_| Self::read_at(data, reader.read_u32::<BE>()? as usize))
+            .collect()
+    }
+
+    /** Reads the font whose table directory starts at `offset`. Table offsets are relative to the whole data. */
+    fn read_at(data: &[u8], offset: usize) -> Result<Self, ReadError> {
+        let directory = data
+            .get(offset..)
+            .ok_or(ReadError::OutOfBounds { tag: *b"ttcf" })?;
+        let (sfnt_version, records) = read_directory(directory)?;
+
+        let table = |tag: &[u8; 4]| -> Result<Option<&[u8]>, ReadError> {
+            records
+                .iter()
+                .find(|r| &r.tag == tag)
+                .map(|r| r.data(data))
+                .transpose()
+        };
+
+        fn parse<T>(
+            tag: &[u8; 4],
+            data: Option<&[u8]>,
+            read: impl Fn(&[u8]) -> Result<T, ReadError>,
+        ) -> Result<Option<T>, ReadError> {
+            data.map(|data| read(data).map_err(|e| e.with_table(*tag)))
+                .transpose()
+        }
+
+        /** Tables like `hmtx` can only be read with values from other tables. */
+        fn dependency<'a, T>(
+            table: Option<&'a T>,
+            tag: &[u8; 4],
+            required_by: &[u8; 4],
+        ) -> Result<&'a T, ReadError> {
+            table.ok_or(ReadError::MissingTable {
+                tag: *tag,
+                required_by: *required_by,
+            })
+        }
+
+        let head = parse(b"head", table(b"head")?, Head::read)?;
+        let hhea = parse(b"hhea", table(b"hhea")?, HHead::read)?;
+        let maxp = parse(b"maxp", table(b"maxp")?, MaxP::read)?;
+
+        let hmtx = parse(b"hmtx", table(b"hmtx")?, |data| {
+            Hmtx::read(
+                data,
+                dependency(hhea.as_ref(), b"hhea", b"hmtx")?.number_of_hmetrics,
+                dependency(maxp.as_ref(), b"maxp", b"hmtx")?.number_of_glyphs,
+            )
+        })?;
+
+        let loca = parse(b"loca", table(b"loca")?, |data| {
+            let head = table(b"head")?;
+            let head = dependency(head.as_ref(), b"head", b"loca")?;
+
+            Loca::read(
+                data,
+                Head::read_index_to_loc_format(head).map_err(|e| e.with_table(*b"head"))?,
+                dependency(maxp.as_ref(), b"maxp", b"loca")?.number_of_glyphs,
+            )
+        })?;
+
+        let glyf = parse(b"glyf", table(b"glyf")?, |data| {
+            Glyf::read(data, dependency(loca.as_ref(), b"loca", b"glyf")?)
+        })?;
+
+        let known = [
+            *b"head", *b"hhea", *b"maxp", *b"OS/2", *b"hmtx", *b"cmap", *b"loca", *b"glyf",
+            *b"name", *b"post",
+        ];
+
+        let raw_tables = records
+            .iter()
+            .filter(|r| !known.contains(&r.tag))
+            .map(|r| {
+                Ok(RawTable {
+                    tag: r.tag,
+                    data: r.data(data)?.to_vec(),
+                })
+            })
+            .collect::<Result<_, ReadError>>()?;
+
+        Ok(Self {
+            sfnt_version,
+            head,
+            hhea,
+            maxp,
+            os2: parse(b"OS/2", table(b"OS/2")?, OS2::read)?,
+            hmtx,
+            cmap: parse(b"cmap", table(b"cmap")?, CMap::read)?,
+            loca,
+            glyf,
+            name: parse(b"name", table(b"name")?, Name::read)?,
+            post: parse(b"post", table(b"post")?, Post::read)?,
+            raw_tables,
+        })
+    }
+
+    /**
+     * The parsed tables in the order the OpenType specification recommends for TrueType fonts, ready to be
+     * written again. [Font::raw_tables] follow in the order they were read.
+     */
+    pub fn into_file(self) -> File {
+        let mut tables: Vec<Box<dyn LayoutableTable>> = Vec::new();
+
+        fn push<T: LayoutableTable + 'static>(
+            tables: &mut Vec<Box<dyn LayoutableTable>>,
+            table: Option<T>,
+        ) {
+            if let Some(table) = table {
+                tables.push(Box::new(table));
+            }
+        }
+
+        push(&mut tables, self.head);
+        push(&mut tables, self.hhea);
+        push(&mut tables, self.maxp);
+        push(&mut tables, self.os2);
+        push(&mut tables, self.hmtx);
+        push(&mut tables, self.cmap);
+        push(&mut tables, self.loca);
+        push(&mut tables, self.glyf);
+        push(&mut tables, self.name);
+        push(&mut tables, self.post);
+
+        for table in self.raw_tables {
+            tables.push(Box::new(table));
+        }
+
+        File::new_with_tables(tables)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        build,
+        open_type::tables::{CharacterRange, Glyph, NameRecord},
+        test::sample_font,
+    };
+
+    fn sample_bytes(glyph_count: u16) -> Vec<u8> {
+        build(&sample_font(glyph_count).into_file(), false).expect("the sample font can be built")
+    }
+
+    #[test]
+    fn written_fonts_can_be_read() -> Result<(), ReadError> {
+        let font = Font::read(&sample_bytes(3))?;
+
+        assert_eq!(font.sfnt_version, TRUE_TYPE_VERSION);
+        assert_eq!(font.maxp.map(|m| m.number_of_glyphs), Some(4));
+        assert_eq!(font.hmtx.map(|h| h.left_side_bearings.len()), Some(3));
+        assert_eq!(font.head.map(|h| h.units_per_em), Some(64));
+
+        let glyf = font.glyf.expect("glyf is read");
+        assert_eq!(glyf.glyphs.len(), 4);
+        assert_eq!(glyf.glyphs[0], Glyph::Empty);
+
+        let cmap = font.cmap.expect("cmap is read");
+        assert_eq!(
+            cmap.ranges(),
+            [CharacterRange {
+                start: 'A',
+                end: 'C',
+                start_index: 1,
+            }]
+        );
+
+        assert_eq!(
+            font.name.expect("name is read").names,
+            [NameRecord {
+                name_id: 1,
+                content: String::from("Sample"),
+            }]
+        );
+
+        assert!(font.raw_tables.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_data_is_rejected() {
+        assert!(matches!(
+            Font::read(b"%PDF-1.7 and more"),
+            Err(ReadError::UnknownSfntVersion(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_tables_are_reported() {
+        let mut data = sample_bytes(3);
+
+        let (_, records) = read_directory(&data).unwrap();
+        let index = records.iter().position(|r| &r.tag == b"hhea").unwrap();
+
+        // Let the table end after its version, the length is the last field of the record
+        let length_offset = 12 + 16 * index + 12;
+        data[length_offset..][..4].copy_from_slice(&4u32.to_be_bytes());
+
+        assert!(matches!(
+            Font::read(&data),
+            Err(ReadError::Truncated { tag }) if &tag == b"hhea"
+        ));
+    }
+}
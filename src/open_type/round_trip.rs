@@ -0,0 +1,415 @@
+use thiserror::Error;
+
+use super::reader::{Font, ReadError};
+use crate::{build, LayoutError};
+
+#[derive(Error, Debug)]
+pub enum RoundTripError {
+    #[error(transparent)]
+    ReadError(#[from] ReadError),
+    #[error(transparent)]
+    LayoutError(#[from] LayoutError),
+    #[error("the tables {} changed when the font was written again", display_tags(.tags))]
+    Mismatch { tags: Vec<[u8; 4]> },
+}
+
+fn display_tags(tags: &[[u8; 4]]) -> String {
+    tags.iter()
+        .map(|tag| format!("'{}'", String::from_utf8_lossy(tag)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/**
+ * Reads the font, writes it again and checks that the written font reads back to the same tables. Returns the
+ * written font.
+ */
+pub fn round_trip(data: &[u8]) -> Result<Vec<u8>, RoundTripError> {
+    let font = Font::read(data)?;
+
+    let written = build(&font.clone().into_file(), false)?;
+
+    let tags = font.differences(&Font::read(&written)?);
+
+    if tags.is_empty() {
+        Ok(written)
+    } else {
+        Err(RoundTripError::Mismatch { tags })
+    }
+}
+
+impl Font {
+    /** The tags of all tables that differ between both fonts, including tables only one of them has. */
+    pub fn differences(&self, other: &Font) -> Vec<[u8; 4]> {
+        fn compare<T: PartialEq>(tags: &mut Vec<[u8; 4]>, tag: &[u8; 4], a: &T, b: &T) {
+            if a != b {
+                tags.push(*tag);
+            }
+        }
+
+        let mut tags = Vec::new();
+
+        compare(&mut tags, b"head", &self.head, &other.head);
+        compare(&mut tags, b"hhea", &self.hhea, &other.hhea);
+        compare(&mut tags, b"maxp", &self.maxp, &other.maxp);
+        compare(&mut tags, b"OS/2", &self.os2, &other.os2);
+        compare(&mut tags, b"hmtx", &self.hmtx, &other.hmtx);
+        compare(&mut tags, b"cmap", &self.cmap, &other.cmap);
+        compare(&mut tags, b"loca", &self.loca, &other.loca);
+        compare(&mut tags, b"glyf", &self.glyf, &other.glyf);
+        compare(&mut tags, b"name", &self.name, &other.name);
+        compare(&mut tags, b"post", &self.post, &other.post);
+
+        fn raw(font: &Font, tag: [u8; 4]) -> Option<&Vec<u8>> {
+            font.raw_tables
+                .iter()
+                .find(|t| t.tag == tag)
+                .map(|t| &t.data)
+        }
+
+        for table in self.raw_tables.iter().chain(other.raw_tables.iter()) {
+            if !tags.contains(&table.tag) && raw(self, table.tag) != raw(other, table.tag) {
+                tags.push(table.tag);
+            }
+        }
+
+        tags
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::{
+        open_type::{
+            tables::*,
+            true_type::{
+                Component, ComponentPlacement, ComponentTransform, Contour, Instrution, Point,
+            },
+            F2Dot14, Fixed,
+        },
+        test::sample_font,
+    };
+
+    fn square(size: i16) -> Glyph {
+        Glyph::Simple {
+            contours: vec![Contour {
+                points: vec![
+                    Point::on_curve(0, 0),
+                    Point::on_curve(0, size),
+                    Point::on_curve(size, size),
+                    Point::on_curve(size, 0),
+                ],
+            }],
+            instructions: vec![],
+        }
+    }
+
+    fn with_glyphs(mut font: Font, glyphs: Vec<Glyph>) -> Font {
+        let count = glyphs.len() as u16;
+        let glyf = Glyf { glyphs };
+
+        font.maxp.as_mut().unwrap().update_limits(&glyf);
+        font.loca = Some(glyf.loca());
+        font.glyf = Some(glyf);
+        font.hmtx = Some(Hmtx {
+            horizontal_metrics: vec![HorizontalMetric {
+                advance_width: 40,
+                left_side_bearing: 0,
+            }],
+            left_side_bearings: vec![0; count as usize - 1],
+        });
+        font.cmap = Some(CMap::new_with_ranges(vec![CharacterRange {
+            start: 'a',
+            end: char::from_u32('a' as u32 + count as u32 - 2).unwrap(),
+            start_index: 1,
+        }]));
+
+        font
+    }
+
+    fn outlines() -> Font {
+        with_glyphs(
+            sample_font(1),
+            vec![
+                Glyph::Empty,
+                square(20),
+                Glyph::Simple {
+                    contours: vec![
+                        Contour {
+                            points: vec![
+                                Point::on_curve(0, 0),
+                                Point::off_curve(-300, 400),
+                                Point::on_curve(0, 800),
+                                Point::off_curve(300, 400),
+                            ],
+                        },
+                        Contour {
+                            points: vec![
+                                Point::on_curve(-10, 10),
+                                Point::on_curve(10, 10),
+                                Point::on_curve(0, 700),
+                            ],
+                        },
+                    ],
+                    instructions: vec![
+                        Instrution::PushBytes(Box::new([1, 2, 3])),
+                        Instrution::Raw(Box::new([0x2B])),
+                    ],
+                },
+                Glyph::Composite {
+                    components: vec![
+                        Component {
+                            glyph_index: 1,
+                            placement: ComponentPlacement::Offset { x: -500, y: 3 },
+                            transform: ComponentTransform::Identity,
+                            round_xy_to_grid: true,
+                            use_my_metrics: true,
+                            overlap_compound: false,
+                        },
+                        Component {
+                            glyph_index: 2,
+                            placement: ComponentPlacement::Offset { x: 5, y: 5 },
+                            transform: ComponentTransform::Matrix {
+                                xx: F2Dot14::from_bits(0x4000),
+                                xy: F2Dot14::from_bits(0x1000),
+                                yx: F2Dot14::from_bits(-0x1000),
+                                yy: F2Dot14::from_bits(0x2000),
+                            },
+                            round_xy_to_grid: false,
+                            use_my_metrics: false,
+                            overlap_compound: true,
+                        },
+                    ],
+                    instructions: vec![],
+                },
+            ],
+        )
+    }
+
+    fn names_and_metrics() -> Font {
+        let mut font = sample_font(3);
+
+        font.head = Some(Head {
+            created: Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap(),
+            modified: Utc.with_ymd_and_hms(2038, 1, 19, 3, 14, 8).unwrap(),
+            revision: Fixed {
+                major: 2,
+                minor: 0x8000,
+            },
+            flags: Flags {
+                baseline: true,
+                sidebearing: true,
+                lossless: true,
+                ..Flags::default()
+            },
+            min_x: -20,
+            min_y: -300,
+            max_x: 1200,
+            max_y: 900,
+            units_per_em: 1000,
+            smalest_recocnizeable_size: 9,
+        });
+        font.name = Some(Name {
+            names: vec![
+                NameRecord {
+                    name_id: 1,
+                    content: String::from("Überschrift"),
+                },
+                NameRecord {
+                    name_id: 2,
+                    content: String::from("Kursiv"),
+                },
+                NameRecord {
+                    name_id: 4,
+                    content: String::from("Überschrift Kursiv 𝄞"),
+                },
+            ],
+        });
+        font.post = Some(Post {
+            italic_angle: Fixed {
+                major: -12,
+                minor: 0x4000,
+            },
+            underline_position: -75,
+            underline_thickness: 50,
+            is_fixed_pitch: false,
+            min_mem_type42: 1,
+            max_mem_type42: 2,
+            min_mem_type1: 3,
+            max_mem_type1: 4,
+        });
+        font.hhea = Some(HHead {
+            ascender: 900,
+            descender: -300,
+            line_gap: 90,
+            advance_width_max: 1200,
+            min_left_side_bearing: -20,
+            min_right_side_bearing: -5,
+            x_max_extent: 1200,
+            caret_slope_rise: 5,
+            caret_slope_run: 1,
+            caret_offset: 3,
+            metric_data_format: 0,
+            number_of_hmetrics: 2,
+        });
+        font.hmtx = Some(Hmtx {
+            horizontal_metrics: vec![
+                HorizontalMetric {
+                    advance_width: 500,
+                    left_side_bearing: 0,
+                },
+                HorizontalMetric {
+                    advance_width: 1200,
+                    left_side_bearing: -20,
+                },
+            ],
+            left_side_bearings: vec![7, -8],
+        });
+        font.os2 = Some(OS2 {
+            avg_glyph_width: 612,
+            weight_class: 700,
+            width_class: 3,
+            subscript: Script {
+                x_size: 650,
+                y_size: 600,
+                x_offset: -10,
+                y_offset: 75,
+            },
+            superscript: Script {
+                x_size: 650,
+                y_size: 600,
+                x_offset: 10,
+                y_offset: 350,
+            },
+            strikeout_size: 50,
+            strikeout_position: 260,
+            panose: Panose {
+                family_type: 2,
+                serif_style: 11,
+                weight: 8,
+                proportion: 3,
+                contrast: 4,
+                stroke_variation: 5,
+                arm_style: 6,
+                letterform: 9,
+                midline: 2,
+                xheight: 4,
+            },
+            typo_ascender: 800,
+            typo_descender: -200,
+            typo_line_gap: 100,
+            win_ascent: 950,
+            win_descent: 320,
+            x_height: 500,
+            cap_height: 700,
+            default_cahr: 0,
+            break_char: 32,
+            max_context: 3,
+        });
+
+        font
+    }
+
+    fn split_cmap() -> Font {
+        let mut font = sample_font(6);
+
+        font.cmap = Some(CMap::new_with_ranges(vec![
+            CharacterRange {
+                start: 'a',
+                end: 'b',
+                start_index: 1,
+            },
+            CharacterRange {
+                start: 'ä',
+                end: 'ä',
+                start_index: 3,
+            },
+            CharacterRange {
+                start: '€',
+                end: '€',
+                start_index: 4,
+            },
+            CharacterRange {
+                start: '𝄞',
+                end: '𝄟',
+                start_index: 5,
+            },
+        ]));
+
+        font
+    }
+
+    fn passthrough_tables() -> Font {
+        let mut font = sample_font(2);
+
+        font.raw_tables = vec![
+            RawTable {
+                tag: *b"DSIG",
+                data: vec![0, 0, 0, 1, 0, 0, 0, 0],
+            },
+            RawTable {
+                tag: *b"gasp",
+                data: vec![0, 1, 0, 1, 0xFF, 0xFF, 0, 0x0F],
+            },
+        ];
+
+        font
+    }
+
+    /** Generated fonts that together use every field of every table the crate writes. */
+    fn corpus() -> Vec<(&'static str, Font)> {
+        vec![
+            ("single glyph", sample_font(1)),
+            ("many glyphs", sample_font(700)),
+            ("outlines", outlines()),
+            ("names and metrics", names_and_metrics()),
+            ("split cmap", split_cmap()),
+            ("passthrough tables", passthrough_tables()),
+        ]
+    }
+
+    #[test]
+    fn written_tables_read_back_the_same() -> Result<(), RoundTripError> {
+        for (name, font) in corpus() {
+            let read = Font::read(&build(&font.clone().into_file(), false)?)?;
+
+            assert_eq!(
+                font.differences(&read),
+                Vec::<[u8; 4]>::new(),
+                "font '{}' did not survive the round trip",
+                name
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unmodified_fonts_are_written_identically() -> Result<(), RoundTripError> {
+        for (name, font) in corpus() {
+            let written = build(&font.into_file(), false)?;
+
+            assert!(
+                round_trip(&written)? == written,
+                "font '{}' changed on the round trip",
+                name
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn changed_tables_are_reported() {
+        let mut other = sample_font(2);
+        other.post = Some(Post {
+            underline_thickness: 7,
+            ..Post::default()
+        });
+
+        assert_eq!(sample_font(2).differences(&other), vec![*b"post"]);
+    }
+}
@@ -6,9 +6,17 @@ pub struct SearchData {
 
 impl SearchData {
     pub fn for_length(lenght: u16) -> Self {
-        let entry_selector = lenght.ilog2() as u16;
-        let search_range = u16::pow(2, entry_selector as u32) * 16;
-        let range_shift = lenght * 16 - search_range;
+        Self::for_units(lenght, 16)
+    }
+
+    /** The binary search header for `count` entries of `unit_size` bytes each. */
+    pub fn for_units(count: u16, unit_size: u16) -> Self {
+        let entry_selector = count.checked_ilog2().unwrap_or(0) as u16;
+        let search_range = match count {
+            0 => 0,
+            _ => u16::pow(2, entry_selector as u32) * unit_size,
+        };
+        let range_shift = count * unit_size - search_range;
 
         Self {
             search_range,
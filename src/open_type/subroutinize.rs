@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+
+use super::postscript::{operators::*, write_charstring_number, ESCAPE};
+
+/** Type 2 interpreters follow at most this many nested subroutine calls. */
+pub const MAX_NESTING: usize = 10;
+
+/** Each of the global and local subroutine INDEXes holds at most this many subroutines. */
+pub const MAX_SUBROUTINES: usize = 65535;
+
+/** Longer sequences rarely repeat, so they are not looked for. */
+const MAX_SEQUENCE_LENGTH: usize = 32;
+
+/** A call takes the subroutine number and the operator, which fits into three bytes for most fonts. */
+const CALL_COST: usize = 3;
+
+/** The end of a subroutine costs `return` and its offset in the INDEX. */
+const SUBROUTINE_COST: usize = 3;
+
+/** Charstrings that share sequences through subroutines. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutinized {
+    pub charstrings: Vec<Vec<u8>>,
+    pub global_subrs: Vec<Vec<u8>>,
+    pub local_subrs: Vec<Vec<u8>>,
+    /** The size of the charstrings before. */
+    pub original_size: usize,
+}
+
+impl Subroutinized {
+    /** The size of the charstrings and subroutines, without the offsets of their INDEXes. */
+    pub fn size(&self) -> usize {
+        self.charstrings
+            .iter()
+            .chain(self.global_subrs.iter())
+            .chain(self.local_subrs.iter())
+            .map(Vec::len)
+            .sum()
+    }
+
+    pub fn saved(&self) -> usize {
+        self.original_size.saturating_sub(self.size())
+    }
+}
+
+/** Calls are stored among the tokens with this bit set and the subroutine in the lower bits. */
+const CALL: u32 = 1 << 31;
+
+/** The body and position at which a sequence starts, for every time it occurs. */
+type Occurrences = Vec<(usize, usize)>;
+
+struct Body {
+    tokens: Vec<u32>,
+    /** How many calls it takes to reach this body from a charstring, 0 for charstrings themselves. */
+    depth: usize,
+}
+
+/**
+ * Moves operator and operand sequences that repeat across CFF charstrings into subroutines, as long as that saves
+ * space. Repeats inside subroutines become nested subroutines up to [MAX_NESTING]. The most used subroutines are
+ * split between the local and global INDEX, as each has its own range of short subroutine numbers. Charstrings
+ * must not contain hints and must keep one operand free for the subroutine numbers, as
+ * [charstring](super::postscript::charstring) does when asked to.
+ */
+pub fn subroutinize(charstrings: &[Vec<u8>]) -> Subroutinized {
+    let mut interner = Interner::default();
+
+    let mut bodies: Vec<Body> = charstrings
+        .iter()
+        .map(|charstring| Body {
+            tokens: tokenize(charstring)
+                .map(|token| interner.intern(token))
+                .collect(),
+            depth: 0,
+        })
+        .collect();
+
+    let end_char = interner.intern(&[ENDCHAR]);
+
+    // Every round finds the repeats in the bodies so far, including the subroutines of the previous one
+    for _ in 0..MAX_NESTING {
+        let budget = 2 * MAX_SUBROUTINES - (bodies.len() - charstrings.len());
+
+        if find_subroutines(&mut bodies, charstrings.len(), &interner, end_char, budget) == 0 {
+            break;
+        }
+    }
+
+    let subroutines = &bodies[charstrings.len()..];
+
+    // The most called subroutines get the numbers that are the cheapest to write
+    let mut calls = vec![0usize; subroutines.len()];
+    for token in bodies.iter().flat_map(|b| b.tokens.iter()) {
+        if token & CALL != 0 {
+            calls[(token & !CALL) as usize] += 1;
+        }
+    }
+
+    let mut by_use: Vec<usize> = (0..subroutines.len()).collect();
+    by_use.sort_by_key(|subroutine| std::cmp::Reverse(calls[*subroutine]));
+
+    // Alternate between both INDEXes, the position in one of them is the rank divided by two
+    let placement: Vec<(bool, usize)> = {
+        let mut placement = vec![(false, 0); subroutines.len()];
+        for (rank, subroutine) in by_use.iter().enumerate() {
+            placement[*subroutine] = (rank % 2 == 1, rank / 2);
+        }
+        placement
+    };
+
+    let global_count = subroutines.len() / 2;
+    let local_count = subroutines.len() - global_count;
+
+    let write = |body: &Body, is_subroutine: bool| {
+        let mut out = Vec::new();
+
+        for token in body.tokens.iter() {
+            if token & CALL == 0 {
+                out.extend(interner.bytes(*token));
+                continue;
+            }
+
+            let (global, number) = placement[(token & !CALL) as usize];
+            let (count, operator) = match global {
+                true => (global_count, CALLGSUBR),
+                false => (local_count, CALLSUBR),
+            };
+
+            write_charstring_number(&mut out, number as i32 - bias(count));
+            out.push(operator);
+        }
+
+        if is_subroutine && body.tokens.last() != Some(&end_char) {
+            out.push(RETURN);
+        }
+
+        out
+    };
+
+    let mut global_subrs = vec![Vec::new(); global_count];
+    let mut local_subrs = vec![Vec::new(); local_count];
+
+    for (subroutine, body) in subroutines.iter().enumerate() {
+        let (global, number) = placement[subroutine];
+        let index = match global {
+            true => &mut global_subrs,
+            false => &mut local_subrs,
+        };
+
+        index[number] = write(body, true);
+    }
+
+    Subroutinized {
+        charstrings: bodies[..charstrings.len()]
+            .iter()
+            .map(|body| write(body, false))
+            .collect(),
+        global_subrs,
+        local_subrs,
+        original_size: charstrings.iter().map(Vec::len).sum(),
+    }
+}
+
+/** Subroutine numbers are written minus the bias, so they start at the smallest number of their length. */
+pub fn bias(count: usize) -> i32 {
+    match count {
+        0..=1239 => 107,
+        1240..=33899 => 1131,
+        _ => 32768,
+    }
+}
+
+/**
+ * Replaces repeats in the bodies by calls to at most `budget` new subroutines, which are appended. Returns how many
+ * were created.
+ */
+fn find_subroutines(
+    bodies: &mut Vec<Body>,
+    charstring_count: usize,
+    interner: &Interner,
+    end_char: u32,
+    budget: usize,
+) -> usize {
+    let mut occurrences: HashMap<&[u32], Occurrences> = HashMap::new();
+
+    for (index, body) in bodies.iter().enumerate() {
+        // Calls from this body would nest too deep
+        if body.depth >= MAX_NESTING {
+            continue;
+        }
+
+        for start in 0..body.tokens.len() {
+            if body.tokens[start] & CALL != 0 {
+                continue;
+            }
+
+            for end in start + 1..(start + MAX_SEQUENCE_LENGTH).min(body.tokens.len()) {
+                let last = body.tokens[end];
+
+                // `endchar` can only end a subroutine, calls are not moved again
+                if last & CALL != 0 || body.tokens[end - 1] == end_char {
+                    break;
+                }
+
+                occurrences
+                    .entry(&body.tokens[start..=end])
+                    .or_default()
+                    .push((index, start));
+            }
+        }
+    }
+
+    let size =
+        |sequence: &[u32]| -> usize { sequence.iter().map(|t| interner.bytes(*t).len()).sum() };
+    let saving = |sequence: &[u32], count: usize| -> isize {
+        let length = size(sequence) as isize;
+
+        count as isize * (length - CALL_COST as isize) - length - SUBROUTINE_COST as isize
+    };
+
+    let mut candidates: Vec<(&[u32], Occurrences)> = occurrences
+        .into_iter()
+        .filter(|(sequence, found)| saving(sequence, found.len()) > 0)
+        .collect();
+
+    candidates.sort_by(|(a, found_a), (b, found_b)| {
+        saving(b, found_b.len())
+            .cmp(&saving(a, found_a.len()))
+            .then_with(|| a.cmp(b))
+    });
+
+    let mut taken: Vec<Vec<bool>> = bodies.iter().map(|b| vec![false; b.tokens.len()]).collect();
+    let mut replacements: Vec<Vec<(usize, usize, u32)>> = vec![Vec::new(); bodies.len()];
+    let mut created: Vec<Body> = Vec::new();
+
+    for (sequence, found) in candidates.iter() {
+        if created.len() == budget {
+            break;
+        }
+
+        // Earlier subroutines may have taken some of the occurrences
+        let mut free = Vec::new();
+
+        for (body, start) in found.iter().copied() {
+            let range = start..start + sequence.len();
+
+            if !taken[body][range.clone()].contains(&true) {
+                taken[body][range].fill(true);
+                free.push((body, start));
+            }
+        }
+
+        if saving(sequence, free.len()) <= 0 {
+            for (body, start) in free {
+                taken[body][start..start + sequence.len()].fill(false);
+            }
+
+            continue;
+        }
+
+        let subroutine = (bodies.len() - charstring_count + created.len()) as u32;
+
+        for (body, start) in free.iter().copied() {
+            replacements[body].push((start, sequence.len(), CALL | subroutine));
+        }
+
+        created.push(Body {
+            tokens: sequence.to_vec(),
+            depth: free
+                .iter()
+                .map(|(body, _)| bodies[*body].depth + 1)
+                .max()
+                .unwrap_or(1),
+        });
+    }
+
+    for (body, mut replacements) in bodies.iter_mut().zip(replacements) {
+        replacements.sort_unstable();
+
+        for (start, length, call) in replacements.into_iter().rev() {
+            body.tokens.splice(start..start + length, [call]);
+        }
+    }
+
+    let count = created.len();
+    bodies.extend(created);
+
+    count
+}
+
+/** Splits a charstring into numbers with their encoding and operators. */
+fn tokenize(charstring: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = charstring;
+
+    std::iter::from_fn(move || {
+        let length = match *rest.first()? {
+            28 => 3,
+            247..=254 => 2,
+            255 => 5,
+            ESCAPE => 2,
+            _ => 1,
+        }
+        .min(rest.len());
+
+        let (token, remaining) = rest.split_at(length);
+        rest = remaining;
+
+        Some(token)
+    })
+}
+
+/** Numbers tokens so sequences can be compared without looking at their bytes. */
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<Vec<u8>, u32>,
+    tokens: Vec<Vec<u8>>,
+}
+
+impl Interner {
+    fn intern(&mut self, token: &[u8]) -> u32 {
+        if let Some(id) = self.ids.get(token) {
+            return *id;
+        }
+
+        let id = self.tokens.len() as u32;
+        self.ids.insert(token.to_vec(), id);
+        self.tokens.push(token.to_vec());
+
+        id
+    }
+
+    fn bytes(&self, id: u32) -> &[u8] {
+        &self.tokens[id as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::open_type::postscript::{charstring, CubicContour, Segment};
+
+    /** Inlines the subroutine calls of a charstring. */
+    fn expand(charstring: &[u8], subroutinized: &Subroutinized, depth: usize) -> Vec<u8> {
+        assert!(depth <= MAX_NESTING);
+
+        let mut out = Vec::new();
+        let mut last_token = 0;
+
+        for token in tokenize(charstring) {
+            let index = match token {
+                [CALLSUBR] => &subroutinized.local_subrs,
+                [CALLGSUBR] => &subroutinized.global_subrs,
+                [RETURN] => continue,
+                _ => {
+                    last_token = out.len();
+                    out.extend(token);
+                    continue;
+                }
+            };
+
+            let number = decode_number(&out[last_token..]) + bias(index.len());
+            out.truncate(last_token);
+
+            out.extend(expand(&index[number as usize], subroutinized, depth + 1));
+        }
+
+        out
+    }
+
+    fn decode_number(token: &[u8]) -> i32 {
+        match token[0] {
+            32..=246 => token[0] as i32 - 139,
+            247..=250 => (token[0] as i32 - 247) * 256 + token[1] as i32 + 108,
+            251..=254 => -(token[0] as i32 - 251) * 256 - token[1] as i32 - 108,
+            _ => i16::from_be_bytes([token[1], token[2]]) as i32,
+        }
+    }
+
+    fn contour(x: i16) -> CubicContour {
+        CubicContour {
+            start_x: x,
+            start_y: 0,
+            segments: vec![
+                Segment::Line { x, y: 200 },
+                Segment::Curve {
+                    x1: x + 50,
+                    y1: 260,
+                    x2: x + 150,
+                    y2: 260,
+                    x: x + 200,
+                    y: 200,
+                },
+                Segment::Line { x: x + 200, y: 0 },
+                Segment::Line { x: x + 120, y: -30 },
+            ],
+        }
+    }
+
+    fn glyph(offset: i16) -> Vec<u8> {
+        charstring(&[contour(offset), contour(offset + 400)], None, true)
+    }
+
+    #[test]
+    fn repeats_become_subroutines() {
+        let charstrings: Vec<_> = (0..20).map(|n| glyph(n * 3)).collect();
+
+        let subroutinized = subroutinize(&charstrings);
+
+        assert!(!subroutinized.local_subrs.is_empty());
+        assert!(!subroutinized.global_subrs.is_empty());
+        assert!(subroutinized.saved() > 0);
+        assert_eq!(
+            subroutinized.saved(),
+            subroutinized.original_size - subroutinized.size()
+        );
+
+        for (original, charstring) in charstrings.iter().zip(subroutinized.charstrings.iter()) {
+            assert_eq!(&expand(charstring, &subroutinized, 0), original);
+        }
+    }
+
+    /** Runs the charstring and returns the deepest the operand stack got, counting subroutine numbers. */
+    fn stack_depth(
+        charstring: &[u8],
+        subroutinized: &Subroutinized,
+        stack: &mut Vec<i32>,
+    ) -> usize {
+        let mut deepest = stack.len();
+
+        for token in tokenize(charstring) {
+            let index = match token {
+                [CALLSUBR] => &subroutinized.local_subrs,
+                [CALLGSUBR] => &subroutinized.global_subrs,
+                [RETURN] | [ENDCHAR] => continue,
+                [0..=31] | [ESCAPE, _] => {
+                    stack.clear();
+                    continue;
+                }
+                _ => {
+                    stack.push(decode_number(token));
+                    deepest = deepest.max(stack.len());
+                    continue;
+                }
+            };
+
+            let number = stack.pop().unwrap() + bias(index.len());
+            deepest = deepest.max(stack_depth(&index[number as usize], subroutinized, stack));
+        }
+
+        deepest
+    }
+
+    #[test]
+    fn calls_in_long_runs_stay_within_the_operand_limit() {
+        // Only the 9th and 10th curves are the same in every glyph, the 9th starts the second run of 8 curves
+        let curves = |glyph: i16| CubicContour {
+            start_x: 0,
+            start_y: 0,
+            segments: (1..=16)
+                .map(|n| {
+                    let y = match n {
+                        9 | 10 => 50,
+                        _ => 50 + glyph * 16 + n,
+                    };
+
+                    Segment::Curve {
+                        x1: n * 100 - 70,
+                        y1: y,
+                        x2: n * 100 - 30,
+                        y2: y,
+                        x: n * 100,
+                        y: 0,
+                    }
+                })
+                .collect(),
+        };
+        let charstrings: Vec<_> = (0..10)
+            .map(|glyph| charstring(&[curves(glyph)], None, true))
+            .collect();
+
+        let subroutinized = subroutinize(&charstrings);
+
+        assert!(subroutinized.saved() > 0);
+        for (original, charstring) in charstrings.iter().zip(subroutinized.charstrings.iter()) {
+            assert_eq!(&expand(charstring, &subroutinized, 0), original);
+            assert!(stack_depth(charstring, &subroutinized, &mut Vec::new()) <= 48);
+        }
+    }
+
+    #[test]
+    fn unique_charstrings_stay_as_they_are() {
+        let charstrings = vec![charstring(&[contour(0)], None, true)];
+
+        let subroutinized = subroutinize(&charstrings);
+
+        assert_eq!(subroutinized.charstrings, charstrings);
+        assert!(subroutinized.local_subrs.is_empty());
+        assert_eq!(subroutinized.saved(), 0);
+    }
+
+    #[test]
+    fn bias_follows_the_subroutine_count() {
+        assert_eq!(bias(0), 107);
+        assert_eq!(bias(1240), 1131);
+        assert_eq!(bias(33900), 32768);
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use thiserror::Error;
+
+use super::{
+    reader::Font,
+    tables::{CMap, CharacterRange, Glyf, Glyph, Hmtx, MaxP},
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SubsetError {
+    #[error("the font has no '{}' table, which subsetting requires", String::from_utf8_lossy(.0))]
+    MissingTable([u8; 4]),
+    #[error("glyph {glyph_id} uses glyph {component} as component, which does not exist")]
+    MissingComponent { glyph_id: u16, component: u16 },
+    #[error("glyph {0} has no horizontal metric")]
+    MissingMetric(u16),
+    #[error("the font has no glyphs, not even '.notdef'")]
+    NoGlyphs,
+}
+
+/**
+ * Keeps the glyphs of the given characters, `.notdef` and every glyph they use as component. The kept glyphs are
+ * renumbered in their original order and `glyf`, `loca`, `hmtx`, `cmap`, `maxp`, `hhea` and the bounds in `head` are
+ * rewritten to match.
+ * `post` is written without glyph names, so it stays valid as it is. Characters the font does not map are ignored.
+ * Raw tables are dropped, they may refer to glyph ids that no longer exist.
+ */
+pub fn subset(font: &Font, chars: &BTreeSet<char>) -> Result<Font, SubsetError> {
+    let cmap = font
+        .cmap
+        .as_ref()
+        .ok_or(SubsetError::MissingTable(*b"cmap"))?;
+    let glyf = font
+        .glyf
+        .as_ref()
+        .ok_or(SubsetError::MissingTable(*b"glyf"))?;
+    if glyf.glyphs.is_empty() {
+        return Err(SubsetError::NoGlyphs);
+    }
+    let hmtx = font
+        .hmtx
+        .as_ref()
+        .ok_or(SubsetError::MissingTable(*b"hmtx"))?;
+
+    let mapping: BTreeMap<char, u16> = chars
+        .iter()
+        .filter_map(|char| {
+            let glyph_id = u16::try_from(cmap.glyph_id(*char)?).ok()?;
+
+            Some((*char, glyph_id))
+        })
+        .filter(|(_, glyph_id)| (*glyph_id as usize) < glyf.glyphs.len())
+        .collect();
+
+    let kept = closure(glyf, std::iter::once(0).chain(mapping.values().copied()))?;
+
+    // Kept glyphs keep their order, so `.notdef` stays glyph 0
+    let new_ids: BTreeMap<u16, u16> = kept
+        .iter()
+        .enumerate()
+        .map(|(new_id, old_id)| (*old_id, new_id as u16))
+        .collect();
+
+    let glyphs = kept
+        .iter()
+        .map(|old_id| renumber(&glyf.glyphs[*old_id as usize], &new_ids))
+        .collect();
+
+    let metrics = kept
+        .iter()
+        .map(|old_id| {
+            hmtx.metric(*old_id as usize)
+                .ok_or(SubsetError::MissingMetric(*old_id))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let glyf = Glyf { glyphs };
+    let hmtx = Hmtx::new_with_metrics(metrics);
+
+    let mut subset = font.clone();
+
+    subset.raw_tables.clear();
+
+    subset.cmap = Some(CMap::new_with_ranges(
+        mapping
+            .iter()
+            .map(|(char, old_id)| CharacterRange {
+                start: *char,
+                end: *char,
+                start_index: new_ids[old_id] as u32,
+            })
+            .collect(),
+    ));
+
+    subset
+        .maxp
+        .get_or_insert_with(MaxP::default)
+        .update_limits(&glyf);
+
+    if let Some(hhea) = subset.hhea.as_mut() {
+        hhea.update_metrics(&glyf, &hmtx);
+    }
+
+    if let Some(head) = subset.head.as_mut() {
+        head.update_bounds(&glyf);
+    }
+
+    subset.loca = Some(glyf.loca());
+    subset.glyf = Some(glyf);
+    subset.hmtx = Some(hmtx);
+
+    Ok(subset)
+}
+
+/** The given glyphs and all glyphs they use as components, directly or nested. */
+fn closure(glyf: &Glyf, roots: impl Iterator<Item = u16>) -> Result<BTreeSet<u16>, SubsetError> {
+    let mut kept = BTreeSet::new();
+    let mut pending: Vec<u16> = roots.collect();
+
+    while let Some(glyph_id) = pending.pop() {
+        if !kept.insert(glyph_id) {
+            continue;
+        }
+
+        if let Some(Glyph::Composite { components, .. }) = glyf.glyphs.get(glyph_id as usize) {
+            for component in components.iter() {
+                if component.glyph_index as usize >= glyf.glyphs.len() {
+                    return Err(SubsetError::MissingComponent {
+                        glyph_id,
+                        component: component.glyph_index,
+                    });
+                }
+
+                pending.push(component.glyph_index);
+            }
+        }
+    }
+
+    Ok(kept)
+}
+
+fn renumber(glyph: &Glyph, new_ids: &BTreeMap<u16, u16>) -> Glyph {
+    match glyph {
+        Glyph::Composite {
+            components,
+            instructions,
+        } => Glyph::Composite {
+            components: components
+                .iter()
+                .map(|component| {
+                    let mut component = component.clone();
+                    component.glyph_index = new_ids[&component.glyph_index];
+                    component
+                })
+                .collect(),
+            instructions: instructions.clone(),
+        },
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        build,
+        open_type::{
+            reader::Font,
+            tables::RawTable,
+            true_type::{Component, ComponentPlacement, ComponentTransform},
+        },
+        test::sample_font,
+    };
+
+    fn chars(text: &str) -> BTreeSet<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn only_used_glyphs_are_kept() {
+        let font = sample_font(5);
+
+        let subset = subset(&font, &chars("BDz")).unwrap();
+
+        let glyf = subset.glyf.as_ref().unwrap();
+        let original = font.glyf.as_ref().unwrap();
+
+        assert_eq!(
+            glyf.glyphs,
+            vec![
+                original.glyphs[0].clone(),
+                original.glyphs[2].clone(),
+                original.glyphs[4].clone(),
+            ]
+        );
+
+        let cmap = subset.cmap.as_ref().unwrap();
+        assert_eq!(cmap.glyph_id('B'), Some(1));
+        assert_eq!(cmap.glyph_id('D'), Some(2));
+        assert_eq!(cmap.glyph_id('A'), None);
+
+        assert_eq!(subset.maxp.as_ref().unwrap().number_of_glyphs, 3);
+        // The glyphs of 'B' and 'D' are squares of 11 and 13 units
+        let head = subset.head.as_ref().unwrap();
+        assert_eq!(
+            (head.min_x, head.min_y, head.max_x, head.max_y),
+            (0, 0, 13, 13)
+        );
+        assert_eq!(subset.hhea.as_ref().unwrap().number_of_hmetrics, 1);
+
+        let written = build(&subset.clone().into_file(), false).unwrap();
+        assert_eq!(
+            Font::read(&written).unwrap().differences(&subset),
+            Vec::<[u8; 4]>::new()
+        );
+    }
+
+    #[test]
+    fn components_are_kept_and_renumbered() {
+        let mut font = sample_font(3);
+
+        let glyphs = &mut font.glyf.as_mut().unwrap().glyphs;
+        glyphs[3] = Glyph::Composite {
+            components: vec![Component {
+                glyph_index: 2,
+                placement: ComponentPlacement::Offset { x: 3, y: 0 },
+                transform: ComponentTransform::Identity,
+                round_xy_to_grid: false,
+                use_my_metrics: false,
+                overlap_compound: false,
+            }],
+            instructions: vec![],
+        };
+        font.loca = Some(font.glyf.as_ref().unwrap().loca());
+
+        let subset = subset(&font, &chars("C")).unwrap();
+
+        let glyphs = &subset.glyf.as_ref().unwrap().glyphs;
+        assert_eq!(glyphs.len(), 3);
+
+        let Glyph::Composite { components, .. } = &glyphs[2] else {
+            panic!("glyph 2 is the composite");
+        };
+        assert_eq!(components[0].glyph_index, 1);
+
+        assert!(build(&subset.into_file(), false).is_ok());
+    }
+
+    #[test]
+    fn fonts_without_glyphs_can_not_be_subset() {
+        let mut font = sample_font(1);
+        font.glyf = None;
+
+        assert_eq!(
+            subset(&font, &chars("A")),
+            Err(SubsetError::MissingTable(*b"glyf"))
+        );
+
+        font.glyf = Some(Glyf { glyphs: vec![] });
+
+        assert_eq!(subset(&font, &chars("A")), Err(SubsetError::NoGlyphs));
+    }
+
+    #[test]
+    fn raw_tables_are_dropped() {
+        let mut font = sample_font(2);
+        font.raw_tables = vec![RawTable {
+            tag: *b"GPOS",
+            data: vec![0, 1, 0, 0, 0, 10, 0, 12, 0, 14],
+        }];
+
+        let subset = subset(&font, &chars("B")).unwrap();
+
+        assert_eq!(subset.raw_tables, vec![]);
+    }
+}
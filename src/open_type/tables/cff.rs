@@ -0,0 +1,396 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout::LayoutError,
+    open_type::{
+        postscript::{
+            charstring, index, write_dict_integer, write_dict_offset, write_font_matrix,
+            CubicContour,
+        },
+        subroutinize::{subroutinize, Subroutinized},
+        tables::{Glyf, Hmtx, LayoutedEncoded},
+        LayoutableTable, LayoutedTable,
+    },
+    Layoutable,
+};
+
+/** Glyph names get string ids after those of the 391 standard strings. */
+const FIRST_CUSTOM_SID: usize = 391;
+
+/**
+ * A CFF table with a single font, whose glyphs are stored as Type 2 charstrings. The first glyph has to be
+ * `.notdef`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cff {
+    /** The PostScript name, as in name id 6. */
+    pub font_name: String,
+    pub units_per_em: u16,
+    pub glyphs: Vec<CffGlyph>,
+    /** Moves repeated parts of the charstrings into subroutines, which takes longer to write but saves space. */
+    #[serde(default)]
+    pub subroutinize: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CffGlyph {
+    pub name: String,
+    pub advance_width: u16,
+    pub contours: Vec<CubicContour>,
+}
+
+impl Cff {
+    /**
+     * Converts TrueType outlines, with composite glyphs resolved. Glyph 0 becomes `.notdef`, the others are named
+     * `glyph` followed by their id. Glyphs whose components are broken are left empty.
+     */
+    pub fn from_glyf(font_name: &str, units_per_em: u16, glyf: &Glyf, hmtx: &Hmtx) -> Self {
+        let glyphs = (0..glyf.glyphs.len())
+            .map(|glyph_id| CffGlyph {
+                name: match glyph_id {
+                    0 => String::from(".notdef"),
+                    _ => format!("glyph{}", glyph_id),
+                },
+                advance_width: hmtx.metric(glyph_id).map_or(0, |m| m.advance_width),
+                contours: glyf
+                    .outline(glyph_id as u16)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(CubicContour::from_quadratic)
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            font_name: font_name.to_string(),
+            units_per_em,
+            glyphs,
+            subroutinize: false,
+        }
+    }
+
+    /** The charstrings as they are written, with the subroutines they call if [Cff::subroutinize] is set. */
+    pub fn charstrings(&self) -> Subroutinized {
+        let default_width = most_common_width(&self.glyphs);
+
+        let charstrings: Vec<_> = self
+            .glyphs
+            .iter()
+            .map(|glyph| {
+                let width = (glyph.advance_width != default_width)
+                    .then_some(glyph.advance_width as i32 - default_width as i32);
+
+                charstring(&glyph.contours, width, self.subroutinize)
+            })
+            .collect();
+
+        if self.subroutinize {
+            return subroutinize(&charstrings);
+        }
+
+        Subroutinized {
+            original_size: charstrings.iter().map(Vec::len).sum(),
+            charstrings,
+            global_subrs: Vec::new(),
+            local_subrs: Vec::new(),
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, LayoutError> {
+        match self.glyphs.first() {
+            Some(glyph) if glyph.name == ".notdef" => {}
+            _ => {
+                return Err(LayoutError::invalid_value(
+                    *b"CFF ",
+                    "glyphs",
+                    "the first glyph has to be .notdef",
+                ))
+            }
+        }
+
+        if self.glyphs.len() > u16::MAX as usize {
+            return Err(LayoutError::invalid_value(
+                *b"CFF ",
+                "glyphs",
+                format!("{} glyphs exceed the maximum of 65535", self.glyphs.len()),
+            ));
+        }
+
+        if self.units_per_em == 0 {
+            return Err(LayoutError::invalid_value(
+                *b"CFF ",
+                "units_per_em",
+                "has to be at least 1",
+            ));
+        }
+
+        // Most glyphs then leave out their width
+        let default_width = most_common_width(&self.glyphs);
+
+        let mut private = Vec::new();
+        write_dict_integer(&mut private, default_width as i32);
+        private.push(20); // defaultWidthX
+        write_dict_integer(&mut private, default_width as i32);
+        private.push(21); // nominalWidthX
+
+        let charstrings = self.charstrings();
+
+        // Local subroutines follow the Private DICT, their offset is relative to it
+        let local_subr_index = match charstrings.local_subrs.is_empty() {
+            true => Vec::new(),
+            false => {
+                let end = private.len() + 6;
+                write_dict_offset(&mut private, end as i32);
+                private.push(19); // Subrs
+
+                index(&charstrings.local_subrs)
+            }
+        };
+
+        let names: Vec<_> = self.glyphs[1..]
+            .iter()
+            .map(|g| g.name.as_bytes().to_vec())
+            .collect();
+
+        let mut charset = vec![0]; // Format 0, .notdef is left out
+        for sid in (0..names.len()).map(|n| FIRST_CUSTOM_SID + n) {
+            charset.extend((sid as u16).to_be_bytes());
+        }
+
+        let header = [1, 0, 4, 4];
+        let name_index = index(&[self.font_name.as_bytes().to_vec()]);
+        let string_index = index(&names);
+        let global_subr_index = index(&charstrings.global_subrs);
+        let charstring_index = index(&charstrings.charstrings);
+
+        // Offsets are written with a fixed size, the placeholder has the length of the final DICT
+        let top_dict_index = |charset: usize, charstrings: usize, private_offset: usize| {
+            index(&[self.top_dict(charset, charstrings, private.len(), private_offset)])
+        };
+
+        let charset_offset = header.len()
+            + name_index.len()
+            + top_dict_index(0, 0, 0).len()
+            + string_index.len()
+            + global_subr_index.len();
+        let charstrings_offset = charset_offset + charset.len();
+        let private_offset = charstrings_offset + charstring_index.len();
+
+        let mut out = Vec::with_capacity(private_offset + private.len() + local_subr_index.len());
+        out.extend(header);
+        out.extend(name_index);
+        out.extend(top_dict_index(
+            charset_offset,
+            charstrings_offset,
+            private_offset,
+        ));
+        out.extend(string_index);
+        out.extend(global_subr_index);
+        out.extend(charset);
+        out.extend(charstring_index);
+        out.extend(private);
+        out.extend(local_subr_index);
+
+        Ok(out)
+    }
+
+    fn top_dict(
+        &self,
+        charset: usize,
+        charstrings: usize,
+        private_size: usize,
+        private_offset: usize,
+    ) -> Vec<u8> {
+        let mut dict = Vec::new();
+
+        write_font_matrix(&mut dict, self.units_per_em);
+
+        let bounds = self
+            .glyphs
+            .iter()
+            .flat_map(|g| g.contours.iter())
+            .flat_map(CubicContour::points)
+            .fold(None::<[i16; 4]>, |bounds, (x, y)| match bounds {
+                None => Some([x, y, x, y]),
+                Some([x_min, y_min, x_max, y_max]) => {
+                    Some([x_min.min(x), y_min.min(y), x_max.max(x), y_max.max(y)])
+                }
+            })
+            .unwrap_or_default();
+
+        for value in bounds {
+            write_dict_integer(&mut dict, value as i32);
+        }
+        dict.push(5); // FontBBox
+
+        write_dict_offset(&mut dict, charset as i32);
+        dict.push(15); // charset
+
+        write_dict_offset(&mut dict, charstrings as i32);
+        dict.push(17); // CharStrings
+
+        write_dict_offset(&mut dict, private_size as i32);
+        write_dict_offset(&mut dict, private_offset as i32);
+        dict.push(18); // Private
+
+        dict
+    }
+}
+
+fn most_common_width(glyphs: &[CffGlyph]) -> u16 {
+    let mut widths: Vec<_> = glyphs.iter().map(|g| g.advance_width).collect();
+    widths.sort_unstable();
+
+    widths
+        .chunk_by(|a, b| a == b)
+        .max_by_key(|run| run.len())
+        .map_or(0, |run| run[0])
+}
+
+impl LayoutableTable for Cff {
+    fn tag(&self) -> [u8; 4] {
+        *b"CFF "
+    }
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for Cff {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        Box::new(LayoutedEncoded::new(*b"CFF ", self.encode(), layouter))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        build,
+        open_type::{
+            postscript::test::{decode, dict_operands, read_index},
+            reader::{read_directory, Font, CFF_VERSION},
+        },
+        test::sample_font,
+    };
+
+    fn cff_font() -> (Font, Cff) {
+        let mut font = sample_font(5);
+
+        let glyf = font.glyf.take().unwrap();
+        font.loca = None;
+        font.maxp.as_mut().unwrap().true_type_limits = None;
+
+        let mut cff = Cff::from_glyf("Sample", 64, &glyf, font.hmtx.as_ref().unwrap());
+        cff.glyphs[2].advance_width = 1200;
+
+        (font, cff)
+    }
+
+    #[test]
+    fn fonts_with_cff_outlines_are_written_as_otto() {
+        let (font, cff) = cff_font();
+
+        let mut file = font.into_file();
+        file.add_table(Box::new(cff));
+
+        file.validate().unwrap();
+        let data = build(&file, false).unwrap();
+
+        let (sfnt_version, records) = read_directory(&data).unwrap();
+        assert_eq!(sfnt_version, CFF_VERSION);
+
+        let maxp = records.iter().find(|r| &r.tag == b"maxp").unwrap();
+        assert_eq!(maxp.length, 6);
+
+        let read = Font::read(&data).unwrap();
+        assert!(read.glyf.is_none());
+        assert!(read.raw_tables.iter().any(|t| &t.tag == b"CFF "));
+    }
+
+    #[test]
+    fn charstrings_keep_outlines_and_widths() {
+        let (_, cff) = cff_font();
+
+        let data = cff.encode().unwrap();
+
+        assert_eq!(&data[..4], &[1, 0, 4, 4]);
+
+        let (names, rest) = read_index(&data[4..], 2);
+        assert_eq!(names, vec![b"Sample"]);
+
+        let (top_dicts, rest) = read_index(rest, 2);
+        let (strings, _) = read_index(rest, 2);
+        assert_eq!(strings[0], b"glyph1");
+
+        let charstrings = dict_operands(top_dicts[0], 17)[0] as usize;
+        let (charstrings, _) = read_index(&data[charstrings..], 2);
+        assert_eq!(charstrings.len(), cff.glyphs.len());
+
+        let private = dict_operands(top_dicts[0], 18);
+        let private = &data[private[1] as usize..][..private[0] as usize];
+        let nominal_width = dict_operands(private, 21)[0];
+
+        for (glyph, charstring) in cff.glyphs.iter().zip(charstrings) {
+            let (width, contours) = decode(charstring, &[]);
+
+            let expected: Vec<_> = glyph
+                .contours
+                .iter()
+                .map(|contour| {
+                    let mut contour = contour.clone();
+                    // The line back to the start is implied
+                    contour.segments.pop();
+                    contour
+                })
+                .collect();
+
+            assert_eq!(contours, expected);
+            assert_eq!(
+                width.map_or(nominal_width, |w| w + nominal_width),
+                glyph.advance_width as i32
+            );
+        }
+    }
+
+    #[test]
+    fn subroutines_follow_their_dicts() {
+        let font = sample_font(90);
+        let mut cff = Cff::from_glyf(
+            "Sample",
+            64,
+            font.glyf.as_ref().unwrap(),
+            font.hmtx.as_ref().unwrap(),
+        );
+
+        let plain = cff.encode().unwrap();
+        cff.subroutinize = true;
+        let data = cff.encode().unwrap();
+        let subroutinized = cff.charstrings();
+
+        assert!(data.len() < plain.len());
+
+        let (_, rest) = read_index(&data[4..], 2);
+        let (top_dicts, rest) = read_index(rest, 2);
+        let (_, rest) = read_index(rest, 2);
+        let (global_subrs, _) = read_index(rest, 2);
+        assert_eq!(global_subrs, subroutinized.global_subrs);
+
+        let private = dict_operands(top_dicts[0], 18);
+        let private = &data[private[1] as usize..];
+        let local_subrs = dict_operands(private, 19)[0] as usize;
+        let (local_subrs, _) = read_index(&private[local_subrs..], 2);
+        assert_eq!(local_subrs, subroutinized.local_subrs);
+    }
+
+    #[test]
+    fn the_first_glyph_has_to_be_notdef() {
+        let (_, mut cff) = cff_font();
+        cff.glyphs.remove(0);
+
+        assert!(matches!(
+            cff.encode(),
+            Err(LayoutError::InvalidValue {
+                field: "glyphs",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,437 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout::LayoutError,
+    open_type::{
+        postscript::{
+            cff2_charstring, cff2_index, write_dict_offset, write_font_matrix, CubicContour,
+            ESCAPE, MAX_CFF2_OPERANDS,
+        },
+        tables::LayoutedEncoded,
+        F2Dot14, LayoutableTable, LayoutedTable,
+    },
+    Layoutable,
+};
+
+/** A curve blends six values, each takes its default and a delta per region, plus the count for `blend`. */
+pub const MAX_REGIONS_PER_SET: usize = (MAX_CFF2_OPERANDS - 1) / 6 - 1;
+
+const HEADER_LENGTH: usize = 5;
+
+/**
+ * A CFF2 table with cubic outlines that vary along the axes of `fvar`. Glyphs describe their variations as deltas
+ * for the regions of one of the [Cff2::region_sets], from which the item variation store is built.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cff2 {
+    pub units_per_em: u16,
+    /** The number of axes in `fvar`, every region spans all of them. */
+    pub axis_count: u16,
+    pub regions: Vec<VariationRegion>,
+    /** The indices into [Cff2::regions] of every item variation data, selected by [Cff2Glyph::vsindex]. */
+    pub region_sets: Vec<Vec<u16>>,
+    pub glyphs: Vec<Cff2Glyph>,
+}
+
+/** The part of the design space in which a delta applies. */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariationRegion {
+    pub axes: Vec<RegionAxis>,
+}
+
+/** The delta applies fully at `peak` and fades out towards `start` and `end`, in normalized coordinates. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionAxis {
+    pub start: F2Dot14,
+    pub peak: F2Dot14,
+    pub end: F2Dot14,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cff2Glyph {
+    /** The outline at the default location. */
+    pub contours: Vec<CubicContour>,
+    /** The region set the deltas are for. */
+    pub vsindex: u16,
+    /**
+     * For every region of the set, contours of the same structure as [Cff2Glyph::contours] that hold the
+     * difference to them. Empty for glyphs that do not vary.
+     */
+    pub deltas: Vec<Vec<CubicContour>>,
+}
+
+impl Cff2 {
+    fn encode(&self) -> Result<Vec<u8>, LayoutError> {
+        self.check()?;
+
+        let charstrings: Vec<_> = self
+            .glyphs
+            .iter()
+            .map(|glyph| cff2_charstring(&glyph.contours, glyph.vsindex, &glyph.deltas))
+            .collect();
+
+        let global_subr_index = cff2_index(&[]);
+        let variation_store = self.variation_store();
+        let charstring_index = cff2_index(&charstrings);
+
+        // No hinting is written, the single Private DICT stays empty
+        let font_dict_index = |private_offset: usize| {
+            let mut font_dict = Vec::new();
+            write_dict_offset(&mut font_dict, 0);
+            write_dict_offset(&mut font_dict, private_offset as i32);
+            font_dict.push(18); // Private
+
+            cff2_index(&[font_dict])
+        };
+
+        // Offsets are written with a fixed size, the placeholder has the length of the final DICT
+        let top_dict_length = self
+            .top_dict(0, variation_store.as_ref().map(|_| 0), 0)
+            .len();
+
+        let variation_store_offset = HEADER_LENGTH + top_dict_length + global_subr_index.len();
+        let charstrings_offset =
+            variation_store_offset + variation_store.as_ref().map_or(0, Vec::len);
+        let font_dict_offset = charstrings_offset + charstring_index.len();
+        let private_offset = font_dict_offset + font_dict_index(0).len();
+
+        let top_dict = self.top_dict(
+            charstrings_offset,
+            variation_store.as_ref().map(|_| variation_store_offset),
+            font_dict_offset,
+        );
+
+        let mut out = Vec::with_capacity(private_offset);
+        out.extend([2, 0, HEADER_LENGTH as u8]);
+        out.extend((top_dict.len() as u16).to_be_bytes());
+        out.extend(top_dict);
+        out.extend(global_subr_index);
+        out.extend(variation_store.unwrap_or_default());
+        out.extend(charstring_index);
+        out.extend(font_dict_index(private_offset));
+
+        Ok(out)
+    }
+
+    fn check(&self) -> Result<(), LayoutError> {
+        let invalid = |field, reason: String| LayoutError::invalid_value(*b"CFF2", field, reason);
+
+        if self.glyphs.is_empty() || self.glyphs.len() > u16::MAX as usize {
+            return Err(invalid(
+                "glyphs",
+                format!("{} glyphs, but 1 to 65535 are required", self.glyphs.len()),
+            ));
+        }
+
+        if self.units_per_em == 0 {
+            return Err(invalid(
+                "units_per_em",
+                String::from("has to be at least 1"),
+            ));
+        }
+
+        if let Some(index) = self
+            .regions
+            .iter()
+            .position(|r| r.axes.len() != self.axis_count as usize)
+        {
+            return Err(invalid(
+                "regions",
+                format!(
+                    "region {} does not span all {} axes",
+                    index, self.axis_count
+                ),
+            ));
+        }
+
+        for (index, set) in self.region_sets.iter().enumerate() {
+            if set.len() > MAX_REGIONS_PER_SET {
+                return Err(invalid(
+                    "region_sets",
+                    format!(
+                        "set {} has {} regions, blending allows {}",
+                        index,
+                        set.len(),
+                        MAX_REGIONS_PER_SET
+                    ),
+                ));
+            }
+
+            if let Some(region) = set.iter().find(|r| **r as usize >= self.regions.len()) {
+                return Err(invalid(
+                    "region_sets",
+                    format!("set {} refers to the missing region {}", index, region),
+                ));
+            }
+        }
+
+        for (glyph_id, glyph) in self.glyphs.iter().enumerate() {
+            let glyph_error = |reason: String| LayoutError::GlyphError {
+                tag: *b"CFF2",
+                glyph_id,
+                source: Box::new(invalid("glyphs", reason)),
+            };
+
+            if glyph.vsindex != 0 || !glyph.deltas.is_empty() {
+                let set = self
+                    .region_sets
+                    .get(glyph.vsindex as usize)
+                    .ok_or_else(|| {
+                        glyph_error(format!("there is no region set {}", glyph.vsindex))
+                    })?;
+
+                if !glyph.deltas.is_empty() && glyph.deltas.len() != set.len() {
+                    return Err(glyph_error(format!(
+                        "{} deltas for the {} regions of set {}",
+                        glyph.deltas.len(),
+                        set.len(),
+                        glyph.vsindex
+                    )));
+                }
+            }
+
+            let matches_outline = |deltas: &Vec<CubicContour>| {
+                deltas.len() == glyph.contours.len()
+                    && deltas
+                        .iter()
+                        .zip(glyph.contours.iter())
+                        .all(|(a, b)| a.has_structure_of(b))
+            };
+
+            if let Some(region) = glyph.deltas.iter().position(|d| !matches_outline(d)) {
+                return Err(glyph_error(format!(
+                    "the deltas of region {} differ from the outline in structure",
+                    region
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn top_dict(
+        &self,
+        charstrings: usize,
+        variation_store: Option<usize>,
+        font_dicts: usize,
+    ) -> Vec<u8> {
+        let mut dict = Vec::new();
+
+        write_font_matrix(&mut dict, self.units_per_em);
+
+        write_dict_offset(&mut dict, charstrings as i32);
+        dict.push(17); // CharStrings
+
+        if let Some(variation_store) = variation_store {
+            write_dict_offset(&mut dict, variation_store as i32);
+            dict.push(24); // vstore
+        }
+
+        write_dict_offset(&mut dict, font_dicts as i32);
+        dict.extend([ESCAPE, 36]); // FDArray
+
+        dict
+    }
+
+    /**
+     * The item variation store with the length in front, `None` without region sets. The item variation data hold
+     * no delta sets, charstrings carry their deltas themselves.
+     */
+    fn variation_store(&self) -> Option<Vec<u8>> {
+        if self.region_sets.is_empty() {
+            return None;
+        }
+
+        let region_list_offset = 8 + 4 * self.region_sets.len();
+        let region_list_length = 4 + 6 * self.axis_count as usize * self.regions.len();
+
+        let mut store = Vec::new();
+        store.extend(1u16.to_be_bytes()); // Format
+        store.extend((region_list_offset as u32).to_be_bytes());
+        store.extend((self.region_sets.len() as u16).to_be_bytes());
+
+        let mut offset = region_list_offset + region_list_length;
+        for set in self.region_sets.iter() {
+            store.extend((offset as u32).to_be_bytes());
+            offset += 6 + 2 * set.len();
+        }
+
+        store.extend(self.axis_count.to_be_bytes());
+        store.extend((self.regions.len() as u16).to_be_bytes());
+        for axis in self.regions.iter().flat_map(|r| r.axes.iter()) {
+            for value in [axis.start, axis.peak, axis.end] {
+                store.extend(value.to_bits().to_be_bytes());
+            }
+        }
+
+        for set in self.region_sets.iter() {
+            store.extend(0u16.to_be_bytes()); // Item count
+            store.extend(0u16.to_be_bytes()); // Word delta count
+            store.extend((set.len() as u16).to_be_bytes());
+            for region in set.iter() {
+                store.extend(region.to_be_bytes());
+            }
+        }
+
+        let mut out = (store.len() as u16).to_be_bytes().to_vec();
+        out.extend(store);
+
+        Some(out)
+    }
+}
+
+impl LayoutableTable for Cff2 {
+    fn tag(&self) -> [u8; 4] {
+        *b"CFF2"
+    }
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for Cff2 {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        Box::new(LayoutedEncoded::new(*b"CFF2", self.encode(), layouter))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        build,
+        open_type::{
+            postscript::{
+                test::{decode, dict_operands, read_index},
+                Segment,
+            },
+            reader::{read_directory, CFF_VERSION},
+        },
+        test::sample_font,
+    };
+
+    fn square(size: i16) -> CubicContour {
+        CubicContour {
+            start_x: 0,
+            start_y: 0,
+            segments: vec![
+                Segment::Line { x: 0, y: size },
+                Segment::Curve {
+                    x1: size / 2,
+                    y1: size + 5,
+                    x2: size / 2,
+                    y2: size + 5,
+                    x: size,
+                    y: size,
+                },
+                Segment::Line { x: size, y: 0 },
+                Segment::Line { x: 0, y: 0 },
+            ],
+        }
+    }
+
+    /** The square grows by 10 towards the bold end of the single axis. */
+    fn variable() -> Cff2 {
+        let axis = RegionAxis {
+            start: F2Dot14::from_bits(0),
+            peak: F2Dot14::from_bits(0x4000),
+            end: F2Dot14::from_bits(0x4000),
+        };
+
+        let delta = |size: i16| {
+            let mut delta = square(0);
+            delta.segments[1] = Segment::Curve {
+                x1: size / 2,
+                y1: size,
+                x2: size / 2,
+                y2: size,
+                x: size,
+                y: size,
+            };
+            delta.segments[0] = Segment::Line { x: 0, y: size };
+            delta.segments[2] = Segment::Line { x: size, y: 0 };
+            delta
+        };
+
+        Cff2 {
+            units_per_em: 64,
+            axis_count: 1,
+            regions: vec![VariationRegion { axes: vec![axis] }],
+            region_sets: vec![vec![0]],
+            glyphs: vec![
+                Cff2Glyph::default(),
+                Cff2Glyph {
+                    contours: vec![square(20)],
+                    vsindex: 0,
+                    deltas: vec![vec![delta(10)]],
+                },
+                Cff2Glyph {
+                    contours: vec![square(30)],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn charstrings_blend_towards_the_regions() {
+        let cff2 = variable();
+        let data = cff2.encode().unwrap();
+
+        assert_eq!(&data[..3], &[2, 0, 5]);
+        let top_dict_length = u16::from_be_bytes([data[3], data[4]]) as usize;
+        let top_dict = &data[5..5 + top_dict_length];
+
+        let store = dict_operands(top_dict, 24)[0] as usize;
+        let store = &data[store + 2..];
+        assert_eq!(&store[..2], &[0, 1]);
+
+        let charstrings = dict_operands(top_dict, 17)[0] as usize;
+        let (charstrings, _) = read_index(&data[charstrings..], 4);
+        assert_eq!(charstrings.len(), 3);
+
+        let closed = |size| {
+            let mut square = square(size);
+            square.segments.pop();
+            square
+        };
+
+        assert_eq!(decode(charstrings[1], &[0.0]).1, vec![closed(20)]);
+        assert_eq!(decode(charstrings[1], &[1.0]).1, vec![closed(30)]);
+        assert_eq!(decode(charstrings[2], &[1.0]).1, vec![closed(30)]);
+        assert!(!charstrings[2].contains(&crate::open_type::postscript::operators::BLEND));
+    }
+
+    #[test]
+    fn variable_fonts_with_cff2_are_written_as_otto() {
+        let mut font = sample_font(2);
+        font.glyf = None;
+        font.loca = None;
+        font.maxp.as_mut().unwrap().true_type_limits = None;
+
+        let mut file = font.into_file();
+        file.add_table(Box::new(variable()));
+
+        let data = build(&file, false).unwrap();
+        let (sfnt_version, records) = read_directory(&data).unwrap();
+
+        assert_eq!(sfnt_version, CFF_VERSION);
+        assert!(records.iter().any(|r| &r.tag == b"CFF2"));
+    }
+
+    #[test]
+    fn deltas_need_the_structure_of_the_outline() {
+        let mut cff2 = variable();
+        cff2.glyphs[1].deltas[0][0].segments.pop();
+
+        assert!(matches!(
+            cff2.encode(),
+            Err(LayoutError::GlyphError { glyph_id: 1, .. })
+        ));
+
+        let mut cff2 = variable();
+        cff2.glyphs[1].vsindex = 1;
+
+        assert!(matches!(
+            cff2.encode(),
+            Err(LayoutError::GlyphError { glyph_id: 1, .. })
+        ));
+    }
+}
@@ -1,25 +1,250 @@
+use serde::{Deserialize, Serialize};
+
 use byteorder::{WriteBytesExt, BE};
 
 use crate::{
-    layout::{Layoutable, Layouted, Reservation},
-    open_type::{LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Layoutable, Layouted, Reservation},
+    open_type::{reader::ReadError, LayoutableTable, LayoutedTable},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CharacterRange {
     pub start: char,
     pub end: char,
     pub start_index: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CMap {
     ranges: Vec<CharacterRange>,
 }
 
 impl CMap {
-    pub fn new_with_ranges(ranges: Vec<CharacterRange>) -> Self {
-        Self { ranges }
+    /**
+     * Sorts the ranges by their first character and merges ranges that continue each other both in characters and
+     * glyph indices, so the same mapping always produces the same groups regardless of how it was split up.
+     */
+    pub fn new_with_ranges(mut ranges: Vec<CharacterRange>) -> Self {
+        ranges.sort_by_key(|r| (r.start, r.end, r.start_index));
+
+        let mut groups: Vec<CharacterRange> = Vec::with_capacity(ranges.len());
+
+        for range in ranges {
+            match groups.last_mut() {
+                Some(last) if last.continues_with(&range) => last.end = range.end,
+                _ => groups.push(range),
+            }
+        }
+
+        Self { ranges: groups }
+    }
+
+    pub fn ranges(&self) -> &[CharacterRange] {
+        &self.ranges
+    }
+
+    /** The glyph the character is mapped to, `None` if it is not mapped. */
+    pub fn glyph_id(&self, char: char) -> Option<u32> {
+        let index = self.ranges.partition_point(|range| range.end < char);
+
+        let range = self.ranges.get(index).filter(|r| r.start <= char)?;
+
+        range
+            .start_index
+            .checked_add(char as u32 - range.start as u32)
+    }
+
+    /**
+     * Every mapped character with its glyph. Ranges are walked by code point so surrogates inside a range are skipped
+     * without shifting the glyphs of the characters after them.
+     */
+    pub fn mappings(&self) -> impl Iterator<Item = (char, u32)> + '_ {
+        self.ranges.iter().flat_map(|range| {
+            (range.start as u32..=range.end as u32).filter_map(move |code| {
+                let char = char::from_u32(code)?;
+                let glyph_id = range.start_index.checked_add(code - range.start as u32)?;
+
+                Some((char, glyph_id))
+            })
+        })
+    }
+
+    /**
+     * Reads the Unicode mapping. A format 12 subtable is preferred since it covers all planes, otherwise the BMP
+     * mapping of a format 4 subtable is used.
+     */
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        use byteorder::ReadBytesExt;
+
+        let mut reader = data;
+
+        let version = reader.read_u16::<BE>()?;
+        if version != 0 {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"cmap",
+                version: version as u32,
+            });
+        }
+
+        let number_of_subtables = reader.read_u16::<BE>()?;
+
+        let mut subtables = Vec::new();
+
+        for _ in 0..number_of_subtables {
+            let platform_id = reader.read_u16::<BE>()?;
+            let encoding_id = reader.read_u16::<BE>()?;
+            let offset = reader.read_u32::<BE>()? as usize;
+
+            let is_unicode =
+                platform_id == 0 || (platform_id == 3 && [1, 10].contains(&encoding_id));
+
+            if is_unicode {
+                let subtable = data
+                    .get(offset..)
+                    .ok_or(ReadError::OutOfBounds { tag: *b"cmap" })?;
+                let format = (&subtable[..]).read_u16::<BE>()?;
+
+                subtables.push((format, subtable));
+            }
+        }
+
+        let find = |format| {
+            subtables
+                .iter()
+                .find(|(f, _)| *f == format)
+                .map(|(_, s)| *s)
+        };
+
+        let ranges = if let Some(subtable) = find(12) {
+            read_format_12(subtable)?
+        } else if let Some(subtable) = find(4) {
+            read_format_4(subtable)?
+        } else {
+            return Err(ReadError::invalid_value(
+                *b"cmap",
+                "subtables",
+                "there is no Unicode subtable of format 4 or 12",
+            ));
+        };
+
+        Ok(Self::new_with_ranges(ranges))
+    }
+}
+
+fn read_format_12(subtable: &[u8]) -> Result<Vec<CharacterRange>, ReadError> {
+    use byteorder::ReadBytesExt;
+
+    // Format, reserved, length and language
+    let mut reader = subtable
+        .get(12..)
+        .ok_or(ReadError::Truncated { tag: *b"cmap" })?;
+
+    let number_of_groups = reader.read_u32::<BE>()?;
+
+    let char = |code: u32| {
+        char::from_u32(code).ok_or_else(|| {
+            ReadError::invalid_value(
+                *b"cmap",
+                "ranges",
+                format!("{:#x} is not a Unicode scalar value", code),
+            )
+        })
+    };
+
+    (0..number_of_groups)
+        .map(|_| {
+            let range = CharacterRange {
+                start: char(reader.read_u32::<BE>()?)?,
+                end: char(reader.read_u32::<BE>()?)?,
+                start_index: reader.read_u32::<BE>()?,
+            };
+
+            if range.start > range.end {
+                return Err(ReadError::invalid_value(
+                    *b"cmap",
+                    "ranges",
+                    format!(
+                        "the group {:#x}..={:#x} ends before it starts",
+                        range.start as u32, range.end as u32
+                    ),
+                ));
+            }
+
+            Ok(range)
+        })
+        .collect()
+}
+
+fn read_format_4(subtable: &[u8]) -> Result<Vec<CharacterRange>, ReadError> {
+    use byteorder::ReadBytesExt;
+
+    let word = |offset: usize| -> Result<u16, ReadError> {
+        let mut bytes = subtable
+            .get(offset..offset + 2)
+            .ok_or(ReadError::Truncated { tag: *b"cmap" })?;
+
+        Ok(bytes.read_u16::<BE>()?)
+    };
+
+    let segment_count = word(6)? as usize / 2;
+
+    let end_codes = 14;
+    let start_codes = end_codes + 2 * segment_count + 2;
+    let id_deltas = start_codes + 2 * segment_count;
+    let id_range_offsets = id_deltas + 2 * segment_count;
+
+    let mut ranges = Vec::new();
+
+    for segment in 0..segment_count {
+        let end = word(end_codes + 2 * segment)?;
+        let start = word(start_codes + 2 * segment)?;
+        let delta = word(id_deltas + 2 * segment)?;
+        let range_offset_position = id_range_offsets + 2 * segment;
+        let range_offset = word(range_offset_position)?;
+
+        if start == 0xFFFF {
+            continue;
+        }
+
+        for code in start..=end {
+            let glyph = if range_offset == 0 {
+                code.wrapping_add(delta)
+            } else {
+                let glyph = word(
+                    range_offset_position + range_offset as usize + 2 * (code - start) as usize,
+                )?;
+
+                if glyph == 0 {
+                    0
+                } else {
+                    glyph.wrapping_add(delta)
+                }
+            };
+
+            // Surrogates are no characters, glyph 0 is the fallback anyway
+            if let Some(char) = char::from_u32(code as u32).filter(|_| glyph != 0) {
+                ranges.push(CharacterRange {
+                    start: char,
+                    end: char,
+                    start_index: glyph as u32,
+                });
+            }
+        }
+    }
+
+    Ok(ranges)
+}
+
+impl CharacterRange {
+    fn continues_with(&self, next: &CharacterRange) -> bool {
+        if self.start > self.end {
+            return false;
+        }
+
+        let length = self.end as u32 - self.start as u32 + 1;
+
+        next.start as u32 == self.end as u32 + 1
+            && self.start_index.checked_add(length) == Some(next.start_index)
     }
 }
 
@@ -33,7 +258,7 @@ impl Layoutable<Box<dyn LayoutedTable>> for CMap {
     fn layout(&self, layouter: &mut crate::layout::Layouter) -> Box<dyn LayoutedTable> {
         Box::new(LayoutedCMap {
             requires_another_pass: true,
-            reservation: layouter.reserve(18 + 8 * (self.ranges.len() + 1) + 10),
+            reservation: layouter.reserve(12 + 16 + 12 * self.ranges.len()),
             ranges: self.ranges.clone(),
         })
     }
@@ -51,6 +276,50 @@ impl LayoutedTable for LayoutedCMap {
     }
 }
 
+impl LayoutedCMap {
+    fn validate(&self) -> Result<(), LayoutError> {
+        for range in self.ranges.iter() {
+            if range.start > range.end {
+                return Err(LayoutError::invalid_value(
+                    *b"cmap",
+                    "ranges",
+                    format!(
+                        "range {:?}..={:?} ends before it starts",
+                        range.start, range.end
+                    ),
+                ));
+            }
+
+            let last_index = range.start_index as u64 + (range.end as u64 - range.start as u64);
+            if last_index > u16::MAX as u64 {
+                return Err(LayoutError::invalid_value(
+                    *b"cmap",
+                    "ranges",
+                    format!(
+                        "range {:?}..={:?} maps to glyph {} which is not a valid glyph id",
+                        range.start, range.end, last_index
+                    ),
+                ));
+            }
+        }
+
+        for pair in self.ranges.windows(2) {
+            if pair[0].end >= pair[1].start {
+                return Err(LayoutError::invalid_value(
+                    *b"cmap",
+                    "ranges",
+                    format!(
+                        "range starting at {:?} is not sorted after the range ending at {:?}",
+                        pair[1].start, pair[0].end
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl Layouted for LayoutedCMap {
     fn reservation(&self) -> &Reservation {
         &self.reservation
@@ -60,7 +329,9 @@ impl Layouted for LayoutedCMap {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::layout::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
+        self.validate()?;
+
         self.requires_another_pass = false;
 
         let mut writer = self.reservation.writer();
@@ -92,3 +363,71 @@ impl Layouted for LayoutedCMap {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn range(start: char, end: char, start_index: u32) -> CharacterRange {
+        CharacterRange {
+            start,
+            end,
+            start_index,
+        }
+    }
+
+    #[test]
+    fn ranges_are_sorted_and_merged() {
+        let cmap = CMap::new_with_ranges(vec![
+            range('x', 'z', 10),
+            range('c', 'd', 3),
+            range('a', 'b', 1),
+        ]);
+
+        let groups: Vec<_> = cmap
+            .ranges()
+            .iter()
+            .map(|r| (r.start, r.end, r.start_index))
+            .collect();
+
+        assert_eq!(groups, vec![('a', 'd', 1), ('x', 'z', 10)]);
+    }
+
+    #[test]
+    fn mappings_skip_surrogates_without_shifting_glyphs() {
+        let cmap = CMap::new_with_ranges(vec![range('\u{D7FF}', '\u{E000}', 1)]);
+
+        let mappings: Vec<_> = cmap.mappings().collect();
+
+        assert_eq!(mappings, vec![('\u{D7FF}', 1), ('\u{E000}', 0x802)]);
+        assert_eq!(cmap.glyph_id('\u{E000}'), Some(0x802));
+    }
+
+    #[test]
+    fn malformed_ranges_are_not_merged() {
+        let cmap = CMap::new_with_ranges(vec![
+            range('b', 'a', 1),
+            range('c', 'c', 3),
+            range('x', 'y', u32::MAX),
+            range('z', 'z', 0),
+        ]);
+
+        assert_eq!(cmap.ranges().len(), 4);
+        assert_eq!(cmap.glyph_id('y'), None);
+    }
+
+    #[test]
+    fn reversed_format_12_groups_are_read_errors() {
+        let mut data = vec![0, 0, 0, 1, 0, 3, 0, 10, 0, 0, 0, 12];
+        data.extend([0, 12, 0, 0, 0, 0, 0, 28, 0, 0, 0, 0, 0, 0, 0, 1]);
+        data.extend([0, 0, 0, 0x62, 0, 0, 0, 0x61, 0, 0, 0, 1]);
+
+        assert!(matches!(
+            CMap::read(&data),
+            Err(ReadError::InvalidValue {
+                field: "ranges",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,387 @@
+use serde::{Deserialize, Serialize};
+use unicode_general_category::{get_general_category, GeneralCategory};
+
+use crate::{
+    layout::LayoutError,
+    open_type::{
+        tables::{class_def, coverage, offset16, CMap, LayoutedEncoded},
+        LayoutableTable, LayoutedTable,
+    },
+    Layoutable,
+};
+
+/** Glyph definitions that lookups rely on, such as which glyphs are marks. */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Gdef {
+    /** Glyphs that are not listed have no class, lookup flags do not skip them. */
+    pub glyph_classes: Vec<(u16, GlyphClass)>,
+    /** Contour points that marks attach to, by glyph. */
+    #[serde(default)]
+    pub attach_points: Vec<(u16, Vec<u16>)>,
+    /** Caret positions between the components of a ligature, by glyph. */
+    #[serde(default)]
+    pub ligature_carets: Vec<(u16, Vec<CaretValue>)>,
+    /** Classes from 1 to 255 that the mark attachment type of lookup flags refers to, by glyph. */
+    #[serde(default)]
+    pub mark_attach_classes: Vec<(u16, u16)>,
+    /** Sets of marks that lookups can filter by, by index. Writes version 1.2 of the table if there are any. */
+    #[serde(default)]
+    pub mark_glyph_sets: Vec<Vec<u16>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum GlyphClass {
+    /** A single character, spacing glyph. */
+    Base = 1,
+    /** Multiple characters, spacing glyph. */
+    Ligature = 2,
+    /** A non-spacing combining glyph. */
+    Mark = 3,
+    /** Part of a single character, spacing glyph. */
+    Component = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaretValue {
+    /** A position along the baseline, in design units. */
+    Coordinate(i16),
+    /** The position of a contour point of the glyph, which follows hinting. */
+    ContourPoint(u16),
+}
+
+impl GlyphClass {
+    /**
+     * Classifies the glyphs of the character map: glyphs of non-spacing and enclosing marks are marks, those of any
+     * other character are bases. A glyph mapped from both is a base.
+     */
+    pub fn infer(cmap: &CMap) -> Vec<(u16, GlyphClass)> {
+        let mut classes: Vec<(u16, GlyphClass)> = Vec::new();
+
+        for (char, glyph_id) in cmap.mappings() {
+            let Ok(glyph) = u16::try_from(glyph_id) else {
+                continue;
+            };
+
+            let class = match get_general_category(char) {
+                GeneralCategory::NonspacingMark | GeneralCategory::EnclosingMark => {
+                    GlyphClass::Mark
+                }
+                _ => GlyphClass::Base,
+            };
+
+            classes.push((glyph, class));
+        }
+
+        // Bases sort before marks, so they are kept if a glyph has both
+        classes.sort();
+        classes.dedup_by_key(|(glyph, _)| *glyph);
+
+        classes
+    }
+}
+
+impl Gdef {
+    pub fn is_empty(&self) -> bool {
+        self.glyph_classes.is_empty()
+            && self.attach_points.is_empty()
+            && self.ligature_carets.is_empty()
+            && self.mark_attach_classes.is_empty()
+            && self.mark_glyph_sets.is_empty()
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, LayoutError> {
+        let glyph_classes: Vec<(u16, u16)> = self
+            .glyph_classes
+            .iter()
+            .map(|(glyph, class)| (*glyph, *class as u16))
+            .collect();
+
+        if let Some((glyph, class)) = self
+            .mark_attach_classes
+            .iter()
+            .find(|(_, class)| !(1..=255).contains(class))
+        {
+            return Err(LayoutError::invalid_value(
+                *b"GDEF",
+                "mark_attach_classes",
+                format!(
+                    "class {} of glyph {} is not between 1 and 255",
+                    class, glyph
+                ),
+            ));
+        }
+
+        let (minor_version, header_length) = match self.mark_glyph_sets.is_empty() {
+            true => (0u16, 12),
+            false => (2u16, 14),
+        };
+
+        let subtables = [
+            (!glyph_classes.is_empty()).then(|| class_def(&sorted(&glyph_classes))),
+            (!self.attach_points.is_empty()).then(|| self.attach_list()),
+            (!self.ligature_carets.is_empty()).then(|| self.ligature_caret_list()),
+            (!self.mark_attach_classes.is_empty())
+                .then(|| class_def(&sorted(&self.mark_attach_classes))),
+        ];
+
+        let mut out = Vec::new();
+        out.extend(1u16.to_be_bytes()); // Major version
+        out.extend(minor_version.to_be_bytes());
+
+        let mut offset = header_length;
+
+        for subtable in subtables.iter() {
+            let Some(subtable) = subtable else {
+                out.extend(0u16.to_be_bytes());
+                continue;
+            };
+
+            let offset16 = offset16(offset).ok_or_else(|| {
+                LayoutError::invalid_value(*b"GDEF", "offset", "the table exceeds 64 KiB")
+            })?;
+            out.extend(offset16.to_be_bytes());
+            offset += subtable.len();
+        }
+
+        let mark_glyph_sets = (minor_version == 2).then(|| self.mark_glyph_sets_def());
+
+        if mark_glyph_sets.is_some() {
+            let offset16 = offset16(offset).ok_or_else(|| {
+                LayoutError::invalid_value(*b"GDEF", "offset", "the table exceeds 64 KiB")
+            })?;
+            out.extend(offset16.to_be_bytes());
+        }
+
+        for subtable in subtables.into_iter().flatten().chain(mark_glyph_sets) {
+            out.extend(subtable);
+        }
+
+        Ok(out)
+    }
+
+    /** Coverage of the glyphs followed by the point indices of each. */
+    fn attach_list(&self) -> Vec<u8> {
+        let mut points = self.attach_points.clone();
+        points.sort_by_key(|(glyph, _)| *glyph);
+        points.dedup_by_key(|(glyph, _)| *glyph);
+
+        let glyphs: Vec<u16> = points.iter().map(|(glyph, _)| *glyph).collect();
+        let coverage = coverage(&glyphs);
+
+        let header_length = 4 + 2 * points.len();
+        let mut offset = header_length + coverage.len();
+
+        let mut out = Vec::new();
+        out.extend((header_length as u16).to_be_bytes());
+        out.extend((points.len() as u16).to_be_bytes());
+
+        for (_, indices) in points.iter() {
+            out.extend((offset as u16).to_be_bytes());
+            offset += 2 + 2 * indices.len();
+        }
+
+        out.extend(coverage);
+
+        for (_, mut indices) in points.into_iter() {
+            indices.sort_unstable();
+
+            out.extend((indices.len() as u16).to_be_bytes());
+            out.extend(indices.into_iter().flat_map(u16::to_be_bytes));
+        }
+
+        out
+    }
+
+    /** Coverage of the ligatures followed by a table per ligature, each with its caret values. */
+    fn ligature_caret_list(&self) -> Vec<u8> {
+        let mut carets = self.ligature_carets.clone();
+        carets.sort_by_key(|(glyph, _)| *glyph);
+        carets.dedup_by_key(|(glyph, _)| *glyph);
+
+        let glyphs: Vec<u16> = carets.iter().map(|(glyph, _)| *glyph).collect();
+        let coverage = coverage(&glyphs);
+
+        let header_length = 4 + 2 * carets.len();
+        let mut offset = header_length + coverage.len();
+
+        let mut out = Vec::new();
+        out.extend((header_length as u16).to_be_bytes());
+        out.extend((carets.len() as u16).to_be_bytes());
+
+        let mut ligatures = Vec::new();
+        for (_, values) in carets.iter() {
+            out.extend((offset as u16).to_be_bytes());
+
+            let ligature = ligature_glyph(values);
+            offset += ligature.len();
+            ligatures.extend(ligature);
+        }
+
+        out.extend(coverage);
+        out.extend(ligatures);
+
+        out
+    }
+
+    /** Format 1 with 32 bit offsets to a coverage per set. */
+    fn mark_glyph_sets_def(&self) -> Vec<u8> {
+        let coverages: Vec<Vec<u8>> = self
+            .mark_glyph_sets
+            .iter()
+            .map(|set| {
+                let mut set = set.clone();
+                set.sort_unstable();
+                set.dedup();
+                coverage(&set)
+            })
+            .collect();
+
+        let mut out = Vec::new();
+        out.extend(1u16.to_be_bytes()); // Format
+        out.extend((coverages.len() as u16).to_be_bytes());
+
+        let mut offset = 4 + 4 * coverages.len();
+        for coverage in coverages.iter() {
+            out.extend((offset as u32).to_be_bytes());
+            offset += coverage.len();
+        }
+
+        out.extend(coverages.into_iter().flatten());
+
+        out
+    }
+}
+
+fn sorted(classes: &[(u16, u16)]) -> Vec<(u16, u16)> {
+    let mut classes = classes.to_vec();
+    classes.sort_by_key(|(glyph, _)| *glyph);
+    classes.dedup_by_key(|(glyph, _)| *glyph);
+    classes
+}
+
+/** The caret values of a ligature, in increasing order as the format requires. */
+fn ligature_glyph(values: &[CaretValue]) -> Vec<u8> {
+    let mut values = values.to_vec();
+    values.sort_by_key(|value| match value {
+        CaretValue::Coordinate(coordinate) => (0, *coordinate as i32),
+        CaretValue::ContourPoint(point) => (1, *point as i32),
+    });
+
+    let mut out = Vec::new();
+    out.extend((values.len() as u16).to_be_bytes());
+
+    // Every caret value table is 4 bytes
+    for n in 0..values.len() {
+        out.extend(((2 + 2 * values.len() + 4 * n) as u16).to_be_bytes());
+    }
+
+    for value in values {
+        match value {
+            CaretValue::Coordinate(coordinate) => {
+                out.extend(1u16.to_be_bytes());
+                out.extend(coordinate.to_be_bytes());
+            }
+            CaretValue::ContourPoint(point) => {
+                out.extend(2u16.to_be_bytes());
+                out.extend(point.to_be_bytes());
+            }
+        }
+    }
+
+    out
+}
+
+impl LayoutableTable for Gdef {
+    fn tag(&self) -> [u8; 4] {
+        *b"GDEF"
+    }
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for Gdef {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        Box::new(LayoutedEncoded::new(*b"GDEF", self.encode(), layouter))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::open_type::tables::{gpos::test::u16_at, CharacterRange};
+
+    use super::*;
+
+    #[test]
+    fn glyph_classes_are_sorted() {
+        let gdef = Gdef {
+            glyph_classes: vec![(5, GlyphClass::Mark), (3, GlyphClass::Base)],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            gdef.encode().unwrap(),
+            vec![0, 1, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 1, 0, 3, 0, 3, 0, 1, 0, 0, 0, 3]
+        );
+    }
+
+    #[test]
+    fn all_subtables_are_written() {
+        let gdef = Gdef {
+            glyph_classes: vec![(1, GlyphClass::Ligature)],
+            attach_points: vec![(2, vec![7, 3])],
+            ligature_carets: vec![(
+                1,
+                vec![CaretValue::Coordinate(300), CaretValue::Coordinate(150)],
+            )],
+            mark_attach_classes: vec![(2, 1)],
+            mark_glyph_sets: vec![vec![2]],
+        };
+
+        let data = gdef.encode().unwrap();
+        assert_eq!(u16_at(&data, 2), 2);
+
+        let attach_list = &data[u16_at(&data, 6) as usize..];
+        assert_eq!(u16_at(attach_list, 2), 1);
+        let points = &attach_list[u16_at(attach_list, 4) as usize..];
+        assert_eq!(&points[..6], &[0, 2, 0, 3, 0, 7]);
+
+        let carets = &data[u16_at(&data, 8) as usize..];
+        let ligature = &carets[u16_at(carets, 4) as usize..];
+        assert_eq!(u16_at(ligature, 0), 2);
+        let first = &ligature[u16_at(ligature, 2) as usize..];
+        assert_eq!(&first[..4], &[0, 1, 0, 150]);
+
+        let mark_sets = &data[u16_at(&data, 12) as usize..];
+        assert_eq!(&mark_sets[..4], &[0, 1, 0, 1]);
+        let coverage = u32::from_be_bytes(mark_sets[4..8].try_into().unwrap()) as usize;
+        assert_eq!(&mark_sets[coverage..coverage + 6], &[0, 1, 0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn classes_are_inferred_from_general_categories() {
+        let cmap = CMap::new_with_ranges(vec![
+            CharacterRange {
+                start: 'a',
+                end: 'b',
+                start_index: 1,
+            },
+            CharacterRange {
+                start: '\u{0301}',
+                end: '\u{0301}',
+                start_index: 3,
+            },
+            CharacterRange {
+                start: '\u{20DD}',
+                end: '\u{20DD}',
+                start_index: 1,
+            },
+        ]);
+
+        assert_eq!(
+            GlyphClass::infer(&cmap),
+            vec![
+                (1, GlyphClass::Base),
+                (2, GlyphClass::Base),
+                (3, GlyphClass::Mark)
+            ]
+        );
+    }
+}
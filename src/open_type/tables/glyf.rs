@@ -1,39 +1,171 @@
-use std::cell::RefMut;
+use serde::{Deserialize, Serialize};
+
+use std::io::{Cursor, Write};
+
+use rayon::prelude::*;
 
 use crate::{
-    layout::{Reservation, SeekWrite},
+    layout::{LayoutError, Reservation, SeekWrite},
     open_type::{
-        true_type::{Contour, Instrution, InstrutionWriteExt},
-        LayoutableTable, LayoutedTable,
+        reader::ReadError,
+        tables::Loca,
+        true_type::{
+            Component, ComponentPlacement, ComponentTransform, Contour, Instrution,
+            InstrutionWriteExt, Point,
+        },
+        F2Dot14, LayoutableTable, LayoutedTable,
     },
     Layoutable, Layouted,
 };
 
-#[derive(Debug, Clone)]
+/** Composite glyphs nesting deeper than this are treated as broken, which also stops reference cycles. */
+pub const MAX_COMPONENT_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Glyph {
+    /** A glyph without an outline, like the space. It takes no bytes in the table. */
+    Empty,
     Simple {
         contours: Vec<Contour>,
         instructions: Vec<Instrution>,
     },
+    Composite {
+        components: Vec<Component>,
+        instructions: Vec<Instrution>,
+    },
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Glyf {
     pub glyphs: Vec<Glyph>,
 }
 
-impl Layoutable<Box<dyn LayoutedTable>> for Glyf {
-    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
-        let glyphs: Vec<_> = self
-            .glyphs
-            .iter()
-            .map(|glyph| -> Box<dyn LayoutedGlyph> {
-                match glyph {
-                    Glyph::Simple {
-                        contours,
-                        instructions,
-                    } => Box::new(LayoutedSimpleGlyph::new(contours, instructions)),
+impl Glyf {
+    /**
+     * The outline of a glyph with all components placed and transformed, `None` if the glyph or one of its
+     * components does not exist or components nest deeper than [MAX_COMPONENT_DEPTH].
+     */
+    pub fn outline(&self, glyph_id: u16) -> Option<Vec<Contour>> {
+        self.outline_at_depth(glyph_id, 0)
+    }
+
+    fn outline_at_depth(&self, glyph_id: u16, depth: usize) -> Option<Vec<Contour>> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return None;
+        }
+
+        match self.glyphs.get(glyph_id as usize)? {
+            Glyph::Empty => Some(vec![]),
+            Glyph::Simple { contours, .. } => Some(contours.clone()),
+            Glyph::Composite { components, .. } => {
+                let mut contours: Vec<Contour> = Vec::new();
+
+                for component in components.iter() {
+                    let transformed: Vec<Vec<(f32, f32, bool)>> = self
+                        .outline_at_depth(component.glyph_index, depth + 1)?
+                        .iter()
+                        .map(|c| {
+                            c.points
+                                .iter()
+                                .map(|p| {
+                                    let (x, y) = component.transform.apply(p.x, p.y);
+                                    (x, y, p.is_on_curve)
+                                })
+                                .collect()
+                        })
+                        .collect();
+
+                    let (dx, dy) = match component.placement {
+                        ComponentPlacement::Offset { x, y } => (x as f32, y as f32),
+                        ComponentPlacement::MatchPoints { parent, child } => {
+                            let parent = contours
+                                .iter()
+                                .flat_map(|c| c.points.iter())
+                                .nth(parent as usize)?;
+                            let child = transformed.iter().flatten().nth(child as usize)?;
+
+                            (parent.x as f32 - child.0, parent.y as f32 - child.1)
+                        }
+                    };
+
+                    contours.extend(transformed.into_iter().map(|points| {
+                        Contour {
+                            points: points
+                                .into_iter()
+                                .map(|(x, y, is_on_curve)| Point {
+                                    is_on_curve,
+                                    x: (x + dx).round() as i16,
+                                    y: (y + dy).round() as i16,
+                                })
+                                .collect(),
+                        }
+                    }));
                 }
+
+                Some(contours)
+            }
+        }
+    }
+
+    /** The `loca` offsets matching the glyphs as this table writes them. */
+    pub fn loca(&self) -> Loca {
+        let mut offsets = Vec::with_capacity(self.glyphs.len() + 1);
+        let mut offset = 0;
+
+        offsets.push(offset);
+
+        for glyph_id in 0..self.glyphs.len() {
+            offset += self.layout_glyph(glyph_id).size() as u32;
+            offsets.push(offset);
+        }
+
+        Loca { offsets }
+    }
+
+    fn layout_glyph(&self, glyph_id: usize) -> Box<dyn LayoutedGlyph> {
+        match &self.glyphs[glyph_id] {
+            Glyph::Empty => Box::new(LayoutedEmptyGlyph),
+            Glyph::Simple {
+                contours,
+                instructions,
+            } => Box::new(LayoutedSimpleGlyph::new(contours, instructions)),
+            Glyph::Composite {
+                components,
+                instructions,
+            } => Box::new(LayoutedCompositeGlyph::new(
+                components,
+                instructions,
+                self.outline(glyph_id as u16).as_deref().map(Bounds::of),
+            )),
+        }
+    }
+
+    /** Reads the glyphs at the given `loca` offsets. Zero length glyphs become [Glyph::Empty]. */
+    pub fn read(data: &[u8], loca: &Loca) -> Result<Self, ReadError> {
+        let glyphs = loca
+            .offsets
+            .windows(2)
+            .enumerate()
+            .map(|(glyph_id, range)| {
+                let glyph = data
+                    .get(range[0] as usize..range[1] as usize)
+                    .ok_or(ReadError::OutOfBounds { tag: *b"glyf" })?;
+
+                read_glyph(glyph).map_err(|source| ReadError::GlyphError {
+                    glyph_id,
+                    source: Box::new(source.with_table(*b"glyf")),
+                })
             })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { glyphs })
+    }
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for Glyf {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        let glyphs: Vec<_> = (0..self.glyphs.len())
+            .map(|glyph_id| self.layout_glyph(glyph_id))
             .collect();
 
         let total_size = glyphs.iter().map(|g| g.size()).sum();
@@ -41,6 +173,7 @@ impl Layoutable<Box<dyn LayoutedTable>> for Glyf {
         Box::new(LayoutedGlyf {
             reservation: layouter.reserve(total_size),
             requires_another_pass: true,
+            parallel: layouter.parallel(),
             glyphs,
         })
     }
@@ -55,9 +188,18 @@ impl LayoutableTable for Glyf {
 struct LayoutedGlyf {
     reservation: Reservation,
     requires_another_pass: bool,
+    parallel: bool,
     glyphs: Vec<Box<dyn LayoutedGlyph>>,
 }
 
+fn glyph_error(glyph_id: usize, source: LayoutError) -> LayoutError {
+    LayoutError::GlyphError {
+        tag: *b"glyf",
+        glyph_id,
+        source: Box::new(source.with_table(*b"glyf")),
+    }
+}
+
 impl Layouted for LayoutedGlyf {
     fn reservation(&self) -> &Reservation {
         &self.reservation
@@ -67,13 +209,42 @@ impl Layouted for LayoutedGlyf {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         self.requires_another_pass = false;
 
+        // Glyphs are encoded on their own and copied in order, so the parallel path produces the same bytes and
+        // reports the same first error as the sequential one.
+        let encode = |(glyph_id, glyph): (usize, &dyn LayoutedGlyph)| {
+            let mut encoded = Cursor::new(Vec::with_capacity(glyph.size()));
+
+            glyph
+                .write(&mut encoded)
+                .map(|_| encoded.into_inner())
+                .map_err(|source| glyph_error(glyph_id, source))
+        };
+
+        let encoded: Vec<_> = if self.parallel {
+            self.glyphs
+                .par_iter()
+                .map(AsRef::as_ref)
+                .enumerate()
+                .map(encode)
+                .collect()
+        } else {
+            self.glyphs
+                .iter()
+                .map(AsRef::as_ref)
+                .enumerate()
+                .map(encode)
+                .collect()
+        };
+
         let mut writer = self.reservation.writer();
 
-        for glyph in self.glyphs.iter_mut() {
-            glyph.write(&mut writer)?;
+        for (glyph_id, glyph) in encoded.into_iter().enumerate() {
+            writer
+                .write_all(&glyph?)
+                .map_err(|source| glyph_error(glyph_id, source.into()))?;
         }
 
         Ok(())
@@ -86,53 +257,149 @@ impl LayoutedTable for LayoutedGlyf {
     }
 }
 
-trait LayoutedGlyph {
+trait LayoutedGlyph: Send + Sync {
     fn size(&self) -> usize;
-    fn write(&self, writer: &mut RefMut<dyn SeekWrite>) -> std::io::Result<()>;
+    fn write(&self, writer: &mut dyn SeekWrite) -> Result<(), LayoutError>;
 }
 
-struct LayoutedSimpleGlyph {
-    size: usize,
-    contours: Vec<Contour>,
-    instructions: Vec<Instrution>,
-    x_min: i16,
-    y_min: i16,
-    x_max: i16,
-    y_max: i16,
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Bounds {
+    pub(crate) x_min: i16,
+    pub(crate) y_min: i16,
+    pub(crate) x_max: i16,
+    pub(crate) y_max: i16,
 }
 
-impl LayoutedSimpleGlyph {
-    fn new(contours: &[Contour], instructions: &[Instrution]) -> Self {
-        #[derive(Debug, Default, Clone, Copy)]
-        struct Envelop {
-            x_min: i16,
-            y_min: i16,
-            x_max: i16,
-            y_max: i16,
-        }
-
-        let envelop = contours.iter().flat_map(|c| c.points.iter()).fold(
-            Envelop::default(),
-            |state, point| Envelop {
+impl Bounds {
+    /** The bounding box of all points, all zero if there are none. */
+    pub(crate) fn of(contours: &[Contour]) -> Self {
+        let mut points = contours.iter().flat_map(|c| c.points.iter());
+
+        let Some(first) = points.next() else {
+            return Self::default();
+        };
+
+        points.fold(
+            Self {
+                x_min: first.x,
+                y_min: first.y,
+                x_max: first.x,
+                y_max: first.y,
+            },
+            |state, point| Self {
                 x_min: state.x_min.min(point.x),
                 y_min: state.y_min.min(point.y),
                 x_max: state.x_max.max(point.x),
                 y_max: state.y_max.max(point.y),
             },
-        );
+        )
+    }
+
+    fn write(&self, writer: &mut dyn SeekWrite) -> std::io::Result<()> {
+        use byteorder::{WriteBytesExt, BE};
+
+        writer.write_i16::<BE>(self.x_min)?;
+        writer.write_i16::<BE>(self.y_min)?;
+        writer.write_i16::<BE>(self.x_max)?;
+        writer.write_i16::<BE>(self.y_max)
+    }
+}
+
+fn validate_instructions(instructions: &[Instrution]) -> Result<(), LayoutError> {
+    for instruction in instructions.iter() {
+        match instruction {
+            Instrution::PushBytes(bytes) if bytes.is_empty() || bytes.len() > 8 => {
+                return Err(LayoutError::invalid_value(
+                    *b"glyf",
+                    "instructions",
+                    format!("PUSHB can push 1 to 8 bytes, not {}", bytes.len()),
+                ));
+            }
+            Instrution::PushBytes(_) | Instrution::Raw(_) => {}
+        }
+    }
+
+    let length: usize = instructions.iter().map(Instrution::size).sum();
+    if length > u16::MAX as usize {
+        return Err(LayoutError::invalid_value(
+            *b"glyf",
+            "instructions",
+            format!("{} bytes exceed the maximum of {}", length, u16::MAX),
+        ));
+    }
+
+    Ok(())
+}
+
+struct LayoutedEmptyGlyph;
+
+impl LayoutedGlyph for LayoutedEmptyGlyph {
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn write(&self, _writer: &mut dyn SeekWrite) -> Result<(), LayoutError> {
+        Ok(())
+    }
+}
+
+struct LayoutedSimpleGlyph {
+    size: usize,
+    contours: Vec<Contour>,
+    instructions: Vec<Instrution>,
+    bounds: Bounds,
+}
 
+impl LayoutedSimpleGlyph {
+    fn new(contours: &[Contour], instructions: &[Instrution]) -> Self {
         LayoutedSimpleGlyph {
-            size: 14
+            size: 12
                 + (contours.len() * 2)
-                + (instructions.len() * 1)
+                + instructions.iter().map(Instrution::size).sum::<usize>()
                 + (contours.iter().map(|c| c.points.len()).sum::<usize>() * 5),
             contours: contours.to_vec(),
             instructions: instructions.to_vec(),
-            x_min: envelop.x_min,
-            y_min: envelop.y_min,
-            x_max: envelop.x_max,
-            y_max: envelop.y_max,
+            bounds: Bounds::of(contours),
+        }
+    }
+}
+
+impl LayoutedSimpleGlyph {
+    fn validate(&self) -> Result<(), LayoutError> {
+        if self.contours.len() > i16::MAX as usize {
+            return Err(LayoutError::invalid_value(
+                *b"glyf",
+                "contours",
+                format!(
+                    "{} contours exceed the maximum of {}",
+                    self.contours.len(),
+                    i16::MAX
+                ),
+            ));
         }
+
+        let number_of_points: usize = self.contours.iter().map(|c| c.points.len()).sum();
+        if number_of_points > u16::MAX as usize {
+            return Err(LayoutError::invalid_value(
+                *b"glyf",
+                "points",
+                format!(
+                    "{} points exceed the maximum of {}",
+                    number_of_points,
+                    u16::MAX
+                ),
+            ));
+        }
+
+        if self.contours.iter().any(|c| c.points.is_empty()) {
+            return Err(LayoutError::invalid_value(
+                *b"glyf",
+                "contours",
+                "a contour needs at least one point",
+            ));
+        }
+
+        validate_instructions(&self.instructions)
     }
 }
 
@@ -141,56 +408,599 @@ impl LayoutedGlyph for LayoutedSimpleGlyph {
         self.size
     }
 
-    fn write(&self, writer: &mut RefMut<dyn SeekWrite>) -> std::io::Result<()> {
+    fn write(&self, writer: &mut dyn SeekWrite) -> Result<(), LayoutError> {
         use byteorder::{WriteBytesExt, BE};
 
-        writer.write_u16::<BE>(self.contours.len() as u16)?;
+        self.validate()?;
 
-        writer.write_i16::<BE>(self.x_min)?;
-        writer.write_i16::<BE>(self.y_min)?;
-        writer.write_i16::<BE>(self.x_max)?;
-        writer.write_i16::<BE>(self.y_max)?;
+        writer.write_i16::<BE>(self.contours.len() as i16)?;
+
+        self.bounds.write(writer)?;
 
         let mut pts_offset = 0;
         for contour in self.contours.iter() {
             pts_offset += contour.points.len() as u16;
 
-            writer.write_u16::<BE>(pts_offset)?;
+            writer.write_u16::<BE>(pts_offset - 1)?;
         }
 
-        writer.write_u16::<BE>(self.instructions.len() as u16)?;
+        writer.write_u16::<BE>(
+            self.instructions
+                .iter()
+                .map(Instrution::size)
+                .sum::<usize>() as u16,
+        )?;
 
         for instruction in self.instructions.iter() {
             writer.write_instruction(instruction)?;
         }
 
+        let points = || self.contours.iter().flat_map(|c| c.points.iter());
+
         // Flags
-        for contour in self.contours.iter() {
-            for point in contour.points.iter() {
-                let mut flags = 0;
+        for point in points() {
+            let mut flags = 0;
 
-                if point.is_on_curve {
-                    flags += 1
-                }
+            if point.is_on_curve {
+                flags += 1
+            }
+
+            writer.write_u8(flags)?;
+        }
+
+        // Coordinates are stored relative to the previous point
+        let mut previous = 0;
+        for point in points() {
+            writer.write_i16::<BE>(point.x.wrapping_sub(previous))?;
+            previous = point.x;
+        }
+
+        let mut previous = 0;
+        for point in points() {
+            writer.write_i16::<BE>(point.y.wrapping_sub(previous))?;
+            previous = point.y;
+        }
+
+        Ok(())
+    }
+}
+
+mod component_flags {
+    pub const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+    pub const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+    pub const ROUND_XY_TO_GRID: u16 = 0x0004;
+    pub const WE_HAVE_A_SCALE: u16 = 0x0008;
+    pub const MORE_COMPONENTS: u16 = 0x0020;
+    pub const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+    pub const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+    pub const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+    pub const USE_MY_METRICS: u16 = 0x0200;
+    pub const OVERLAP_COMPOUND: u16 = 0x0400;
+}
+
+/** Whether the placement of a component fits into bytes instead of words. */
+fn fits_into_bytes(placement: &ComponentPlacement) -> bool {
+    match *placement {
+        ComponentPlacement::Offset { x, y } => i8::try_from(x).is_ok() && i8::try_from(y).is_ok(),
+        ComponentPlacement::MatchPoints { parent, child } => {
+            u8::try_from(parent).is_ok() && u8::try_from(child).is_ok()
+        }
+    }
+}
+
+struct LayoutedCompositeGlyph {
+    size: usize,
+    components: Vec<Component>,
+    instructions: Vec<Instrution>,
+    bounds: Option<Bounds>,
+}
+
+impl LayoutedCompositeGlyph {
+    fn new(components: &[Component], instructions: &[Instrution], bounds: Option<Bounds>) -> Self {
+        let components_size: usize = components
+            .iter()
+            .map(|c| 4 + if fits_into_bytes(&c.placement) { 2 } else { 4 } + c.transform.size())
+            .sum();
+
+        let instructions_size = if instructions.is_empty() {
+            0
+        } else {
+            2 + instructions.iter().map(Instrution::size).sum::<usize>()
+        };
+
+        Self {
+            size: 10 + components_size + instructions_size,
+            components: components.to_vec(),
+            instructions: instructions.to_vec(),
+            bounds,
+        }
+    }
+}
+
+impl LayoutedGlyph for LayoutedCompositeGlyph {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn write(&self, writer: &mut dyn SeekWrite) -> Result<(), LayoutError> {
+        use byteorder::{WriteBytesExt, BE};
+
+        if self.components.is_empty() {
+            return Err(LayoutError::invalid_value(
+                *b"glyf",
+                "components",
+                "a composite glyph needs at least one component",
+            ));
+        }
+
+        let bounds = self.bounds.ok_or_else(|| {
+            LayoutError::invalid_value(
+                *b"glyf",
+                "components",
+                format!(
+                    "a component refers to a missing glyph or components nest deeper than {}",
+                    MAX_COMPONENT_DEPTH
+                ),
+            )
+        })?;
+
+        validate_instructions(&self.instructions)?;
 
-                writer.write_u8(flags)?;
+        writer.write_i16::<BE>(-1)?;
+
+        bounds.write(writer)?;
+
+        write_components(&self.components, !self.instructions.is_empty(), writer)?;
+
+        if !self.instructions.is_empty() {
+            writer.write_u16::<BE>(
+                self.instructions
+                    .iter()
+                    .map(Instrution::size)
+                    .sum::<usize>() as u16,
+            )?;
+
+            for instruction in self.instructions.iter() {
+                writer.write_instruction(instruction)?;
             }
         }
 
-        // x-Coordinates
-        for contour in self.contours.iter() {
-            for point in contour.points.iter() {
-                writer.write_i16::<BE>(point.x)?;
+        Ok(())
+    }
+}
+
+/**
+ * Writes the components of a composite glyph as they appear in `glyf`, without the header and instructions.
+ * `has_instructions` sets the flag on the last component that announces instructions after it.
+ */
+pub(crate) fn write_components<W: Write + ?Sized>(
+    components: &[Component],
+    has_instructions: bool,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    use byteorder::{WriteBytesExt, BE};
+    use component_flags::*;
+
+    for (index, component) in components.iter().enumerate() {
+        let bytes = fits_into_bytes(&component.placement);
+
+        let mut flags = 0;
+
+        if !bytes {
+            flags |= ARG_1_AND_2_ARE_WORDS;
+        }
+        if let ComponentPlacement::Offset { .. } = component.placement {
+            flags |= ARGS_ARE_XY_VALUES;
+        }
+        if component.round_xy_to_grid {
+            flags |= ROUND_XY_TO_GRID;
+        }
+        flags |= match component.transform {
+            ComponentTransform::Identity => 0,
+            ComponentTransform::Scale(_) => WE_HAVE_A_SCALE,
+            ComponentTransform::ScaleXY { .. } => WE_HAVE_AN_X_AND_Y_SCALE,
+            ComponentTransform::Matrix { .. } => WE_HAVE_A_TWO_BY_TWO,
+        };
+        if index + 1 < components.len() {
+            flags |= MORE_COMPONENTS;
+        } else if has_instructions {
+            flags |= WE_HAVE_INSTRUCTIONS;
+        }
+        if component.use_my_metrics {
+            flags |= USE_MY_METRICS;
+        }
+        if component.overlap_compound {
+            flags |= OVERLAP_COMPOUND;
+        }
+
+        writer.write_u16::<BE>(flags)?;
+        writer.write_u16::<BE>(component.glyph_index)?;
+
+        match (component.placement, bytes) {
+            (ComponentPlacement::Offset { x, y }, true) => {
+                writer.write_i8(x as i8)?;
+                writer.write_i8(y as i8)?;
+            }
+            (ComponentPlacement::Offset { x, y }, false) => {
+                writer.write_i16::<BE>(x)?;
+                writer.write_i16::<BE>(y)?;
+            }
+            (ComponentPlacement::MatchPoints { parent, child }, true) => {
+                writer.write_u8(parent as u8)?;
+                writer.write_u8(child as u8)?;
+            }
+            (ComponentPlacement::MatchPoints { parent, child }, false) => {
+                writer.write_u16::<BE>(parent)?;
+                writer.write_u16::<BE>(child)?;
             }
         }
 
-        // y-Coordinates
-        for contour in self.contours.iter() {
-            for point in contour.points.iter() {
-                writer.write_i16::<BE>(point.y)?;
+        match component.transform {
+            ComponentTransform::Identity => {}
+            ComponentTransform::Scale(scale) => {
+                writer.write_i16::<BE>(scale.to_bits())?;
+            }
+            ComponentTransform::ScaleXY { x, y } => {
+                writer.write_i16::<BE>(x.to_bits())?;
+                writer.write_i16::<BE>(y.to_bits())?;
+            }
+            ComponentTransform::Matrix { xx, xy, yx, yy } => {
+                writer.write_i16::<BE>(xx.to_bits())?;
+                writer.write_i16::<BE>(xy.to_bits())?;
+                writer.write_i16::<BE>(yx.to_bits())?;
+                writer.write_i16::<BE>(yy.to_bits())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+mod point_flags {
+    pub const ON_CURVE_POINT: u8 = 0x01;
+    pub const X_SHORT_VECTOR: u8 = 0x02;
+    pub const Y_SHORT_VECTOR: u8 = 0x04;
+    pub const REPEAT_FLAG: u8 = 0x08;
+    pub const X_IS_SAME_OR_POSITIVE_X_SHORT_VECTOR: u8 = 0x10;
+    pub const Y_IS_SAME_OR_POSITIVE_Y_SHORT_VECTOR: u8 = 0x20;
+}
+
+fn read_glyph(data: &[u8]) -> Result<Glyph, ReadError> {
+    use byteorder::{ReadBytesExt, BE};
+
+    if data.is_empty() {
+        return Ok(Glyph::Empty);
+    }
+
+    let mut reader = Cursor::new(data);
+
+    let number_of_contours = reader.read_i16::<BE>()?;
+
+    // The bounding box is recalculated when writing
+    reader.set_position(10);
+
+    if number_of_contours >= 0 {
+        read_simple_glyph(&mut reader, number_of_contours as usize)
+    } else {
+        read_composite_glyph(&mut reader)
+    }
+}
+
+fn read_instructions(reader: &mut Cursor<&[u8]>) -> Result<Vec<Instrution>, ReadError> {
+    use byteorder::{ReadBytesExt, BE};
+    use std::io::Read;
+
+    let length = reader.read_u16::<BE>()?;
+
+    let mut bytecode = vec![0; length as usize];
+    reader.read_exact(&mut bytecode)?;
+
+    Ok(Instrution::decode(&bytecode))
+}
+
+fn read_simple_glyph(
+    reader: &mut Cursor<&[u8]>,
+    number_of_contours: usize,
+) -> Result<Glyph, ReadError> {
+    use byteorder::{ReadBytesExt, BE};
+    use point_flags::*;
+
+    let end_points = (0..number_of_contours)
+        .map(|_| reader.read_u16::<BE>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if end_points.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(ReadError::invalid_value(
+            *b"glyf",
+            "end_points",
+            "contour end points are not increasing",
+        ));
+    }
+
+    let number_of_points = end_points.last().map_or(0, |last| *last as usize + 1);
+
+    let instructions = read_instructions(reader)?;
+
+    let mut flags = Vec::with_capacity(number_of_points);
+    while flags.len() < number_of_points {
+        let flag = reader.read_u8()?;
+        flags.push(flag);
+
+        if flag & REPEAT_FLAG != 0 {
+            for _ in 0..reader.read_u8()? {
+                flags.push(flag);
+            }
+        }
+    }
+    flags.truncate(number_of_points);
+
+    let mut read_coordinates = |short: u8, same_or_positive: u8| {
+        let mut value: i16 = 0;
+
+        flags
+            .iter()
+            .map(|flag| {
+                let delta = if flag & short != 0 {
+                    let magnitude = reader.read_u8()? as i16;
+
+                    if flag & same_or_positive != 0 {
+                        magnitude
+                    } else {
+                        -magnitude
+                    }
+                } else if flag & same_or_positive != 0 {
+                    0
+                } else {
+                    reader.read_i16::<BE>()?
+                };
+
+                value = value.wrapping_add(delta);
+
+                Ok(value)
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+    };
+
+    let xs = read_coordinates(X_SHORT_VECTOR, X_IS_SAME_OR_POSITIVE_X_SHORT_VECTOR)?;
+    let ys = read_coordinates(Y_SHORT_VECTOR, Y_IS_SAME_OR_POSITIVE_Y_SHORT_VECTOR)?;
+
+    let mut points = flags
+        .iter()
+        .zip(xs.into_iter().zip(ys))
+        .map(|(flag, (x, y))| Point {
+            is_on_curve: flag & ON_CURVE_POINT != 0,
+            x,
+            y,
+        });
+
+    let mut start = 0;
+    let contours = end_points
+        .iter()
+        .map(|end| {
+            let length = *end as usize + 1 - start;
+            start = *end as usize + 1;
+
+            Contour {
+                points: points.by_ref().take(length).collect(),
+            }
+        })
+        .collect();
+
+    Ok(Glyph::Simple {
+        contours,
+        instructions,
+    })
+}
+
+fn read_composite_glyph(reader: &mut Cursor<&[u8]>) -> Result<Glyph, ReadError> {
+    use byteorder::{ReadBytesExt, BE};
+    use component_flags::*;
+
+    let mut components = Vec::new();
+
+    loop {
+        let flags = reader.read_u16::<BE>()?;
+        let glyph_index = reader.read_u16::<BE>()?;
+
+        let words = flags & ARG_1_AND_2_ARE_WORDS != 0;
+
+        let placement = if flags & ARGS_ARE_XY_VALUES != 0 {
+            let (x, y) = if words {
+                (reader.read_i16::<BE>()?, reader.read_i16::<BE>()?)
+            } else {
+                (reader.read_i8()? as i16, reader.read_i8()? as i16)
+            };
+
+            ComponentPlacement::Offset { x, y }
+        } else {
+            let (parent, child) = if words {
+                (reader.read_u16::<BE>()?, reader.read_u16::<BE>()?)
+            } else {
+                (reader.read_u8()? as u16, reader.read_u8()? as u16)
+            };
+
+            ComponentPlacement::MatchPoints { parent, child }
+        };
+
+        let mut read_f2dot14 = || reader.read_i16::<BE>().map(F2Dot14::from_bits);
+
+        let transform = if flags & WE_HAVE_A_SCALE != 0 {
+            ComponentTransform::Scale(read_f2dot14()?)
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            ComponentTransform::ScaleXY {
+                x: read_f2dot14()?,
+                y: read_f2dot14()?,
             }
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            ComponentTransform::Matrix {
+                xx: read_f2dot14()?,
+                xy: read_f2dot14()?,
+                yx: read_f2dot14()?,
+                yy: read_f2dot14()?,
+            }
+        } else {
+            ComponentTransform::Identity
+        };
+
+        components.push(Component {
+            glyph_index,
+            placement,
+            transform,
+            round_xy_to_grid: flags & ROUND_XY_TO_GRID != 0,
+            use_my_metrics: flags & USE_MY_METRICS != 0,
+            overlap_compound: flags & OVERLAP_COMPOUND != 0,
+        });
+
+        if flags & MORE_COMPONENTS == 0 {
+            let instructions = if flags & WE_HAVE_INSTRUCTIONS != 0 {
+                read_instructions(reader)?
+            } else {
+                vec![]
+            };
+
+            return Ok(Glyph::Composite {
+                components,
+                instructions,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Layouter;
+
+    fn square(size: i16) -> Glyph {
+        Glyph::Simple {
+            contours: vec![Contour {
+                points: vec![
+                    Point::on_curve(0, 0),
+                    Point::on_curve(0, size),
+                    Point::off_curve(size, size),
+                    Point::on_curve(size, 0),
+                ],
+            }],
+            instructions: vec![Instrution::PushBytes(Box::new([5, 2]))],
+        }
+    }
+
+    fn component(glyph_index: u16, placement: ComponentPlacement) -> Component {
+        Component {
+            glyph_index,
+            placement,
+            transform: ComponentTransform::Identity,
+            round_xy_to_grid: false,
+            use_my_metrics: false,
+            overlap_compound: false,
         }
+    }
+
+    fn write(glyf: &Glyf) -> Result<Vec<u8>, LayoutError> {
+        let mut layouter = Layouter::new(1);
+        let mut layouted = glyf.layout(&mut layouter);
+
+        layouted.pass(&layouter.get_result())?;
+
+        Ok(layouter.get_result())
+    }
+
+    #[test]
+    fn invalid_glyphs_are_reported_with_their_id() {
+        let contour = Contour {
+            points: vec![Point::on_curve(0, 0), Point::on_curve(10, 10)],
+        };
+
+        let glyf = Glyf {
+            glyphs: vec![
+                Glyph::Simple {
+                    contours: vec![],
+                    instructions: vec![],
+                },
+                Glyph::Simple {
+                    contours: vec![contour],
+                    instructions: vec![Instrution::PushBytes(Box::new([0; 9]))],
+                },
+            ],
+        };
+
+        let error = write(&glyf).unwrap_err();
+
+        assert!(matches!(error, LayoutError::GlyphError { glyph_id: 1, .. }));
+    }
+
+    #[test]
+    fn written_glyphs_can_be_read() -> Result<(), Box<dyn std::error::Error>> {
+        let glyf = Glyf {
+            glyphs: vec![
+                Glyph::Empty,
+                square(10),
+                Glyph::Composite {
+                    components: vec![
+                        Component {
+                            transform: ComponentTransform::Scale(F2Dot14::from_bits(0x2000)),
+                            round_xy_to_grid: true,
+                            use_my_metrics: true,
+                            ..component(1, ComponentPlacement::Offset { x: 300, y: -2 })
+                        },
+                        component(
+                            1,
+                            ComponentPlacement::MatchPoints {
+                                parent: 3,
+                                child: 0,
+                            },
+                        ),
+                    ],
+                    instructions: vec![],
+                },
+            ],
+        };
+
+        let data = write(&glyf)?;
+
+        assert_eq!(Glyf::read(&data, &glyf.loca())?, glyf);
 
         Ok(())
     }
+
+    #[test]
+    fn composite_outlines_are_placed() {
+        let glyf = Glyf {
+            glyphs: vec![
+                square(10),
+                Glyph::Composite {
+                    components: vec![Component {
+                        transform: ComponentTransform::Scale(F2Dot14::from_bits(0x2000)),
+                        ..component(0, ComponentPlacement::Offset { x: 5, y: 0 })
+                    }],
+                    instructions: vec![],
+                },
+            ],
+        };
+
+        let outline = glyf.outline(1).unwrap();
+
+        assert_eq!(
+            outline[0].points,
+            vec![
+                Point::on_curve(5, 0),
+                Point::on_curve(5, 5),
+                Point::off_curve(10, 5),
+                Point::on_curve(10, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn cyclic_components_have_no_outline() {
+        let glyf = Glyf {
+            glyphs: vec![Glyph::Composite {
+                components: vec![component(0, ComponentPlacement::Offset { x: 0, y: 0 })],
+                instructions: vec![],
+            }],
+        };
+
+        assert_eq!(glyf.outline(0), None);
+        assert!(write(&glyf).is_err());
+    }
 }
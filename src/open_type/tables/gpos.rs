@@ -0,0 +1,1071 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout::LayoutError,
+    open_type::{
+        tables::{
+            class_def, context_subtables, coverage, encode_layout_table, lookup_names, offset16,
+            split_subtables, ContextRule, EncodedLookup, Feature, LanguageSystem, LayoutedEncoded,
+            DEFAULT_LANGUAGE, DEFAULT_SCRIPT,
+        },
+        LayoutableTable, LayoutedTable,
+    },
+    Layoutable,
+};
+
+const SINGLE_ADJUSTMENT: u16 = 1;
+const PAIR_ADJUSTMENT: u16 = 2;
+const MARK_TO_BASE: u16 = 4;
+const MARK_TO_LIGATURE: u16 = 5;
+const MARK_TO_MARK: u16 = 6;
+const CONTEXT: u16 = 7;
+const CHAINED_CONTEXT: u16 = 8;
+
+/** Glyph positioning: features whose lookups move glyphs and change their advances. */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Gpos {
+    pub language_systems: Vec<LanguageSystem>,
+    pub features: Vec<Feature>,
+    pub lookups: Vec<PositionLookup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PositionLookup {
+    /** Lets context rules refer to the lookup. */
+    #[serde(default)]
+    pub name: Option<String>,
+    /** See [lookup_flags](super::lookup_flags). */
+    pub flags: u16,
+    /** The index of the mark glyph set of `GDEF` that marks are filtered by. */
+    #[serde(default)]
+    pub mark_filtering_set: Option<u16>,
+    pub positioning: Positioning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Positioning {
+    /** Adjusts single glyphs, of adjustments for the same glyph the first counts. */
+    Single {
+        adjustments: Vec<(u16, ValueRecord)>,
+    },
+    /**
+     * Adjusts pairs of glyphs. Pairs of single glyphs take precedence over pairs of classes, of pairs given twice
+     * the first counts.
+     */
+    Pair {
+        pairs: Vec<GlyphPair>,
+        class_pairs: Vec<ClassPair>,
+    },
+    /** Applies other lookups where sequences of glyphs match, see [ContextRule]. */
+    Context { rules: Vec<ContextRule> },
+    /** Attaches marks to the preceding base glyph, whose anchors are given by mark class. */
+    MarkToBase {
+        marks: Vec<MarkAnchor>,
+        bases: Vec<(u16, Vec<Option<Anchor>>)>,
+    },
+    /** Attaches marks to a component of the preceding ligature, anchors are given by component and mark class. */
+    MarkToLigature {
+        marks: Vec<MarkAnchor>,
+        ligatures: Vec<(u16, Vec<Vec<Option<Anchor>>>)>,
+    },
+    /** Attaches marks to the preceding mark, whose anchors are given by mark class. */
+    MarkToMark {
+        marks: Vec<MarkAnchor>,
+        base_marks: Vec<(u16, Vec<Option<Anchor>>)>,
+    },
+}
+
+/** A point glyphs are attached by, in font units. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Anchor {
+    pub x: i16,
+    pub y: i16,
+}
+
+/** A mark of a mark attachment lookup, which attaches to anchors of its class. Of anchors for the same mark the first counts. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarkAnchor {
+    pub glyph: u16,
+    pub class: u16,
+    pub anchor: Anchor,
+}
+
+/** An adjustment of a glyph, in font units. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ValueRecord {
+    pub x_placement: i16,
+    pub y_placement: i16,
+    pub x_advance: i16,
+    pub y_advance: i16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlyphPair {
+    pub first: u16,
+    pub second: u16,
+    pub first_value: ValueRecord,
+    pub second_value: ValueRecord,
+}
+
+/** Adjusts every pair of a glyph in `first` followed by a glyph in `second`. */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassPair {
+    pub first: Vec<u16>,
+    pub second: Vec<u16>,
+    pub first_value: ValueRecord,
+    pub second_value: ValueRecord,
+}
+
+impl Gpos {
+    /** A `kern` feature for all scripts, with a single lookup that adjusts the advance of the first glyphs. */
+    pub fn kerning(pairs: Vec<GlyphPair>, class_pairs: Vec<ClassPair>) -> Self {
+        Self {
+            language_systems: vec![LanguageSystem {
+                script: DEFAULT_SCRIPT,
+                language: DEFAULT_LANGUAGE,
+                features: vec![0],
+            }],
+            features: vec![Feature {
+                tag: *b"kern",
+                lookups: vec![0],
+            }],
+            lookups: vec![PositionLookup {
+                name: None,
+                flags: 0,
+                mark_filtering_set: None,
+                positioning: Positioning::Pair { pairs, class_pairs },
+            }],
+        }
+    }
+
+    /**
+     * Adds a feature with the lookups, for every language system. If there are none yet, the feature applies to the
+     * default language of all scripts.
+     */
+    pub fn add_feature(&mut self, tag: [u8; 4], lookups: Vec<PositionLookup>) {
+        if self.language_systems.is_empty() {
+            self.language_systems.push(LanguageSystem {
+                script: DEFAULT_SCRIPT,
+                language: DEFAULT_LANGUAGE,
+                features: Vec::new(),
+            });
+        }
+
+        let feature = self.features.len() as u16;
+        for system in self.language_systems.iter_mut() {
+            system.features.push(feature);
+        }
+
+        let first = self.lookups.len() as u16;
+        self.features.push(Feature {
+            tag,
+            lookups: (first..first + lookups.len() as u16).collect(),
+        });
+        self.lookups.extend(lookups);
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, LayoutError> {
+        let lookup_index = lookup_names(
+            *b"GPOS",
+            self.lookups.iter().map(|lookup| lookup.name.as_deref()),
+        )?;
+
+        let lookups = self
+            .lookups
+            .iter()
+            .map(|lookup| {
+                let (lookup_type, subtables) = match &lookup.positioning {
+                    Positioning::Single { adjustments } => {
+                        let mut by_glyph = BTreeMap::new();
+                        for (glyph, value) in adjustments.iter() {
+                            by_glyph.entry(*glyph).or_insert(*value);
+                        }
+
+                        let adjustments: Vec<(u16, ValueRecord)> = by_glyph.into_iter().collect();
+                        let mut subtables = Vec::new();
+                        split_subtables(*b"GPOS", &adjustments, single_subtable, &mut subtables)?;
+
+                        (SINGLE_ADJUSTMENT, subtables)
+                    }
+                    Positioning::Pair { pairs, class_pairs } => {
+                        (PAIR_ADJUSTMENT, pair_subtables(pairs, class_pairs)?)
+                    }
+                    Positioning::Context { rules } => {
+                        match context_subtables(*b"GPOS", rules, &lookup_index)? {
+                            (true, subtables) => (CHAINED_CONTEXT, subtables),
+                            (false, subtables) => (CONTEXT, subtables),
+                        }
+                    }
+                    Positioning::MarkToBase { marks, bases } => {
+                        (MARK_TO_BASE, mark_subtables(marks, bases, anchor_matrix)?)
+                    }
+                    Positioning::MarkToLigature { marks, ligatures } => (
+                        MARK_TO_LIGATURE,
+                        mark_subtables(marks, ligatures, ligature_array)?,
+                    ),
+                    Positioning::MarkToMark { marks, base_marks } => (
+                        MARK_TO_MARK,
+                        mark_subtables(marks, base_marks, anchor_matrix)?,
+                    ),
+                };
+
+                Ok(EncodedLookup {
+                    lookup_type,
+                    flags: lookup.flags,
+                    mark_filtering_set: lookup.mark_filtering_set,
+                    subtables,
+                })
+            })
+            .collect::<Result<Vec<_>, LayoutError>>()?;
+
+        encode_layout_table(*b"GPOS", &self.language_systems, &self.features, &lookups)
+    }
+}
+
+impl GlyphPair {
+    pub fn kerning(first: u16, second: u16, value: i16) -> Self {
+        Self {
+            first,
+            second,
+            first_value: ValueRecord::kerning(value),
+            second_value: ValueRecord::default(),
+        }
+    }
+}
+
+impl ClassPair {
+    pub fn kerning(first: Vec<u16>, second: Vec<u16>, value: i16) -> Self {
+        Self {
+            first,
+            second,
+            first_value: ValueRecord::kerning(value),
+            second_value: ValueRecord::default(),
+        }
+    }
+}
+
+impl ValueRecord {
+    pub fn kerning(x_advance: i16) -> Self {
+        Self {
+            x_advance,
+            ..Default::default()
+        }
+    }
+
+    /** The bits of the fields that are not 0, only those are written. */
+    pub fn format(&self) -> u16 {
+        [
+            self.x_placement,
+            self.y_placement,
+            self.x_advance,
+            self.y_advance,
+        ]
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value != 0)
+        .fold(0, |format, (bit, _)| format | 1 << bit)
+    }
+
+    pub(crate) fn write(&self, format: u16, out: &mut Vec<u8>) {
+        let values = [
+            self.x_placement,
+            self.y_placement,
+            self.x_advance,
+            self.y_advance,
+        ];
+
+        for (bit, value) in values.iter().enumerate() {
+            if format & 1 << bit != 0 {
+                out.extend(value.to_be_bytes());
+            }
+        }
+    }
+}
+
+pub(crate) fn value_size(format: u16) -> usize {
+    2 * format.count_ones() as usize
+}
+
+/** Format 1 if all glyphs get the same adjustment, format 2 otherwise. */
+fn single_subtable(adjustments: &[(u16, ValueRecord)]) -> Option<Vec<u8>> {
+    let glyphs: Vec<u16> = adjustments.iter().map(|(glyph, _)| *glyph).collect();
+    let format = adjustments.iter().fold(0, |f, (_, v)| f | v.format());
+
+    let mut out = Vec::new();
+
+    if adjustments.iter().all(|(_, v)| *v == adjustments[0].1) {
+        out.extend(1u16.to_be_bytes()); // Format
+        out.extend(offset16(6 + value_size(format))?.to_be_bytes());
+        out.extend(format.to_be_bytes());
+        adjustments[0].1.write(format, &mut out);
+    } else {
+        out.extend(2u16.to_be_bytes()); // Format
+        out.extend(offset16(8 + adjustments.len() * value_size(format))?.to_be_bytes());
+        out.extend(format.to_be_bytes());
+        out.extend((adjustments.len() as u16).to_be_bytes());
+        for (_, value) in adjustments.iter() {
+            value.write(format, &mut out);
+        }
+    }
+
+    out.extend(coverage(&glyphs));
+
+    Some(out)
+}
+
+type PairValues = (ValueRecord, ValueRecord);
+
+/** Pairs of single glyphs in format 1 subtables, followed by pairs of classes in format 2 subtables. */
+fn pair_subtables(
+    pairs: &[GlyphPair],
+    class_pairs: &[ClassPair],
+) -> Result<Vec<Vec<u8>>, LayoutError> {
+    let mut by_first: BTreeMap<u16, BTreeMap<u16, PairValues>> = BTreeMap::new();
+
+    for pair in pairs.iter() {
+        by_first
+            .entry(pair.first)
+            .or_default()
+            .entry(pair.second)
+            .or_insert((pair.first_value, pair.second_value));
+    }
+
+    let groups: Vec<PairGroup> = by_first
+        .into_iter()
+        .map(|(first, seconds)| (first, seconds.into_iter().collect()))
+        .collect();
+
+    let mut subtables = Vec::new();
+
+    if !groups.is_empty() {
+        glyph_pair_subtables(groups, &mut subtables);
+    }
+
+    let classes = disjoint_classes(class_pairs);
+
+    if !classes.values.is_empty() {
+        class_pair_subtables(classes, &mut subtables)?;
+    }
+
+    Ok(subtables)
+}
+
+/** A first glyph with its second glyphs, sorted. */
+type PairGroup = (u16, Vec<(u16, PairValues)>);
+
+/**
+ * Writes the groups into one subtable, or into several if offsets would not fit otherwise. The pairs of a single
+ * first glyph can be spread over subtables too, as a subtable without the second glyph passes on to the next one.
+ */
+fn glyph_pair_subtables(mut groups: Vec<PairGroup>, subtables: &mut Vec<Vec<u8>>) {
+    if let Some(subtable) = glyph_pair_subtable(&groups) {
+        subtables.push(subtable);
+        return;
+    }
+
+    let second_half = if groups.len() > 1 {
+        groups.split_off(groups.len() / 2)
+    } else {
+        let (first, pairs) = &mut groups[0];
+        vec![(*first, pairs.split_off(pairs.len() / 2))]
+    };
+
+    glyph_pair_subtables(groups, subtables);
+    glyph_pair_subtables(second_half, subtables);
+}
+
+/** A PairPos format 1 subtable, `None` if an offset does not fit into 16 bits. */
+fn glyph_pair_subtable(groups: &[PairGroup]) -> Option<Vec<u8>> {
+    let values = || {
+        groups
+            .iter()
+            .flat_map(|(_, pairs)| pairs.iter().map(|(_, v)| v))
+    };
+    let first_format = values().fold(0, |format, v| format | v.0.format());
+    let second_format = values().fold(0, |format, v| format | v.1.format());
+
+    let mut pair_sets = Vec::new();
+    let mut offsets = Vec::new();
+    let header_length = 10 + 2 * groups.len();
+
+    for (_, pairs) in groups.iter() {
+        offsets.push(header_length + pair_sets.len());
+
+        pair_sets.extend((pairs.len() as u16).to_be_bytes());
+        for (second, (first_value, second_value)) in pairs.iter() {
+            pair_sets.extend(second.to_be_bytes());
+            first_value.write(first_format, &mut pair_sets);
+            second_value.write(second_format, &mut pair_sets);
+        }
+    }
+
+    let coverage_offset = header_length + pair_sets.len();
+    if coverage_offset > u16::MAX as usize {
+        return None;
+    }
+
+    let firsts: Vec<u16> = groups.iter().map(|(first, _)| *first).collect();
+
+    let mut out = Vec::new();
+    out.extend(1u16.to_be_bytes()); // Format
+    out.extend((coverage_offset as u16).to_be_bytes());
+    out.extend(first_format.to_be_bytes());
+    out.extend(second_format.to_be_bytes());
+    out.extend((groups.len() as u16).to_be_bytes());
+    for offset in offsets {
+        out.extend((offset as u16).to_be_bytes());
+    }
+    out.extend(pair_sets);
+    out.extend(coverage(&firsts));
+
+    Some(out)
+}
+
+/** Class pairs with classes that do not overlap, so they share one class definition per side. */
+#[derive(Default)]
+struct Classes {
+    firsts: Vec<Vec<u16>>,
+    seconds: Vec<Vec<u16>>,
+    values: BTreeMap<(usize, usize), PairValues>,
+}
+
+/**
+ * Splits the classes of the pairs into classes that do not overlap, as a glyph can only be in one class per side and
+ * subtable. Glyphs that are in the same classes of the pairs share a class. Where pairs overlap, the first one
+ * applies, as in [Kern::from_pairs](super::Kern::from_pairs).
+ */
+fn disjoint_classes(class_pairs: &[ClassPair]) -> Classes {
+    let pairs: Vec<&ClassPair> = class_pairs
+        .iter()
+        .filter(|pair| !pair.first.is_empty() && !pair.second.is_empty())
+        .collect();
+
+    let (firsts, firsts_of_pair) = split_classes(pairs.iter().map(|pair| &pair.first));
+    let (seconds, seconds_of_pair) = split_classes(pairs.iter().map(|pair| &pair.second));
+
+    let mut values = BTreeMap::new();
+
+    for (index, pair) in pairs.iter().enumerate() {
+        for first in firsts_of_pair[index].iter() {
+            for second in seconds_of_pair[index].iter() {
+                values
+                    .entry((*first, *second))
+                    .or_insert((pair.first_value, pair.second_value));
+            }
+        }
+    }
+
+    Classes {
+        firsts,
+        seconds,
+        values,
+    }
+}
+
+/** Groups the glyphs by the classes they are in. Returns the groups and, for every class, the groups it consists of. */
+fn split_classes<'a>(
+    classes: impl Iterator<Item = &'a Vec<u16>>,
+) -> (Vec<Vec<u16>>, Vec<Vec<usize>>) {
+    let mut memberships: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+    let mut class_count = 0;
+
+    for (class, glyphs) in classes.enumerate() {
+        for glyph in glyphs.iter() {
+            let member_of = memberships.entry(*glyph).or_default();
+
+            if member_of.last() != Some(&class) {
+                member_of.push(class);
+            }
+        }
+
+        class_count += 1;
+    }
+
+    let mut groups: BTreeMap<Vec<usize>, Vec<u16>> = BTreeMap::new();
+    for (glyph, member_of) in memberships {
+        groups.entry(member_of).or_default().push(glyph);
+    }
+
+    let mut groups_of_class = vec![Vec::new(); class_count];
+    let mut glyphs = Vec::with_capacity(groups.len());
+
+    for (group, (member_of, members)) in groups.into_iter().enumerate() {
+        for class in member_of {
+            groups_of_class[class].push(group);
+        }
+
+        glyphs.push(members);
+    }
+
+    (glyphs, groups_of_class)
+}
+
+/** Writes the classes into one subtable, or splits the first classes among several if offsets do not fit. */
+fn class_pair_subtables(classes: Classes, subtables: &mut Vec<Vec<u8>>) -> Result<(), LayoutError> {
+    if let Some(subtable) = class_pair_subtable(&classes) {
+        subtables.push(subtable);
+        return Ok(());
+    }
+
+    if classes.firsts.len() == 1 {
+        return Err(LayoutError::invalid_value(
+            *b"GPOS",
+            "class_pairs",
+            format!(
+                "a class is kerned against {} classes, more than a subtable can hold",
+                classes.seconds.len()
+            ),
+        ));
+    }
+
+    let half = classes.firsts.len() / 2;
+
+    for firsts in [0..half, half..classes.firsts.len()] {
+        let mut part = Classes::default();
+        let mut second_index = BTreeMap::new();
+
+        for ((first, second), values) in classes.values.iter() {
+            if !firsts.contains(first) {
+                continue;
+            }
+
+            let second = *second_index.entry(*second).or_insert_with(|| {
+                part.seconds.push(classes.seconds[*second].clone());
+                part.seconds.len() - 1
+            });
+
+            part.values.insert((first - firsts.start, second), *values);
+        }
+
+        part.firsts = classes.firsts[firsts].to_vec();
+        class_pair_subtables(part, subtables)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * A PairPos format 2 subtable, `None` if an offset does not fit into 16 bits. The first classes are numbered from
+ * 0, the second ones from 1 as class 0 holds all glyphs without a class.
+ */
+fn class_pair_subtable(classes: &Classes) -> Option<Vec<u8>> {
+    let first_format = classes.values.values().fold(0, |f, v| f | v.0.format());
+    let second_format = classes.values.values().fold(0, |f, v| f | v.1.format());
+
+    let first_count = classes.firsts.len();
+    let second_count = classes.seconds.len() + 1;
+    let record_size = value_size(first_format) + value_size(second_format);
+
+    let mut records = Vec::with_capacity(first_count * second_count * record_size);
+    for first in 0..first_count {
+        for second in 0..second_count {
+            let (first_value, second_value) = second
+                .checked_sub(1)
+                .and_then(|second| classes.values.get(&(first, second)))
+                .copied()
+                .unwrap_or_default();
+
+            first_value.write(first_format, &mut records);
+            second_value.write(second_format, &mut records);
+        }
+    }
+
+    let class_list = |classes: &[Vec<u16>], first_class: usize| {
+        let mut glyphs: Vec<(u16, u16)> = classes
+            .iter()
+            .enumerate()
+            .flat_map(|(class, glyphs)| {
+                glyphs
+                    .iter()
+                    .map(move |glyph| (*glyph, (first_class + class) as u16))
+            })
+            .collect();
+        glyphs.sort_unstable();
+        glyphs
+    };
+
+    let firsts = class_list(&classes.firsts, 0);
+    let covered: Vec<u16> = firsts.iter().map(|(glyph, _)| *glyph).collect();
+
+    let coverage = coverage(&covered);
+    let first_class_def = class_def(&firsts);
+    let second_class_def = class_def(&class_list(&classes.seconds, 1));
+
+    let coverage_offset = 16 + records.len();
+    let first_class_def_offset = coverage_offset + coverage.len();
+    let second_class_def_offset = first_class_def_offset + first_class_def.len();
+
+    if second_class_def_offset > u16::MAX as usize {
+        return None;
+    }
+
+    let mut out = Vec::new();
+    out.extend(2u16.to_be_bytes()); // Format
+    out.extend((coverage_offset as u16).to_be_bytes());
+    out.extend(first_format.to_be_bytes());
+    out.extend(second_format.to_be_bytes());
+    out.extend((first_class_def_offset as u16).to_be_bytes());
+    out.extend((second_class_def_offset as u16).to_be_bytes());
+    out.extend((first_count as u16).to_be_bytes());
+    out.extend((second_count as u16).to_be_bytes());
+    out.extend(records);
+    out.extend(coverage);
+    out.extend(first_class_def);
+    out.extend(second_class_def);
+
+    Some(out)
+}
+
+impl LayoutableTable for Gpos {
+    fn tag(&self) -> [u8; 4] {
+        *b"GPOS"
+    }
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for Gpos {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        Box::new(LayoutedEncoded::new(*b"GPOS", self.encode(), layouter))
+    }
+}
+
+/**
+ * MarkBasePos, MarkLigPos and MarkMarkPos share their format: coverages of the marks and of the glyphs they attach
+ * to, the mark array and an array of the anchors of the other glyphs. The other glyphs are split over several
+ * subtables if needed, each with all marks.
+ */
+fn mark_subtables<T: Clone>(
+    marks: &[MarkAnchor],
+    targets: &[(u16, T)],
+    target_array: fn(&[T], usize) -> Option<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, LayoutError> {
+    let mut by_glyph = BTreeMap::new();
+    for mark in marks.iter() {
+        by_glyph.entry(mark.glyph).or_insert(*mark);
+    }
+    let marks: Vec<MarkAnchor> = by_glyph.into_values().collect();
+
+    let mut by_glyph = BTreeMap::new();
+    for (glyph, anchors) in targets.iter() {
+        by_glyph.entry(*glyph).or_insert(anchors);
+    }
+    let targets: Vec<(u16, T)> = by_glyph
+        .into_iter()
+        .map(|(glyph, anchors)| (glyph, anchors.clone()))
+        .collect();
+
+    let class_count = marks
+        .iter()
+        .map(|mark| mark.class as usize + 1)
+        .max()
+        .unwrap_or(0);
+
+    let subtable = |targets: &[(u16, T)]| -> Option<Vec<u8>> {
+        let mark_glyphs: Vec<u16> = marks.iter().map(|mark| mark.glyph).collect();
+        let target_glyphs: Vec<u16> = targets.iter().map(|(glyph, _)| *glyph).collect();
+        let anchors: Vec<T> = targets.iter().map(|(_, anchors)| anchors.clone()).collect();
+
+        let mark_coverage = coverage(&mark_glyphs);
+        let target_coverage = coverage(&target_glyphs);
+        let mark_array = mark_array(&marks)?;
+        let target_array = target_array(&anchors, class_count)?;
+
+        let mut out = Vec::new();
+        out.extend(1u16.to_be_bytes()); // Format
+        out.extend(12u16.to_be_bytes());
+        out.extend(offset16(12 + mark_coverage.len())?.to_be_bytes());
+        out.extend((class_count as u16).to_be_bytes());
+        out.extend(offset16(12 + mark_coverage.len() + target_coverage.len())?.to_be_bytes());
+        out.extend(
+            offset16(12 + mark_coverage.len() + target_coverage.len() + mark_array.len())?
+                .to_be_bytes(),
+        );
+        out.extend(mark_coverage);
+        out.extend(target_coverage);
+        out.extend(mark_array);
+        out.extend(target_array);
+
+        Some(out)
+    };
+
+    if marks.is_empty() || targets.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut subtables = Vec::new();
+    split_subtables(*b"GPOS", &targets, subtable, &mut subtables)?;
+
+    Ok(subtables)
+}
+
+/** Anchor tables in format 1 after the records that point to them, identical anchors are written once. */
+struct Anchors {
+    data: Vec<u8>,
+    offsets: HashMap<Anchor, usize>,
+}
+
+impl Anchors {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            offsets: HashMap::new(),
+        }
+    }
+
+    /** The offset of the anchor from the start of the anchors. */
+    fn offset(&mut self, anchor: Anchor) -> usize {
+        *self.offsets.entry(anchor).or_insert_with(|| {
+            let offset = self.data.len();
+            self.data.extend(1u16.to_be_bytes()); // Format
+            self.data.extend(anchor.x.to_be_bytes());
+            self.data.extend(anchor.y.to_be_bytes());
+            offset
+        })
+    }
+}
+
+/** The mark class and anchor of each mark, in coverage order. */
+fn mark_array(marks: &[MarkAnchor]) -> Option<Vec<u8>> {
+    let header_length = 2 + 4 * marks.len();
+    let mut anchors = Anchors::new();
+
+    let mut out = Vec::new();
+    out.extend((marks.len() as u16).to_be_bytes());
+    for mark in marks.iter() {
+        out.extend(mark.class.to_be_bytes());
+        out.extend(offset16(header_length + anchors.offset(mark.anchor))?.to_be_bytes());
+    }
+    out.extend(anchors.data);
+
+    Some(out)
+}
+
+/** A BaseArray, Mark2Array or LigatureAttach: an anchor per mark class for each row, missing anchors at offset 0. */
+fn anchor_matrix(rows: &[Vec<Option<Anchor>>], class_count: usize) -> Option<Vec<u8>> {
+    let header_length = 2 + 2 * class_count * rows.len();
+    let mut anchors = Anchors::new();
+
+    let mut out = Vec::new();
+    out.extend((rows.len() as u16).to_be_bytes());
+    for row in rows.iter() {
+        for class in 0..class_count {
+            let offset = match row.get(class).copied().flatten() {
+                Some(anchor) => offset16(header_length + anchors.offset(anchor))?,
+                None => 0,
+            };
+            out.extend(offset.to_be_bytes());
+        }
+    }
+    out.extend(anchors.data);
+
+    Some(out)
+}
+
+/** The LigatureArray, with the anchors of each ligature by component. */
+fn ligature_array(ligatures: &[Vec<Vec<Option<Anchor>>>], class_count: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    out.extend((ligatures.len() as u16).to_be_bytes());
+
+    let mut attachments = Vec::new();
+    for components in ligatures.iter() {
+        out.extend(offset16(2 + 2 * ligatures.len() + attachments.len())?.to_be_bytes());
+        attachments.extend(anchor_matrix(components, class_count)?);
+    }
+    out.extend(attachments);
+
+    Some(out)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([data[offset], data[offset + 1]])
+    }
+
+    /** The coverage index of the glyph. */
+    pub(crate) fn covered(coverage: &[u8], glyph: u16) -> Option<usize> {
+        let count = u16_at(coverage, 2) as usize;
+
+        match u16_at(coverage, 0) {
+            1 => (0..count).position(|n| u16_at(coverage, 4 + 2 * n) == glyph),
+            _ => (0..count).find_map(|n| {
+                let range = 4 + 6 * n;
+                let (start, end) = (u16_at(coverage, range), u16_at(coverage, range + 2));
+
+                (start..=end)
+                    .contains(&glyph)
+                    .then(|| (u16_at(coverage, range + 4) + glyph - start) as usize)
+            }),
+        }
+    }
+
+    pub(crate) fn class_of(class_def: &[u8], glyph: u16) -> u16 {
+        match u16_at(class_def, 0) {
+            1 => {
+                let start = u16_at(class_def, 2);
+                let count = u16_at(class_def, 4);
+
+                match glyph.checked_sub(start).filter(|n| *n < count) {
+                    Some(n) => u16_at(class_def, 6 + 2 * n as usize),
+                    None => 0,
+                }
+            }
+            _ => (0..u16_at(class_def, 2) as usize)
+                .map(|n| 4 + 6 * n)
+                .find(|range| {
+                    (u16_at(class_def, *range)..=u16_at(class_def, range + 2)).contains(&glyph)
+                })
+                .map_or(0, |range| u16_at(class_def, range + 4)),
+        }
+    }
+
+    /** The subtables of a lookup with extensions resolved, and the lookup type. */
+    pub(crate) fn lookup_subtables(
+        data: &[u8],
+        lookup: usize,
+        extension_type: u16,
+    ) -> (u16, Vec<&[u8]>) {
+        let lookup_list = &data[u16_at(data, 8) as usize..];
+        let table = &lookup_list[u16_at(lookup_list, 2 + 2 * lookup) as usize..];
+        let lookup_type = u16_at(table, 0);
+
+        let subtables: Vec<&[u8]> = (0..u16_at(table, 4) as usize)
+            .map(|n| &table[u16_at(table, 6 + 2 * n) as usize..])
+            .collect();
+
+        if lookup_type != extension_type {
+            return (lookup_type, subtables);
+        }
+
+        let lookup_type = u16_at(subtables[0], 2);
+        let subtables = subtables
+            .into_iter()
+            .map(|extension| {
+                let offset = u32::from_be_bytes(extension[4..8].try_into().unwrap());
+                &extension[offset as usize..]
+            })
+            .collect();
+
+        (lookup_type, subtables)
+    }
+
+    /** The kerning of the pair, as the first lookup applies it. */
+    fn kerning(data: &[u8], first: u16, second: u16) -> Option<i16> {
+        let (lookup_type, subtables) = lookup_subtables(data, 0, 9);
+        assert_eq!(lookup_type, PAIR_ADJUSTMENT);
+
+        subtables.into_iter().find_map(|subtable| {
+            let coverage_index = covered(&subtable[u16_at(subtable, 2) as usize..], first)?;
+            let (first_format, second_format) = (u16_at(subtable, 4), u16_at(subtable, 6));
+            assert_eq!(first_format, 4);
+
+            let record_size = value_size(first_format) + value_size(second_format);
+
+            match u16_at(subtable, 0) {
+                1 => {
+                    let pair_set = &subtable[u16_at(subtable, 10 + 2 * coverage_index) as usize..];
+
+                    (0..u16_at(pair_set, 0) as usize)
+                        .map(|n| 2 + n * (2 + record_size))
+                        .find(|record| u16_at(pair_set, *record) == second)
+                        .map(|record| u16_at(pair_set, record + 2) as i16)
+                }
+                _ => {
+                    let first_class = class_of(&subtable[u16_at(subtable, 8) as usize..], first);
+                    let second_class = class_of(&subtable[u16_at(subtable, 10) as usize..], second);
+                    let record = 16
+                        + (first_class as usize * u16_at(subtable, 14) as usize
+                            + second_class as usize)
+                            * record_size;
+
+                    Some(u16_at(subtable, record) as i16)
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn glyph_pairs_take_precedence_over_class_pairs() {
+        let gpos = Gpos::kerning(
+            vec![GlyphPair::kerning(1, 2, -30), GlyphPair::kerning(1, 2, 99)],
+            vec![
+                ClassPair::kerning(vec![1, 3], vec![2, 4], -10),
+                ClassPair::kerning(vec![1, 3], vec![5], -20),
+                // Overlaps the first class, which is split so both fit into one subtable
+                ClassPair::kerning(vec![3, 6], vec![2], -40),
+            ],
+        );
+
+        let data = gpos.encode().unwrap();
+
+        assert_eq!(&data[..4], &[0, 1, 0, 0]);
+        assert_eq!(lookup_subtables(&data, 0, 9).1.len(), 2);
+
+        assert_eq!(kerning(&data, 1, 2), Some(-30));
+        assert_eq!(kerning(&data, 3, 4), Some(-10));
+        assert_eq!(kerning(&data, 1, 5), Some(-20));
+        assert_eq!(kerning(&data, 3, 2), Some(-10));
+        assert_eq!(kerning(&data, 6, 2), Some(-40));
+        assert_eq!(kerning(&data, 2, 1), None);
+        // Class 0 of the second glyphs kerns by nothing
+        assert_eq!(kerning(&data, 1, 7), Some(0));
+    }
+
+    #[test]
+    fn earlier_class_pairs_win_over_later_overlapping_ones() {
+        let data = Gpos::kerning(
+            vec![],
+            vec![
+                ClassPair::kerning(vec![1], vec![10], -10),
+                ClassPair::kerning(vec![1, 2], vec![10], -20),
+                ClassPair::kerning(vec![2], vec![10], -30),
+            ],
+        )
+        .encode()
+        .unwrap();
+
+        assert_eq!(kerning(&data, 1, 10), Some(-10));
+        assert_eq!(kerning(&data, 2, 10), Some(-20));
+    }
+
+    #[test]
+    fn pairs_next_to_overlapping_ones_are_kept() {
+        let data = Gpos::kerning(
+            vec![],
+            vec![
+                ClassPair::kerning(vec![1, 2], vec![10], -10),
+                ClassPair::kerning(vec![1], vec![11], -20),
+                ClassPair::kerning(vec![2, 3], vec![10, 11], -30),
+            ],
+        )
+        .encode()
+        .unwrap();
+
+        assert_eq!(lookup_subtables(&data, 0, 9).1.len(), 1);
+        assert_eq!(kerning(&data, 1, 10), Some(-10));
+        assert_eq!(kerning(&data, 1, 11), Some(-20));
+        assert_eq!(kerning(&data, 2, 10), Some(-10));
+        assert_eq!(kerning(&data, 2, 11), Some(-30));
+        assert_eq!(kerning(&data, 3, 10), Some(-30));
+        assert_eq!(kerning(&data, 3, 12), Some(0));
+    }
+
+    #[test]
+    fn large_kerning_is_split_into_subtables() {
+        let pairs: Vec<_> = (0..200u16)
+            .flat_map(|first| (0..120u16).map(move |second| (first, second)))
+            .map(|(first, second)| GlyphPair::kerning(first, 1000 + second, -(second as i16) - 1))
+            .collect();
+
+        let data = Gpos::kerning(pairs, Vec::new()).encode().unwrap();
+
+        let (_, subtables) = lookup_subtables(&data, 0, 9);
+        assert!(subtables.len() > 1);
+
+        assert_eq!(kerning(&data, 0, 1000), Some(-1));
+        assert_eq!(kerning(&data, 199, 1119), Some(-120));
+    }
+
+    /** The anchors of the mark and of the glyph or ligature component it attaches to, if any. */
+    fn attachment(
+        subtable: &[u8],
+        lookup_type: u16,
+        mark: u16,
+        target: u16,
+        component: usize,
+    ) -> Option<(Anchor, Anchor)> {
+        let anchor = |table: &[u8], offset: u16| Anchor {
+            x: u16_at(table, offset as usize + 2) as i16,
+            y: u16_at(table, offset as usize + 4) as i16,
+        };
+
+        let mark_index = covered(&subtable[u16_at(subtable, 2) as usize..], mark)?;
+        let target_index = covered(&subtable[u16_at(subtable, 4) as usize..], target)?;
+        let class_count = u16_at(subtable, 6) as usize;
+
+        let mark_array = &subtable[u16_at(subtable, 8) as usize..];
+        let class = u16_at(mark_array, 2 + 4 * mark_index) as usize;
+        let mark_anchor = anchor(mark_array, u16_at(mark_array, 4 + 4 * mark_index));
+
+        let mut array = &subtable[u16_at(subtable, 10) as usize..];
+        let mut row = target_index;
+        if lookup_type == MARK_TO_LIGATURE {
+            array = &array[u16_at(array, 2 + 2 * target_index) as usize..];
+            row = component;
+        }
+
+        let offset = u16_at(array, 2 + 2 * (row * class_count + class));
+        (offset != 0).then(|| (mark_anchor, anchor(array, offset)))
+    }
+
+    #[test]
+    fn marks_attach_to_bases_and_ligature_components() {
+        let anchor = |x, y| Anchor { x, y };
+        let marks = vec![
+            MarkAnchor {
+                glyph: 5,
+                class: 0,
+                anchor: anchor(50, 400),
+            },
+            MarkAnchor {
+                glyph: 6,
+                class: 1,
+                anchor: anchor(50, -10),
+            },
+        ];
+        let lookup = |positioning| PositionLookup {
+            name: None,
+            flags: 0,
+            mark_filtering_set: None,
+            positioning,
+        };
+
+        let mut gpos = Gpos::default();
+        gpos.add_feature(
+            *b"mark",
+            vec![
+                lookup(Positioning::MarkToBase {
+                    marks: marks.clone(),
+                    bases: vec![
+                        (2, vec![Some(anchor(250, 500)), Some(anchor(250, 0))]),
+                        (1, vec![Some(anchor(200, 500))]),
+                    ],
+                }),
+                lookup(Positioning::MarkToLigature {
+                    marks,
+                    ligatures: vec![(
+                        3,
+                        vec![
+                            vec![Some(anchor(100, 500))],
+                            vec![Some(anchor(400, 520)), Some(anchor(400, 0))],
+                        ],
+                    )],
+                }),
+            ],
+        );
+        let data = gpos.encode().unwrap();
+
+        let (lookup_type, subtables) = lookup_subtables(&data, 0, 9);
+        assert_eq!(lookup_type, MARK_TO_BASE);
+        assert_eq!(
+            attachment(subtables[0], lookup_type, 6, 2, 0),
+            Some((anchor(50, -10), anchor(250, 0)))
+        );
+        assert_eq!(
+            attachment(subtables[0], lookup_type, 5, 1, 0),
+            Some((anchor(50, 400), anchor(200, 500)))
+        );
+        assert_eq!(attachment(subtables[0], lookup_type, 6, 1, 0), None);
+
+        let (lookup_type, subtables) = lookup_subtables(&data, 1, 9);
+        assert_eq!(lookup_type, MARK_TO_LIGATURE);
+        assert_eq!(
+            attachment(subtables[0], lookup_type, 5, 3, 1),
+            Some((anchor(50, 400), anchor(400, 520)))
+        );
+        assert_eq!(attachment(subtables[0], lookup_type, 6, 3, 0), None);
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout::LayoutError,
+    open_type::{
+        tables::{
+            context_subtables, coverage, encode_layout_table, lookup_names, offset16,
+            split_subtables, ContextRule, EncodedLookup, Feature, LanguageSystem, LayoutedEncoded,
+            DEFAULT_LANGUAGE, DEFAULT_SCRIPT,
+        },
+        LayoutableTable, LayoutedTable,
+    },
+    Layoutable,
+};
+
+/** Glyph substitution: features whose lookups replace glyphs by others. */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Gsub {
+    pub language_systems: Vec<LanguageSystem>,
+    pub features: Vec<Feature>,
+    pub lookups: Vec<SubstitutionLookup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubstitutionLookup {
+    /** Lets context rules refer to the lookup. */
+    #[serde(default)]
+    pub name: Option<String>,
+    /** See [lookup_flags](super::lookup_flags). */
+    pub flags: u16,
+    /** The index of the mark glyph set of `GDEF` that marks are filtered by. */
+    #[serde(default)]
+    pub mark_filtering_set: Option<u16>,
+    pub substitution: Substitution,
+}
+
+/** Of substitutions for the same glyph, or ligatures of the same components, the first counts. */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Substitution {
+    /** Replaces a glyph by another one. */
+    Single { substitutions: Vec<(u16, u16)> },
+    /** Replaces a glyph by a sequence of glyphs. */
+    Multiple { substitutions: Vec<(u16, Vec<u16>)> },
+    /** Offers alternates of a glyph to choose from, the first is used if the application does not choose. */
+    Alternate { alternates: Vec<(u16, Vec<u16>)> },
+    /** Replaces sequences of glyphs by a single one. Where ligatures start with the same glyph, the longest applies. */
+    Ligature { ligatures: Vec<Ligature> },
+    /** Applies other lookups where sequences of glyphs match, see [ContextRule]. */
+    Context { rules: Vec<ContextRule> },
+    /**
+     * Replaces glyphs by others where their context matches, applied from the end of the text to its start so a
+     * substitution can depend on the ones after it. The first matching rule counts.
+     */
+    ReverseChainSingle { rules: Vec<ReverseChainRule> },
+}
+
+/** Replaces glyphs by others where they are preceded and followed by the given sets of glyphs, in text order. */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReverseChainRule {
+    #[serde(default)]
+    pub backtrack: Vec<Vec<u16>>,
+    #[serde(default)]
+    pub lookahead: Vec<Vec<u16>>,
+    pub substitutions: Vec<(u16, u16)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ligature {
+    pub components: Vec<u16>,
+    pub glyph: u16,
+}
+
+impl Gsub {
+    /**
+     * Adds a feature with a single lookup, for every language system. If there are none yet, the feature applies to
+     * the default language of all scripts.
+     */
+    pub fn add_feature(&mut self, tag: [u8; 4], lookup: SubstitutionLookup) {
+        if self.language_systems.is_empty() {
+            self.language_systems.push(LanguageSystem {
+                script: DEFAULT_SCRIPT,
+                language: DEFAULT_LANGUAGE,
+                features: Vec::new(),
+            });
+        }
+
+        let feature = self.features.len() as u16;
+        for system in self.language_systems.iter_mut() {
+            system.features.push(feature);
+        }
+
+        self.features.push(Feature {
+            tag,
+            lookups: vec![self.lookups.len() as u16],
+        });
+        self.lookups.push(lookup);
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, LayoutError> {
+        let lookup_index = lookup_names(
+            *b"GSUB",
+            self.lookups.iter().map(|lookup| lookup.name.as_deref()),
+        )?;
+
+        let lookups = self
+            .lookups
+            .iter()
+            .map(|lookup| {
+                let (lookup_type, subtables) = lookup.substitution.subtables(&lookup_index)?;
+
+                Ok(EncodedLookup {
+                    lookup_type,
+                    flags: lookup.flags,
+                    mark_filtering_set: lookup.mark_filtering_set,
+                    subtables,
+                })
+            })
+            .collect::<Result<Vec<_>, LayoutError>>()?;
+
+        encode_layout_table(*b"GSUB", &self.language_systems, &self.features, &lookups)
+    }
+}
+
+impl Substitution {
+    /** The lookup type and the subtables. */
+    fn subtables(
+        &self,
+        lookup_index: &BTreeMap<&str, u16>,
+    ) -> Result<(u16, Vec<Vec<u8>>), LayoutError> {
+        let mut subtables = Vec::new();
+
+        let lookup_type = match self {
+            Substitution::Single { substitutions } => {
+                let substitutions = first_per_glyph(substitutions);
+                split_subtables(*b"GSUB", &substitutions, single_subtable, &mut subtables)?;
+                1
+            }
+            Substitution::Multiple { substitutions } => {
+                if substitutions
+                    .iter()
+                    .any(|(_, sequence)| sequence.is_empty())
+                {
+                    return Err(invalid("substitutions", "a glyph is replaced by nothing"));
+                }
+
+                let sequences = first_per_glyph(substitutions);
+                split_subtables(*b"GSUB", &sequences, sequence_subtable, &mut subtables)?;
+                2
+            }
+            Substitution::Alternate { alternates } => {
+                if alternates
+                    .iter()
+                    .any(|(_, alternates)| alternates.is_empty())
+                {
+                    return Err(invalid("alternates", "a glyph has no alternates"));
+                }
+
+                let sequences = first_per_glyph(alternates);
+                split_subtables(*b"GSUB", &sequences, sequence_subtable, &mut subtables)?;
+                3
+            }
+            Substitution::Ligature { ligatures } => {
+                if ligatures.iter().any(|l| l.components.is_empty()) {
+                    return Err(invalid("ligatures", "a ligature has no components"));
+                }
+
+                let sets = ligature_sets(ligatures);
+                split_subtables(*b"GSUB", &sets, ligature_subtable, &mut subtables)?;
+                4
+            }
+            Substitution::Context { rules } => {
+                let (chained, context) = context_subtables(*b"GSUB", rules, lookup_index)?;
+                subtables = context;
+
+                match chained {
+                    true => 6,
+                    false => 5,
+                }
+            }
+            Substitution::ReverseChainSingle { rules } => {
+                for rule in rules.iter() {
+                    let context = rule.backtrack.iter().chain(rule.lookahead.iter());
+                    if context.clone().any(Vec::is_empty) {
+                        return Err(invalid("rules", "a position matches no glyph"));
+                    }
+
+                    let substitutions = first_per_glyph(&rule.substitutions);
+                    split_subtables(
+                        *b"GSUB",
+                        &substitutions,
+                        |substitutions| reverse_chain_subtable(rule, substitutions),
+                        &mut subtables,
+                    )?;
+                }
+
+                8
+            }
+        };
+
+        Ok((lookup_type, subtables))
+    }
+}
+
+fn invalid(field: &'static str, reason: &str) -> LayoutError {
+    LayoutError::invalid_value(*b"GSUB", field, reason)
+}
+
+/** Sorted by glyph, of entries for the same glyph only the first. */
+fn first_per_glyph<T: Clone>(entries: &[(u16, T)]) -> Vec<(u16, T)> {
+    let mut by_glyph = BTreeMap::new();
+
+    for (glyph, entry) in entries.iter() {
+        by_glyph.entry(*glyph).or_insert(entry);
+    }
+
+    by_glyph
+        .into_iter()
+        .map(|(glyph, entry)| (glyph, entry.clone()))
+        .collect()
+}
+
+/** Format 1 if all glyphs move by the same delta, format 2 otherwise. */
+fn single_subtable(substitutions: &[(u16, u16)]) -> Option<Vec<u8>> {
+    let glyphs: Vec<u16> = substitutions.iter().map(|(glyph, _)| *glyph).collect();
+    let delta = |(glyph, substitute): &(u16, u16)| substitute.wrapping_sub(*glyph);
+
+    let mut out = Vec::new();
+
+    if substitutions
+        .iter()
+        .all(|s| delta(s) == delta(&substitutions[0]))
+    {
+        out.extend(1u16.to_be_bytes()); // Format
+        out.extend(6u16.to_be_bytes()); // Coverage offset
+        out.extend(delta(&substitutions[0]).to_be_bytes());
+    } else {
+        out.extend(2u16.to_be_bytes()); // Format
+        out.extend(offset16(6 + 2 * substitutions.len())?.to_be_bytes());
+        out.extend((substitutions.len() as u16).to_be_bytes());
+        for (_, substitute) in substitutions.iter() {
+            out.extend(substitute.to_be_bytes());
+        }
+    }
+
+    out.extend(coverage(&glyphs));
+
+    Some(out)
+}
+
+/**
+ * The structure of multiple and alternate substitution subtables: a coverage and for each covered glyph an offset to
+ * a list of glyphs.
+ */
+fn sequence_subtable(sequences: &[(u16, Vec<u16>)]) -> Option<Vec<u8>> {
+    let header_length = 6 + 2 * sequences.len();
+
+    let mut offsets = Vec::new();
+    let mut tables = Vec::new();
+
+    for (_, sequence) in sequences.iter() {
+        offsets.push(offset16(header_length + tables.len())?);
+
+        tables.extend((sequence.len() as u16).to_be_bytes());
+        for glyph in sequence.iter() {
+            tables.extend(glyph.to_be_bytes());
+        }
+    }
+
+    let covered: Vec<u16> = sequences.iter().map(|(glyph, _)| *glyph).collect();
+
+    let mut out = Vec::new();
+    out.extend(1u16.to_be_bytes()); // Format
+    out.extend(offset16(header_length + tables.len())?.to_be_bytes());
+    out.extend((sequences.len() as u16).to_be_bytes());
+    for offset in offsets {
+        out.extend(offset.to_be_bytes());
+    }
+    out.extend(tables);
+    out.extend(coverage(&covered));
+
+    Some(out)
+}
+
+/**
+ * Groups the ligatures by their first component. Within a group longer ligatures come first, as the first one that
+ * matches is applied.
+ */
+fn ligature_sets(ligatures: &[Ligature]) -> Vec<(u16, Vec<&Ligature>)> {
+    let mut sets: BTreeMap<u16, Vec<&Ligature>> = BTreeMap::new();
+
+    for ligature in ligatures.iter() {
+        let set = sets.entry(ligature.components[0]).or_default();
+
+        if set.iter().all(|l| l.components != ligature.components) {
+            set.push(ligature);
+        }
+    }
+
+    for set in sets.values_mut() {
+        // Stable, so ligatures of the same length keep their order
+        set.sort_by_key(|ligature| std::cmp::Reverse(ligature.components.len()));
+    }
+
+    sets.into_iter().collect()
+}
+
+fn ligature_subtable(sets: &[(u16, Vec<&Ligature>)]) -> Option<Vec<u8>> {
+    let header_length = 6 + 2 * sets.len();
+
+    let mut offsets = Vec::new();
+    let mut tables = Vec::new();
+
+    for (_, ligatures) in sets.iter() {
+        offsets.push(offset16(header_length + tables.len())?);
+
+        let set_header_length = 2 + 2 * ligatures.len();
+        let mut ligature_tables = Vec::new();
+
+        tables.extend((ligatures.len() as u16).to_be_bytes());
+        for ligature in ligatures.iter() {
+            tables.extend(offset16(set_header_length + ligature_tables.len())?.to_be_bytes());
+
+            ligature_tables.extend(ligature.glyph.to_be_bytes());
+            ligature_tables.extend((ligature.components.len() as u16).to_be_bytes());
+            for component in ligature.components[1..].iter() {
+                ligature_tables.extend(component.to_be_bytes());
+            }
+        }
+        tables.extend(ligature_tables);
+    }
+
+    let covered: Vec<u16> = sets.iter().map(|(glyph, _)| *glyph).collect();
+
+    let mut out = Vec::new();
+    out.extend(1u16.to_be_bytes()); // Format
+    out.extend(offset16(header_length + tables.len())?.to_be_bytes());
+    out.extend((sets.len() as u16).to_be_bytes());
+    for offset in offsets {
+        out.extend(offset.to_be_bytes());
+    }
+    out.extend(tables);
+    out.extend(coverage(&covered));
+
+    Some(out)
+}
+
+/** Format 1, with a coverage for every position of the context. */
+fn reverse_chain_subtable(
+    rule: &ReverseChainRule,
+    substitutions: &[(u16, u16)],
+) -> Option<Vec<u8>> {
+    let (backtrack, lookahead) = (&rule.backtrack, &rule.lookahead);
+
+    let sorted = |set: &Vec<u16>| {
+        let mut set = set.clone();
+        set.sort_unstable();
+        set.dedup();
+        set
+    };
+
+    // The backtrack is stored nearest glyph first
+    let context: Vec<Vec<u16>> = backtrack
+        .iter()
+        .rev()
+        .chain(lookahead.iter())
+        .map(sorted)
+        .collect();
+
+    let header_length = 10 + 2 * context.len() + 2 * substitutions.len();
+    let glyphs: Vec<u16> = substitutions.iter().map(|(glyph, _)| *glyph).collect();
+
+    let mut coverages = coverage(&glyphs);
+    let mut offsets = Vec::new();
+    for set in context.iter() {
+        offsets.push(offset16(header_length + coverages.len())?);
+        coverages.extend(coverage(set));
+    }
+    offset16(header_length + coverages.len())?;
+
+    let mut out = Vec::new();
+    out.extend(1u16.to_be_bytes()); // Format
+    out.extend((header_length as u16).to_be_bytes());
+    out.extend((backtrack.len() as u16).to_be_bytes());
+    for offset in offsets[..backtrack.len()].iter() {
+        out.extend(offset.to_be_bytes());
+    }
+    out.extend((lookahead.len() as u16).to_be_bytes());
+    for offset in offsets[backtrack.len()..].iter() {
+        out.extend(offset.to_be_bytes());
+    }
+    out.extend((substitutions.len() as u16).to_be_bytes());
+    for (_, substitute) in substitutions.iter() {
+        out.extend(substitute.to_be_bytes());
+    }
+    out.extend(coverages);
+
+    Some(out)
+}
+
+impl LayoutableTable for Gsub {
+    fn tag(&self) -> [u8; 4] {
+        *b"GSUB"
+    }
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for Gsub {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        Box::new(LayoutedEncoded::new(*b"GSUB", self.encode(), layouter))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::open_type::tables::gpos::test::{covered, lookup_subtables, u16_at};
+
+    /** What the lookup replaces the glyphs at the start of the run by, and how many glyphs it consumes. */
+    pub(crate) fn substitute(data: &[u8], lookup: usize, run: &[u16]) -> Option<(Vec<u16>, usize)> {
+        let (lookup_type, subtables) = lookup_subtables(data, lookup, 7);
+
+        subtables.into_iter().find_map(|subtable| {
+            let index = covered(&subtable[u16_at(subtable, 2) as usize..], run[0])?;
+            let list = |table: &[u8]| -> Vec<u16> {
+                (0..u16_at(table, 0) as usize)
+                    .map(|n| u16_at(table, 2 + 2 * n))
+                    .collect()
+            };
+
+            match (lookup_type, u16_at(subtable, 0)) {
+                (1, 1) => Some((vec![run[0].wrapping_add(u16_at(subtable, 4))], 1)),
+                (1, _) => Some((vec![u16_at(subtable, 6 + 2 * index)], 1)),
+                (8, _) => {
+                    let backtrack = u16_at(subtable, 4) as usize;
+                    let lookahead = u16_at(subtable, 6 + 2 * backtrack) as usize;
+                    let substitutes = 10 + 2 * backtrack + 2 * lookahead;
+
+                    Some((vec![u16_at(subtable, substitutes + 2 * index)], 1))
+                }
+                (2 | 3, _) => {
+                    let sequence = &subtable[u16_at(subtable, 6 + 2 * index) as usize..];
+                    Some((list(sequence), 1))
+                }
+                _ => {
+                    let set = &subtable[u16_at(subtable, 6 + 2 * index) as usize..];
+
+                    list(set).into_iter().find_map(|offset| {
+                        let ligature = &set[offset as usize..];
+                        let count = u16_at(ligature, 2) as usize;
+                        let components = (1..count).map(|n| u16_at(ligature, 2 + 2 * n));
+
+                        (run.len() >= count && components.eq(run[1..count].iter().copied()))
+                            .then(|| (vec![u16_at(ligature, 0)], count))
+                    })
+                }
+            }
+        })
+    }
+
+    fn gsub(substitution: Substitution) -> Vec<u8> {
+        let mut gsub = Gsub::default();
+        gsub.add_feature(
+            *b"liga",
+            SubstitutionLookup {
+                name: None,
+                flags: 0,
+                mark_filtering_set: None,
+                substitution,
+            },
+        );
+        gsub.encode().unwrap()
+    }
+
+    #[test]
+    fn single_and_multiple_substitutions() {
+        let shifted = gsub(Substitution::Single {
+            substitutions: vec![(3, 13), (4, 14), (3, 20)],
+        });
+        assert_eq!(substitute(&shifted, 0, &[3]), Some((vec![13], 1)));
+        assert_eq!(substitute(&shifted, 0, &[4]), Some((vec![14], 1)));
+        assert_eq!(substitute(&shifted, 0, &[5]), None);
+
+        let listed = gsub(Substitution::Single {
+            substitutions: vec![(3, 1), (4, 14)],
+        });
+        assert_eq!(substitute(&listed, 0, &[3]), Some((vec![1], 1)));
+
+        let multiple = gsub(Substitution::Multiple {
+            substitutions: vec![(7, vec![1, 2, 3])],
+        });
+        assert_eq!(substitute(&multiple, 0, &[7]), Some((vec![1, 2, 3], 1)));
+
+        let alternate = gsub(Substitution::Alternate {
+            alternates: vec![(7, vec![8, 9])],
+        });
+        assert_eq!(substitute(&alternate, 0, &[7]), Some((vec![8, 9], 1)));
+    }
+
+    #[test]
+    fn longer_ligatures_apply_first() {
+        let data = gsub(Substitution::Ligature {
+            ligatures: vec![
+                Ligature {
+                    components: vec![1, 1],
+                    glyph: 10,
+                },
+                Ligature {
+                    components: vec![1, 1, 2],
+                    glyph: 11,
+                },
+                Ligature {
+                    components: vec![2, 3],
+                    glyph: 12,
+                },
+            ],
+        });
+
+        assert_eq!(substitute(&data, 0, &[1, 1, 2]), Some((vec![11], 3)));
+        assert_eq!(substitute(&data, 0, &[1, 1, 3]), Some((vec![10], 2)));
+        assert_eq!(substitute(&data, 0, &[2, 3]), Some((vec![12], 2)));
+        assert_eq!(substitute(&data, 0, &[2, 1]), None);
+    }
+
+    #[test]
+    fn context_lookups_refer_to_lookups_by_name() {
+        let mut gsub = Gsub::default();
+        gsub.lookups.push(SubstitutionLookup {
+            name: Some(String::from("swash")),
+            flags: 0,
+            mark_filtering_set: None,
+            substitution: Substitution::Single {
+                substitutions: vec![(1, 2)],
+            },
+        });
+        gsub.add_feature(
+            *b"calt",
+            SubstitutionLookup {
+                name: None,
+                flags: 0,
+                mark_filtering_set: None,
+                substitution: Substitution::Context {
+                    rules: vec![ContextRule {
+                        backtrack: vec![vec![3]],
+                        input: vec![vec![1]],
+                        lookahead: Vec::new(),
+                        lookups: vec![crate::open_type::tables::SequenceLookup {
+                            index: 0,
+                            lookup: String::from("swash"),
+                        }],
+                    }],
+                },
+            },
+        );
+        gsub.add_feature(
+            *b"rclt",
+            SubstitutionLookup {
+                name: None,
+                flags: 0,
+                mark_filtering_set: None,
+                substitution: Substitution::ReverseChainSingle {
+                    rules: vec![ReverseChainRule {
+                        backtrack: Vec::new(),
+                        lookahead: vec![vec![5, 6]],
+                        substitutions: vec![(4, 7)],
+                    }],
+                },
+            },
+        );
+
+        let data = gsub.encode().unwrap();
+
+        let (lookup_type, subtables) = lookup_subtables(&data, 1, 7);
+        assert_eq!(lookup_type, 6);
+        assert_eq!(u16_at(subtables[0], 0), 1);
+
+        // Backtrack glyph 3, the input glyph, no lookahead, the single substitution at the input glyph
+        let set = &subtables[0][u16_at(subtables[0], 6) as usize..];
+        let rule = &set[u16_at(set, 2) as usize..];
+        assert_eq!(&rule[..14], &[0, 1, 0, 3, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0]);
+
+        assert_eq!(lookup_subtables(&data, 2, 7).0, 8);
+        assert_eq!(substitute(&data, 2, &[4]), Some((vec![7], 1)));
+
+        gsub.lookups[0].name = Some(String::from("other"));
+        assert!(gsub.encode().is_err());
+    }
+
+    #[test]
+    fn large_lookups_are_split() {
+        let alternates: Vec<(u16, Vec<u16>)> =
+            (0..2000).map(|glyph| (glyph, (0..20).collect())).collect();
+
+        let data = gsub(Substitution::Alternate { alternates });
+
+        assert!(lookup_subtables(&data, 0, 7).1.len() > 1);
+        assert_eq!(substitute(&data, 0, &[1999]), Some(((0..20).collect(), 1)));
+    }
+}
@@ -1,13 +1,19 @@
-use std::cell::LazyCell;
+use serde::{Deserialize, Serialize};
+
+use std::sync::LazyLock;
 
 use chrono::{DateTime, Utc};
 
 use crate::{
-    layout::{Layoutable, Layouted, Reservation},
-    open_type::{Fixed, LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Layoutable, Layouted, Reservation},
+    open_type::{
+        reader::ReadError,
+        tables::{Bounds, Glyf},
+        Fixed, LayoutableTable, LayoutedTable,
+    },
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Flags {
     /**
      * Bit 0: Baseline for font at y=0.
@@ -65,19 +71,39 @@ impl Default for Flags {
 
 impl Flags {
     pub fn as_u16(&self) -> u16 {
-        (if self.baseline { 2 ^ 0 } else { 0 })
-            | (if self.sidebearing { 2 ^ 1 } else { 0 })
-            | (if self.depends_on_pointsize { 2 ^ 2 } else { 0 })
-            | (if self.force_ppem { 2 ^ 3 } else { 0 })
-            | (if self.dynamic_advance_width { 2 ^ 4 } else { 0 })
-            | (if self.lossless { 2 ^ 11 } else { 0 })
-            | (if self.converted { 2 ^ 12 } else { 0 })
-            | (if self.cleartype_optimized { 2 ^ 13 } else { 0 })
-            | (if self.last_resort { 2 ^ 14 } else { 0 })
+        (if self.baseline { 1 << 0 } else { 0 })
+            | (if self.sidebearing { 1 << 1 } else { 0 })
+            | (if self.depends_on_pointsize { 1 << 2 } else { 0 })
+            | (if self.force_ppem { 1 << 3 } else { 0 })
+            | (if self.dynamic_advance_width {
+                1 << 4
+            } else {
+                0
+            })
+            | (if self.lossless { 1 << 11 } else { 0 })
+            | (if self.converted { 1 << 12 } else { 0 })
+            | (if self.cleartype_optimized { 1 << 13 } else { 0 })
+            | (if self.last_resort { 1 << 14 } else { 0 })
+    }
+
+    pub fn from_u16(bits: u16) -> Self {
+        let bit = |n: u16| bits & (1 << n) != 0;
+
+        Self {
+            baseline: bit(0),
+            sidebearing: bit(1),
+            depends_on_pointsize: bit(2),
+            force_ppem: bit(3),
+            dynamic_advance_width: bit(4),
+            lossless: bit(11),
+            converted: bit(12),
+            cleartype_optimized: bit(13),
+            last_resort: bit(14),
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Head {
     pub revision: Fixed,
     pub flags: Flags,
@@ -99,6 +125,106 @@ pub struct Head {
     pub max_y: i16,
 }
 
+impl Head {
+    /** Recomputes the bounding box of all glyphs. Glyphs without contours are skipped, the box is kept if no glyph has any. */
+    pub fn update_bounds(&mut self, glyf: &Glyf) {
+        let bounds = (0..glyf.glyphs.len())
+            .filter_map(|glyph_id| glyf.outline(glyph_id as u16))
+            .filter(|outline| !outline.is_empty())
+            .map(|outline| Bounds::of(&outline))
+            .reduce(|a, b| Bounds {
+                x_min: a.x_min.min(b.x_min),
+                y_min: a.y_min.min(b.y_min),
+                x_max: a.x_max.max(b.x_max),
+                y_max: a.y_max.max(b.y_max),
+            });
+
+        if let Some(bounds) = bounds {
+            self.min_x = bounds.x_min;
+            self.min_y = bounds.y_min;
+            self.max_x = bounds.x_max;
+            self.max_y = bounds.y_max;
+        }
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        use crate::open_type::FixedReadExt;
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = std::io::Cursor::new(data);
+
+        let major_version = reader.read_u16::<BE>()?;
+        if major_version != 1 {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"head",
+                version: major_version as u32,
+            });
+        }
+        reader.read_u16::<BE>()?; // Minor Version
+
+        let revision = reader.read_fixed::<BE>()?;
+
+        reader.read_u32::<BE>()?; // checksumAdjustment
+
+        let magic_number = reader.read_u32::<BE>()?;
+        if magic_number != 0x5F0F3CF5 {
+            return Err(ReadError::invalid_value(
+                *b"head",
+                "magic_number",
+                format!("{:#010x} is not 0x5f0f3cf5", magic_number),
+            ));
+        }
+
+        let flags = Flags::from_u16(reader.read_u16::<BE>()?);
+        let units_per_em = reader.read_u16::<BE>()?;
+
+        let mut read_date = |field| {
+            let seconds = reader.read_i64::<BE>()?;
+
+            // Duration::seconds panics for values that do not fit in milliseconds
+            seconds
+                .checked_mul(1000)
+                .map(chrono::Duration::milliseconds)
+                .and_then(|delta| EMPOCH.checked_add_signed(delta))
+                .ok_or_else(|| {
+                    ReadError::invalid_value(
+                        *b"head",
+                        field,
+                        format!("{} seconds since 1904 is not a valid date", seconds),
+                    )
+                })
+        };
+
+        let created = read_date("created")?;
+        let modified = read_date("modified")?;
+
+        Ok(Self {
+            revision,
+            flags,
+            created,
+            modified,
+            units_per_em,
+            min_x: reader.read_i16::<BE>()?,
+            min_y: reader.read_i16::<BE>()?,
+            max_x: reader.read_i16::<BE>()?,
+            max_y: reader.read_i16::<BE>()?,
+            smalest_recocnizeable_size: {
+                reader.read_u16::<BE>()?; // macStyle
+                reader.read_u16::<BE>()?
+            },
+        })
+    }
+
+    /** Reads `indexToLocFormat`, which tells whether `loca` stores short or long offsets. */
+    pub fn read_index_to_loc_format(data: &[u8]) -> Result<i16, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data.get(50..).unwrap_or_default();
+
+        Ok(reader.read_i16::<BE>()?)
+    }
+}
+
 impl LayoutableTable for Head {
     fn tag(&self) -> [u8; 4] {
         *b"head"
@@ -111,10 +237,10 @@ impl Layoutable<Box<dyn LayoutedTable>> for Head {
             requires_another_pass: true,
             reservation: layouter.reserve(54),
             checksum: 0,
-            revision: self.revision.clone(),
+            revision: self.revision,
             flags: self.flags.as_u16(),
-            created: self.created.clone(),
-            modified: self.modified.clone(),
+            created: self.created,
+            modified: self.modified,
             min_x: self.min_x,
             min_y: self.min_y,
             max_x: self.max_x,
@@ -141,7 +267,7 @@ struct LayoutedHead {
     smalest_recocnizeable_size: u16,
 }
 
-const EMPOCH: LazyCell<DateTime<Utc>> = LazyCell::new(|| {
+static EMPOCH: LazyLock<DateTime<Utc>> = LazyLock::new(|| {
     DateTime::parse_from_rfc3339("1904-01-01T00:00:00Z")
         .expect("it is a constant")
         .with_timezone(&Utc)
@@ -156,10 +282,12 @@ impl Layouted for LayoutedHead {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, current_file: &[u8]) -> Result<(), crate::layout::LayoutError> {
+    fn pass(&mut self, current_file: &[u8]) -> Result<(), LayoutError> {
         use crate::open_type::FixedWriteExt;
         use byteorder::{WriteBytesExt, BE};
 
+        self.validate()?;
+
         let old_checksum = self.checksum;
         self.checksum = checksum(current_file)?;
 
@@ -204,6 +332,44 @@ impl Layouted for LayoutedHead {
     }
 }
 
+impl LayoutedHead {
+    fn validate(&self) -> Result<(), LayoutError> {
+        if !(16..=16384).contains(&self.units_per_em) {
+            return Err(LayoutError::invalid_value(
+                *b"head",
+                "units_per_em",
+                format!("{} is not within 16 to 16384", self.units_per_em),
+            ));
+        }
+
+        if self.min_x > self.max_x {
+            return Err(LayoutError::invalid_value(
+                *b"head",
+                "min_x",
+                format!("{} is greater than max_x {}", self.min_x, self.max_x),
+            ));
+        }
+
+        if self.min_y > self.max_y {
+            return Err(LayoutError::invalid_value(
+                *b"head",
+                "min_y",
+                format!("{} is greater than max_y {}", self.min_y, self.max_y),
+            ));
+        }
+
+        if self.modified < self.created {
+            return Err(LayoutError::invalid_value(
+                *b"head",
+                "modified",
+                "lies before the creation date",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 impl LayoutedTable for LayoutedHead {
     fn tag(&self) -> [u8; 4] {
         *b"head"
@@ -236,3 +402,20 @@ fn checksum(file: &[u8]) -> std::io::Result<u32> {
 
     Ok(0xB1B0AFBAu32.wrapping_sub(sum))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_are_set_by_bit_position() {
+        let flags = Flags {
+            baseline: true,
+            last_resort: true,
+            ..Flags::default()
+        };
+
+        assert_eq!(flags.as_u16(), 0b0100_0000_0001_1001);
+        assert_eq!(Flags::from_u16(flags.as_u16()), flags);
+    }
+}
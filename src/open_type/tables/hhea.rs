@@ -1,13 +1,19 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    layout::Reservation,
-    open_type::{LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Reservation},
+    open_type::{
+        reader::ReadError,
+        tables::{Bounds, Glyf, Hmtx, HorizontalMetric},
+        LayoutableTable, LayoutedTable,
+    },
     Layoutable, Layouted,
 };
 
 /**
 NOTE: The ascender, descender and linegap values in this table are Apple specific; see Apple's specification for details regarding Apple platforms. The sTypoAscender, sTypoDescender and sTypoLineGap fields in the OS/2 table are used on the Windows platform, and are recommended for new text-layout implementations. Font developers should evaluate behavior in target applications that may use fields in this table or in the OS/2 table to ensure consistent layout. See the descriptions of the OS/2 fields for additional details.
  */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct HHead {
     /**
     Typographic ascent—see note above.
@@ -59,6 +65,95 @@ pub struct HHead {
     pub number_of_hmetrics: u16,
 }
 
+impl HHead {
+    /**
+     * Recomputes the values that follow from the glyphs: the number of metrics, the maximum advance width and the
+     * side bearing extremes. The extremes only consider glyphs with contours and are kept if there are none.
+     */
+    pub fn update_metrics(&mut self, glyf: &Glyf, hmtx: &Hmtx) {
+        let metrics: Vec<_> = (0..glyf.glyphs.len())
+            .filter_map(|glyph_id| hmtx.metric(glyph_id))
+            .collect();
+
+        self.number_of_hmetrics = hmtx.horizontal_metrics.len() as u16;
+        self.advance_width_max = metrics.iter().map(|m| m.advance_width).max().unwrap_or(0);
+
+        let extents: Vec<_> = metrics
+            .iter()
+            .enumerate()
+            .filter_map(|(glyph_id, metric)| {
+                let outline = glyf.outline(glyph_id as u16)?;
+
+                (!outline.is_empty()).then(|| (metric, Bounds::of(&outline)))
+            })
+            .collect();
+
+        if extents.is_empty() {
+            return;
+        }
+
+        // lsb + (xMax - xMin) is where the outline ends
+        let extent = |(m, b): &(&HorizontalMetric, Bounds)| {
+            m.left_side_bearing as i32 + b.x_max as i32 - b.x_min as i32
+        };
+
+        self.min_left_side_bearing = extents
+            .iter()
+            .map(|(m, _)| m.left_side_bearing)
+            .min()
+            .unwrap_or(0);
+        self.min_right_side_bearing = extents
+            .iter()
+            .map(|e| e.0.advance_width as i32 - extent(e))
+            .min()
+            .unwrap_or(0) as i16;
+        self.x_max_extent = extents.iter().map(extent).max().unwrap_or(0) as i16;
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data;
+
+        let major_version = reader.read_u16::<BE>()?;
+        if major_version != 1 {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"hhea",
+                version: major_version as u32,
+            });
+        }
+        reader.read_u16::<BE>()?; // minor version
+
+        let ascender = reader.read_i16::<BE>()?;
+        let descender = reader.read_i16::<BE>()?;
+        let line_gap = reader.read_i16::<BE>()?;
+        let advance_width_max = reader.read_u16::<BE>()?;
+        let min_left_side_bearing = reader.read_i16::<BE>()?;
+        let min_right_side_bearing = reader.read_i16::<BE>()?;
+        let x_max_extent = reader.read_i16::<BE>()?;
+        let caret_slope_rise = reader.read_i16::<BE>()?;
+        let caret_slope_run = reader.read_i16::<BE>()?;
+        let caret_offset = reader.read_i16::<BE>()?;
+
+        reader.read_u64::<BE>()?; // reserved
+
+        Ok(Self {
+            ascender,
+            descender,
+            line_gap,
+            advance_width_max,
+            min_left_side_bearing,
+            min_right_side_bearing,
+            x_max_extent,
+            caret_slope_rise,
+            caret_slope_run,
+            caret_offset,
+            metric_data_format: reader.read_i16::<BE>()?,
+            number_of_hmetrics: reader.read_u16::<BE>()?,
+        })
+    }
+}
+
 impl Layoutable<Box<dyn LayoutedTable>> for HHead {
     fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
         Box::new(LayoutedHHead {
@@ -90,9 +185,28 @@ impl Layouted for LayoutedHHead {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         use byteorder::{WriteBytesExt, BE};
 
+        if self.table.metric_data_format != 0 {
+            return Err(LayoutError::invalid_value(
+                *b"hhea",
+                "metric_data_format",
+                format!(
+                    "{} is not the current format 0",
+                    self.table.metric_data_format
+                ),
+            ));
+        }
+
+        if self.table.number_of_hmetrics == 0 {
+            return Err(LayoutError::invalid_value(
+                *b"hhea",
+                "number_of_hmetrics",
+                "at least one metric is required",
+            ));
+        }
+
         self.requires_another_pass = false;
 
         let mut writer = self.reservation.writer();
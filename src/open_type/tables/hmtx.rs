@@ -1,15 +1,85 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    layout::Reservation,
-    open_type::{LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Reservation},
+    open_type::{reader::ReadError, LayoutableTable, LayoutedTable},
     Layoutable, Layouted,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Hmtx {
     pub horizontal_metrics: Vec<HorizontalMetric>,
     pub left_side_bearings: Vec<i16>,
 }
 
+impl Hmtx {
+    /** The metric of a glyph. Glyphs after the last horizontal metric share its advance width. */
+    pub fn metric(&self, glyph_id: usize) -> Option<HorizontalMetric> {
+        match self.horizontal_metrics.get(glyph_id) {
+            Some(metric) => Some(metric.clone()),
+            None => Some(HorizontalMetric {
+                advance_width: self.horizontal_metrics.last()?.advance_width,
+                left_side_bearing: *self
+                    .left_side_bearings
+                    .get(glyph_id - self.horizontal_metrics.len())?,
+            }),
+        }
+    }
+
+    /**
+     * Stores one metric per glyph, leaving out the advance widths of the trailing glyphs that share the advance width
+     * of the glyph before them.
+     */
+    pub fn new_with_metrics(metrics: Vec<HorizontalMetric>) -> Self {
+        let mut count = metrics.len();
+
+        while count > 1 && metrics[count - 1].advance_width == metrics[count - 2].advance_width {
+            count -= 1;
+        }
+
+        let mut horizontal_metrics = metrics;
+        let left_side_bearings = horizontal_metrics
+            .split_off(count)
+            .into_iter()
+            .map(|m| m.left_side_bearing)
+            .collect();
+
+        Self {
+            horizontal_metrics,
+            left_side_bearings,
+        }
+    }
+
+    /** Reads the metrics given the counts from `hhea` and `maxp`. */
+    pub fn read(
+        data: &[u8],
+        number_of_hmetrics: u16,
+        number_of_glyphs: u16,
+    ) -> Result<Self, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data;
+
+        let horizontal_metrics = (0..number_of_hmetrics)
+            .map(|_| {
+                Ok(HorizontalMetric {
+                    advance_width: reader.read_u16::<BE>()?,
+                    left_side_bearing: reader.read_i16::<BE>()?,
+                })
+            })
+            .collect::<Result<_, ReadError>>()?;
+
+        let left_side_bearings = (number_of_hmetrics..number_of_glyphs.max(number_of_hmetrics))
+            .map(|_| reader.read_i16::<BE>())
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            horizontal_metrics,
+            left_side_bearings,
+        })
+    }
+}
+
 impl Layoutable<Box<dyn LayoutedTable>> for Hmtx {
     fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
         Box::new(LayoutedHmtx {
@@ -27,7 +97,7 @@ impl LayoutableTable for Hmtx {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct HorizontalMetric {
     /** Advance width, in font design units. */
     pub advance_width: u16,
@@ -50,9 +120,17 @@ impl Layouted for LayoutedHmtx {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         use byteorder::{WriteBytesExt, BE};
 
+        if self.table.horizontal_metrics.is_empty() {
+            return Err(LayoutError::invalid_value(
+                *b"hmtx",
+                "horizontal_metrics",
+                "at least one metric is required",
+            ));
+        }
+
         self.requires_another_pass = false;
 
         let mut writer = self.reservation.writer();
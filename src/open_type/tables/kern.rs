@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout::{LayoutError, Reservation},
+    open_type::{
+        search::SearchData,
+        tables::{ClassPair, GlyphPair},
+        LayoutableTable, LayoutedTable,
+    },
+    Layoutable, Layouted,
+};
+
+/** Pairs per subtable, the length of a subtable has to fit into 16 bits. */
+const MAX_PAIRS_PER_SUBTABLE: usize = (u16::MAX as usize - 14) / 6;
+
+/**
+ * The legacy kerning table, for applications that do not read `GPOS`. Written as version 0 with horizontal format 0
+ * subtables. Values of all subtables add up, so pairs that do not fit into one are spread over several.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Kern {
+    pub pairs: Vec<KernPair>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernPair {
+    pub left: u16,
+    pub right: u16,
+    /** Added to the advance of the left glyph, in font units. */
+    pub value: i16,
+}
+
+impl Kern {
+    /**
+     * The horizontal advance adjustments of the first glyphs of the pairs that `GPOS` kerning is built from. Classes
+     * are expanded into every pair of their glyphs; as in `GPOS`, glyph pairs take precedence and of pairs given
+     * twice the first counts.
+     */
+    pub fn from_pairs(pairs: &[GlyphPair], class_pairs: &[ClassPair]) -> Self {
+        let mut values = BTreeMap::new();
+
+        for pair in pairs.iter() {
+            values
+                .entry((pair.first, pair.second))
+                .or_insert(pair.first_value.x_advance);
+        }
+
+        for pair in class_pairs.iter() {
+            for left in pair.first.iter() {
+                for right in pair.second.iter() {
+                    values
+                        .entry((*left, *right))
+                        .or_insert(pair.first_value.x_advance);
+                }
+            }
+        }
+
+        Self {
+            pairs: values
+                .into_iter()
+                .filter(|(_, value)| *value != 0)
+                .map(|((left, right), value)| KernPair { left, right, value })
+                .collect(),
+        }
+    }
+
+    /** The pairs sorted as the binary search requires, without duplicates. */
+    fn sorted_pairs(&self) -> Vec<KernPair> {
+        let mut pairs = self.pairs.clone();
+        pairs.sort_by_key(|pair| (pair.left, pair.right));
+        pairs.dedup_by_key(|pair| (pair.left, pair.right));
+        pairs
+    }
+}
+
+fn subtable_count(pairs: usize) -> usize {
+    pairs.div_ceil(MAX_PAIRS_PER_SUBTABLE).max(1)
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for Kern {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        let pairs = self.sorted_pairs();
+
+        Box::new(LayoutedKern {
+            requires_another_pass: true,
+            reservation: layouter.reserve(4 + 14 * subtable_count(pairs.len()) + 6 * pairs.len()),
+            pairs,
+        })
+    }
+}
+
+impl LayoutableTable for Kern {
+    fn tag(&self) -> [u8; 4] {
+        *b"kern"
+    }
+}
+
+struct LayoutedKern {
+    reservation: Reservation,
+    requires_another_pass: bool,
+    pairs: Vec<KernPair>,
+}
+
+impl Layouted for LayoutedKern {
+    fn reservation(&self) -> &Reservation {
+        &self.reservation
+    }
+
+    fn requires_another_pass(&self) -> bool {
+        self.requires_another_pass
+    }
+
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
+        use byteorder::{WriteBytesExt, BE};
+
+        self.requires_another_pass = false;
+
+        let mut writer = self.reservation.writer();
+
+        writer.write_u16::<BE>(0)?; // Version
+        writer.write_u16::<BE>(subtable_count(self.pairs.len()) as u16)?;
+
+        let chunks: Vec<&[KernPair]> = match self.pairs.is_empty() {
+            true => vec![&[]],
+            false => self.pairs.chunks(MAX_PAIRS_PER_SUBTABLE).collect(),
+        };
+
+        for pairs in chunks {
+            let search_data = SearchData::for_units(pairs.len() as u16, 6);
+
+            writer.write_u16::<BE>(0)?; // Subtable version
+            writer.write_u16::<BE>((14 + 6 * pairs.len()) as u16)?;
+            writer.write_u16::<BE>(0x0001)?; // Horizontal kerning, format 0
+            writer.write_u16::<BE>(pairs.len() as u16)?;
+            writer.write_u16::<BE>(search_data.search_range)?;
+            writer.write_u16::<BE>(search_data.entry_selector)?;
+            writer.write_u16::<BE>(search_data.range_shift)?;
+
+            for pair in pairs {
+                writer.write_u16::<BE>(pair.left)?;
+                writer.write_u16::<BE>(pair.right)?;
+                writer.write_i16::<BE>(pair.value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl LayoutedTable for LayoutedKern {
+    fn tag(&self) -> [u8; 4] {
+        *b"kern"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::build;
+
+    #[test]
+    fn pairs_are_sorted_and_classes_expanded() {
+        let kern = Kern::from_pairs(
+            &[GlyphPair::kerning(3, 1, -20), GlyphPair::kerning(1, 2, -10)],
+            &[ClassPair::kerning(vec![1, 3], vec![1, 2], -5)],
+        );
+
+        let data = build(&kern, false).unwrap();
+
+        assert_eq!(&data[..4], &[0, 0, 0, 1]);
+        // 4 pairs: search range 4 * 6, entry selector 2, range shift 0
+        assert_eq!(&data[4..18], &[0, 0, 0, 38, 0, 1, 0, 4, 0, 24, 0, 2, 0, 0]);
+
+        let pairs: Vec<(u16, u16, i16)> = data[18..]
+            .chunks(6)
+            .take(4)
+            .map(|pair| {
+                (
+                    u16::from_be_bytes([pair[0], pair[1]]),
+                    u16::from_be_bytes([pair[2], pair[3]]),
+                    i16::from_be_bytes([pair[4], pair[5]]),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            pairs,
+            vec![(1, 1, -5), (1, 2, -10), (3, 1, -20), (3, 2, -5)]
+        );
+    }
+
+    #[test]
+    fn many_pairs_are_spread_over_subtables() {
+        let pairs: Vec<GlyphPair> = (0..MAX_PAIRS_PER_SUBTABLE as u16 + 10)
+            .map(|n| GlyphPair::kerning(n, n, -1))
+            .collect();
+
+        let data = build(&Kern::from_pairs(&pairs, &[]), false).unwrap();
+
+        assert_eq!(&data[2..4], &[0, 2]);
+
+        let second = 4 + 14 + 6 * MAX_PAIRS_PER_SUBTABLE;
+        assert_eq!(&data[second + 6..second + 8], &[0, 10]);
+        assert_eq!(data.len(), second + 14 + 60);
+    }
+}
@@ -1,13 +1,51 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    layout::Reservation,
-    open_type::{LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Reservation},
+    open_type::{reader::ReadError, LayoutableTable, LayoutedTable},
     Layoutable, Layouted,
 };
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Loca {
     pub offsets: Vec<u32>,
 }
 
+impl Loca {
+    /**
+     * Reads the `number_of_glyphs + 1` offsets. `index_to_loc_format` comes from `head`: 0 for short offsets, which
+     * are stored divided by two, and 1 for long offsets.
+     */
+    pub fn read(
+        data: &[u8],
+        index_to_loc_format: i16,
+        number_of_glyphs: u16,
+    ) -> Result<Self, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data;
+        let count = number_of_glyphs as usize + 1;
+
+        let offsets = match index_to_loc_format {
+            0 => (0..count)
+                .map(|_| Ok(reader.read_u16::<BE>()? as u32 * 2))
+                .collect::<Result<_, ReadError>>()?,
+            1 => (0..count)
+                .map(|_| reader.read_u32::<BE>())
+                .collect::<Result<_, _>>()?,
+            format => {
+                return Err(ReadError::invalid_value(
+                    *b"head",
+                    "index_to_loc_format",
+                    format!("{} is neither 0 nor 1", format),
+                ))
+            }
+        };
+
+        Ok(Self { offsets })
+    }
+}
+
 impl Layoutable<Box<dyn LayoutedTable>> for Loca {
     fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
         Box::new(LayoutedLoca {
@@ -39,9 +77,20 @@ impl Layouted for LayoutedLoca {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         use byteorder::{WriteBytesExt, BE};
 
+        if let Some(index) = self.offsets.windows(2).position(|pair| pair[0] > pair[1]) {
+            return Err(LayoutError::invalid_value(
+                *b"loca",
+                "offsets",
+                format!(
+                    "offset of glyph {} lies before the offset of its predecessor",
+                    index + 1
+                ),
+            ));
+        }
+
         self.requires_another_pass = false;
 
         let mut writer = self.reservation.writer();
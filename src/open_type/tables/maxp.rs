@@ -1,11 +1,188 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    layout::Reservation,
-    open_type::{LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Reservation},
+    open_type::{
+        reader::ReadError,
+        tables::{Glyf, Glyph, MAX_COMPONENT_DEPTH},
+        true_type::Instrution,
+        LayoutableTable, LayoutedTable,
+    },
     Layoutable, Layouted,
 };
 
+/**
+ * Version 1.0 of the table, written when [MaxP::true_type_limits] are present. Fonts with CFF outlines use
+ * version 0.5, which only holds the glyph count.
+ */
+const VERSION_1_0: u32 = 0x00010000;
+const VERSION_0_5: u32 = 0x00005000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MaxP {
     pub number_of_glyphs: u16,
+    #[serde(default)]
+    pub true_type_limits: Option<TrueTypeLimits>,
+}
+
+/** The limits an interpreter of TrueType outlines and instructions needs to prepare for. */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrueTypeLimits {
+    pub max_points: u16,
+    pub max_contours: u16,
+    pub max_composite_points: u16,
+    pub max_composite_contours: u16,
+    /** 2 if instructions use the twilight zone, 1 otherwise. */
+    pub max_zones: u16,
+    pub max_twilight_points: u16,
+    pub max_storage: u16,
+    pub max_function_defs: u16,
+    pub max_instruction_defs: u16,
+    pub max_stack_elements: u16,
+    pub max_size_of_instructions: u16,
+    pub max_component_elements: u16,
+    pub max_component_depth: u16,
+}
+
+impl MaxP {
+    /** Reads the glyph count and, from version 1.0 tables, the TrueType limits. */
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data;
+
+        let version = reader.read_u32::<BE>()?;
+        if version != VERSION_0_5 && version != VERSION_1_0 {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"maxp",
+                version,
+            });
+        }
+
+        let number_of_glyphs = reader.read_u16::<BE>()?;
+
+        let true_type_limits = if version == VERSION_1_0 {
+            let mut read = || reader.read_u16::<BE>();
+
+            Some(TrueTypeLimits {
+                max_points: read()?,
+                max_contours: read()?,
+                max_composite_points: read()?,
+                max_composite_contours: read()?,
+                max_zones: read()?,
+                max_twilight_points: read()?,
+                max_storage: read()?,
+                max_function_defs: read()?,
+                max_instruction_defs: read()?,
+                max_stack_elements: read()?,
+                max_size_of_instructions: read()?,
+                max_component_elements: read()?,
+                max_component_depth: read()?,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            number_of_glyphs,
+            true_type_limits,
+        })
+    }
+
+    /**
+     * Counts the glyphs and recomputes the limits that follow from them. Limits set by the hinting programs, like
+     * the number of function definitions, are kept.
+     */
+    pub fn update_limits(&mut self, glyf: &Glyf) {
+        self.number_of_glyphs = glyf.glyphs.len() as u16;
+
+        let limits = self.true_type_limits.get_or_insert_with(|| TrueTypeLimits {
+            max_zones: 1,
+            ..Default::default()
+        });
+
+        let computed = TrueTypeLimits::of(glyf);
+
+        limits.max_points = computed.max_points;
+        limits.max_contours = computed.max_contours;
+        limits.max_composite_points = computed.max_composite_points;
+        limits.max_composite_contours = computed.max_composite_contours;
+        limits.max_size_of_instructions = computed.max_size_of_instructions;
+        limits.max_component_elements = computed.max_component_elements;
+        limits.max_component_depth = computed.max_component_depth;
+    }
+}
+
+impl TrueTypeLimits {
+    /** The limits that follow from the glyphs. Those of the hinting programs are left at their minimum. */
+    pub fn of(glyf: &Glyf) -> Self {
+        let mut limits = Self {
+            max_zones: 1,
+            ..Default::default()
+        };
+
+        let saturate = |value: usize| value.min(u16::MAX as usize) as u16;
+
+        for (glyph_id, glyph) in glyf.glyphs.iter().enumerate() {
+            let instructions = match glyph {
+                Glyph::Empty => continue,
+                Glyph::Simple {
+                    contours,
+                    instructions,
+                } => {
+                    let points = contours.iter().map(|c| c.points.len()).sum();
+
+                    limits.max_points = limits.max_points.max(saturate(points));
+                    limits.max_contours = limits.max_contours.max(saturate(contours.len()));
+
+                    instructions
+                }
+                Glyph::Composite {
+                    components,
+                    instructions,
+                } => {
+                    if let Some(outline) = glyf.outline(glyph_id as u16) {
+                        let points = outline.iter().map(|c| c.points.len()).sum();
+
+                        limits.max_composite_points =
+                            limits.max_composite_points.max(saturate(points));
+                        limits.max_composite_contours =
+                            limits.max_composite_contours.max(saturate(outline.len()));
+                    }
+
+                    limits.max_component_elements = limits
+                        .max_component_elements
+                        .max(saturate(components.len()));
+                    limits.max_component_depth = limits
+                        .max_component_depth
+                        .max(component_depth(glyf, glyph_id, 0));
+
+                    instructions
+                }
+            };
+
+            let size = instructions.iter().map(Instrution::size).sum();
+            limits.max_size_of_instructions = limits.max_size_of_instructions.max(saturate(size));
+        }
+
+        limits
+    }
+}
+
+/** How deep the components of the glyph nest, 0 for glyphs without components. */
+fn component_depth(glyf: &Glyf, glyph_id: usize, depth: usize) -> u16 {
+    if depth > MAX_COMPONENT_DEPTH {
+        return depth as u16;
+    }
+
+    match glyf.glyphs.get(glyph_id) {
+        Some(Glyph::Composite { components, .. }) => components
+            .iter()
+            .map(|c| 1 + component_depth(glyf, c.glyph_index as usize, depth + 1))
+            .max()
+            .unwrap_or(0),
+        _ => 0,
+    }
 }
 
 impl LayoutableTable for MaxP {
@@ -16,10 +193,16 @@ impl LayoutableTable for MaxP {
 
 impl Layoutable<Box<dyn LayoutedTable>> for MaxP {
     fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        let length = if self.true_type_limits.is_some() {
+            32
+        } else {
+            6
+        };
+
         Box::new(LayoutedMaxP {
             requires_another_pass: true,
-            number_of_glyphs: self.number_of_glyphs,
-            reservation: layouter.reserve(6),
+            table: self.clone(),
+            reservation: layouter.reserve(length),
         })
     }
 }
@@ -27,7 +210,7 @@ impl Layoutable<Box<dyn LayoutedTable>> for MaxP {
 struct LayoutedMaxP {
     requires_another_pass: bool,
     reservation: Reservation,
-    number_of_glyphs: u16,
+    table: MaxP,
 }
 
 impl LayoutedTable for LayoutedMaxP {
@@ -45,15 +228,49 @@ impl Layouted for LayoutedMaxP {
         &self.reservation
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         use byteorder::{WriteBytesExt, BE};
 
+        if self.table.number_of_glyphs == 0 {
+            return Err(LayoutError::invalid_value(
+                *b"maxp",
+                "number_of_glyphs",
+                "a font needs at least the .notdef glyph",
+            ));
+        }
+
         self.requires_another_pass = false;
 
         let mut writer = self.reservation.writer();
 
-        writer.write_i32::<BE>(0x00005000)?;
-        writer.write_u16::<BE>(self.number_of_glyphs)?;
+        match &self.table.true_type_limits {
+            Some(limits) => {
+                writer.write_u32::<BE>(VERSION_1_0)?;
+                writer.write_u16::<BE>(self.table.number_of_glyphs)?;
+
+                for value in [
+                    limits.max_points,
+                    limits.max_contours,
+                    limits.max_composite_points,
+                    limits.max_composite_contours,
+                    limits.max_zones,
+                    limits.max_twilight_points,
+                    limits.max_storage,
+                    limits.max_function_defs,
+                    limits.max_instruction_defs,
+                    limits.max_stack_elements,
+                    limits.max_size_of_instructions,
+                    limits.max_component_elements,
+                    limits.max_component_depth,
+                ] {
+                    writer.write_u16::<BE>(value)?;
+                }
+            }
+            None => {
+                writer.write_u32::<BE>(VERSION_0_5)?;
+                writer.write_u16::<BE>(self.table.number_of_glyphs)?;
+            }
+        }
 
         Ok(())
     }
@@ -1,23 +1,39 @@
+mod cff;
+mod cff2;
 mod cmap;
+mod gdef;
 mod glyf;
+mod gpos;
+mod gsub;
 mod head;
 mod hhea;
 mod hmtx;
+mod kern;
 mod loca;
 mod maxp;
 mod name;
 mod os2;
+mod otl;
 mod post;
+mod raw;
 //mod svg;
 
+pub use cff::*;
+pub use cff2::*;
 pub use cmap::*;
+pub use gdef::*;
 pub use glyf::*;
+pub use gpos::*;
+pub use gsub::*;
 pub use head::*;
 pub use hhea::*;
 pub use hmtx::*;
+pub use kern::*;
 pub use loca::*;
 pub use maxp::*;
 pub use name::*;
 pub use os2::*;
+pub use otl::*;
 pub use post::*;
+pub use raw::*;
 //pub use svg::*;
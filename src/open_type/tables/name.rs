@@ -1,23 +1,101 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    layout::Reservation,
-    open_type::{LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Reservation},
+    open_type::{reader::ReadError, LayoutableTable, LayoutedTable},
     Layoutable, Layouted,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NameRecord {
     pub name_id: u16,
     pub content: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Name {
     pub names: Vec<NameRecord>,
 }
 
+impl Name {
+    /**
+     * Reads one string per name id. Unicode and Windows records are preferred, Macintosh records are only used for
+     * ids that have no Unicode record; their non-ASCII characters are replaced.
+     */
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data;
+
+        let version = reader.read_u16::<BE>()?;
+        if version > 1 {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"name",
+                version: version as u32,
+            });
+        }
+
+        let count = reader.read_u16::<BE>()?;
+        let storage_offset = reader.read_u16::<BE>()? as usize;
+
+        let mut unicode = Vec::new();
+        let mut macintosh = Vec::new();
+
+        for _ in 0..count {
+            let platform_id = reader.read_u16::<BE>()?;
+            reader.read_u16::<BE>()?; // EncodingID
+            reader.read_u16::<BE>()?; // LanguageID
+            let name_id = reader.read_u16::<BE>()?;
+            let length = reader.read_u16::<BE>()? as usize;
+            let offset = reader.read_u16::<BE>()? as usize;
+
+            let start = storage_offset + offset;
+            let bytes = data
+                .get(start..start + length)
+                .ok_or(ReadError::OutOfBounds { tag: *b"name" })?;
+
+            match platform_id {
+                0 | 3 => {
+                    let units: Vec<u16> = bytes
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                        .collect();
+
+                    unicode.push(NameRecord {
+                        name_id,
+                        content: String::from_utf16_lossy(&units),
+                    });
+                }
+                1 => macintosh.push(NameRecord {
+                    name_id,
+                    content: bytes
+                        .iter()
+                        .map(|b| if b.is_ascii() { *b as char } else { '\u{FFFD}' })
+                        .collect(),
+                }),
+                _ => {}
+            }
+        }
+
+        let mut names: Vec<NameRecord> = Vec::new();
+
+        for record in unicode.into_iter().chain(macintosh) {
+            if !names.iter().any(|n| n.name_id == record.name_id) {
+                names.push(record);
+            }
+        }
+
+        Ok(Self { names })
+    }
+}
+
 impl Layoutable<Box<dyn LayoutedTable>> for Name {
     fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
-        let prepared_records: Vec<_> = self.names.iter().map(From::from).collect();
+        let mut prepared_records: Vec<_> = self.names.iter().map(From::from).collect();
+
+        // Records have to be sorted by platform, encoding, language and name id. All records share the first three,
+        // the stable sort keeps the given order for records with the same id.
+        prepared_records.sort_by_key(|r: &PreparedNameRecord| r.name_id);
 
         let total_num_of_chars: usize = prepared_records
             .iter()
@@ -68,9 +146,11 @@ impl Layouted for LayoutedName {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         use byteorder::{WriteBytesExt, BE};
 
+        self.validate()?;
+
         self.requires_another_pass = false;
 
         let mut writer = self.reservation.writer();
@@ -78,7 +158,7 @@ impl Layouted for LayoutedName {
         writer.write_u16::<BE>(0)?; // Version
         writer.write_u16::<BE>(self.names.len() as u16)?;
 
-        writer.write_u16::<BE>(12 * self.names.len() as u16)?; // StorageOffset
+        writer.write_u16::<BE>(6 + 12 * self.names.len() as u16)?; // StorageOffset
 
         let mut start_offset = 0;
 
@@ -106,6 +186,34 @@ impl Layouted for LayoutedName {
     }
 }
 
+impl LayoutedName {
+    fn validate(&self) -> Result<(), LayoutError> {
+        if 6 + 12 * self.names.len() > u16::MAX as usize {
+            return Err(LayoutError::invalid_value(
+                *b"name",
+                "names",
+                format!("{} records do not fit into the table", self.names.len()),
+            ));
+        }
+
+        let mut storage_length = 0;
+
+        for record in self.names.iter() {
+            storage_length += record.data.len() * 2;
+
+            if storage_length > u16::MAX as usize {
+                return Err(LayoutError::invalid_value(
+                    *b"name",
+                    "content",
+                    format!("name {} exceeds the 64KiB string storage", record.name_id),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl LayoutedTable for LayoutedName {
     fn tag(&self) -> [u8; 4] {
         *b"name"
@@ -1,10 +1,12 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    layout::Reservation,
-    open_type::{LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Reservation},
+    open_type::{reader::ReadError, LayoutableTable, LayoutedTable},
     Layoutable, Layouted,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Script {
     pub x_size: i16,
     pub y_size: i16,
@@ -12,7 +14,7 @@ pub struct Script {
     pub y_offset: i16,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Panose {
     pub family_type: u8,
     pub serif_style: u8,
@@ -26,24 +28,7 @@ pub struct Panose {
     pub xheight: u8,
 }
 
-impl Default for Panose {
-    fn default() -> Self {
-        Self {
-            family_type: 0,
-            serif_style: 0,
-            weight: 0,
-            proportion: 0,
-            contrast: 0,
-            stroke_variation: 0,
-            arm_style: 0,
-            letterform: 0,
-            midline: 0,
-            xheight: 0,
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OS2 {
     pub avg_glyph_width: i16,
     /** 400 - Normal */
@@ -74,6 +59,88 @@ pub struct OS2 {
     pub max_context: u16,
 }
 
+impl OS2 {
+    /**
+     * Reads any version of the table. Fields that older versions do not have are left at 0, the fields this crate
+     * always writes itself (licensing, unicode ranges, vendor, ...) are skipped.
+     */
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        use byteorder::{ReadBytesExt, BE};
+        use helpers::*;
+
+        let mut reader = data;
+
+        let version = reader.read_u16::<BE>()?;
+        if version > 5 {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"OS/2",
+                version: version as u32,
+            });
+        }
+
+        let avg_glyph_width = reader.read_i16::<BE>()?;
+        let weight_class = reader.read_u16::<BE>()?;
+        let width_class = reader.read_u16::<BE>()?;
+
+        reader.read_u16::<BE>()?; // fsType
+
+        let subscript = reader.read_script()?;
+        let superscript = reader.read_script()?;
+
+        let strikeout_size = reader.read_i16::<BE>()?;
+        let strikeout_position = reader.read_i16::<BE>()?;
+
+        reader.read_i16::<BE>()?; // sFamilyClass
+
+        let panose = reader.read_panose()?;
+
+        // ulUnicodeRange1-4, achVendID, fsSelection, usFirstCharIndex and usLastCharIndex
+        reader = reader
+            .get(26..)
+            .ok_or(ReadError::Truncated { tag: *b"OS/2" })?;
+
+        let typo_ascender = reader.read_i16::<BE>()?;
+        let typo_descender = reader.read_i16::<BE>()?;
+        let typo_line_gap = reader.read_i16::<BE>()?;
+
+        let win_ascent = reader.read_u16::<BE>()?;
+        let win_descent = reader.read_u16::<BE>()?;
+
+        let mut table = Self {
+            avg_glyph_width,
+            weight_class,
+            width_class,
+            subscript,
+            superscript,
+            strikeout_size,
+            strikeout_position,
+            panose,
+            typo_ascender,
+            typo_descender,
+            typo_line_gap,
+            win_ascent,
+            win_descent,
+            x_height: 0,
+            cap_height: 0,
+            default_cahr: 0,
+            break_char: 0,
+            max_context: 0,
+        };
+
+        if version >= 2 {
+            reader.read_u64::<BE>()?; // ulCodePageRange1-2
+
+            table.x_height = reader.read_i16::<BE>()?;
+            table.cap_height = reader.read_i16::<BE>()?;
+            table.default_cahr = reader.read_u16::<BE>()?;
+            table.break_char = reader.read_u16::<BE>()?;
+            table.max_context = reader.read_u16::<BE>()?;
+        }
+
+        Ok(table)
+    }
+}
+
 impl Layoutable<Box<dyn LayoutedTable>> for OS2 {
     fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
         Box::new(OS2Layouted {
@@ -111,9 +178,26 @@ impl Layouted for OS2Layouted {
         &self.reservation
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         use byteorder::{WriteBytesExt, BE};
         use helpers::*;
+        use std::io::Write;
+
+        if !(1..=1000).contains(&self.table.weight_class) {
+            return Err(LayoutError::invalid_value(
+                *b"OS/2",
+                "weight_class",
+                format!("{} is not within 1 to 1000", self.table.weight_class),
+            ));
+        }
+
+        if !(1..=9).contains(&self.table.width_class) {
+            return Err(LayoutError::invalid_value(
+                *b"OS/2",
+                "width_class",
+                format!("{} is not within 1 to 9", self.table.width_class),
+            ));
+        }
 
         self.requires_another_pass = false;
 
@@ -161,6 +245,9 @@ impl Layouted for OS2Layouted {
         writer.write_u32::<BE>(0)?; // ulCodePageRange1 Bits 0–31
         writer.write_u32::<BE>(0)?; // ulCodePageRange2 Bits 32–63
 
+        writer.write_i16::<BE>(self.table.x_height)?;
+        writer.write_i16::<BE>(self.table.cap_height)?;
+
         writer.write_u16::<BE>(self.table.default_cahr)?;
         writer.write_u16::<BE>(self.table.break_char)?;
         writer.write_u16::<BE>(self.table.max_context)?;
@@ -173,10 +260,44 @@ impl Layouted for OS2Layouted {
 }
 
 mod helpers {
-    use byteorder::{WriteBytesExt, BE};
+    use byteorder::{ReadBytesExt, WriteBytesExt, BE};
 
     use super::{Panose, Script};
 
+    pub trait ReadExt: std::io::Read {
+        fn read_script(&mut self) -> std::io::Result<Script> {
+            Ok(Script {
+                x_size: self.read_i16::<BE>()?,
+                y_size: self.read_i16::<BE>()?,
+                x_offset: self.read_i16::<BE>()?,
+                y_offset: self.read_i16::<BE>()?,
+            })
+        }
+
+        fn read_panose(&mut self) -> std::io::Result<Panose> {
+            let mut bytes = [0; 10];
+            self.read_exact(&mut bytes)?;
+
+            let [family_type, serif_style, weight, proportion, contrast, stroke_variation, arm_style, letterform, midline, xheight] =
+                bytes;
+
+            Ok(Panose {
+                family_type,
+                serif_style,
+                weight,
+                proportion,
+                contrast,
+                stroke_variation,
+                arm_style,
+                letterform,
+                midline,
+                xheight,
+            })
+        }
+    }
+
+    impl<R: std::io::Read + ?Sized> ReadExt for R {}
+
     pub trait WriteExt: std::io::Write {
         fn write_script(&mut self, script: &Script) -> std::io::Result<()> {
             self.write_i16::<BE>(script.x_size)?;
@@ -184,7 +305,7 @@ mod helpers {
             self.write_i16::<BE>(script.x_offset)?;
             self.write_i16::<BE>(script.y_offset)?;
 
-            return Ok(());
+            Ok(())
         }
 
         fn write_panose(&mut self, panose: &Panose) -> std::io::Result<()> {
@@ -199,7 +320,7 @@ mod helpers {
             self.write_u8(panose.midline)?;
             self.write_u8(panose.xheight)?;
 
-            return Ok(());
+            Ok(())
         }
     }
 
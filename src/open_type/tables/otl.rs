@@ -0,0 +1,1088 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::layout::LayoutError;
+
+/** The default language of a script. */
+pub const DEFAULT_LANGUAGE: [u8; 4] = *b"dflt";
+
+/** The script that applies to text whose script has no entry of its own. */
+pub const DEFAULT_SCRIPT: [u8; 4] = *b"DFLT";
+
+/** Lookup flags that apply to every lookup type. */
+pub mod lookup_flags {
+    pub const RIGHT_TO_LEFT: u16 = 0x0001;
+    pub const IGNORE_BASE_GLYPHS: u16 = 0x0002;
+    pub const IGNORE_LIGATURES: u16 = 0x0004;
+    pub const IGNORE_MARKS: u16 = 0x0008;
+    /** Set for lookups with a mark filtering set, only marks of that set of `GDEF` are not skipped. */
+    pub const USE_MARK_FILTERING_SET: u16 = 0x0010;
+    /** Only marks of this attachment class of `GDEF` are not skipped, shifted into the high byte. */
+    pub const MARK_ATTACHMENT_TYPE: u16 = 0xFF00;
+}
+
+/** The features that apply to text in a script and language, by index into the feature list. */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LanguageSystem {
+    pub script: [u8; 4],
+    /** [DEFAULT_LANGUAGE] for the default language of the script. */
+    pub language: [u8; 4],
+    pub features: Vec<u16>,
+}
+
+/** A feature applies its lookups, given by index into the lookup list, in the order of that list. */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Feature {
+    pub tag: [u8; 4],
+    pub lookups: Vec<u16>,
+}
+
+/**
+ * Applies other lookups of the same table to a sequence of glyphs, if it is preceded and followed by the given
+ * glyphs. Every position of the sequence and its context matches any glyph of a set.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextRule {
+    /** The glyphs before the sequence, in text order. */
+    #[serde(default)]
+    pub backtrack: Vec<Vec<u16>>,
+    pub input: Vec<Vec<u16>>,
+    /** The glyphs after the sequence, in text order. */
+    #[serde(default)]
+    pub lookahead: Vec<Vec<u16>>,
+    /** Applied in this order, each at a position of the input sequence. */
+    pub lookups: Vec<SequenceLookup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceLookup {
+    /** The position in the input sequence. */
+    pub index: u16,
+    /** The name of a lookup of the same table. */
+    pub lookup: String,
+}
+
+/** A lookup of `GSUB` or `GPOS` whose subtables are encoded already, each on its own. */
+pub(crate) struct EncodedLookup {
+    pub lookup_type: u16,
+    pub flags: u16,
+    /** The index of a mark glyph set of `GDEF`, sets [lookup_flags::USE_MARK_FILTERING_SET] when written. */
+    pub mark_filtering_set: Option<u16>,
+    pub subtables: Vec<Vec<u8>>,
+}
+
+/**
+ * Encodes the common structure of `GSUB` and `GPOS`: the script list, the feature list and the lookup list.
+ * Features are sorted by tag as required, the language systems are renumbered to match. When the subtables are too
+ * large for 16 bit offsets, every lookup reaches them through extension subtables.
+ */
+pub(crate) fn encode_layout_table(
+    tag: [u8; 4],
+    language_systems: &[LanguageSystem],
+    features: &[Feature],
+    lookups: &[EncodedLookup],
+) -> Result<Vec<u8>, LayoutError> {
+    let invalid = |field, reason: String| LayoutError::invalid_value(tag, field, reason);
+
+    if let Some((feature, lookup)) = features
+        .iter()
+        .flat_map(|f| f.lookups.iter().map(move |l| (f.tag, *l)))
+        .find(|(_, lookup)| *lookup as usize >= lookups.len())
+    {
+        return Err(invalid(
+            "features",
+            format!(
+                "feature '{}' refers to the missing lookup {}",
+                String::from_utf8_lossy(&feature),
+                lookup
+            ),
+        ));
+    }
+
+    if let Some(system) = language_systems
+        .iter()
+        .find(|s| s.features.iter().any(|f| *f as usize >= features.len()))
+    {
+        return Err(invalid(
+            "language_systems",
+            format!(
+                "script '{}' refers to a missing feature",
+                String::from_utf8_lossy(&system.script)
+            ),
+        ));
+    }
+
+    // Stable, so features with the same tag keep their order
+    let mut order: Vec<usize> = (0..features.len()).collect();
+    order.sort_by_key(|index| features[*index].tag);
+
+    let mut new_index = vec![0u16; features.len()];
+    for (new, old) in order.iter().enumerate() {
+        new_index[*old] = new as u16;
+    }
+
+    let script_list = script_list(language_systems, &new_index);
+    let feature_list = feature_list(order.iter().map(|index| &features[*index]));
+    let lookup_list = lookup_list(lookups, extension_type(tag));
+
+    let script_list_offset = 10;
+    let feature_list_offset = script_list_offset + script_list.len();
+    let lookup_list_offset = feature_list_offset + feature_list.len();
+
+    if lookup_list_offset > u16::MAX as usize {
+        return Err(invalid(
+            "features",
+            String::from("the script and feature lists exceed 64 KiB"),
+        ));
+    }
+
+    let mut out = Vec::new();
+    out.extend(1u16.to_be_bytes()); // Major version
+    out.extend(0u16.to_be_bytes()); // Minor version
+    for offset in [script_list_offset, feature_list_offset, lookup_list_offset] {
+        out.extend((offset as u16).to_be_bytes());
+    }
+    out.extend(script_list);
+    out.extend(feature_list);
+    out.extend(lookup_list);
+
+    Ok(out)
+}
+
+fn extension_type(tag: [u8; 4]) -> u16 {
+    match &tag {
+        b"GSUB" => 7,
+        _ => 9,
+    }
+}
+
+fn script_list(language_systems: &[LanguageSystem], new_index: &[u16]) -> Vec<u8> {
+    let mut scripts: Vec<[u8; 4]> = language_systems.iter().map(|s| s.script).collect();
+    scripts.sort();
+    scripts.dedup();
+
+    let mut tables = Vec::new();
+    let mut offsets = Vec::new();
+    let records_length = 2 + 6 * scripts.len();
+
+    for script in scripts.iter() {
+        offsets.push(records_length + tables.len());
+
+        let mut systems: Vec<&LanguageSystem> = language_systems
+            .iter()
+            .filter(|s| &s.script == script)
+            .collect();
+        systems.sort_by_key(|s| s.language);
+        systems.dedup_by_key(|s| s.language);
+
+        let default = systems.iter().find(|s| s.language == DEFAULT_LANGUAGE);
+        let languages: Vec<_> = systems
+            .iter()
+            .filter(|s| s.language != DEFAULT_LANGUAGE)
+            .collect();
+
+        let language_system = |system: &LanguageSystem| {
+            let mut features: Vec<u16> = system
+                .features
+                .iter()
+                .map(|f| new_index[*f as usize])
+                .collect();
+            features.sort();
+            features.dedup();
+
+            let mut out = Vec::new();
+            out.extend(0u16.to_be_bytes()); // Lookup order, reserved
+            out.extend(0xFFFFu16.to_be_bytes()); // No required feature
+            out.extend((features.len() as u16).to_be_bytes());
+            for feature in features {
+                out.extend(feature.to_be_bytes());
+            }
+            out
+        };
+
+        let mut script_table = Vec::new();
+        let mut system_tables = Vec::new();
+        let header_length = 4 + 6 * languages.len();
+
+        let default_offset = match default {
+            Some(default) => {
+                let offset = header_length;
+                system_tables.extend(language_system(default));
+                offset
+            }
+            None => 0,
+        };
+
+        script_table.extend((default_offset as u16).to_be_bytes());
+        script_table.extend((languages.len() as u16).to_be_bytes());
+
+        for system in languages {
+            script_table.extend(system.language);
+            script_table.extend(((header_length + system_tables.len()) as u16).to_be_bytes());
+            system_tables.extend(language_system(system));
+        }
+
+        script_table.extend(system_tables);
+        tables.extend(script_table);
+    }
+
+    let mut out = Vec::new();
+    out.extend((scripts.len() as u16).to_be_bytes());
+    for (script, offset) in scripts.iter().zip(offsets) {
+        out.extend(script);
+        out.extend((offset as u16).to_be_bytes());
+    }
+    out.extend(tables);
+
+    out
+}
+
+fn feature_list<'a>(features: impl ExactSizeIterator<Item = &'a Feature>) -> Vec<u8> {
+    let mut records = Vec::new();
+    let mut tables = Vec::new();
+    let records_length = 2 + 6 * features.len();
+    let count = features.len();
+
+    for feature in features {
+        records.extend(feature.tag);
+        records.extend(((records_length + tables.len()) as u16).to_be_bytes());
+
+        tables.extend(0u16.to_be_bytes()); // No feature parameters
+        tables.extend((feature.lookups.len() as u16).to_be_bytes());
+        for lookup in feature.lookups.iter() {
+            tables.extend(lookup.to_be_bytes());
+        }
+    }
+
+    let mut out = (count as u16).to_be_bytes().to_vec();
+    out.extend(records);
+    out.extend(tables);
+
+    out
+}
+
+/**
+ * The lookup tables come first, followed by all subtables. If an offset to a subtable does not fit into 16 bits,
+ * extension subtables with 32 bit offsets take the place of the subtables.
+ */
+fn lookup_list(lookups: &[EncodedLookup], extension_type: u16) -> Vec<u8> {
+    let header_length = |lookup: &EncodedLookup| {
+        6 + 2 * lookup.subtables.len() + 2 * usize::from(lookup.mark_filtering_set.is_some())
+    };
+
+    let list_header_length = 2 + 2 * lookups.len();
+    let headers_length: usize = lookups.iter().map(header_length).sum();
+
+    // Offsets from every lookup table to its subtables, if they follow the lookup tables directly
+    let mut header_offset = list_header_length;
+    let mut subtable_offset = list_header_length + headers_length;
+    let mut use_extensions = false;
+
+    for lookup in lookups.iter() {
+        for subtable in lookup.subtables.iter() {
+            use_extensions |= subtable_offset - header_offset > u16::MAX as usize;
+            subtable_offset += subtable.len();
+        }
+
+        header_offset += header_length(lookup);
+    }
+
+    let mut out = Vec::new();
+    out.extend((lookups.len() as u16).to_be_bytes());
+
+    let mut header_offset = list_header_length;
+    for lookup in lookups.iter() {
+        out.extend((header_offset as u16).to_be_bytes());
+        header_offset += header_length(lookup);
+    }
+
+    // Where the subtables, or the extension subtables pointing to them, start
+    let mut subtable_offset = list_header_length + headers_length;
+    let mut header_offset = list_header_length;
+
+    for lookup in lookups.iter() {
+        let lookup_type = match use_extensions {
+            true => extension_type,
+            false => lookup.lookup_type,
+        };
+
+        out.extend(lookup_type.to_be_bytes());
+        let flags = match lookup.mark_filtering_set {
+            Some(_) => lookup.flags | lookup_flags::USE_MARK_FILTERING_SET,
+            None => lookup.flags & !lookup_flags::USE_MARK_FILTERING_SET,
+        };
+
+        out.extend(flags.to_be_bytes());
+        out.extend((lookup.subtables.len() as u16).to_be_bytes());
+
+        for subtable in lookup.subtables.iter() {
+            out.extend(((subtable_offset - header_offset) as u16).to_be_bytes());
+
+            subtable_offset += match use_extensions {
+                true => 8,
+                false => subtable.len(),
+            };
+        }
+
+        if let Some(mark_filtering_set) = lookup.mark_filtering_set {
+            out.extend(mark_filtering_set.to_be_bytes());
+        }
+
+        header_offset += header_length(lookup);
+    }
+
+    if use_extensions {
+        let count = lookups.iter().map(|l| l.subtables.len()).sum::<usize>();
+        let mut extension_offset = out.len();
+        let mut data_offset = out.len() + 8 * count;
+
+        for lookup in lookups.iter() {
+            for subtable in lookup.subtables.iter() {
+                out.extend(1u16.to_be_bytes()); // Format
+                out.extend(lookup.lookup_type.to_be_bytes());
+                out.extend(((data_offset - extension_offset) as u32).to_be_bytes());
+
+                extension_offset += 8;
+                data_offset += subtable.len();
+            }
+        }
+    }
+
+    for subtable in lookups.iter().flat_map(|l| l.subtables.iter()) {
+        out.extend(subtable);
+    }
+
+    out
+}
+
+/** The index of every named lookup, lookup names have to be unique. */
+pub(crate) fn lookup_names<'a>(
+    tag: [u8; 4],
+    names: impl Iterator<Item = Option<&'a str>>,
+) -> Result<BTreeMap<&'a str, u16>, LayoutError> {
+    let mut lookup_index = BTreeMap::new();
+
+    for (index, name) in names.enumerate() {
+        if let Some(name) = name {
+            if lookup_index.insert(name, index as u16).is_some() {
+                return Err(LayoutError::invalid_value(
+                    tag,
+                    "lookups",
+                    format!("there are two lookups named '{}'", name),
+                ));
+            }
+        }
+    }
+
+    Ok(lookup_index)
+}
+
+/** An offset or length, if it fits into 16 bits. */
+pub(crate) fn offset16(offset: usize) -> Option<u16> {
+    u16::try_from(offset).ok()
+}
+
+/**
+ * Writes the entries into one subtable, or halves them until each part fits into a subtable with 16 bit offsets. A
+ * glyph is only ever in one of the parts, so the order of the subtables does not matter.
+ */
+pub(crate) fn split_subtables<T>(
+    tag: [u8; 4],
+    entries: &[T],
+    subtable: impl Fn(&[T]) -> Option<Vec<u8>> + Copy,
+    subtables: &mut Vec<Vec<u8>>,
+) -> Result<(), LayoutError> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(data) = subtable(entries) {
+        subtables.push(data);
+        return Ok(());
+    }
+
+    if entries.len() == 1 {
+        return Err(LayoutError::invalid_value(
+            tag,
+            "lookups",
+            "the data of a single glyph exceeds the 64 KiB of a subtable",
+        ));
+    }
+
+    let (first, second) = entries.split_at(entries.len() / 2);
+    split_subtables(tag, first, subtable, subtables)?;
+    split_subtables(tag, second, subtable, subtables)
+}
+
+/**
+ * Encodes context rules, returns whether they need the chaining lookup type and the subtables. Rules of single
+ * glyphs are written in format 1 and rules whose sets can serve as classes in format 2, otherwise every rule gets a
+ * format 3 subtable of its own. Rules that start with the same glyph are tried in order.
+ */
+pub(crate) fn context_subtables(
+    tag: [u8; 4],
+    rules: &[ContextRule],
+    lookup_index: &BTreeMap<&str, u16>,
+) -> Result<(bool, Vec<Vec<u8>>), LayoutError> {
+    let invalid = |reason: String| LayoutError::invalid_value(tag, "rules", reason);
+
+    let normalize = |sets: &[Vec<u16>]| -> Vec<Vec<u16>> {
+        sets.iter()
+            .map(|set| {
+                let mut set = set.clone();
+                set.sort_unstable();
+                set.dedup();
+                set
+            })
+            .collect()
+    };
+
+    let mut encoded = Vec::new();
+
+    for rule in rules.iter() {
+        let input = normalize(&rule.input);
+        let backtrack = normalize(&rule.backtrack);
+        let lookahead = normalize(&rule.lookahead);
+
+        if input.is_empty()
+            || [&backtrack, &input, &lookahead]
+                .iter()
+                .any(|sets| sets.iter().any(Vec::is_empty))
+        {
+            return Err(invalid(String::from(
+                "a rule matches an empty sequence or an empty set of glyphs",
+            )));
+        }
+
+        let lookups = rule
+            .lookups
+            .iter()
+            .map(|record| {
+                if record.index as usize >= input.len() {
+                    return Err(invalid(format!(
+                        "lookup '{}' applies at {}, beyond the input sequence",
+                        record.lookup, record.index
+                    )));
+                }
+
+                match lookup_index.get(record.lookup.as_str()) {
+                    Some(index) => Ok((record.index, *index)),
+                    None => Err(invalid(format!(
+                        "there is no lookup named '{}'",
+                        record.lookup
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<_>, LayoutError>>()?;
+
+        encoded.push(Rule {
+            backtrack: backtrack.into_iter().rev().collect(),
+            input,
+            lookahead,
+            lookups,
+        });
+    }
+
+    let chained = encoded
+        .iter()
+        .any(|rule| !rule.backtrack.is_empty() || !rule.lookahead.is_empty());
+
+    let mut subtables = Vec::new();
+
+    if encoded.iter().all(Rule::is_glyphs) {
+        let mut by_first: BTreeMap<u16, Vec<&Rule>> = BTreeMap::new();
+        for rule in encoded.iter() {
+            by_first.entry(rule.input[0][0]).or_default().push(rule);
+        }
+
+        let sets: Vec<(u16, Vec<&Rule>)> = by_first.into_iter().collect();
+        split_subtables(
+            tag,
+            &sets,
+            |sets| glyph_context(chained, sets),
+            &mut subtables,
+        )?;
+
+        return Ok((chained, subtables));
+    }
+
+    if let Some(subtable) = class_context(chained, &encoded) {
+        subtables.push(subtable);
+        return Ok((chained, subtables));
+    }
+
+    for rule in encoded.iter() {
+        split_subtables(
+            tag,
+            std::slice::from_ref(rule),
+            |rule| coverage_context(chained, &rule[0]),
+            &mut subtables,
+        )?;
+    }
+
+    Ok((chained, subtables))
+}
+
+/** A validated context rule, the backtrack nearest glyph first as it is stored. */
+struct Rule {
+    backtrack: Vec<Vec<u16>>,
+    input: Vec<Vec<u16>>,
+    lookahead: Vec<Vec<u16>>,
+    lookups: Vec<(u16, u16)>,
+}
+
+impl Rule {
+    fn is_glyphs(&self) -> bool {
+        [&self.backtrack, &self.input, &self.lookahead]
+            .iter()
+            .all(|sets| sets.iter().all(|set| set.len() == 1))
+    }
+}
+
+/** A rule of format 1 or 2, with glyphs or classes. The first input is implied by the rule set. */
+fn sequence_rule(
+    chained: bool,
+    backtrack: &[u16],
+    input: &[u16],
+    lookahead: &[u16],
+    lookups: &[(u16, u16)],
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    let list = |out: &mut Vec<u8>, values: &[u16]| {
+        for value in values {
+            out.extend(value.to_be_bytes());
+        }
+    };
+
+    if chained {
+        out.extend((backtrack.len() as u16).to_be_bytes());
+        list(&mut out, backtrack);
+        out.extend((input.len() as u16).to_be_bytes());
+        list(&mut out, &input[1..]);
+        out.extend((lookahead.len() as u16).to_be_bytes());
+        list(&mut out, lookahead);
+        out.extend((lookups.len() as u16).to_be_bytes());
+    } else {
+        out.extend((input.len() as u16).to_be_bytes());
+        out.extend((lookups.len() as u16).to_be_bytes());
+        list(&mut out, &input[1..]);
+    }
+
+    for (index, lookup) in lookups {
+        out.extend(index.to_be_bytes());
+        out.extend(lookup.to_be_bytes());
+    }
+
+    out
+}
+
+/**
+ * Writes the rule sets that follow a header of `header_length`. Empty sets get no table, their offset is 0. Returns
+ * the offsets and the data, or `None` if an offset does not fit.
+ */
+fn rule_sets(header_length: usize, sets: &[Vec<Vec<u8>>]) -> Option<(Vec<u16>, Vec<u8>)> {
+    let mut offsets = Vec::new();
+    let mut tables = Vec::new();
+
+    for rules in sets.iter() {
+        if rules.is_empty() {
+            offsets.push(0);
+            continue;
+        }
+
+        offsets.push(offset16(header_length + tables.len())?);
+
+        let set_header_length = 2 + 2 * rules.len();
+        let mut rule_tables: Vec<u8> = Vec::new();
+
+        tables.extend((rules.len() as u16).to_be_bytes());
+        for rule in rules.iter() {
+            tables.extend(offset16(set_header_length + rule_tables.len())?.to_be_bytes());
+            rule_tables.extend(rule);
+        }
+        tables.extend(rule_tables);
+    }
+
+    Some((offsets, tables))
+}
+
+/** Format 1: rule sets by first glyph, rules of glyph sequences. */
+fn glyph_context(chained: bool, sets: &[(u16, Vec<&Rule>)]) -> Option<Vec<u8>> {
+    let first = |sets: &[Vec<u16>]| sets.iter().map(|set| set[0]).collect::<Vec<_>>();
+
+    let encoded: Vec<Vec<Vec<u8>>> = sets
+        .iter()
+        .map(|(_, rules)| {
+            rules
+                .iter()
+                .map(|rule| {
+                    sequence_rule(
+                        chained,
+                        &first(&rule.backtrack),
+                        &first(&rule.input),
+                        &first(&rule.lookahead),
+                        &rule.lookups,
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    let header_length = 6 + 2 * sets.len();
+    let (offsets, tables) = rule_sets(header_length, &encoded)?;
+
+    let covered: Vec<u16> = sets.iter().map(|(glyph, _)| *glyph).collect();
+
+    let mut out = Vec::new();
+    out.extend(1u16.to_be_bytes()); // Format
+    out.extend(offset16(header_length + tables.len())?.to_be_bytes());
+    out.extend((sets.len() as u16).to_be_bytes());
+    for offset in offsets {
+        out.extend(offset.to_be_bytes());
+    }
+    out.extend(tables);
+    out.extend(coverage(&covered));
+
+    Some(out)
+}
+
+/** The distinct sets, numbered from class 1 on, or `None` if two of them overlap. */
+fn classes<'a>(sets: impl Iterator<Item = &'a Vec<u16>>) -> Option<Vec<&'a Vec<u16>>> {
+    let mut classes: Vec<&Vec<u16>> = Vec::new();
+    let mut class_of = BTreeMap::new();
+
+    for set in sets {
+        if classes.contains(&set) {
+            continue;
+        }
+
+        for glyph in set.iter() {
+            if class_of.insert(*glyph, classes.len()).is_some() {
+                return None;
+            }
+        }
+
+        classes.push(set);
+    }
+
+    Some(classes)
+}
+
+fn class_values(classes: &[&Vec<u16>]) -> Vec<(u16, u16)> {
+    let mut values: Vec<(u16, u16)> = classes
+        .iter()
+        .enumerate()
+        .flat_map(|(class, set)| set.iter().map(move |glyph| (*glyph, class as u16 + 1)))
+        .collect();
+    values.sort_unstable();
+    values
+}
+
+/** Format 2: rule sets by class of the first glyph, rules of class sequences. `None` if sets overlap. */
+fn class_context(chained: bool, rules: &[Rule]) -> Option<Vec<u8>> {
+    let backtrack = classes(rules.iter().flat_map(|rule| rule.backtrack.iter()))?;
+    let input = classes(rules.iter().flat_map(|rule| rule.input.iter()))?;
+    let lookahead = classes(rules.iter().flat_map(|rule| rule.lookahead.iter()))?;
+
+    let class = |classes: &[&Vec<u16>], set: &Vec<u16>| {
+        classes.iter().position(|class| *class == set).unwrap() as u16 + 1
+    };
+    let sequence = |classes: &[&Vec<u16>], sets: &[Vec<u16>]| -> Vec<u16> {
+        sets.iter().map(|set| class(classes, set)).collect()
+    };
+
+    let mut sets = vec![Vec::new(); input.len() + 1];
+    for rule in rules.iter() {
+        sets[class(&input, &rule.input[0]) as usize].push(sequence_rule(
+            chained,
+            &sequence(&backtrack, &rule.backtrack),
+            &sequence(&input, &rule.input),
+            &sequence(&lookahead, &rule.lookahead),
+            &rule.lookups,
+        ));
+    }
+
+    let mut covered: Vec<u16> = rules
+        .iter()
+        .flat_map(|rule| rule.input[0].iter().copied())
+        .collect();
+    covered.sort_unstable();
+    covered.dedup();
+
+    let header_length = match chained {
+        true => 12 + 2 * sets.len(),
+        false => 8 + 2 * sets.len(),
+    };
+    let (offsets, tables) = rule_sets(header_length, &sets)?;
+
+    let coverage = coverage(&covered);
+    let mut class_defs = vec![class_def(&class_values(&input))];
+    if chained {
+        class_defs.insert(0, class_def(&class_values(&backtrack)));
+        class_defs.push(class_def(&class_values(&lookahead)));
+    }
+
+    let mut out = Vec::new();
+    out.extend(2u16.to_be_bytes()); // Format
+
+    let mut offset = header_length + tables.len();
+    out.extend(offset16(offset)?.to_be_bytes());
+    offset += coverage.len();
+    for class_def in class_defs.iter() {
+        out.extend(offset16(offset)?.to_be_bytes());
+        offset += class_def.len();
+    }
+
+    out.extend((sets.len() as u16).to_be_bytes());
+    for offset in offsets {
+        out.extend(offset.to_be_bytes());
+    }
+    out.extend(tables);
+    out.extend(coverage);
+    for class_def in class_defs {
+        out.extend(class_def);
+    }
+
+    Some(out)
+}
+
+/** Format 3: a single rule with a coverage for every position. */
+fn coverage_context(chained: bool, rule: &Rule) -> Option<Vec<u8>> {
+    let positions = rule.backtrack.len() + rule.input.len() + rule.lookahead.len();
+    let header_length = match chained {
+        true => 10 + 2 * positions + 4 * rule.lookups.len(),
+        false => 6 + 2 * positions + 4 * rule.lookups.len(),
+    };
+
+    let mut coverages = Vec::new();
+    let mut offsets = |sets: &[Vec<u16>]| -> Option<Vec<u8>> {
+        let mut out = (sets.len() as u16).to_be_bytes().to_vec();
+        for set in sets {
+            out.extend(offset16(header_length + coverages.len())?.to_be_bytes());
+            coverages.extend(coverage(set));
+        }
+        Some(out)
+    };
+
+    let mut out = Vec::new();
+    out.extend(3u16.to_be_bytes()); // Format
+
+    if chained {
+        out.extend(offsets(&rule.backtrack)?);
+        out.extend(offsets(&rule.input)?);
+        out.extend(offsets(&rule.lookahead)?);
+        out.extend((rule.lookups.len() as u16).to_be_bytes());
+    } else {
+        let input = offsets(&rule.input)?;
+        out.extend(&input[..2]);
+        out.extend((rule.lookups.len() as u16).to_be_bytes());
+        out.extend(&input[2..]);
+    }
+
+    for (index, lookup) in rule.lookups.iter() {
+        out.extend(index.to_be_bytes());
+        out.extend(lookup.to_be_bytes());
+    }
+    out.extend(coverages);
+
+    Some(out)
+}
+
+/** Lists the glyphs, which have to be sorted and unique, as ranges or one by one, whatever is shorter. */
+pub(crate) fn coverage(glyphs: &[u16]) -> Vec<u8> {
+    let ranges = ranges(glyphs.iter().map(|g| (*g, 0)));
+
+    let mut out = Vec::new();
+
+    if 6 * ranges.len() < 2 * glyphs.len() {
+        out.extend(2u16.to_be_bytes());
+        out.extend((ranges.len() as u16).to_be_bytes());
+
+        let mut index = 0u16;
+        for (start, end, _) in ranges {
+            out.extend(start.to_be_bytes());
+            out.extend(end.to_be_bytes());
+            out.extend(index.to_be_bytes());
+            index += end - start + 1;
+        }
+    } else {
+        out.extend(1u16.to_be_bytes());
+        out.extend((glyphs.len() as u16).to_be_bytes());
+        for glyph in glyphs {
+            out.extend(glyph.to_be_bytes());
+        }
+    }
+
+    out
+}
+
+/**
+ * Assigns classes to glyphs, given as pairs of glyph and class sorted by glyph. Glyphs that are not listed belong
+ * to class 0. Written as an array or as ranges, whatever is shorter.
+ */
+pub(crate) fn class_def(classes: &[(u16, u16)]) -> Vec<u8> {
+    let classes: Vec<(u16, u16)> = classes.iter().copied().filter(|(_, c)| *c != 0).collect();
+    let ranges = ranges(classes.iter().copied());
+
+    let mut out = Vec::new();
+
+    let array_length = match (classes.first(), classes.last()) {
+        (Some(first), Some(last)) => (last.0 - first.0) as usize + 1,
+        _ => 0,
+    };
+
+    if 6 * ranges.len() < 2 * array_length {
+        out.extend(2u16.to_be_bytes());
+        out.extend((ranges.len() as u16).to_be_bytes());
+
+        for (start, end, class) in ranges {
+            out.extend(start.to_be_bytes());
+            out.extend(end.to_be_bytes());
+            out.extend(class.to_be_bytes());
+        }
+    } else {
+        let start = classes.first().map_or(0, |c| c.0);
+
+        out.extend(1u16.to_be_bytes());
+        out.extend(start.to_be_bytes());
+        out.extend((array_length as u16).to_be_bytes());
+
+        let mut next = classes.iter().peekable();
+        for glyph in (0..array_length).map(|n| start + n as u16) {
+            let class = next.next_if(|(g, _)| *g == glyph).map_or(0, |(_, c)| *c);
+            out.extend(class.to_be_bytes());
+        }
+    }
+
+    out
+}
+
+/** Joins consecutive glyphs with the same value into ranges of first glyph, last glyph and value. */
+fn ranges(glyphs: impl Iterator<Item = (u16, u16)>) -> Vec<(u16, u16, u16)> {
+    let mut ranges: Vec<(u16, u16, u16)> = Vec::new();
+
+    for (glyph, value) in glyphs {
+        match ranges.last_mut() {
+            Some((_, end, last)) if *end + 1 == glyph && *last == value => *end = glyph,
+            _ => ranges.push((glyph, glyph, value)),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::open_type::tables::gpos::test::{class_of, covered, u16_at};
+
+    /** The lookups the first matching subtable applies at the position of the text, as sequence index and lookup. */
+    fn apply(
+        subtables: &[Vec<u8>],
+        chained: bool,
+        text: &[u16],
+        at: usize,
+    ) -> Option<Vec<(u16, u16)>> {
+        subtables.iter().find_map(|subtable| {
+            let format = u16_at(subtable, 0);
+            let at_offset = |offset: usize| &subtable[u16_at(subtable, offset) as usize..];
+
+            // How a value of a rule matches a glyph, by kind of position: 0 backtrack, 1 input, 2 lookahead
+            let class_defs: Vec<&[u8]> = match (format, chained) {
+                (2, true) => vec![at_offset(4), at_offset(6), at_offset(8)],
+                (2, false) => vec![at_offset(4); 3],
+                _ => Vec::new(),
+            };
+            let matches = |kind: usize, value: u16, glyph: u16| match format {
+                1 => value == glyph,
+                2 => class_of(class_defs[kind], glyph) == value,
+                _ => covered(&subtable[value as usize..], glyph).is_some(),
+            };
+
+            let rules: Vec<&[u8]> = match format {
+                3 => vec![&subtable[2..]],
+                _ => {
+                    let coverage = at_offset(2);
+                    let index = covered(coverage, text[at])?;
+                    let sets = match (format, chained) {
+                        (1, _) => 6,
+                        (_, true) => 12,
+                        (_, false) => 8,
+                    };
+                    let set = match format {
+                        1 => index,
+                        _ => class_of(class_defs[1], text[at]) as usize,
+                    };
+                    let set_offset = u16_at(subtable, sets + 2 * set) as usize;
+                    if set_offset == 0 {
+                        return None;
+                    }
+                    let set = &subtable[set_offset..];
+
+                    (0..u16_at(set, 0) as usize)
+                        .map(|n| &set[u16_at(set, 2 + 2 * n) as usize..])
+                        .collect()
+                }
+            };
+
+            rules.into_iter().find_map(|rule| {
+                let list = |offset: usize, count: usize| -> Vec<u16> {
+                    (0..count).map(|n| u16_at(rule, offset + 2 * n)).collect()
+                };
+                // Format 3 lists the first input glyph too
+                let implied = usize::from(format != 3);
+
+                let (backtrack, input, lookahead, records) = if chained {
+                    let backtrack = list(2, u16_at(rule, 0) as usize);
+                    let mut offset = 2 + 2 * backtrack.len();
+                    let input_count = u16_at(rule, offset) as usize;
+                    let input = list(offset + 2, input_count - implied);
+                    offset += 2 + 2 * input.len();
+                    let lookahead = list(offset + 2, u16_at(rule, offset) as usize);
+                    offset += 2 + 2 * lookahead.len();
+                    let records = list(offset + 2, 2 * u16_at(rule, offset) as usize);
+                    (backtrack, input, lookahead, records)
+                } else {
+                    let input = list(4, u16_at(rule, 0) as usize - implied);
+                    let records = list(4 + 2 * input.len(), 2 * u16_at(rule, 2) as usize);
+                    (Vec::new(), input, Vec::new(), records)
+                };
+
+                let start = at + implied;
+                let matched = backtrack.len() <= at
+                    && start + input.len() + lookahead.len() <= text.len()
+                    && backtrack
+                        .iter()
+                        .enumerate()
+                        .all(|(n, v)| matches(0, *v, text[at - 1 - n]))
+                    && input
+                        .iter()
+                        .enumerate()
+                        .all(|(n, v)| matches(1, *v, text[start + n]))
+                    && lookahead
+                        .iter()
+                        .enumerate()
+                        .all(|(n, v)| matches(2, *v, text[start + input.len() + n]));
+
+                matched.then(|| records.chunks(2).map(|r| (r[0], r[1])).collect())
+            })
+        })
+    }
+
+    fn rule(
+        backtrack: &[&[u16]],
+        input: &[&[u16]],
+        lookahead: &[&[u16]],
+        lookup: &str,
+    ) -> ContextRule {
+        let sets = |sets: &[&[u16]]| sets.iter().map(|set| set.to_vec()).collect();
+
+        ContextRule {
+            backtrack: sets(backtrack),
+            input: sets(input),
+            lookahead: sets(lookahead),
+            lookups: vec![SequenceLookup {
+                index: input.len() as u16 - 1,
+                lookup: String::from(lookup),
+            }],
+        }
+    }
+
+    #[test]
+    fn context_rules_take_the_smallest_format() {
+        let names = BTreeMap::from([("a", 1), ("b", 2)]);
+
+        // Single glyphs, format 1 without context
+        let rules = [
+            rule(&[], &[&[1], &[2]], &[], "a"),
+            rule(&[], &[&[1]], &[], "b"),
+        ];
+        let (chained, subtables) = context_subtables(*b"GSUB", &rules, &names).unwrap();
+        assert!(!chained);
+        assert_eq!(u16_at(&subtables[0], 0), 1);
+        assert_eq!(apply(&subtables, chained, &[1, 2], 0), Some(vec![(1, 1)]));
+        assert_eq!(apply(&subtables, chained, &[1, 3], 0), Some(vec![(0, 2)]));
+        assert_eq!(apply(&subtables, chained, &[2, 1], 0), None);
+
+        // Disjoint sets, format 2 with context
+        let rules = [
+            rule(&[&[7]], &[&[1, 2], &[3]], &[&[4, 5]], "a"),
+            rule(&[], &[&[1, 2], &[4, 5]], &[], "b"),
+        ];
+        let (chained, subtables) = context_subtables(*b"GSUB", &rules, &names).unwrap();
+        assert!(chained);
+        assert_eq!(u16_at(&subtables[0], 0), 2);
+        assert_eq!(
+            apply(&subtables, chained, &[7, 2, 3, 5], 1),
+            Some(vec![(1, 1)])
+        );
+        assert_eq!(apply(&subtables, chained, &[6, 2, 3, 5], 1), None);
+        assert_eq!(apply(&subtables, chained, &[1, 4], 0), Some(vec![(1, 2)]));
+
+        // Overlapping sets, format 3 for each rule
+        let rules = [
+            rule(&[], &[&[1, 2]], &[&[3]], "a"),
+            rule(&[], &[&[2, 3]], &[], "b"),
+        ];
+        let (chained, subtables) = context_subtables(*b"GSUB", &rules, &names).unwrap();
+        assert_eq!(subtables.len(), 2);
+        assert_eq!(u16_at(&subtables[0], 0), 3);
+        assert_eq!(apply(&subtables, chained, &[2, 3], 0), Some(vec![(0, 1)]));
+        assert_eq!(apply(&subtables, chained, &[2, 2], 0), Some(vec![(0, 2)]));
+
+        let unknown = [rule(&[], &[&[1]], &[], "c")];
+        assert!(context_subtables(*b"GSUB", &unknown, &names).is_err());
+    }
+
+    #[test]
+    fn coverage_and_classes_take_the_shorter_format() {
+        assert_eq!(coverage(&[3, 7]), vec![0, 1, 0, 2, 0, 3, 0, 7]);
+        assert_eq!(coverage(&[3, 4, 5, 6]), vec![0, 2, 0, 1, 0, 3, 0, 6, 0, 0]);
+
+        assert_eq!(
+            class_def(&[(4, 1), (5, 0), (6, 2)]),
+            vec![0, 1, 0, 4, 0, 3, 0, 1, 0, 0, 0, 2]
+        );
+        assert_eq!(
+            class_def(&(10..20).map(|g| (g, 1)).collect::<Vec<_>>()),
+            vec![0, 2, 0, 1, 0, 10, 0, 19, 0, 1]
+        );
+    }
+
+    #[test]
+    fn large_lookups_use_extensions() {
+        let lookups = |size| {
+            vec![
+                EncodedLookup {
+                    lookup_type: 2,
+                    flags: 0,
+                    mark_filtering_set: None,
+                    subtables: vec![vec![1; size], vec![2; size]],
+                },
+                EncodedLookup {
+                    lookup_type: 2,
+                    flags: 0,
+                    mark_filtering_set: None,
+                    subtables: vec![vec![3; 10]],
+                },
+            ]
+        };
+
+        let small = lookup_list(&lookups(100), 9);
+        assert_eq!(&small[6..8], &[0, 2]);
+
+        let large = lookup_list(&lookups(40000), 9);
+        let lookup = u16::from_be_bytes([large[2], large[3]]) as usize;
+        assert_eq!(&large[lookup..lookup + 2], &[0, 9]);
+
+        // The extension of the second subtable points to its data
+        let extension =
+            lookup + u16::from_be_bytes([large[lookup + 8], large[lookup + 9]]) as usize;
+        assert_eq!(&large[extension..extension + 4], &[0, 1, 0, 2]);
+        let data = extension
+            + u32::from_be_bytes(large[extension + 4..extension + 8].try_into().unwrap()) as usize;
+        assert_eq!(large[data], 2);
+        assert_eq!(large[data - 1], 1);
+    }
+}
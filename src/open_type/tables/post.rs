@@ -1,10 +1,12 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    layout::Reservation,
-    open_type::{Fixed, LayoutableTable, LayoutedTable},
+    layout::{LayoutError, Reservation},
+    open_type::{reader::ReadError, Fixed, LayoutableTable, LayoutedTable},
     Layoutable, Layouted,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Post {
     /** Italic angle in counter-clockwise degrees from the vertical. Zero for upright text, negative for text that leans to the right (forward). */
     pub italic_angle: Fixed,
@@ -39,6 +41,35 @@ impl Default for Post {
     }
 }
 
+impl Post {
+    /** Reads the header. Glyph names of version 2.0 tables are dropped, the table is always written as version 3.0. */
+    pub fn read(data: &[u8]) -> Result<Self, ReadError> {
+        use crate::open_type::FixedReadExt;
+        use byteorder::{ReadBytesExt, BE};
+
+        let mut reader = data;
+
+        let version = reader.read_u32::<BE>()?;
+        if ![0x00010000, 0x00020000, 0x00025000, 0x00030000].contains(&version) {
+            return Err(ReadError::UnsupportedVersion {
+                tag: *b"post",
+                version,
+            });
+        }
+
+        Ok(Self {
+            italic_angle: reader.read_fixed::<BE>()?,
+            underline_position: reader.read_i16::<BE>()?,
+            underline_thickness: reader.read_i16::<BE>()?,
+            is_fixed_pitch: reader.read_u32::<BE>()? != 0,
+            min_mem_type42: reader.read_u32::<BE>()?,
+            max_mem_type42: reader.read_u32::<BE>()?,
+            min_mem_type1: reader.read_u32::<BE>()?,
+            max_mem_type1: reader.read_u32::<BE>()?,
+        })
+    }
+}
+
 impl Layoutable<Box<dyn LayoutedTable>> for Post {
     fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
         Box::new(LayoutedPost {
@@ -70,10 +101,21 @@ impl Layouted for LayoutedPost {
         self.requires_another_pass
     }
 
-    fn pass(&mut self, _current_file: &[u8]) -> Result<(), crate::LayoutError> {
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
         use crate::open_type::FixedWriteExt;
         use byteorder::{WriteBytesExt, BE};
 
+        if !(-90..=90).contains(&self.table.italic_angle.major) {
+            return Err(LayoutError::invalid_value(
+                *b"post",
+                "italic_angle",
+                format!(
+                    "{} degrees is not within -90 to 90",
+                    self.table.italic_angle.major
+                ),
+            ));
+        }
+
         self.requires_another_pass = false;
 
         let mut writer = self.reservation.writer();
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout::{LayoutError, Reservation},
+    open_type::{LayoutableTable, LayoutedTable},
+    Layoutable, Layouted,
+};
+
+/**
+ * A table kept as the bytes it consists of, e.g. one this crate does not understand or a hand-made one. It is
+ * written as is and sorted and checksummed like every other table.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawTable {
+    pub tag: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Layoutable<Box<dyn LayoutedTable>> for RawTable {
+    fn layout(&self, layouter: &mut crate::Layouter) -> Box<dyn LayoutedTable> {
+        Box::new(LayoutedRawTable {
+            reservation: layouter.reserve(self.data.len()),
+            requires_another_pass: true,
+            table: self.clone(),
+        })
+    }
+}
+
+impl LayoutableTable for RawTable {
+    fn tag(&self) -> [u8; 4] {
+        self.tag
+    }
+}
+
+struct LayoutedRawTable {
+    reservation: Reservation,
+    requires_another_pass: bool,
+    table: RawTable,
+}
+
+impl Layouted for LayoutedRawTable {
+    fn reservation(&self) -> &Reservation {
+        &self.reservation
+    }
+
+    fn requires_another_pass(&self) -> bool {
+        self.requires_another_pass
+    }
+
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
+        use std::io::Write;
+
+        // Tags consist of printable ASCII, trailing spaces pad shorter ones
+        if !self.table.tag.iter().all(|b| (0x20..=0x7E).contains(b)) {
+            return Err(LayoutError::invalid_value(
+                self.table.tag,
+                "tag",
+                format!(
+                    "{:?} contains bytes that are not printable ASCII",
+                    self.table.tag
+                ),
+            ));
+        }
+
+        self.requires_another_pass = false;
+
+        self.reservation.writer().write_all(&self.table.data)?;
+
+        Ok(())
+    }
+}
+
+impl LayoutedTable for LayoutedRawTable {
+    fn tag(&self) -> [u8; 4] {
+        self.table.tag
+    }
+}
+
+/**
+ * A table that is encoded as a whole during layout, since its length is only known once it is encoded. Encoding
+ * errors are kept and reported by the first pass.
+ */
+pub(crate) struct LayoutedEncoded {
+    tag: [u8; 4],
+    requires_another_pass: bool,
+    reservation: Reservation,
+    data: Vec<u8>,
+    error: Option<LayoutError>,
+}
+
+impl LayoutedEncoded {
+    pub(crate) fn new(
+        tag: [u8; 4],
+        encoded: Result<Vec<u8>, LayoutError>,
+        layouter: &mut crate::Layouter,
+    ) -> Self {
+        let (data, error) = match encoded {
+            Ok(data) => (data, None),
+            Err(error) => (Vec::new(), Some(error)),
+        };
+
+        Self {
+            tag,
+            requires_another_pass: true,
+            reservation: layouter.reserve(data.len()),
+            data,
+            error,
+        }
+    }
+}
+
+impl Layouted for LayoutedEncoded {
+    fn reservation(&self) -> &Reservation {
+        &self.reservation
+    }
+
+    fn requires_another_pass(&self) -> bool {
+        self.requires_another_pass
+    }
+
+    fn pass(&mut self, _current_file: &[u8]) -> Result<(), LayoutError> {
+        use std::io::Write;
+
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        self.requires_another_pass = false;
+
+        self.reservation.writer().write_all(&self.data)?;
+
+        Ok(())
+    }
+}
+
+impl LayoutedTable for LayoutedEncoded {
+    fn tag(&self) -> [u8; 4] {
+        self.tag
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{build, open_type::reader::Font, test::sample_font};
+
+    #[test]
+    fn raw_tables_are_written_as_they_are() {
+        let mut font = sample_font(2);
+        font.raw_tables = vec![
+            RawTable {
+                tag: *b"DSIG",
+                data: vec![0, 0, 0, 1, 0, 0, 0, 0],
+            },
+            RawTable {
+                tag: *b"meta",
+                data: vec![1, 2, 3],
+            },
+        ];
+
+        let data = build(&font.clone().into_file(), false).unwrap();
+
+        assert_eq!(Font::read(&data).unwrap().raw_tables, font.raw_tables);
+    }
+
+    #[test]
+    fn tags_have_to_be_printable() {
+        let mut font = sample_font(1);
+        font.raw_tables = vec![RawTable {
+            tag: *b"a\0\0\0",
+            data: vec![],
+        }];
+
+        assert!(matches!(
+            build(&font.into_file(), false),
+            Err(LayoutError::InvalidValue { tag, field: "tag", .. }) if &tag == b"a\0\0\0"
+        ));
+    }
+}
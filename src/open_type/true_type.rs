@@ -1,10 +1,13 @@
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+use crate::open_type::F2Dot14;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Contour {
     pub points: Vec<Point>,
 }
 
-/** */
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Point {
     pub is_on_curve: bool,
     pub x: i16,
@@ -22,16 +25,138 @@ impl Point {
 
     pub fn off_curve(x: i16, y: i16) -> Self {
         Self {
-            is_on_curve: true,
+            is_on_curve: false,
             x,
             y,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/** A reference to another glyph inside a composite glyph. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Component {
+    pub glyph_index: u16,
+    pub placement: ComponentPlacement,
+    pub transform: ComponentTransform,
+    /** Round the offset to the pixel grid when hinting. */
+    pub round_xy_to_grid: bool,
+    /** Use the advance width and side bearings of this component for the composite glyph. */
+    pub use_my_metrics: bool,
+    /** The outlines of the components overlap. Only the first component of a glyph may set this. */
+    pub overlap_compound: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ComponentPlacement {
+    /** Moves the component by the given amount. */
+    Offset { x: i16, y: i16 },
+    /** Moves the component so its point `child` lies on the point `parent` of the components placed before it. */
+    MatchPoints { parent: u16, child: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ComponentTransform {
+    Identity,
+    Scale(F2Dot14),
+    ScaleXY {
+        x: F2Dot14,
+        y: F2Dot14,
+    },
+    /** `x' = xx * x + yx * y` and `y' = xy * x + yy * y` */
+    Matrix {
+        xx: F2Dot14,
+        xy: F2Dot14,
+        yx: F2Dot14,
+        yy: F2Dot14,
+    },
+}
+
+impl ComponentTransform {
+    pub fn apply(&self, x: i16, y: i16) -> (f32, f32) {
+        let (x, y) = (x as f32, y as f32);
+
+        match self {
+            ComponentTransform::Identity => (x, y),
+            ComponentTransform::Scale(scale) => (x * scale.to_f32(), y * scale.to_f32()),
+            ComponentTransform::ScaleXY { x: sx, y: sy } => (x * sx.to_f32(), y * sy.to_f32()),
+            ComponentTransform::Matrix { xx, xy, yx, yy } => (
+                xx.to_f32() * x + yx.to_f32() * y,
+                xy.to_f32() * x + yy.to_f32() * y,
+            ),
+        }
+    }
+
+    /** The number of bytes the transform occupies in a component record. */
+    pub fn size(&self) -> usize {
+        match self {
+            ComponentTransform::Identity => 0,
+            ComponentTransform::Scale(_) => 2,
+            ComponentTransform::ScaleXY { .. } => 4,
+            ComponentTransform::Matrix { .. } => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Instrution {
     PushBytes(Box<[u8]>),
+    /** Any other instructions, copied as they are. */
+    Raw(Box<[u8]>),
+}
+
+impl Instrution {
+    /** The number of bytes the encoded instruction occupies. */
+    pub fn size(&self) -> usize {
+        match self {
+            Instrution::PushBytes(bytes) => 1 + bytes.len(),
+            Instrution::Raw(bytes) => bytes.len(),
+        }
+    }
+
+    /**
+     * Splits bytecode into instructions. `PUSHB` becomes [Instrution::PushBytes], everything in between is kept as
+     * [Instrution::Raw]. Truncated bytecode is kept as it is.
+     */
+    pub fn decode(bytecode: &[u8]) -> Vec<Instrution> {
+        let mut instructions = Vec::new();
+        let mut raw_start = 0;
+        let mut position = 0;
+
+        while position < bytecode.len() {
+            let opcode = bytecode[position];
+
+            let length = match opcode {
+                // NPUSHB
+                0x40 => 2 + *bytecode.get(position + 1).unwrap_or(&0) as usize,
+                // NPUSHW
+                0x41 => 2 + 2 * *bytecode.get(position + 1).unwrap_or(&0) as usize,
+                // PUSHB[abc]
+                0xB0..=0xB7 => 2 + (opcode - 0xB0) as usize,
+                // PUSHW[abc]
+                0xB8..=0xBF => 1 + 2 * (opcode - 0xB7) as usize,
+                _ => 1,
+            };
+
+            let end = position + length;
+
+            if (0xB0..=0xB7).contains(&opcode) && end <= bytecode.len() {
+                if raw_start < position {
+                    instructions.push(Instrution::Raw(bytecode[raw_start..position].into()));
+                }
+
+                instructions.push(Instrution::PushBytes(bytecode[position + 1..end].into()));
+                raw_start = end;
+            }
+
+            position = end;
+        }
+
+        if raw_start < bytecode.len() {
+            instructions.push(Instrution::Raw(bytecode[raw_start..].into()));
+        }
+
+        instructions
+    }
 }
 
 pub trait InstrutionWriteExt: std::io::Write {
@@ -41,7 +166,11 @@ pub trait InstrutionWriteExt: std::io::Write {
 
         match instruction {
             I::PushBytes(bytes) => {
-                self.write_u8(0xB0 + bytes.len() as u8)?;
+                // PUSHB[abc] pushes abc + 1 bytes
+                self.write_u8(0xB0 + bytes.len() as u8 - 1)?;
+                self.write_all(bytes.as_ref())?;
+            }
+            I::Raw(bytes) => {
                 self.write_all(bytes.as_ref())?;
             }
         }
@@ -51,3 +180,22 @@ pub trait InstrutionWriteExt: std::io::Write {
 }
 
 impl<W: std::io::Write + ?Sized> InstrutionWriteExt for W {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn push_bytes_are_decoded_between_other_instructions() {
+        let instructions = Instrution::decode(&[0xB1, 5, 2, 0x40, 2, 1, 1, 0x2B, 0xB0, 9]);
+
+        assert_eq!(
+            instructions,
+            vec![
+                Instrution::PushBytes(Box::new([5, 2])),
+                Instrution::Raw(Box::new([0x40, 2, 1, 1, 0x2B])),
+                Instrution::PushBytes(Box::new([9])),
+            ]
+        );
+    }
+}
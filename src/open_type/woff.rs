@@ -0,0 +1,294 @@
+use std::io::Write;
+
+use byteorder::{WriteBytesExt, BE};
+use flate2::{write::ZlibEncoder, Compression};
+use thiserror::Error;
+
+use super::reader::{read_directory, ReadError, TableRecord};
+
+#[derive(Error, Debug)]
+pub enum WoffError {
+    #[error(transparent)]
+    ReadError(#[from] ReadError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+pub const WOFF_SIGNATURE: u32 = u32::from_be_bytes(*b"wOFF");
+
+/** The optional blocks that follow the tables of a WOFF file. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WoffOptions {
+    /** Extended metadata as XML, stored compressed. */
+    pub metadata: Option<String>,
+    /** Data for the font vendor, stored as it is. */
+    pub private_data: Option<Vec<u8>>,
+}
+
+const HEADER_LENGTH: usize = 44;
+const ENTRY_LENGTH: usize = 20;
+
+/**
+ * Wraps a laid out sfnt font into a WOFF 1.0 file. Every table is compressed with zlib on its own and stored
+ * uncompressed when that is not smaller. The directory keeps the checksums and lengths of the original tables.
+ */
+pub fn woff(sfnt: &[u8], options: &WoffOptions) -> Result<Vec<u8>, WoffError> {
+    let (flavor, mut records) = read_directory(sfnt)?;
+    records.sort_by_key(|r| r.tag);
+
+    let tables = records
+        .iter()
+        .map(|record| {
+            let data = record.data(sfnt)?;
+            let compressed = compress(data)?;
+
+            if compressed.len() < data.len() {
+                Ok(compressed)
+            } else {
+                Ok(data.to_vec())
+            }
+        })
+        .collect::<Result<Vec<_>, WoffError>>()?;
+
+    let total_sfnt_size = 12
+        + 16 * records.len()
+        + records
+            .iter()
+            .map(|r| padded(r.length as usize))
+            .sum::<usize>();
+
+    let (major_version, minor_version) = font_version(sfnt, &records);
+
+    let mut offset = HEADER_LENGTH + ENTRY_LENGTH * records.len();
+    let mut offsets = Vec::with_capacity(tables.len());
+
+    for table in tables.iter() {
+        offsets.push(offset);
+        offset += padded(table.len());
+    }
+
+    let metadata = options
+        .metadata
+        .as_ref()
+        .map(|metadata| compress(metadata.as_bytes()))
+        .transpose()?;
+
+    let meta_offset = offset;
+    let meta_length = metadata.as_ref().map_or(0, Vec::len);
+
+    if let Some(metadata) = &metadata {
+        offset += if options.private_data.is_some() {
+            padded(metadata.len())
+        } else {
+            metadata.len()
+        };
+    }
+
+    let private_offset = offset;
+    let private_length = options.private_data.as_ref().map_or(0, Vec::len);
+
+    let length = private_offset + private_length;
+
+    let mut out = Vec::with_capacity(length);
+
+    out.write_u32::<BE>(WOFF_SIGNATURE)?;
+    out.write_u32::<BE>(flavor)?;
+    out.write_u32::<BE>(length as u32)?;
+    out.write_u16::<BE>(records.len() as u16)?;
+    out.write_u16::<BE>(0)?; // Reserved
+    out.write_u32::<BE>(total_sfnt_size as u32)?;
+    out.write_u16::<BE>(major_version)?;
+    out.write_u16::<BE>(minor_version)?;
+
+    if metadata.is_some() {
+        out.write_u32::<BE>(meta_offset as u32)?;
+        out.write_u32::<BE>(meta_length as u32)?;
+        out.write_u32::<BE>(options.metadata.as_ref().map_or(0, String::len) as u32)?;
+    } else {
+        out.write_all(&[0; 12])?;
+    }
+
+    if options.private_data.is_some() {
+        out.write_u32::<BE>(private_offset as u32)?;
+        out.write_u32::<BE>(private_length as u32)?;
+    } else {
+        out.write_all(&[0; 8])?;
+    }
+
+    for ((record, table), offset) in records.iter().zip(tables.iter()).zip(offsets.iter()) {
+        out.write_all(&record.tag)?;
+        out.write_u32::<BE>(*offset as u32)?;
+        out.write_u32::<BE>(table.len() as u32)?;
+        out.write_u32::<BE>(record.length)?;
+        out.write_u32::<BE>(record.checksum)?;
+    }
+
+    for table in tables.iter() {
+        out.write_all(table)?;
+        pad(&mut out);
+    }
+
+    if let Some(metadata) = &metadata {
+        out.write_all(metadata)?;
+
+        if options.private_data.is_some() {
+            pad(&mut out);
+        }
+    }
+
+    if let Some(private_data) = &options.private_data {
+        out.write_all(private_data)?;
+    }
+
+    assert_eq!(out.len(), length);
+
+    Ok(out)
+}
+
+/** The font revision from `head`, which serves as the version of WOFF files. */
+pub(super) fn font_version(sfnt: &[u8], records: &[TableRecord]) -> (u16, u16) {
+    records
+        .iter()
+        .find(|r| &r.tag == b"head")
+        .and_then(|r| r.data(sfnt).ok()?.get(4..8))
+        .map(|revision| {
+            (
+                u16::from_be_bytes([revision[0], revision[1]]),
+                u16::from_be_bytes([revision[2], revision[3]]),
+            )
+        })
+        .unwrap_or((0, 0))
+}
+
+fn compress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+pub(super) fn padded(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+pub(super) fn pad(out: &mut Vec<u8>) {
+    out.resize(padded(out.len()), 0);
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    use super::*;
+    use crate::{build, open_type::tables::RawTable, test::sample_font};
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /** The tag, uncompressed data and checksum of every table in the WOFF file. */
+    fn tables(data: &[u8]) -> Vec<([u8; 4], Vec<u8>, u32)> {
+        let count = u16::from_be_bytes([data[12], data[13]]) as usize;
+
+        (0..count)
+            .map(|index| {
+                let entry = HEADER_LENGTH + ENTRY_LENGTH * index;
+
+                let tag = data[entry..entry + 4].try_into().unwrap();
+                let offset = u32_at(data, entry + 4) as usize;
+                let compressed = u32_at(data, entry + 8) as usize;
+                let length = u32_at(data, entry + 12) as usize;
+
+                let stored = &data[offset..offset + compressed];
+                let mut table = Vec::new();
+
+                if compressed < length {
+                    ZlibDecoder::new(stored).read_to_end(&mut table).unwrap();
+                } else {
+                    table.extend_from_slice(stored);
+                }
+
+                (tag, table, u32_at(data, entry + 16))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tables_are_stored_with_their_original_checksums() -> Result<(), WoffError> {
+        let mut font = sample_font(40);
+        // Too short to become smaller when compressed
+        font.raw_tables.push(RawTable {
+            tag: *b"DSIG",
+            data: vec![0, 0, 0, 1, 0, 0, 0, 0],
+        });
+
+        let sfnt = build(&font.into_file(), false).unwrap();
+        let data = woff(&sfnt, &WoffOptions::default())?;
+
+        assert_eq!(&data[0..4], b"wOFF");
+        assert_eq!(u32_at(&data, 8) as usize, data.len());
+        assert_eq!(u32_at(&data, 16) as usize, sfnt.len());
+        assert!(data.len() < sfnt.len());
+
+        let (_, records) = read_directory(&sfnt)?;
+        let tables = tables(&data);
+
+        assert_eq!(tables.len(), records.len());
+
+        for (tag, table, checksum) in tables {
+            let record = records.iter().find(|r| r.tag == tag).unwrap();
+
+            assert_eq!(table, record.data(&sfnt)?);
+            assert_eq!(checksum, record.checksum);
+        }
+
+        let glyf = HEADER_LENGTH + ENTRY_LENGTH * 3;
+        assert_eq!(&data[glyf..glyf + 4], b"glyf");
+        assert!(u32_at(&data, glyf + 8) < u32_at(&data, glyf + 12));
+
+        let dsig = HEADER_LENGTH;
+        assert_eq!(&data[dsig..dsig + 4], b"DSIG");
+        assert_eq!(u32_at(&data, dsig + 8), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_and_private_data_follow_the_tables() -> Result<(), WoffError> {
+        let sfnt = build(&sample_font(2).into_file(), false).unwrap();
+
+        let metadata = String::from("<?xml version=\"1.0\"?><metadata version=\"1.0\"/>");
+        let options = WoffOptions {
+            metadata: Some(metadata.clone()),
+            private_data: Some(vec![1, 2, 3]),
+        };
+
+        let data = woff(&sfnt, &options)?;
+
+        let meta_offset = u32_at(&data, 24) as usize;
+        let meta_length = u32_at(&data, 28) as usize;
+        assert_eq!(u32_at(&data, 32) as usize, metadata.len());
+
+        let mut decoded = String::new();
+        ZlibDecoder::new(&data[meta_offset..meta_offset + meta_length])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, metadata);
+
+        let private_offset = u32_at(&data, 36) as usize;
+        assert_eq!(private_offset % 4, 0);
+        assert_eq!(u32_at(&data, 40), 3);
+        assert_eq!(&data[private_offset..], &[1, 2, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_data_is_rejected() {
+        assert!(matches!(
+            woff(b"wOFF and more", &WoffOptions::default()),
+            Err(WoffError::ReadError(ReadError::UnknownSfntVersion(_)))
+        ));
+    }
+}
@@ -0,0 +1,1035 @@
+use std::io::Write;
+
+use byteorder::{WriteBytesExt, BE};
+use thiserror::Error;
+
+use super::{
+    reader::{read_directory, Font, ReadError},
+    tables::{write_components, Bounds, Glyf, Glyph, Head, Hmtx},
+    true_type::{Instrution, InstrutionWriteExt},
+    woff::{font_version, pad, padded, WoffOptions},
+};
+
+#[derive(Error, Debug)]
+pub enum Woff2Error {
+    #[error(transparent)]
+    ReadError(#[from] ReadError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("glyph {0} refers to a missing component or its components nest too deep")]
+    BrokenComposite(usize),
+}
+
+pub const WOFF2_SIGNATURE: u32 = u32::from_be_bytes(*b"wOF2");
+
+/** Tags the table directory stores as their index in this list instead of spelling them out. */
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+/** The index in the directory flags that announces a tag spelled out after them. */
+const ARBITRARY_TAG: u8 = 63;
+
+const HEADER_LENGTH: usize = 48;
+
+/**
+ * Wraps a laid out sfnt font into a WOFF 2.0 file. `glyf` and `loca` are replaced by the transformed glyph streams and
+ * `hmtx` leaves out side bearings that equal the left edge of the glyph. All tables are compressed together with
+ * Brotli.
+ */
+pub fn woff2(sfnt: &[u8], options: &WoffOptions) -> Result<Vec<u8>, Woff2Error> {
+    let (flavor, mut records) = read_directory(sfnt)?;
+    records.sort_by_key(|r| r.tag);
+
+    // The transformed `loca` is empty and has to follow `glyf` directly
+    let glyf_position = records.iter().position(|r| &r.tag == b"glyf");
+    let loca_position = records.iter().position(|r| &r.tag == b"loca");
+
+    let font = if let (Some(glyf), Some(loca)) = (glyf_position, loca_position) {
+        let loca = records.remove(loca);
+        records.insert(glyf + 1, loca);
+
+        Some(Font::read(sfnt)?)
+    } else {
+        None
+    };
+
+    let glyf = font.as_ref().and_then(|f| f.glyf.as_ref());
+
+    let mut directory = Vec::new();
+    let mut tables = Vec::new();
+
+    for record in records.iter() {
+        let transformed = match (&record.tag, glyf) {
+            (b"glyf", Some(glyf)) => {
+                let head = records.iter().find(|r| &r.tag == b"head");
+                let index_format = match head {
+                    Some(head) => Head::read_index_to_loc_format(head.data(sfnt)?)?,
+                    None => 1,
+                };
+
+                Some((0, transform_glyf(glyf, index_format)?))
+            }
+            (b"loca", Some(_)) => Some((0, vec![])),
+            (b"hmtx", Some(glyf)) => font
+                .as_ref()
+                .and_then(|f| f.hmtx.as_ref())
+                .and_then(|hmtx| transform_hmtx(hmtx, glyf))
+                .map(|table| (1, table)),
+            _ => None,
+        };
+
+        let index = KNOWN_TAGS.iter().position(|tag| *tag == &record.tag);
+
+        // Version 3 is the null transform of `glyf` and `loca`, for all other tables it is version 0
+        let version = match (&transformed, &record.tag) {
+            (Some((version, _)), _) => *version,
+            (None, b"glyf" | b"loca") => 3,
+            (None, _) => 0,
+        };
+
+        directory.write_u8(index.map_or(ARBITRARY_TAG, |i| i as u8) | version << 6)?;
+
+        if index.is_none() {
+            directory.write_all(&record.tag)?;
+        }
+
+        write_base_128(&mut directory, record.length)?;
+
+        match transformed {
+            Some((_, table)) => {
+                write_base_128(&mut directory, table.len() as u32)?;
+                tables.extend_from_slice(&table);
+            }
+            None => tables.extend_from_slice(record.data(sfnt)?),
+        }
+    }
+
+    let compressed = compress(&tables)?;
+
+    let total_sfnt_size = 12
+        + 16 * records.len()
+        + records
+            .iter()
+            .map(|r| padded(r.length as usize))
+            .sum::<usize>();
+
+    let (major_version, minor_version) = font_version(sfnt, &records);
+
+    let metadata = options
+        .metadata
+        .as_ref()
+        .map(|metadata| compress(metadata.as_bytes()))
+        .transpose()?;
+
+    let blocks_follow = metadata.is_some() || options.private_data.is_some();
+
+    let mut offset = HEADER_LENGTH + directory.len() + compressed.len();
+
+    if blocks_follow {
+        offset = padded(offset);
+    }
+
+    let meta_offset = offset;
+    let meta_length = metadata.as_ref().map_or(0, Vec::len);
+
+    if let Some(metadata) = &metadata {
+        offset += if options.private_data.is_some() {
+            padded(metadata.len())
+        } else {
+            metadata.len()
+        };
+    }
+
+    let private_offset = offset;
+    let private_length = options.private_data.as_ref().map_or(0, Vec::len);
+
+    let length = private_offset + private_length;
+
+    let mut out = Vec::with_capacity(length);
+
+    out.write_u32::<BE>(WOFF2_SIGNATURE)?;
+    out.write_u32::<BE>(flavor)?;
+    out.write_u32::<BE>(length as u32)?;
+    out.write_u16::<BE>(records.len() as u16)?;
+    out.write_u16::<BE>(0)?; // Reserved
+    out.write_u32::<BE>(total_sfnt_size as u32)?;
+    out.write_u32::<BE>(compressed.len() as u32)?;
+    out.write_u16::<BE>(major_version)?;
+    out.write_u16::<BE>(minor_version)?;
+
+    if metadata.is_some() {
+        out.write_u32::<BE>(meta_offset as u32)?;
+        out.write_u32::<BE>(meta_length as u32)?;
+        out.write_u32::<BE>(options.metadata.as_ref().map_or(0, String::len) as u32)?;
+    } else {
+        out.write_all(&[0; 12])?;
+    }
+
+    if options.private_data.is_some() {
+        out.write_u32::<BE>(private_offset as u32)?;
+        out.write_u32::<BE>(private_length as u32)?;
+    } else {
+        out.write_all(&[0; 8])?;
+    }
+
+    out.write_all(&directory)?;
+    out.write_all(&compressed)?;
+
+    if blocks_follow {
+        pad(&mut out);
+    }
+
+    if let Some(metadata) = &metadata {
+        out.write_all(metadata)?;
+
+        if options.private_data.is_some() {
+            pad(&mut out);
+        }
+    }
+
+    if let Some(private_data) = &options.private_data {
+        out.write_all(private_data)?;
+    }
+
+    assert_eq!(out.len(), length);
+
+    Ok(out)
+}
+
+fn compress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    encoder.write_all(data)?;
+
+    Ok(encoder.into_inner())
+}
+
+/** Splits the glyphs into the streams of the transformed `glyf` table, which compress far better than the glyphs. */
+fn transform_glyf(glyf: &Glyf, index_format: i16) -> Result<Vec<u8>, Woff2Error> {
+    let mut contour_counts = Vec::new();
+    let mut point_counts = Vec::new();
+    let mut flags = Vec::new();
+    let mut coordinates = Vec::new();
+    let mut composites = Vec::new();
+    let mut bounds_bitmap = vec![0; 4 * glyf.glyphs.len().div_ceil(32)];
+    let mut bounds = Vec::new();
+    let mut bytecode = Vec::new();
+
+    let instructions_size =
+        |instructions: &[Instrution]| -> usize { instructions.iter().map(Instrution::size).sum() };
+
+    for (glyph_id, glyph) in glyf.glyphs.iter().enumerate() {
+        match glyph {
+            Glyph::Empty => contour_counts.write_i16::<BE>(0)?,
+            Glyph::Simple {
+                contours,
+                instructions,
+            } => {
+                contour_counts.write_i16::<BE>(contours.len() as i16)?;
+
+                // Coordinates are stored relative to the previous point, also across contours
+                let mut previous = (0i16, 0i16);
+
+                for contour in contours.iter() {
+                    write_255_u16(&mut point_counts, contour.points.len() as u16)?;
+
+                    for point in contour.points.iter() {
+                        write_triplet(
+                            &mut flags,
+                            &mut coordinates,
+                            point.is_on_curve,
+                            point.x.wrapping_sub(previous.0),
+                            point.y.wrapping_sub(previous.1),
+                        )?;
+
+                        previous = (point.x, point.y);
+                    }
+                }
+
+                // The bounding box is left out as it follows from the points
+                write_255_u16(&mut coordinates, instructions_size(instructions) as u16)?;
+
+                for instruction in instructions.iter() {
+                    bytecode.write_instruction(instruction)?;
+                }
+            }
+            Glyph::Composite {
+                components,
+                instructions,
+            } => {
+                contour_counts.write_i16::<BE>(-1)?;
+
+                let glyph_bounds = glyf
+                    .outline(glyph_id as u16)
+                    .as_deref()
+                    .map(Bounds::of)
+                    .ok_or(Woff2Error::BrokenComposite(glyph_id))?;
+
+                bounds_bitmap[glyph_id / 8] |= 0x80 >> (glyph_id % 8);
+
+                bounds.write_i16::<BE>(glyph_bounds.x_min)?;
+                bounds.write_i16::<BE>(glyph_bounds.y_min)?;
+                bounds.write_i16::<BE>(glyph_bounds.x_max)?;
+                bounds.write_i16::<BE>(glyph_bounds.y_max)?;
+
+                write_components(components, !instructions.is_empty(), &mut composites)?;
+
+                if !instructions.is_empty() {
+                    write_255_u16(&mut coordinates, instructions_size(instructions) as u16)?;
+
+                    for instruction in instructions.iter() {
+                        bytecode.write_instruction(instruction)?;
+                    }
+                }
+            }
+        }
+    }
+
+    bounds_bitmap.extend_from_slice(&bounds);
+
+    let streams = [
+        contour_counts,
+        point_counts,
+        flags,
+        coordinates,
+        composites,
+        bounds_bitmap,
+        bytecode,
+    ];
+
+    let mut out = Vec::new();
+
+    out.write_u16::<BE>(0)?; // Reserved
+    out.write_u16::<BE>(0)?; // Option flags, there is no overlap bitmap
+    out.write_u16::<BE>(glyf.glyphs.len() as u16)?;
+    out.write_i16::<BE>(index_format)?;
+
+    for stream in streams.iter() {
+        out.write_u32::<BE>(stream.len() as u32)?;
+    }
+
+    for stream in streams.iter() {
+        out.write_all(stream)?;
+    }
+
+    Ok(out)
+}
+
+/**
+ * Leaves out the left side bearings that equal the minimum x of their glyph, which a decoder reads from the glyph
+ * again. `None` if no side bearings can be left out.
+ */
+fn transform_hmtx(hmtx: &Hmtx, glyf: &Glyf) -> Option<Vec<u8>> {
+    let is_x_min = |glyph_id: usize, left_side_bearing: i16| {
+        glyf.outline(glyph_id as u16)
+            .map(|outline| Bounds::of(&outline).x_min == left_side_bearing)
+            .unwrap_or(false)
+    };
+
+    let proportional = hmtx
+        .horizontal_metrics
+        .iter()
+        .enumerate()
+        .all(|(glyph_id, metric)| is_x_min(glyph_id, metric.left_side_bearing));
+
+    let monospaced = hmtx
+        .left_side_bearings
+        .iter()
+        .enumerate()
+        .all(|(index, lsb)| is_x_min(hmtx.horizontal_metrics.len() + index, *lsb));
+
+    let drop_proportional = proportional && !hmtx.horizontal_metrics.is_empty();
+    let drop_monospaced = monospaced && !hmtx.left_side_bearings.is_empty();
+
+    if !drop_proportional && !drop_monospaced {
+        return None;
+    }
+
+    let mut out = Vec::new();
+
+    out.push(drop_proportional as u8 | (drop_monospaced as u8) << 1);
+
+    for metric in hmtx.horizontal_metrics.iter() {
+        out.extend_from_slice(&metric.advance_width.to_be_bytes());
+    }
+
+    if !drop_proportional {
+        for metric in hmtx.horizontal_metrics.iter() {
+            out.extend_from_slice(&metric.left_side_bearing.to_be_bytes());
+        }
+    }
+
+    if !drop_monospaced {
+        for lsb in hmtx.left_side_bearings.iter() {
+            out.extend_from_slice(&lsb.to_be_bytes());
+        }
+    }
+
+    Some(out)
+}
+
+/** Big endian groups of 7 bits, the high bit marks that another group follows. */
+fn write_base_128(out: &mut Vec<u8>, value: u32) -> std::io::Result<()> {
+    let groups = (32 - value.leading_zeros()).div_ceil(7).max(1);
+
+    for group in (0..groups).rev() {
+        let bits = (value >> (7 * group)) as u8 & 0x7F;
+
+        out.write_u8(if group > 0 { bits | 0x80 } else { bits })?;
+    }
+
+    Ok(())
+}
+
+/** Small values take a single byte, the three largest byte values announce larger ones. */
+fn write_255_u16(out: &mut Vec<u8>, value: u16) -> std::io::Result<()> {
+    const WORD_CODE: u8 = 253;
+    const ONE_MORE_BYTE_CODE_2: u8 = 254;
+    const ONE_MORE_BYTE_CODE_1: u8 = 255;
+
+    match value {
+        0..=252 => out.write_u8(value as u8),
+        253..=505 => {
+            out.write_u8(ONE_MORE_BYTE_CODE_1)?;
+            out.write_u8((value - 253) as u8)
+        }
+        506..=761 => {
+            out.write_u8(ONE_MORE_BYTE_CODE_2)?;
+            out.write_u8((value - 506) as u8)
+        }
+        _ => {
+            out.write_u8(WORD_CODE)?;
+            out.write_u16::<BE>(value)
+        }
+    }
+}
+
+/**
+ * Encodes a point as a flag byte and one to four bytes of coordinates. The flag selects how many bits each delta takes
+ * and carries their signs, its high bit marks off-curve points.
+ */
+fn write_triplet(
+    flags: &mut Vec<u8>,
+    out: &mut Vec<u8>,
+    is_on_curve: bool,
+    dx: i16,
+    dy: i16,
+) -> std::io::Result<()> {
+    let on_curve_bit = if is_on_curve { 0 } else { 0x80 };
+
+    let (x, y) = (dx.unsigned_abs(), dy.unsigned_abs());
+    let x_sign = (dx >= 0) as u8;
+    let y_sign = (dy >= 0) as u8;
+    let signs = x_sign | y_sign << 1;
+
+    if dx == 0 && y < 1280 {
+        flags.push(on_curve_bit | ((y & 0xF00) >> 7) as u8 | y_sign);
+        out.push(y as u8);
+    } else if dy == 0 && x < 1280 {
+        flags.push(on_curve_bit | (10 + ((x & 0xF00) >> 7) as u8 + x_sign));
+        out.push(x as u8);
+    } else if x <= 64 && y <= 64 {
+        let (x, y) = (x - 1, y - 1);
+
+        flags.push(on_curve_bit | (20 + (x & 0x30) as u8 + ((y & 0x30) >> 2) as u8 + signs));
+        out.push(((x & 0xF) << 4 | (y & 0xF)) as u8);
+    } else if x <= 768 && y <= 768 {
+        let (x, y) = (x - 1, y - 1);
+
+        flags.push(on_curve_bit | (84 + 12 * (x >> 8) as u8 + ((y >> 8) << 2) as u8 + signs));
+        out.push(x as u8);
+        out.push(y as u8);
+    } else if x < 4096 && y < 4096 {
+        flags.push(on_curve_bit | (120 + signs));
+        out.push((x >> 4) as u8);
+        out.push(((x & 0xF) << 4 | y >> 8) as u8);
+        out.push(y as u8);
+    } else {
+        flags.push(on_curve_bit | (124 + signs));
+        out.write_u16::<BE>(x)?;
+        out.write_u16::<BE>(y)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use byteorder::ReadBytesExt;
+
+    use super::*;
+    use crate::{
+        build,
+        open_type::{
+            reader::checksum,
+            tables::{HorizontalMetric, RawTable},
+            true_type::{Component, ComponentPlacement, ComponentTransform, Contour, Point},
+        },
+        test::sample_font,
+    };
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_base_128(reader: &mut impl Read) -> u32 {
+        let mut value = 0;
+
+        loop {
+            let byte = reader.read_u8().unwrap();
+            value = value << 7 | (byte & 0x7F) as u32;
+
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    fn read_255_u16(reader: &mut impl Read) -> u16 {
+        match reader.read_u8().unwrap() {
+            253 => reader.read_u16::<BE>().unwrap(),
+            254 => 506 + reader.read_u8().unwrap() as u16,
+            255 => 253 + reader.read_u8().unwrap() as u16,
+            code => code as u16,
+        }
+    }
+
+    /** The point deltas and whether the point is on the curve. */
+    fn read_triplet(flag: u8, reader: &mut impl Read) -> (bool, i16, i16) {
+        let mut byte = || reader.read_u8().unwrap() as i32;
+        let with_sign = |flag: u8, value: i32| (if flag & 1 != 0 { value } else { -value }) as i16;
+
+        let is_on_curve = flag & 0x80 == 0;
+        let flag = flag & 0x7F;
+
+        let (dx, dy) = if flag < 10 {
+            (0, with_sign(flag, ((flag as i32 & 14) << 7) + byte()))
+        } else if flag < 20 {
+            (
+                with_sign(flag, (((flag as i32 - 10) & 14) << 7) + byte()),
+                0,
+            )
+        } else if flag < 84 {
+            let b0 = flag as i32 - 20;
+            let b1 = byte();
+            (
+                with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+                with_sign(flag >> 1, 1 + ((b0 & 0x0C) << 2) + (b1 & 0x0F)),
+            )
+        } else if flag < 120 {
+            let b0 = flag as i32 - 84;
+            (
+                with_sign(flag, 1 + ((b0 / 12) << 8) + byte()),
+                with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + byte()),
+            )
+        } else if flag < 124 {
+            let (b0, b1, b2) = (byte(), byte(), byte());
+            (
+                with_sign(flag, (b0 << 4) + (b1 >> 4)),
+                with_sign(flag >> 1, ((b1 & 0x0F) << 8) + b2),
+            )
+        } else {
+            let (b0, b1, b2, b3) = (byte(), byte(), byte(), byte());
+            (
+                with_sign(flag, (b0 << 8) + b1),
+                with_sign(flag >> 1, (b2 << 8) + b3),
+            )
+        };
+
+        (is_on_curve, dx, dy)
+    }
+
+    /** The length of the component data at the start of the slice and whether instructions follow it. */
+    fn components_length(data: &[u8]) -> (usize, bool) {
+        let mut length = 0;
+
+        loop {
+            let flags = u16::from_be_bytes([data[length], data[length + 1]]);
+
+            length += if flags & 0x0001 != 0 { 8 } else { 6 };
+            length += if flags & 0x0008 != 0 {
+                2
+            } else if flags & 0x0040 != 0 {
+                4
+            } else if flags & 0x0080 != 0 {
+                8
+            } else {
+                0
+            };
+
+            if flags & 0x0020 == 0 {
+                return (length, flags & 0x0100 != 0);
+            }
+        }
+    }
+
+    /** Rebuilds `glyf` and `loca` the way the glyf table of this crate writes glyphs, plus the minimum x per glyph. */
+    fn reconstruct_glyf(data: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<i16>) {
+        let mut header = Cursor::new(data);
+        header.set_position(4);
+
+        let number_of_glyphs = header.read_u16::<BE>().unwrap() as usize;
+        let index_format = header.read_u16::<BE>().unwrap();
+
+        let mut offset = 36;
+        let mut streams: Vec<Cursor<&[u8]>> = (0..7)
+            .map(|_| {
+                let length = header.read_u32::<BE>().unwrap() as usize;
+                let stream = Cursor::new(&data[offset..offset + length]);
+                offset += length;
+                stream
+            })
+            .collect();
+
+        let [contour_counts, point_counts, flags, coordinates, composites, bounds, bytecode] =
+            &mut streams[..]
+        else {
+            unreachable!()
+        };
+
+        let bitmap_length = 4 * number_of_glyphs.div_ceil(32) as u64;
+        let bitmap = bounds.get_ref()[..bitmap_length as usize].to_vec();
+        bounds.set_position(bitmap_length);
+
+        let mut glyf = Vec::new();
+        let mut loca = vec![0];
+        let mut x_mins = Vec::new();
+
+        for glyph_id in 0..number_of_glyphs {
+            let contour_count = contour_counts.read_i16::<BE>().unwrap();
+            let has_bounds = bitmap[glyph_id / 8] & (0x80 >> (glyph_id % 8)) != 0;
+
+            let mut glyph = Vec::new();
+            let mut x_min = 0;
+
+            if contour_count > 0 {
+                let counts: Vec<_> = (0..contour_count)
+                    .map(|_| read_255_u16(point_counts))
+                    .collect();
+
+                let mut points = Vec::new();
+                let mut previous = (0i16, 0i16);
+
+                for _ in 0..counts.iter().sum::<u16>() {
+                    let (on_curve, dx, dy) = read_triplet(flags.read_u8().unwrap(), coordinates);
+                    previous = (previous.0.wrapping_add(dx), previous.1.wrapping_add(dy));
+                    points.push(Point {
+                        is_on_curve: on_curve,
+                        x: previous.0,
+                        y: previous.1,
+                    });
+                }
+
+                let computed = Bounds::of(&[Contour {
+                    points: points.clone(),
+                }]);
+                x_min = computed.x_min;
+
+                glyph.write_i16::<BE>(contour_count).unwrap();
+                for value in [
+                    computed.x_min,
+                    computed.y_min,
+                    computed.x_max,
+                    computed.y_max,
+                ] {
+                    glyph.write_i16::<BE>(value).unwrap();
+                }
+
+                let mut end = 0;
+                for count in counts {
+                    end += count;
+                    glyph.write_u16::<BE>(end - 1).unwrap();
+                }
+
+                let length = read_255_u16(coordinates);
+                glyph.write_u16::<BE>(length).unwrap();
+                let mut code = vec![0; length as usize];
+                bytecode.read_exact(&mut code).unwrap();
+                glyph.extend_from_slice(&code);
+
+                for point in points.iter() {
+                    glyph.push(point.is_on_curve as u8);
+                }
+
+                let mut previous = 0i16;
+                for point in points.iter() {
+                    glyph
+                        .write_i16::<BE>(point.x.wrapping_sub(previous))
+                        .unwrap();
+                    previous = point.x;
+                }
+
+                let mut previous = 0i16;
+                for point in points.iter() {
+                    glyph
+                        .write_i16::<BE>(point.y.wrapping_sub(previous))
+                        .unwrap();
+                    previous = point.y;
+                }
+            } else if contour_count < 0 {
+                assert!(has_bounds);
+
+                glyph.write_i16::<BE>(-1).unwrap();
+
+                let mut bbox = [0; 8];
+                bounds.read_exact(&mut bbox).unwrap();
+                glyph.extend_from_slice(&bbox);
+                x_min = i16::from_be_bytes([bbox[0], bbox[1]]);
+
+                let rest = &composites.get_ref()[composites.position() as usize..];
+                let (length, has_instructions) = components_length(rest);
+                glyph.extend_from_slice(&rest[..length]);
+                composites.set_position(composites.position() + length as u64);
+
+                if has_instructions {
+                    let length = read_255_u16(coordinates);
+                    glyph.write_u16::<BE>(length).unwrap();
+                    let mut code = vec![0; length as usize];
+                    bytecode.read_exact(&mut code).unwrap();
+                    glyph.extend_from_slice(&code);
+                }
+            }
+
+            glyf.extend_from_slice(&glyph);
+            loca.push(glyf.len());
+            x_mins.push(x_min);
+        }
+
+        let loca = loca
+            .into_iter()
+            .flat_map(|offset| match index_format {
+                0 => ((offset / 2) as u16).to_be_bytes().to_vec(),
+                _ => (offset as u32).to_be_bytes().to_vec(),
+            })
+            .collect();
+
+        (glyf, loca, x_mins)
+    }
+
+    fn reconstruct_hmtx(data: &[u8], number_of_hmetrics: usize, x_mins: &[i16]) -> Vec<u8> {
+        let mut reader = Cursor::new(data);
+        let flags = reader.read_u8().unwrap();
+
+        let advances: Vec<_> = (0..number_of_hmetrics)
+            .map(|_| reader.read_u16::<BE>().unwrap())
+            .collect();
+
+        let mut out = Vec::new();
+
+        let proportional: Vec<_> = (0..number_of_hmetrics)
+            .map(|glyph_id| match flags & 1 {
+                0 => reader.read_i16::<BE>().unwrap(),
+                _ => x_mins[glyph_id],
+            })
+            .collect();
+
+        for (advance, lsb) in advances.iter().zip(proportional) {
+            out.write_u16::<BE>(*advance).unwrap();
+            out.write_i16::<BE>(lsb).unwrap();
+        }
+
+        for x_min in x_mins.iter().skip(number_of_hmetrics) {
+            let lsb = match flags & 2 {
+                0 => reader.read_i16::<BE>().unwrap(),
+                _ => *x_min,
+            };
+            out.write_i16::<BE>(lsb).unwrap();
+        }
+
+        out
+    }
+
+    /** Decodes a WOFF2 file into the tables of the font, keyed by tag, and whether each was transformed. */
+    fn decode(data: &[u8]) -> Vec<([u8; 4], Vec<u8>, bool)> {
+        let mut reader = Cursor::new(data);
+        assert_eq!(reader.read_u32::<BE>().unwrap(), WOFF2_SIGNATURE);
+
+        reader.set_position(12);
+        let number_of_tables = reader.read_u16::<BE>().unwrap();
+
+        reader.set_position(20);
+        let compressed_length = reader.read_u32::<BE>().unwrap() as usize;
+
+        reader.set_position(HEADER_LENGTH as u64);
+
+        let entries: Vec<_> = (0..number_of_tables)
+            .map(|_| {
+                let flags = reader.read_u8().unwrap();
+
+                let tag = match flags & 0x3F {
+                    ARBITRARY_TAG => {
+                        let mut tag = [0; 4];
+                        reader.read_exact(&mut tag).unwrap();
+                        tag
+                    }
+                    index => *KNOWN_TAGS[index as usize],
+                };
+
+                let version = flags >> 6;
+                let transformed = match &tag {
+                    b"glyf" | b"loca" => version == 0,
+                    _ => version != 0,
+                };
+
+                let length = read_base_128(&mut reader);
+                let stored = if transformed {
+                    read_base_128(&mut reader)
+                } else {
+                    length
+                };
+
+                (tag, stored as usize, transformed)
+            })
+            .collect();
+
+        let start = reader.position() as usize;
+        let mut tables = Vec::new();
+        brotli::Decompressor::new(&data[start..start + compressed_length], 4096)
+            .read_to_end(&mut tables)
+            .unwrap();
+
+        let mut offset = 0;
+        let mut decoded: Vec<_> = entries
+            .into_iter()
+            .map(|(tag, length, transformed)| {
+                let table = tables[offset..offset + length].to_vec();
+                offset += length;
+                (tag, table, transformed)
+            })
+            .collect();
+        assert_eq!(offset, tables.len());
+
+        let table = |decoded: &[([u8; 4], Vec<u8>, bool)], tag: &[u8; 4]| {
+            decoded.iter().position(|(t, _, _)| t == tag)
+        };
+
+        if let Some(glyf) = table(&decoded, b"glyf").filter(|i| decoded[*i].2) {
+            let (glyf_table, loca_table, x_mins) = reconstruct_glyf(&decoded[glyf].1);
+            decoded[glyf].1 = glyf_table;
+
+            let loca = table(&decoded, b"loca").unwrap();
+            decoded[loca].1 = loca_table;
+
+            if let Some(hmtx) = table(&decoded, b"hmtx").filter(|i| decoded[*i].2) {
+                let hhea = &decoded[table(&decoded, b"hhea").unwrap()].1;
+                let number_of_hmetrics = u16::from_be_bytes([hhea[34], hhea[35]]) as usize;
+
+                decoded[hmtx].1 = reconstruct_hmtx(&decoded[hmtx].1, number_of_hmetrics, &x_mins);
+            }
+        }
+
+        decoded
+    }
+
+    /** Puts the tables back into an sfnt font. */
+    fn assemble(flavor: u32, mut tables: Vec<([u8; 4], Vec<u8>, bool)>) -> Vec<u8> {
+        tables.sort_by_key(|(tag, _, _)| *tag);
+
+        let mut out = Vec::new();
+        out.write_u32::<BE>(flavor).unwrap();
+        out.write_u16::<BE>(tables.len() as u16).unwrap();
+        out.write_all(&[0; 6]).unwrap();
+
+        let mut offset = 12 + 16 * tables.len();
+
+        for (tag, table, _) in tables.iter() {
+            out.write_all(tag).unwrap();
+            out.write_u32::<BE>(checksum(table)).unwrap();
+            out.write_u32::<BE>(offset as u32).unwrap();
+            out.write_u32::<BE>(table.len() as u32).unwrap();
+            offset += padded(table.len());
+        }
+
+        for (_, table, _) in tables.iter() {
+            out.write_all(table).unwrap();
+            pad(&mut out);
+        }
+
+        out
+    }
+
+    fn font_with_all_kinds_of_glyphs() -> Font {
+        let mut font = sample_font(40);
+
+        let glyphs = &mut font.glyf.as_mut().unwrap().glyphs;
+
+        // Deltas that need every size of triplet
+        glyphs[1] = Glyph::Simple {
+            contours: vec![
+                Contour {
+                    points: vec![
+                        Point::on_curve(0, 0),
+                        Point::off_curve(0, 1279),
+                        Point::on_curve(-1279, 1279),
+                        Point::off_curve(-1215, 1215),
+                    ],
+                },
+                Contour {
+                    points: vec![
+                        Point::on_curve(-447, 447),
+                        Point::on_curve(2000, -1500),
+                        Point::off_curve(-30000, 30000),
+                        Point::on_curve(i16::MIN, i16::MAX),
+                    ],
+                },
+            ],
+            instructions: vec![],
+        };
+
+        glyphs[2] = Glyph::Composite {
+            components: vec![
+                Component {
+                    glyph_index: 3,
+                    placement: ComponentPlacement::Offset { x: 300, y: -4 },
+                    transform: ComponentTransform::Identity,
+                    round_xy_to_grid: true,
+                    use_my_metrics: true,
+                    overlap_compound: false,
+                },
+                Component {
+                    glyph_index: 4,
+                    placement: ComponentPlacement::Offset { x: 1, y: 2 },
+                    transform: ComponentTransform::Scale(crate::open_type::F2Dot14::from_bits(
+                        0x2000,
+                    )),
+                    round_xy_to_grid: false,
+                    use_my_metrics: false,
+                    overlap_compound: false,
+                },
+            ],
+            instructions: vec![Instrution::PushBytes(Box::new([1, 2, 3]))],
+        };
+
+        let glyf = font.glyf.as_ref().unwrap();
+        font.loca = Some(glyf.loca());
+
+        // Side bearings have to match the glyphs for `hmtx` to be transformed
+        let metrics = (0..glyf.glyphs.len())
+            .map(|glyph_id| HorizontalMetric {
+                advance_width: 40,
+                left_side_bearing: glyf
+                    .outline(glyph_id as u16)
+                    .map_or(0, |outline| Bounds::of(&outline).x_min),
+            })
+            .collect();
+        font.hmtx = Some(Hmtx::new_with_metrics(metrics));
+
+        font.raw_tables.push(RawTable {
+            tag: *b"DSIG",
+            data: vec![0, 0, 0, 1, 0, 0, 0, 0],
+        });
+
+        font
+    }
+
+    #[test]
+    fn decoded_tables_match_the_original() -> Result<(), Woff2Error> {
+        let sfnt = build(&font_with_all_kinds_of_glyphs().into_file(), false).unwrap();
+        let data = woff2(&sfnt, &WoffOptions::default())?;
+
+        assert_eq!(&data[0..4], b"wOF2");
+        assert_eq!(u32_at(&data, 8) as usize, data.len());
+        assert_eq!(u32_at(&data, 16) as usize, sfnt.len());
+        assert!(
+            data.len()
+                < crate::open_type::woff::woff(&sfnt, &WoffOptions::default())
+                    .unwrap()
+                    .len()
+        );
+
+        let tables = decode(&data);
+
+        let transformed: Vec<_> = tables.iter().filter(|t| t.2).map(|t| t.0).collect();
+        assert_eq!(transformed, vec![*b"glyf", *b"loca", *b"hmtx"]);
+
+        let (_, records) = read_directory(&sfnt)?;
+        assert_eq!(tables.len(), records.len());
+
+        for (tag, table, _) in tables.iter() {
+            let record = records.iter().find(|r| &r.tag == tag).unwrap();
+
+            assert_eq!(
+                table,
+                record.data(&sfnt)?,
+                "{}",
+                String::from_utf8_lossy(tag)
+            );
+        }
+
+        assert_eq!(
+            Font::read(&assemble(u32_at(&data, 4), tables))?,
+            Font::read(&sfnt)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn side_bearings_apart_from_the_outline_are_kept() -> Result<(), Woff2Error> {
+        let mut font = sample_font(3);
+        let hmtx = font.hmtx.as_mut().unwrap();
+        hmtx.horizontal_metrics[0].left_side_bearing = -3;
+        hmtx.left_side_bearings[1] = 7;
+
+        let sfnt = build(&font.clone().into_file(), false).unwrap();
+        let tables = decode(&woff2(&sfnt, &WoffOptions::default())?);
+
+        let hmtx = tables.iter().find(|t| &t.0 == b"hmtx").unwrap();
+        assert!(!hmtx.2);
+
+        assert_eq!(Font::read(&assemble(0x00010000, tables))?, font);
+
+        Ok(())
+    }
+
+    #[test]
+    fn triplets_decode_to_the_same_deltas() {
+        let values = [
+            0,
+            1,
+            -1,
+            63,
+            64,
+            65,
+            -65,
+            767,
+            768,
+            769,
+            1279,
+            1280,
+            -1280,
+            4095,
+            4096,
+            i16::MAX,
+            i16::MIN,
+        ];
+
+        for dx in values {
+            for dy in values {
+                for is_on_curve in [true, false] {
+                    let mut flags = Vec::new();
+                    let mut data = Vec::new();
+                    write_triplet(&mut flags, &mut data, is_on_curve, dx, dy).unwrap();
+
+                    let mut reader = Cursor::new(&data[..]);
+                    assert_eq!(
+                        read_triplet(flags[0], &mut reader),
+                        (is_on_curve, dx, dy),
+                        "({}, {})",
+                        dx,
+                        dy
+                    );
+                    assert_eq!(reader.position() as usize, data.len());
+                }
+            }
+        }
+    }
+}